@@ -53,7 +53,8 @@ pub fn generate<T: AsRef<str>>(
     Ok(bindings)
 }
 
-fn c_header_from_btf(path: &Path) -> Result<String, Error> {
+/// Dumps the types of the BTF file `path` as a C header with bpftool.
+pub fn c_header_from_btf(path: &Path) -> Result<String, Error> {
     let output = Command::new("bpftool")
         .args(&["btf", "dump", "file"])
         .arg(path)
@@ -3,36 +3,43 @@ use std::{
     collections::{HashMap, HashSet},
     error::Error,
     ffi::CString,
-    fs, io,
+    fs, io, mem,
     os::{raw::c_int, unix::io::RawFd},
     path::{Path, PathBuf},
 };
 
-use log::debug;
+use log::{debug, warn};
 use thiserror::Error;
 
 use crate::{
     generated::{
-        bpf_map_type::BPF_MAP_TYPE_PERF_EVENT_ARRAY, AYA_PERF_EVENT_IOC_DISABLE,
-        AYA_PERF_EVENT_IOC_ENABLE, AYA_PERF_EVENT_IOC_SET_BPF,
+        bpf_insn,
+        bpf_map_type::{
+            BPF_MAP_TYPE_ARRAY_OF_MAPS, BPF_MAP_TYPE_HASH_OF_MAPS, BPF_MAP_TYPE_PERF_EVENT_ARRAY,
+        },
+        bpf_prog_type, AYA_PERF_EVENT_IOC_DISABLE, AYA_PERF_EVENT_IOC_ENABLE,
+        AYA_PERF_EVENT_IOC_SET_BPF,
     },
-    maps::{Map, MapError, MapLock, MapRef, MapRefMut},
+    maps::{Map, MapCreateOptions, MapError, MapLock, MapRef, MapRefMut},
     obj::{
         btf::{Btf, BtfError},
         MapKind, Object, ParseError, ProgramSection,
     },
     programs::{
-        BtfTracePoint, CgroupSkb, CgroupSkbAttachType, Extension, FEntry, FExit, KProbe, LircMode2,
-        Lsm, PerfEvent, ProbeKind, Program, ProgramData, ProgramError, RawTracePoint,
-        SchedClassifier, SkMsg, SkSkb, SkSkbKind, SockOps, SocketFilter, TracePoint, UProbe, Xdp,
+        BtfTracePoint, CgroupDevice, CgroupSkb, CgroupSkbAttachType, CgroupSock, CgroupSockAddr,
+        CgroupSockAddrAttachType, CgroupSockAttachType, Extension, FEntry, FExit, Iter, KProbe,
+        LircMode2, Lsm, PerfEvent, ProbeKind, Program, ProgramData, ProgramError, RawTracePoint,
+        SchedClassifier, SkMsg, SkSkb, SkSkbKind, SockOps, SocketFilter, TracePoint, UProbe, Usdt,
+        Xdp, XdpAttachType,
     },
     sys::{
-        bpf_load_btf, bpf_map_freeze, bpf_map_update_elem_ptr, is_btf_datasec_supported,
-        is_btf_decl_tag_supported, is_btf_float_supported, is_btf_func_global_supported,
-        is_btf_func_supported, is_btf_supported, is_btf_type_tag_supported, is_prog_name_supported,
-        retry_with_verifier_logs,
+        bpf_enable_stats, bpf_get_object, bpf_map_get_info_by_fd, bpf_map_update_elem_ptr,
+        btf_load_with_log, is_btf_datasec_supported, is_btf_decl_tag_supported,
+        is_btf_float_supported, is_btf_func_global_supported, is_btf_func_supported,
+        is_btf_supported, is_btf_type_tag_supported, is_prog_name_supported, kernel_version,
+        BtfLoadError, BPF_STATS_RUN_TIME,
     },
-    util::{bytes_of, possible_cpus, VerifierLog, POSSIBLE_CPUS},
+    util::{bytes_of, ensure_memlock_sufficient, page_size, possible_cpus, POSSIBLE_CPUS},
 };
 
 pub(crate) const BPF_OBJ_NAME_LEN: usize = 16;
@@ -86,6 +93,53 @@ impl Default for PinningType {
     }
 }
 
+/// The verbosity of the kernel verifier log.
+///
+/// See [`BpfLoader::log_level`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VerifierLogLevel {
+    /// Don't request a log when programs load successfully.
+    Off,
+    /// Log the instructions the verifier walks through.
+    Level1,
+    /// Log the verifier state at every instruction.
+    Level2,
+    /// Log statistics about the verification, like the number of processed
+    /// instructions.
+    Stats,
+}
+
+impl VerifierLogLevel {
+    pub(crate) fn bits(self) -> u32 {
+        match self {
+            VerifierLogLevel::Off => 0,
+            VerifierLogLevel::Level1 => 1,
+            VerifierLogLevel::Level2 => 2,
+            VerifierLogLevel::Stats => 4,
+        }
+    }
+}
+
+// options applied to all the programs loaded by a BpfLoader
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct LoaderOptions {
+    pub(crate) log_level: VerifierLogLevel,
+    pub(crate) log_buf_size: usize,
+    pub(crate) token_fd: Option<RawFd>,
+    pub(crate) kern_version: Option<u32>,
+}
+
+impl Default for LoaderOptions {
+    fn default() -> Self {
+        LoaderOptions {
+            log_level: VerifierLogLevel::Off,
+            log_buf_size: 0,
+            token_fd: None,
+            kern_version: None,
+        }
+    }
+}
+
 // Features implements BPF and BTF feature detection
 #[derive(Default, Debug)]
 pub(crate) struct Features {
@@ -163,6 +217,28 @@ pub struct BpfLoader<'a> {
     globals: HashMap<&'a str, &'a [u8]>,
     features: Features,
     extensions: HashSet<&'a str>,
+    all_extensions: bool,
+    inner_map_prototypes: HashMap<&'a str, RawFd>,
+    map_create_options: HashMap<&'a str, MapCreateOptions>,
+    max_entries: HashMap<&'a str, u32>,
+    max_entries_fn: Option<MaxEntriesFn>,
+    replaced_maps: HashMap<&'a str, RawFd>,
+    options: LoaderOptions,
+    no_btf: bool,
+    section_renames: HashMap<&'a str, &'a str>,
+    allow_unknown_sections: bool,
+    enable_stats: bool,
+    auto_raise_memlock: bool,
+}
+
+type MaxEntriesCallback = dyn Fn(&str, u32) -> u32;
+
+struct MaxEntriesFn(Box<MaxEntriesCallback>);
+
+impl std::fmt::Debug for MaxEntriesFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MaxEntriesFn")
+    }
 }
 
 impl<'a> BpfLoader<'a> {
@@ -176,6 +252,18 @@ impl<'a> BpfLoader<'a> {
             globals: HashMap::new(),
             features,
             extensions: HashSet::new(),
+            all_extensions: false,
+            inner_map_prototypes: HashMap::new(),
+            map_create_options: HashMap::new(),
+            max_entries: HashMap::new(),
+            max_entries_fn: None,
+            replaced_maps: HashMap::new(),
+            options: LoaderOptions::default(),
+            no_btf: false,
+            section_renames: HashMap::new(),
+            allow_unknown_sections: true,
+            enable_stats: false,
+            auto_raise_memlock: false,
         }
     }
 
@@ -183,7 +271,9 @@ impl<'a> BpfLoader<'a> {
     ///
     /// The loader defaults to loading `BTF` info using [Btf::from_sys_fs].
     /// Use this method if you want to load `BTF` from a custom location or
-    /// pass `None` to disable `BTF` relocations entirely.
+    /// pass `None` to disable `BTF` relocations entirely. This undoes an
+    /// earlier call to [`no_btf`](Self::no_btf).
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -198,6 +288,165 @@ impl<'a> BpfLoader<'a> {
     /// ```
     pub fn btf(&mut self, btf: Option<&'a Btf>) -> &mut BpfLoader<'a> {
         self.btf = btf.map(Cow::Borrowed);
+        self.no_btf = false;
+        self
+    }
+
+    /// Sets the target [BTF](Btf) info, taking ownership of it.
+    ///
+    /// Like [`btf`](Self::btf), the provided BTF is used instead of
+    /// `/sys/kernel/btf/vmlinux` to apply CO-RE relocations. This can be used
+    /// to test programs against the BTF of a different kernel, or on hosts
+    /// that don't expose `/sys/kernel/btf/vmlinux`.
+    ///
+    /// Programs that resolve their attach point with BTF, like
+    /// [`FEntry`](crate::programs::FEntry), take the BTF as an argument of
+    /// their `load()` method, so the same BTF must be passed there.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{BpfLoader, Btf, Endianness};
+    ///
+    /// let btf = Btf::parse_file("/path/to/vmlinux.btf", Endianness::default())?;
+    /// let bpf = BpfLoader::new()
+    ///     .btf_override(btf)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn btf_override(&mut self, btf: Btf) -> &mut BpfLoader<'a> {
+        self.btf = Some(Cow::Owned(btf));
+        self.no_btf = false;
+        self
+    }
+
+    /// Skips all BTF processing.
+    ///
+    /// No CO-RE relocations are applied and the BTF of the object, if any,
+    /// isn't loaded into the kernel. Use this for programs that don't rely on
+    /// BTF, for example when loading on kernels without BTF support. A later
+    /// call to [`btf`](Self::btf) or [`btf_override`](Self::btf_override)
+    /// enables BTF processing again.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new().no_btf().load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn no_btf(&mut self) -> &mut BpfLoader<'a> {
+        self.btf = None;
+        self.no_btf = true;
+        self
+    }
+
+    /// Renames an ELF section before the programs and maps are extracted.
+    ///
+    /// The type of a program is derived from the name of its section, for
+    /// example `xdp/...` or `kprobe/...`. This can be used to load objects
+    /// whose sections were given non-standard names by their build system,
+    /// without changing the source of the programs. The BTF of the object
+    /// keeps referring to the original name.
+    ///
+    /// Loading fails with [`BpfError::ParseError`] if the object doesn't
+    /// contain a section named `old`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .rewrite_section_name("xdp_v2/filter", "xdp/filter")
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn rewrite_section_name(&mut self, old: &'a str, new: &'a str) -> &mut BpfLoader<'a> {
+        self.section_renames.insert(old, new);
+        self
+    }
+
+    /// Sets whether program sections of unknown type are skipped.
+    ///
+    /// A program section whose name doesn't start with a known program type,
+    /// eg because of a typo or because it was written for a newer version of
+    /// aya, is skipped with a warning by default. Pass `false` to make loading
+    /// fail with [`BpfError::ParseError`] instead. Sections that are renamed
+    /// with [`rewrite_section_name`](Self::rewrite_section_name) are checked
+    /// after they are renamed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .allow_unknown_sections(false)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn allow_unknown_sections(&mut self, allow: bool) -> &mut BpfLoader<'a> {
+        self.allow_unknown_sections = allow;
+        self
+    }
+
+    /// Enables the collection of run time statistics while the loaded
+    /// [`Bpf`] instance is alive.
+    ///
+    /// The statistics of a program are returned by
+    /// [`Program::stats`](crate::programs::Program::stats). There's no
+    /// per-program load flag: the kernel collects them for every program in
+    /// the system for as long as at least one process asked for them, either
+    /// with `BPF_ENABLE_STATS` like this method does or with the
+    /// `kernel.bpf_stats_enabled` sysctl. Collecting them adds some overhead
+    /// to every program run. Enabling them requires `CAP_SYS_ADMIN`, and
+    /// loading fails with [`BpfError::ProgramError`] otherwise.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.8.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .enable_stats(true)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn enable_stats(&mut self, enable: bool) -> &mut BpfLoader<'a> {
+        self.enable_stats = enable;
+        self
+    }
+
+    /// Sets whether `RLIMIT_MEMLOCK` is raised before loading, if it's too low
+    /// for the maps and programs of the object.
+    ///
+    /// Before 5.11, the kernel charges the memory used by maps and programs
+    /// against `RLIMIT_MEMLOCK` and loading fails with `EPERM` when the limit
+    /// is exceeded. When enabled, the loader estimates the memory needed by
+    /// the object on those kernels and raises the limit with
+    /// [`ensure_memlock_sufficient`](crate::util::ensure_memlock_sufficient)
+    /// before creating anything, failing with
+    /// [`BpfError::InsufficientMemlock`] if it can't be raised. Newer kernels
+    /// use memory cgroup accounting and the limit is left alone.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .auto_raise_memlock(true)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn auto_raise_memlock(&mut self, enable: bool) -> &mut BpfLoader<'a> {
+        self.auto_raise_memlock = enable;
         self
     }
 
@@ -267,6 +516,11 @@ impl<'a> BpfLoader<'a> {
     /// the program type is forced to be ] [`Extension`] and is not
     /// inferred from the ELF section name.
     ///
+    /// This only changes how the program is loaded: an [`Extension`] replaces
+    /// a function of another loaded program once attached. To make the
+    /// programs of the object use a map that already exists instead of
+    /// creating a new one, see [`BpfLoader::replace_map`].
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -283,6 +537,325 @@ impl<'a> BpfLoader<'a> {
         self
     }
 
+    /// Treat the provided programs as [`Extension`]s.
+    ///
+    /// This is equivalent to calling [`BpfLoader::extension`] for each name.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .extensions(&["myfunc", "otherfunc"])
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn extensions(&mut self, names: &[&'a str]) -> &mut BpfLoader<'a> {
+        for name in names {
+            self.extension(name);
+        }
+        self
+    }
+
+    /// Treat all the programs in `freplace` sections as [`Extension`]s.
+    ///
+    /// This is equivalent to calling [`BpfLoader::extension`] for each
+    /// program whose section name starts with `freplace`. Programs in other
+    /// sections, for example `xdp` programs meant to be attached to a
+    /// dispatcher program, are only loaded as [`Extension`]s if they're
+    /// listed with [`BpfLoader::extension`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .all_extensions()
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn all_extensions(&mut self) -> &mut BpfLoader<'a> {
+        self.all_extensions = true;
+        self
+    }
+
+    fn is_extension(&self, name: &str, section: &ProgramSection) -> bool {
+        self.extensions.contains(name)
+            || (self.all_extensions && matches!(section, ProgramSection::Extension { .. }))
+    }
+
+    /// Sets the inner map prototype used to create the map-in-map `outer_map_name`.
+    ///
+    /// Maps of type `BPF_MAP_TYPE_ARRAY_OF_MAPS` and `BPF_MAP_TYPE_HASH_OF_MAPS`
+    /// need a prototype of the maps they're going to store at creation time. The
+    /// kernel only uses `inner_map_fd` to read the type, key size, value size and
+    /// max entries of the inner maps, so the fd can be closed once loading
+    /// completes.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// # let inner_map_fd = 0;
+    /// let bpf = BpfLoader::new()
+    ///     .with_inner_map_prototype("OUTER", inner_map_fd)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn with_inner_map_prototype(
+        &mut self,
+        outer_map_name: &'a str,
+        inner_map_fd: RawFd,
+    ) -> &mut BpfLoader<'a> {
+        self.inner_map_prototypes
+            .insert(outer_map_name, inner_map_fd);
+        self
+    }
+
+    /// Sets the options used to create the map `map_name`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{maps::MapCreateOptions, BpfLoader};
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .map_create_options("RX_QUEUES", MapCreateOptions::new().numa_node(0))
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn map_create_options(
+        &mut self,
+        map_name: &'a str,
+        options: MapCreateOptions,
+    ) -> &mut BpfLoader<'a> {
+        self.map_create_options.insert(map_name, options);
+        self
+    }
+
+    /// Overrides the `max_entries` of the map `map_name`.
+    ///
+    /// The value defined in the object file is replaced before the map is
+    /// created. This is useful to size maps at runtime, for example based on
+    /// the number of CPUs or the available memory. Maps that are reused from a
+    /// pinned path keep the size they were created with, and loading fails
+    /// with [`BpfError::MaxEntriesMismatch`] if it differs from the override.
+    ///
+    /// With aya-bpf, the value defined in the object file is the
+    /// `max_entries` argument given to the map constructor, eg
+    /// `HashMap::with_max_entries(1024, 0)`, and `map_name` is the name of
+    /// the static marked with `#[map]` or the `name` argument of the
+    /// attribute. The `#[map]` attribute places the definition in the
+    /// `maps/<name>` section, from which it's parsed when the object is
+    /// loaded, so the compiled value acts as the default that this
+    /// override replaces. Maps created with the `pinned` constructors are
+    /// pinned by name under [`BpfLoader::map_pin_path`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .max_entries_override("CONNECTIONS", 65536)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn max_entries_override(
+        &mut self,
+        map_name: &'a str,
+        max_entries: u32,
+    ) -> &mut BpfLoader<'a> {
+        self.max_entries.insert(map_name, max_entries);
+        self
+    }
+
+    /// Overrides the `max_entries` of all the maps.
+    ///
+    /// `f` is called with the name of each map and the `max_entries` defined in
+    /// the object file, and returns the value to create the map with. Values
+    /// set with [`BpfLoader::max_entries_override`] take precedence.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     // double the size of all the hash maps
+    ///     .max_entries_override_all(|name, max_entries| {
+    ///         if name.ends_with("_HASH") {
+    ///             max_entries * 2
+    ///         } else {
+    ///             max_entries
+    ///         }
+    ///     })
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn max_entries_override_all<F: Fn(&str, u32) -> u32 + 'static>(
+        &mut self,
+        f: F,
+    ) -> &mut BpfLoader<'a> {
+        self.max_entries_fn = Some(MaxEntriesFn(Box::new(f)));
+        self
+    }
+
+    /// Uses an existing map instead of creating the map called `name`.
+    ///
+    /// The file descriptor is duplicated, so `map_fd` can be closed once the
+    /// object is loaded. The map must have the type, key size and value size
+    /// the object expects. The map is used as is: it isn't initialized with
+    /// the data of the object and its `max_entries` overrides are ignored.
+    ///
+    /// This is useful when the maps are created by another process, for
+    /// example a control plane sharing maps between multiple programs.
+    /// Unlike [`BpfLoader::extension`], this doesn't change how any program
+    /// is loaded, only which map its relocations point to.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # let shared_map_fd = 42;
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .replace_map("SHARED_STATE", shared_map_fd)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn replace_map(&mut self, name: &'a str, map_fd: RawFd) -> &mut BpfLoader<'a> {
+        self.replaced_maps.insert(name, map_fd);
+        self
+    }
+
+    /// Loads the object using a BPF token.
+    ///
+    /// A BPF token lets a process without `CAP_BPF` and `CAP_SYS_ADMIN` in
+    /// the initial user namespace load programs and create maps. Tokens are
+    /// created with [`bpf_token_create`](crate::sys::bpf_token_create) from a
+    /// BPF filesystem mounted with the `delegate_cmds`, `delegate_maps`,
+    /// `delegate_progs` and `delegate_attachs` options. The filesystem must be
+    /// mounted by a privileged process and owned by the user namespace of the
+    /// process using the token, which can then only perform the operations
+    /// the mount delegates. Anyone holding the token fd gets the delegated
+    /// permissions, so it should be handled like a capability.
+    ///
+    /// The token is used to load the BTF of the object, to create its maps
+    /// and to load its programs.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 6.9.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{sys::bpf_token_create, BpfLoader};
+    /// use std::{fs::File, os::unix::io::AsRawFd};
+    ///
+    /// let bpffs = File::open("/sys/fs/bpf")?;
+    /// let token_fd = bpf_token_create(bpffs.as_raw_fd())?;
+    /// let bpf = BpfLoader::new()
+    ///     .with_token(token_fd)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    ///
+    pub fn with_token(&mut self, token_fd: RawFd) -> &mut BpfLoader<'a> {
+        self.options.token_fd = Some(token_fd);
+        self
+    }
+
+    /// Sets the kernel version passed to the kernel when the programs are
+    /// loaded, overriding the `version` section of the object.
+    ///
+    /// By default programs are loaded with the version of the object, or
+    /// with the version of the running kernel if the object works with any
+    /// kernel. Kernels older than 5.0 only load kprobe programs whose version
+    /// is the one of the running kernel, and kprobes built for another
+    /// version are retried with the running version unless it's overridden.
+    /// Newer kernels ignore the version. See also
+    /// [`Program::set_kern_version`](crate::programs::Program::set_kern_version).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// // LINUX_VERSION_CODE of 4.9.0
+    /// let bpf = BpfLoader::new()
+    ///     .kern_version_override(4 << 16 | 9 << 8)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn kern_version_override(&mut self, version: u32) -> &mut BpfLoader<'a> {
+        self.options.kern_version = Some(version);
+        self
+    }
+
+    /// Sets the verbosity of the verifier log of the programs.
+    ///
+    /// When a program fails to load the verifier log is always included in
+    /// the returned [`ProgramError::LoadError`](crate::programs::ProgramError::LoadError).
+    /// When the level is not [`VerifierLogLevel::Off`], the log is also
+    /// captured for the programs that load successfully and can be retrieved
+    /// with [`Program::verifier_log`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{BpfLoader, VerifierLogLevel};
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .log_level(VerifierLogLevel::Stats)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn log_level(&mut self, level: VerifierLogLevel) -> &mut BpfLoader<'a> {
+        self.options.log_level = level;
+        self
+    }
+
+    /// Sets the initial size of the verifier log buffer of the programs.
+    ///
+    /// The buffer is grown as needed if the log doesn't fit. Pre-sizing it
+    /// avoids loading programs with large logs multiple times.
+    ///
+    /// The size only applies when a [`log_level`](BpfLoader::log_level) other
+    /// than [`VerifierLogLevel::Off`] is set. With the default level no log is
+    /// requested on the first attempt, and programs that fail to load are
+    /// retried with a buffer of the default size.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{BpfLoader, VerifierLogLevel};
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .log_level(VerifierLogLevel::Level1)
+    ///     .log_buf_size(1024 * 1024)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn log_buf_size(&mut self, size: usize) -> &mut BpfLoader<'a> {
+        self.options.log_buf_size = size;
+        self
+    }
+
     /// Loads eBPF bytecode from a file.
     ///
     /// # Examples
@@ -314,10 +887,59 @@ impl<'a> BpfLoader<'a> {
     /// # Ok::<(), aya::BpfError>(())
     /// ```
     pub fn load(&mut self, data: &[u8]) -> Result<Bpf, BpfError> {
-        let mut obj = Object::parse(data)?;
+        self.parse(data)?.load()
+    }
+
+    /// Parses eBPF bytecode from a buffer without loading it.
+    ///
+    /// Parsing reads the ELF object, its BTF and its relocations and applies
+    /// the globals set with [`set_global`](BpfLoader::set_global), but it
+    /// doesn't create any maps or programs in the kernel. The returned
+    /// [`ParsedBpf`] can be inspected to decide whether the object should be
+    /// loaded, and [`ParsedBpf::load`] then loads it with the options of this
+    /// loader.
+    ///
+    /// Note that [`BpfLoader::new`] probes the features supported by the
+    /// kernel, which does make some syscalls.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    /// use std::fs;
+    ///
+    /// let data = fs::read("file.o").unwrap();
+    /// let mut loader = BpfLoader::new();
+    /// let parsed = loader.parse(&data)?;
+    /// if parsed.map_names().any(|name| name == "EVENTS") {
+    ///     let bpf = parsed.load()?;
+    /// }
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn parse<'l>(&'l mut self, data: &[u8]) -> Result<ParsedBpf<'l, 'a>, BpfError> {
+        let mut obj = Object::parse(data, &self.section_renames, self.allow_unknown_sections)?;
+        obj.verify_maps_consistent()?;
         obj.patch_map_data(self.globals.clone())?;
+        Ok(ParsedBpf { loader: self, obj })
+    }
 
-        let btf_fd = if self.features.btf {
+    fn load_object(&mut self, mut obj: Object) -> Result<Bpf, BpfError> {
+        if self.auto_raise_memlock && matches!(kernel_version(), Ok(k_ver) if k_ver < (5, 11, 0)) {
+            ensure_memlock_sufficient(memlock_estimate(&obj, page_size()))
+                .map_err(BpfError::InsufficientMemlock)?;
+        }
+        let stats = if self.enable_stats {
+            let fd = bpf_enable_stats(BPF_STATS_RUN_TIME).map_err(|(_, io_error)| {
+                ProgramError::SyscallError {
+                    call: "bpf_enable_stats".to_owned(),
+                    io_error,
+                }
+            })? as RawFd;
+            Some(StatsFd(fd))
+        } else {
+            None
+        };
+        let btf_fd = if self.features.btf && !self.no_btf {
             if let Some(ref mut obj_btf) = obj.btf {
                 // fixup btf
                 let section_data = obj.section_sizes.clone();
@@ -325,7 +947,7 @@ impl<'a> BpfLoader<'a> {
                 obj_btf.fixup_and_sanitize(&section_data, &symbol_offsets, &self.features)?;
                 // load btf to the kernel
                 let raw_btf = obj_btf.to_bytes();
-                Some(load_btf(raw_btf)?)
+                Some(load_btf(raw_btf, self.options.token_fd)?)
             } else {
                 None
             }
@@ -337,6 +959,7 @@ impl<'a> BpfLoader<'a> {
             obj.relocate_btf(btf)?;
         }
 
+        let obj_btf = obj.btf.as_ref();
         let mut maps = HashMap::new();
         for (name, mut obj) in obj.maps.drain() {
             if obj.def.map_type == BPF_MAP_TYPE_PERF_EVENT_ARRAY as u32 && obj.def.max_entries == 0
@@ -348,33 +971,96 @@ impl<'a> BpfLoader<'a> {
                     })?
                     .len() as u32;
             }
+            let original_max_entries = obj.def.max_entries;
+            if let Some(max_entries) = self.max_entries.get(name.as_str()) {
+                obj.def.max_entries = *max_entries;
+            } else if let Some(MaxEntriesFn(f)) = &self.max_entries_fn {
+                obj.def.max_entries = f(&name, obj.def.max_entries);
+            }
+            let mut options = self
+                .map_create_options
+                .get(name.as_str())
+                .cloned()
+                .unwrap_or_default();
+            if let Some(fd) = self.inner_map_prototypes.get(name.as_str()) {
+                options.inner_map_fd = Some(*fd);
+            }
+            options.token_fd = self.options.token_fd;
+            // the types set with MapCreateOptions::with_btf take precedence
+            if options.btf_data.is_none() {
+                if let (Some(fd), Some((key_type_id, value_type_id))) =
+                    (btf_fd, obj_btf.and_then(|btf| btf.map_kv_type_ids(&name)))
+                {
+                    options.btf_fd = Some(fd);
+                    options.btf_key_type_id = key_type_id;
+                    options.btf_value_type_id = value_type_id;
+                }
+            }
+            if (obj.def.map_type == BPF_MAP_TYPE_ARRAY_OF_MAPS as u32
+                || obj.def.map_type == BPF_MAP_TYPE_HASH_OF_MAPS as u32)
+                && options.inner_map_fd.is_none()
+            {
+                return Err(BpfError::MissingInnerMapPrototype { name });
+            }
             let mut map = Map {
                 obj,
                 fd: None,
                 pinned: false,
+                frozen: false,
+                name: Some(name.clone()),
             };
-            let fd = match map.obj.def.pinning {
-                PinningType::ByName => {
+            let replaced = self.replaced_maps.get(name.as_str()).copied();
+            let fd = match (replaced, map.obj.def.pinning) {
+                (Some(map_fd), _) => {
+                    let overridden = map.obj.def.max_entries != original_max_entries;
+                    let fd = reuse_map(&name, &mut map, map_fd)?;
+                    if overridden {
+                        warn!(
+                            "map {} is replaced, ignoring its max_entries override",
+                            name
+                        );
+                    }
+                    fd
+                }
+                (None, PinningType::ByName) => {
                     let path = match &self.map_pin_path {
                         Some(p) => p,
                         None => return Err(BpfError::NoPinPath),
                     };
+                    let requested_max_entries = map.obj.def.max_entries;
                     // try to open map in case it's already pinned
                     match map.open_pinned(&name, path) {
                         Ok(fd) => {
+                            // open_pinned sets max_entries to the size of the
+                            // pinned map, which only matters if it was overridden
+                            if requested_max_entries != original_max_entries
+                                && map.obj.def.max_entries != requested_max_entries
+                            {
+                                return Err(BpfError::MaxEntriesMismatch {
+                                    name,
+                                    max_entries: map.obj.def.max_entries,
+                                    expected: requested_max_entries,
+                                });
+                            }
                             map.pinned = true;
                             fd as RawFd
                         }
+                        // the map is pinned but doesn't match its definition
+                        Err(
+                            e @ (MapError::InvalidMapType { .. }
+                            | MapError::InvalidKeySize { .. }
+                            | MapError::InvalidValueSize { .. }),
+                        ) => return Err(e.into()),
                         Err(_) => {
-                            let fd = map.create(&name)?;
+                            let fd = map.create_with_options(&name, &options)?;
                             map.pin(&name, path)?;
                             fd
                         }
                     }
                 }
-                PinningType::None => map.create(&name)?,
+                (None, PinningType::None) => map.create_with_options(&name, &options)?,
             };
-            if !map.obj.data.is_empty() && map.obj.kind != MapKind::Bss {
+            if replaced.is_none() && !map.obj.data.is_empty() && map.obj.kind != MapKind::Bss {
                 bpf_map_update_elem_ptr(fd, &0 as *const _, map.obj.data.as_mut_ptr(), 0).map_err(
                     |(code, io_error)| MapError::SyscallError {
                         call: "bpf_map_update_elem".to_owned(),
@@ -383,12 +1069,8 @@ impl<'a> BpfLoader<'a> {
                     },
                 )?;
             }
-            if map.obj.kind == MapKind::Rodata {
-                bpf_map_freeze(fd).map_err(|(code, io_error)| MapError::SyscallError {
-                    call: "bpf_map_freeze".to_owned(),
-                    code,
-                    io_error,
-                })?;
+            if replaced.is_none() && map.obj.kind == MapKind::Rodata {
+                map.freeze()?;
             }
             maps.insert(name, map);
         }
@@ -414,9 +1096,19 @@ impl<'a> BpfLoader<'a> {
                     attach_btf_obj_fd: None,
                     attach_btf_id: None,
                     attach_prog_fd: None,
+                    attach_prog_id: None,
+                    attach_func_name: None,
+                    prog_ifindex: None,
+                    prog_flags: 0,
+                    attach_cookie: None,
                     btf_fd,
+                    log_level: self.options.log_level,
+                    log_buf_size: self.options.log_buf_size,
+                    verifier_log: None,
+                    token_fd: self.options.token_fd,
+                    kern_version: self.options.kern_version,
                 };
-                let program = if self.extensions.contains(name.as_str()) {
+                let program = if self.is_extension(&name, &data.obj.section) {
                     Program::Extension(Extension { data })
                 } else {
                     match &data.obj.section {
@@ -436,13 +1128,25 @@ impl<'a> BpfLoader<'a> {
                             data,
                             kind: ProbeKind::URetProbe,
                         }),
+                        ProgramSection::Usdt { .. } => Program::Usdt(Usdt { data }),
                         ProgramSection::TracePoint { .. } => {
                             Program::TracePoint(TracePoint { data })
                         }
                         ProgramSection::SocketFilter { .. } => {
                             Program::SocketFilter(SocketFilter { data })
                         }
-                        ProgramSection::Xdp { .. } => Program::Xdp(Xdp { data }),
+                        ProgramSection::Xdp { .. } => Program::Xdp(Xdp {
+                            data,
+                            attach_type: XdpAttachType::Interface,
+                        }),
+                        ProgramSection::XdpCpuMap { .. } => Program::Xdp(Xdp {
+                            data,
+                            attach_type: XdpAttachType::CpuMap,
+                        }),
+                        ProgramSection::XdpDevMap { .. } => Program::Xdp(Xdp {
+                            data,
+                            attach_type: XdpAttachType::DevMap,
+                        }),
                         ProgramSection::SkMsg { .. } => Program::SkMsg(SkMsg { data }),
                         ProgramSection::SkSkbStreamParser { .. } => Program::SkSkb(SkSkb {
                             data,
@@ -474,6 +1178,47 @@ impl<'a> BpfLoader<'a> {
                             data,
                             expected_attach_type: Some(CgroupSkbAttachType::Egress),
                         }),
+                        ProgramSection::CgroupSockCreate { .. } => {
+                            Program::CgroupSock(CgroupSock {
+                                data,
+                                attach_type: CgroupSockAttachType::SockCreate,
+                            })
+                        }
+                        ProgramSection::CgroupSockPostBind4 { .. } => {
+                            Program::CgroupSock(CgroupSock {
+                                data,
+                                attach_type: CgroupSockAttachType::PostBind4,
+                            })
+                        }
+                        ProgramSection::CgroupSockPostBind6 { .. } => {
+                            Program::CgroupSock(CgroupSock {
+                                data,
+                                attach_type: CgroupSockAttachType::PostBind6,
+                            })
+                        }
+                        ProgramSection::CgroupSockRelease { .. } => {
+                            Program::CgroupSock(CgroupSock {
+                                data,
+                                attach_type: CgroupSockAttachType::SockRelease,
+                            })
+                        }
+                        ProgramSection::CgroupSockAddrConnect4 { .. }
+                        | ProgramSection::CgroupSockAddrConnect6 { .. }
+                        | ProgramSection::CgroupSockAddrBind4 { .. }
+                        | ProgramSection::CgroupSockAddrBind6 { .. }
+                        | ProgramSection::CgroupSockAddrSendmsg4 { .. }
+                        | ProgramSection::CgroupSockAddrSendmsg6 { .. }
+                        | ProgramSection::CgroupSockAddrRecvmsg4 { .. }
+                        | ProgramSection::CgroupSockAddrRecvmsg6 { .. }
+                        | ProgramSection::CgroupSockAddrGetpeername4 { .. }
+                        | ProgramSection::CgroupSockAddrGetpeername6 { .. } => {
+                            let attach_type =
+                                CgroupSockAddrAttachType::from_section(&data.obj.section).unwrap();
+                            Program::CgroupSockAddr(CgroupSockAddr { data, attach_type })
+                        }
+                        ProgramSection::CgroupDevice { .. } => {
+                            Program::CgroupDevice(CgroupDevice { data })
+                        }
                         ProgramSection::LircMode2 { .. } => Program::LircMode2(LircMode2 { data }),
                         ProgramSection::PerfEvent { .. } => Program::PerfEvent(PerfEvent { data }),
                         ProgramSection::RawTracePoint { .. } => {
@@ -484,8 +1229,12 @@ impl<'a> BpfLoader<'a> {
                             Program::BtfTracePoint(BtfTracePoint { data })
                         }
                         ProgramSection::FEntry { .. } => Program::FEntry(FEntry { data }),
-                        ProgramSection::FExit { .. } => Program::FExit(FExit { data }),
+                        ProgramSection::FExit { .. } => Program::FExit(FExit {
+                            data,
+                            return_access: false,
+                        }),
                         ProgramSection::Extension { .. } => Program::Extension(Extension { data }),
+                        ProgramSection::Iter { .. } => Program::Iter(Iter { data }),
                     }
                 };
                 (name, program)
@@ -495,7 +1244,166 @@ impl<'a> BpfLoader<'a> {
             .drain()
             .map(|(name, map)| (name, MapLock::new(map)))
             .collect();
-        Ok(Bpf { maps, programs })
+        Ok(Bpf {
+            maps,
+            programs,
+            _stats: stats,
+        })
+    }
+
+    /// Loads eBPF bytecode from a buffer, then loads all the programs and
+    /// pins them and all the maps in `bpffs_dir`.
+    ///
+    /// Each program is loaded with the `load` method of its type, so this is
+    /// meant for program types that don't need any load time argument, and
+    /// [`BpfError::LoadArgumentsRequired`] is returned for the others, eg
+    /// [`FEntry`] or [`Lsm`] programs. The program
+    /// and map names are used as pin names, with the dots that bpffs doesn't
+    /// allow replaced by underscores and leading dots removed, for example
+    /// `.rodata` is pinned as `rodata`. Maps with
+    /// [pinning](crate::BpfLoader::map_pin_path) enabled are left where
+    /// they're already pinned.
+    ///
+    /// The returned [`BpfPinnedSet`] contains the pin paths and the [`Bpf`]
+    /// instance is dropped: the pins keep the programs and maps alive. This
+    /// is the usual pattern for long running daemons that must survive
+    /// restarts. If loading or pinning fails, the pins created so far are
+    /// removed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    /// use std::fs;
+    ///
+    /// let data = fs::read("file.o").unwrap();
+    /// let pinned = BpfLoader::new().auto_pin(&data, "/sys/fs/bpf/myapp")?;
+    /// let counters_fd = pinned.open_map("COUNTERS")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn auto_pin<P: AsRef<Path>>(
+        &mut self,
+        data: &[u8],
+        bpffs_dir: P,
+    ) -> Result<BpfPinnedSet, BpfError> {
+        let dir = bpffs_dir.as_ref();
+        let mut bpf = self.load(data)?;
+        let mut pinned = BpfPinnedSet {
+            programs: HashMap::new(),
+            maps: HashMap::new(),
+        };
+        let mut created = Vec::new();
+
+        let res = (|| {
+            for (name, program) in bpf.programs_mut() {
+                load_without_arguments(name, program)?;
+                let path = dir.join(pin_name(name));
+                program.pin(&path)?;
+                created.push(path.clone());
+                pinned.programs.insert(name.to_owned(), path);
+            }
+            for (name, map) in bpf.maps_mut() {
+                let mut map = map?;
+                let path = if map.pinned {
+                    match &self.map_pin_path {
+                        Some(pin_path) => pin_path.join(name),
+                        None => continue,
+                    }
+                } else {
+                    let pin_name = pin_name(name);
+                    map.pin(&pin_name, dir)?;
+                    let path = dir.join(pin_name);
+                    created.push(path.clone());
+                    path
+                };
+                pinned.maps.insert(name.to_owned(), path);
+            }
+            Ok::<_, BpfError>(())
+        })();
+
+        if let Err(e) = res {
+            for path in &created {
+                let _ = fs::remove_file(path);
+            }
+            return Err(e);
+        }
+        Ok(pinned)
+    }
+}
+
+// bpffs doesn't allow dots in file names
+fn pin_name(name: &str) -> String {
+    name.trim_start_matches('.').replace('.', "_")
+}
+
+/// The programs and maps pinned by [`BpfLoader::auto_pin`].
+///
+/// The programs and maps stay loaded as long as they're pinned, even once no
+/// process has them open. Use [`open_program`](BpfPinnedSet::open_program)
+/// and [`open_map`](BpfPinnedSet::open_map) to get a new file descriptor for
+/// them, for example after a restart.
+#[derive(Debug)]
+pub struct BpfPinnedSet {
+    programs: HashMap<String, PathBuf>,
+    maps: HashMap<String, PathBuf>,
+}
+
+impl BpfPinnedSet {
+    /// Returns the path the program `name` is pinned at.
+    pub fn program_path(&self, name: &str) -> Option<&Path> {
+        self.programs.get(name).map(PathBuf::as_path)
+    }
+
+    /// Returns the path the map `name` is pinned at.
+    pub fn map_path(&self, name: &str) -> Option<&Path> {
+        self.maps.get(name).map(PathBuf::as_path)
+    }
+
+    /// Opens the pinned program `name` and returns a new file descriptor for
+    /// it.
+    ///
+    /// The fd is created with `O_CLOEXEC` and must be closed by the caller.
+    /// It can be passed to [`Program::from_fd`].
+    pub fn open_program(&self, name: &str) -> Result<RawFd, ProgramError> {
+        let path = self
+            .programs
+            .get(name)
+            .ok_or_else(|| ProgramError::InvalidPinPath {
+                error: format!("program `{}` is not pinned", name),
+            })?;
+        let path = CString::new(path.to_string_lossy().into_owned()).map_err(|e| {
+            ProgramError::InvalidPinPath {
+                error: e.to_string(),
+            }
+        })?;
+        bpf_get_object(&path)
+            .map(|fd| fd as RawFd)
+            .map_err(|(_, io_error)| ProgramError::SyscallError {
+                call: "bpf_obj_get".to_owned(),
+                io_error,
+            })
+    }
+
+    /// Opens the pinned map `name` and returns a new file descriptor for it.
+    ///
+    /// The fd is created with `O_CLOEXEC` and must be closed by the caller.
+    /// It can be passed to [`BpfLoader::replace_map`].
+    pub fn open_map(&self, name: &str) -> Result<RawFd, MapError> {
+        let path = self.maps.get(name).ok_or_else(|| MapError::MapNotFound {
+            name: name.to_owned(),
+        })?;
+        let path = CString::new(path.to_string_lossy().into_owned()).map_err(|e| {
+            MapError::InvalidPinPath {
+                error: e.to_string(),
+            }
+        })?;
+        bpf_get_object(&path)
+            .map(|fd| fd as RawFd)
+            .map_err(|(code, io_error)| MapError::SyscallError {
+                call: "bpf_obj_get".to_owned(),
+                code,
+                io_error,
+            })
     }
 }
 
@@ -505,11 +1413,117 @@ impl<'a> Default for BpfLoader<'a> {
     }
 }
 
+/// An eBPF object that has been parsed but not loaded yet.
+///
+/// Returned by [`BpfLoader::parse`].
+pub struct ParsedBpf<'l, 'a> {
+    loader: &'l mut BpfLoader<'a>,
+    obj: Object,
+}
+
+impl<'l, 'a> ParsedBpf<'l, 'a> {
+    /// An iterator over the names of the programs of the object.
+    pub fn program_names(&self) -> impl Iterator<Item = &str> {
+        self.obj.programs.keys().map(|name| name.as_str())
+    }
+
+    /// An iterator over the names of the maps of the object.
+    pub fn map_names(&self) -> impl Iterator<Item = &str> {
+        self.obj.maps.keys().map(|name| name.as_str())
+    }
+
+    /// Returns the type the program will be loaded with, or `None` if there's
+    /// no program with the given name.
+    ///
+    /// Programs selected with [`BpfLoader::extension`] or
+    /// [`BpfLoader::all_extensions`] are reported as `BPF_PROG_TYPE_EXT`.
+    pub fn program_type(&self, name: &str) -> Option<bpf_prog_type> {
+        let program = self.obj.programs.get(name)?;
+        if self.loader.is_extension(name, &program.section) {
+            Some(bpf_prog_type::BPF_PROG_TYPE_EXT)
+        } else {
+            Some(program.section.prog_type())
+        }
+    }
+
+    /// Returns the type of the map as declared in the object, or `None` if
+    /// there's no map with the given name.
+    pub fn map_type(&self, name: &str) -> Option<u32> {
+        self.obj.maps.get(name).map(|map| map.def.map_type)
+    }
+
+    /// Returns the definitions of the maps that loading the object will
+    /// create, keyed by map name.
+    ///
+    /// The definitions are the ones declared in the object, before
+    /// [`BpfLoader::max_entries_override`] and the other loader options are
+    /// applied.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let data = std::fs::read("file.o")?;
+    /// let mut loader = BpfLoader::new();
+    /// let parsed = loader.parse(&data)?;
+    /// for (name, map) in parsed.extract_maps() {
+    ///     println!("{}: type {} max_entries {}", name, map.map_type, map.max_entries);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn extract_maps(&self) -> HashMap<String, ParsedMap> {
+        self.obj.extract_maps()
+    }
+
+    /// Creates the maps of the object in the kernel and relocates its
+    /// programs, like [`BpfLoader::load`] does.
+    ///
+    /// The programs themselves still have to be loaded with
+    /// [`Program::load`](crate::programs::Program::load).
+    pub fn load(self) -> Result<Bpf, BpfError> {
+        self.loader.load_object(self.obj)
+    }
+}
+
+/// The definition of a map of an object, returned by
+/// [`ParsedBpf::extract_maps`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedMap {
+    /// The type of the map, a `bpf_map_type` value.
+    pub map_type: u32,
+    /// The size of the keys, in bytes.
+    pub key_size: u32,
+    /// The size of the values, in bytes.
+    pub value_size: u32,
+    /// The maximum number of entries.
+    pub max_entries: u32,
+    /// The `BPF_F_*` map flags.
+    pub flags: u32,
+    /// The name of the key type, if the map is annotated with
+    /// `BPF_ANNOTATE_KV_PAIR` and the object has BTF.
+    pub btf_key_type: Option<String>,
+    /// The name of the value type, if the map is annotated with
+    /// `BPF_ANNOTATE_KV_PAIR` and the object has BTF.
+    pub btf_value_type: Option<String>,
+}
+
 /// The main entry point into the library, used to work with eBPF programs and maps.
 #[derive(Debug)]
 pub struct Bpf {
     maps: HashMap<String, MapLock>,
     programs: HashMap<String, Program>,
+    _stats: Option<StatsFd>,
+}
+
+// keeps the statistics enabled by BpfLoader::enable_stats until it's dropped
+#[derive(Debug)]
+struct StatsFd(RawFd);
+
+impl Drop for StatsFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
 }
 
 impl Bpf {
@@ -610,7 +1624,17 @@ impl Bpf {
             })
     }
 
-    /// An iterator over all the maps.
+    /// Returns the number of maps.
+    pub fn map_count(&self) -> usize {
+        self.maps.len()
+    }
+
+    /// Returns true if there's a map with the given name.
+    pub fn has_map(&self, name: &str) -> bool {
+        self.maps.contains_key(name)
+    }
+
+    /// An iterator over all the maps, sorted by name.
     ///
     /// # Examples
     /// ```no_run
@@ -625,14 +1649,40 @@ impl Bpf {
     /// # Ok::<(), aya::BpfError>(())
     /// ```
     pub fn maps(&self) -> impl Iterator<Item = (&str, Result<MapRef, MapError>)> {
-        let ret = self.maps.iter().map(|(name, lock)| {
+        let mut maps = self.maps.iter().collect::<Vec<_>>();
+        maps.sort_unstable_by_key(|(name, _)| name.as_str());
+        maps.into_iter().map(|(name, lock)| {
             (
                 name.as_str(),
                 lock.try_read()
                     .map_err(|_| MapError::BorrowError { name: name.clone() }),
             )
-        });
-        ret
+        })
+    }
+
+    /// An iterator mutably referencing all of the maps, sorted by name.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # let mut bpf = aya::Bpf::load(&[])?;
+    /// for (name, map) in bpf.maps_mut() {
+    ///     let map = map?;
+    ///     if map.pinned {
+    ///         println!("map `{}` is pinned", name);
+    ///     }
+    /// }
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn maps_mut(&mut self) -> impl Iterator<Item = (&str, Result<MapRefMut, MapError>)> {
+        let mut maps = self.maps.iter().collect::<Vec<_>>();
+        maps.sort_unstable_by_key(|(name, _)| name.as_str());
+        maps.into_iter().map(|(name, lock)| {
+            (
+                name.as_str(),
+                lock.try_write()
+                    .map_err(|_| MapError::BorrowError { name: name.clone() }),
+            )
+        })
     }
 
     /// Returns a reference to the program with the given name.
@@ -676,7 +1726,17 @@ impl Bpf {
         self.programs.get_mut(name)
     }
 
-    /// An iterator over all the programs.
+    /// Returns the number of programs.
+    pub fn program_count(&self) -> usize {
+        self.programs.len()
+    }
+
+    /// Returns true if there's a program with the given name.
+    pub fn has_program(&self, name: &str) -> bool {
+        self.programs.contains_key(name)
+    }
+
+    /// An iterator over all the programs, sorted by name.
     ///
     /// # Examples
     /// ```no_run
@@ -691,10 +1751,16 @@ impl Bpf {
     /// # Ok::<(), aya::BpfError>(())
     /// ```
     pub fn programs(&self) -> impl Iterator<Item = (&str, &Program)> {
-        self.programs.iter().map(|(s, p)| (s.as_str(), p))
+        let mut programs = self
+            .programs
+            .iter()
+            .map(|(s, p)| (s.as_str(), p))
+            .collect::<Vec<_>>();
+        programs.sort_unstable_by_key(|(name, _)| *name);
+        programs.into_iter()
     }
 
-    /// An iterator mutably referencing all of the programs.
+    /// An iterator mutably referencing all of the programs, sorted by name.
     ///
     /// # Examples
     /// ```no_run
@@ -707,7 +1773,13 @@ impl Bpf {
     /// # Ok::<(), aya::BpfError>(())
     /// ```
     pub fn programs_mut(&mut self) -> impl Iterator<Item = (&str, &mut Program)> {
-        self.programs.iter_mut().map(|(s, p)| (s.as_str(), p))
+        let mut programs = self
+            .programs
+            .iter_mut()
+            .map(|(s, p)| (s.as_str(), p))
+            .collect::<Vec<_>>();
+        programs.sort_unstable_by_key(|(name, _)| *name);
+        programs.into_iter()
     }
 }
 
@@ -735,6 +1807,13 @@ pub enum BpfError {
         name: u32,
     },
 
+    /// A map-in-map was found but no inner map prototype was provided for it
+    #[error("no inner map prototype provided for map-in-map `{name}`")]
+    MissingInnerMapPrototype {
+        /// The name of the outer map
+        name: String,
+    },
+
     /// Invalid path
     #[error("invalid path `{error}`")]
     InvalidPath {
@@ -760,6 +1839,30 @@ pub enum BpfError {
         error: Box<dyn Error + Send + Sync>,
     },
 
+    /// A map passed to [`BpfLoader::replace_map`] has the wrong type
+    #[error("map `{name}` has type {map_type}, expected {expected}")]
+    MapTypeMismatch {
+        /// The name of the map
+        name: String,
+        /// The type of the map that was passed
+        map_type: u32,
+        /// The type defined in the object
+        expected: u32,
+    },
+
+    /// A pinned map was created with a different size than the one set with
+    /// [`BpfLoader::max_entries_override`] or
+    /// [`BpfLoader::max_entries_override_all`]
+    #[error("pinned map `{name}` has max_entries {max_entries}, expected {expected}")]
+    MaxEntriesMismatch {
+        /// The name of the map
+        name: String,
+        /// The max_entries of the pinned map
+        max_entries: u32,
+        /// The overridden max_entries
+        expected: u32,
+    },
+
     #[error("map error")]
     /// A map error
     MapError(#[from] MapError),
@@ -767,24 +1870,364 @@ pub enum BpfError {
     #[error("program error")]
     /// A program error
     ProgramError(#[from] ProgramError),
+
+    /// `RLIMIT_MEMLOCK` is too low for the object and couldn't be raised.
+    ///
+    /// See [`BpfLoader::auto_raise_memlock`].
+    #[error("RLIMIT_MEMLOCK is too low to load the object")]
+    InsufficientMemlock(#[source] io::Error),
+
+    /// The program can't be loaded by [`BpfLoader::auto_pin`] because its
+    /// type needs load time arguments, eg the BTF of the attach target.
+    #[error("program `{name}` needs load time arguments and can't be loaded automatically")]
+    LoadArgumentsRequired {
+        /// The name of the program
+        name: String,
+    },
 }
 
-fn load_btf(raw_btf: Vec<u8>) -> Result<RawFd, BtfError> {
-    let mut logger = VerifierLog::new();
-    let ret = retry_with_verifier_logs(10, &mut logger, |logger| {
-        bpf_load_btf(raw_btf.as_slice(), logger)
-    });
-    match ret {
-        Ok(fd) => Ok(fd as RawFd),
-        Err((_, io_error)) => {
-            logger.truncate();
-            Err(BtfError::LoadError {
-                io_error,
-                verifier_log: logger
-                    .as_c_str()
-                    .map(|s| s.to_string_lossy().to_string())
-                    .unwrap_or_else(|| "[none]".to_owned()),
+// Loads `program` with the load method of its type, which sets the load time
+// attributes of the type such as the expected attach type.
+fn load_without_arguments(name: &str, program: &mut Program) -> Result<(), BpfError> {
+    match program {
+        Program::KProbe(p) => p.load(),
+        Program::UProbe(p) => p.load(),
+        Program::Usdt(p) => p.load(),
+        Program::TracePoint(p) => p.load(),
+        Program::SocketFilter(p) => p.load(),
+        Program::Xdp(p) => p.load(),
+        Program::SkMsg(p) => p.load(),
+        Program::SkSkb(p) => p.load(),
+        Program::SockOps(p) => p.load(),
+        Program::SchedClassifier(p) => p.load(),
+        Program::CgroupSkb(p) => p.load(),
+        Program::CgroupSock(p) => p.load(),
+        Program::CgroupSockAddr(p) => p.load(),
+        Program::CgroupDevice(p) => p.load(),
+        Program::LircMode2(p) => p.load(),
+        Program::PerfEvent(p) => p.load(),
+        Program::RawTracePoint(p) => p.load(),
+        Program::Lsm(_)
+        | Program::BtfTracePoint(_)
+        | Program::FEntry(_)
+        | Program::FExit(_)
+        | Program::Extension(_)
+        | Program::Iter(_) => {
+            return Err(BpfError::LoadArgumentsRequired {
+                name: name.to_owned(),
             })
         }
+    }?;
+    Ok(())
+}
+
+// Estimates the memory charged against RLIMIT_MEMLOCK by the maps and
+// programs of `obj`. The kernel charges whole pages for each map and program,
+// so this is a lower bound, to which some headroom is added.
+fn memlock_estimate(obj: &Object, page_size: usize) -> usize {
+    // page_size is a power of 2. The definitions come from the object, so
+    // the arithmetic saturates instead of overflowing with huge maps on 32-bit
+    let round_up = |bytes: usize| bytes.saturating_add(page_size - 1) & !(page_size - 1);
+    let maps = obj
+        .maps
+        .values()
+        .map(|map| {
+            let def = &map.def;
+            round_up(
+                (def.key_size as usize)
+                    .saturating_add(def.value_size as usize)
+                    .saturating_mul(def.max_entries as usize),
+            )
+        })
+        .fold(0usize, usize::saturating_add);
+    let programs = obj
+        .programs
+        .values()
+        .map(|prog| {
+            round_up(
+                prog.function
+                    .instructions
+                    .len()
+                    .saturating_mul(mem::size_of::<bpf_insn>()),
+            )
+        })
+        .fold(0usize, usize::saturating_add);
+    // the maps and programs of the kernel structures, the verifier, etc
+    const HEADROOM: usize = 1024 * 1024;
+    maps.saturating_add(programs).saturating_add(HEADROOM)
+}
+
+// checks that `map_fd` is compatible with the definition of `map` and makes
+// `map` use a duplicate of it
+fn reuse_map(name: &str, map: &mut Map, map_fd: RawFd) -> Result<RawFd, BpfError> {
+    let info = bpf_map_get_info_by_fd(map_fd).map_err(|io_error| MapError::SyscallError {
+        call: "bpf_obj_get_info_by_fd".to_owned(),
+        code: -1,
+        io_error,
+    })?;
+    let def = &mut map.obj.def;
+    if info.type_ != def.map_type {
+        return Err(BpfError::MapTypeMismatch {
+            name: name.to_owned(),
+            map_type: info.type_,
+            expected: def.map_type,
+        });
+    }
+    if info.key_size != def.key_size {
+        return Err(MapError::InvalidKeySize {
+            size: info.key_size as usize,
+            expected: def.key_size as usize,
+        }
+        .into());
+    }
+    if info.value_size != def.value_size {
+        return Err(MapError::InvalidValueSize {
+            size: info.value_size as usize,
+            expected: def.value_size as usize,
+        }
+        .into());
+    }
+    def.max_entries = info.max_entries;
+
+    let fd = unsafe { libc::fcntl(map_fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(MapError::SyscallError {
+            call: "fcntl".to_owned(),
+            code: fd as libc::c_long,
+            io_error: io::Error::last_os_error(),
+        }
+        .into());
+    }
+    map.fd = Some(fd);
+
+    Ok(fd)
+}
+
+pub(crate) fn load_btf(raw_btf: Vec<u8>, token_fd: Option<RawFd>) -> Result<RawFd, BtfError> {
+    btf_load_with_log(raw_btf.as_slice(), token_fd, 0, 0).map_err(
+        |BtfLoadError { io_error, log }| BtfError::LoadError {
+            io_error,
+            verifier_log: log,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use libc::EFAULT;
+
+    use super::*;
+    use crate::{
+        generated::{
+            bpf_cmd, bpf_map_info, bpf_map_type::BPF_MAP_TYPE_HASH,
+            bpf_prog_type::BPF_PROG_TYPE_XDP, BPF_PSEUDO_MAP_FD,
+        },
+        obj::fake::{insn, FakeElf, BPF_EXIT, BPF_LD_IMM64, BPF_MOV64_IMM},
+        sys::{override_syscall, MockCall, MockKernel, Syscall},
+    };
+
+    // an XDP program returning XDP_PASS that loads the fd of the COUNTERS map
+    fn fake_object(pinning: PinningType) -> Vec<u8> {
+        FakeElf::new()
+            .license("GPL")
+            .map(
+                "COUNTERS",
+                bpf_map_def {
+                    map_type: BPF_MAP_TYPE_HASH as u32,
+                    key_size: 4,
+                    value_size: 8,
+                    max_entries: 16,
+                    pinning,
+                    ..Default::default()
+                },
+            )
+            .program(
+                "xdp/pass",
+                &[
+                    insn(BPF_LD_IMM64, 0),
+                    insn(0, 0),
+                    insn(BPF_MOV64_IMM, 2),
+                    insn(BPF_EXIT, 0),
+                ],
+            )
+            .relocation("xdp/pass", 0, "COUNTERS")
+            .build()
+    }
+
+    #[test]
+    fn test_load_creates_maps_and_relocates_programs() {
+        let mut loader = {
+            // the feature probes succeed too, so every feature is enabled
+            let _kernel = MockKernel::new();
+            BpfLoader::new()
+        };
+        let mut kernel = MockKernel::new();
+        kernel.expect_prog_load(1);
+
+        let mut bpf = loader
+            .btf(None)
+            .load(&fake_object(PinningType::None))
+            .unwrap();
+        kernel.assert_map_created("COUNTERS", BPF_MAP_TYPE_HASH);
+        let map_fd = bpf.map("COUNTERS").unwrap().fd.unwrap();
+
+        let xdp: &mut Xdp = bpf.program_mut("pass").unwrap().try_into().unwrap();
+        let ins = &xdp.data.obj.function.instructions[0];
+        assert_eq!(ins.imm, map_fd);
+        assert_eq!(ins.src_reg(), BPF_PSEUDO_MAP_FD as u8);
+        // creating the map doesn't load any program
+        assert!(!kernel
+            .calls()
+            .iter()
+            .any(|call| matches!(call, MockCall::ProgLoad { .. })));
+
+        xdp.load().unwrap();
+        assert!(kernel.calls().contains(&MockCall::ProgLoad {
+            name: "pass".to_owned(),
+            prog_type: BPF_PROG_TYPE_XDP as u32,
+        }));
+    }
+
+    #[test]
+    fn test_load_pinned_map_max_entries_override() {
+        let mut loader = {
+            let _kernel = MockKernel::new();
+            BpfLoader::new()
+        };
+        // COUNTERS is already pinned with the size defined in the object
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET,
+                ..
+            } => Ok(1000),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_map_info) };
+                info.type_ = BPF_MAP_TYPE_HASH as u32;
+                info.key_size = 4;
+                info.value_size = 8;
+                info.max_entries = 16;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+        let obj = fake_object(PinningType::ByName);
+        loader.btf(None).map_pin_path("/sys/fs/bpf/test");
+
+        let bpf = loader.load(&obj).unwrap();
+        let map = bpf.map("COUNTERS").unwrap();
+        assert!(map.pinned);
+        assert_eq!(map.obj.def.max_entries, 16);
+
+        assert!(loader
+            .max_entries_override("COUNTERS", 16)
+            .load(&obj)
+            .is_ok());
+        assert!(matches!(
+            loader.max_entries_override("COUNTERS", 64).load(&obj),
+            Err(BpfError::MaxEntriesMismatch {
+                name,
+                max_entries: 16,
+                expected: 64,
+            }) if name == "COUNTERS"
+        ));
+    }
+
+    #[test]
+    fn test_btf_after_no_btf() {
+        let _kernel = MockKernel::new();
+        let btf = Btf::new();
+        let mut loader = BpfLoader::new();
+
+        loader.no_btf().btf(Some(&btf));
+        assert!(!loader.no_btf);
+        assert!(loader.btf.is_some());
+
+        loader.no_btf().btf(None);
+        assert!(!loader.no_btf);
+        assert!(loader.btf.is_none());
+
+        loader.btf(Some(&btf)).no_btf();
+        assert!(loader.no_btf);
+        assert!(loader.btf.is_none());
+    }
+
+    #[test]
+    fn test_programs_and_maps_sorted_by_name() {
+        let mut loader = {
+            let _kernel = MockKernel::new();
+            BpfLoader::new()
+        };
+        let _kernel = MockKernel::new();
+        let def = bpf_map_def {
+            map_type: BPF_MAP_TYPE_HASH as u32,
+            key_size: 4,
+            value_size: 8,
+            max_entries: 16,
+            ..Default::default()
+        };
+        let pass = [insn(BPF_MOV64_IMM, 2), insn(BPF_EXIT, 0)];
+        let obj = FakeElf::new()
+            .license("GPL")
+            .map("PORTS", def)
+            .map("ADDRS", def)
+            .map("COUNTERS", def)
+            .program("xdp/second", &pass)
+            .program("xdp/third", &pass)
+            .program("xdp/first", &pass)
+            .build();
+
+        let mut bpf = loader.btf(None).load(&obj).unwrap();
+        assert_eq!(
+            bpf.programs().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["first", "second", "third"]
+        );
+        assert_eq!(
+            bpf.programs_mut().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["first", "second", "third"]
+        );
+        assert_eq!(
+            bpf.maps().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["ADDRS", "COUNTERS", "PORTS"]
+        );
+        assert_eq!(
+            bpf.maps_mut().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["ADDRS", "COUNTERS", "PORTS"]
+        );
+        assert_eq!(bpf.program_count(), 3);
+        assert_eq!(bpf.map_count(), 3);
+        assert!(bpf.has_program("second"));
+        assert!(!bpf.has_program("fourth"));
+        assert!(bpf.has_map("PORTS"));
+        assert!(!bpf.has_map("SOCKETS"));
+    }
+
+    #[test]
+    fn test_parse_inspect_load() {
+        let mut loader = {
+            let _kernel = MockKernel::new();
+            BpfLoader::new()
+        };
+        let kernel = MockKernel::new();
+        let obj = fake_object(PinningType::None);
+
+        let parsed = loader.btf(None).parse(&obj).unwrap();
+        // parsing doesn't touch the kernel
+        assert!(kernel.calls().is_empty());
+        assert_eq!(parsed.program_names().collect::<Vec<_>>(), ["pass"]);
+        assert_eq!(parsed.map_names().collect::<Vec<_>>(), ["COUNTERS"]);
+        assert_eq!(parsed.program_type("pass"), Some(BPF_PROG_TYPE_XDP));
+        assert_eq!(parsed.program_type("drop"), None);
+        assert_eq!(parsed.map_type("COUNTERS"), Some(BPF_MAP_TYPE_HASH as u32));
+        assert_eq!(parsed.map_type("EVENTS"), None);
+        assert_eq!(parsed.extract_maps()["COUNTERS"].max_entries, 16);
+
+        let bpf = parsed.load().unwrap();
+        kernel.assert_map_created("COUNTERS", BPF_MAP_TYPE_HASH);
+        assert!(bpf.program("pass").is_some());
     }
 }
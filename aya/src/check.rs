@@ -0,0 +1,351 @@
+//! Offline checks of eBPF objects.
+//!
+//! [`check_elf`] validates an object against the BTF of a target kernel
+//! without calling any BPF syscall, so it can run in CI on machines that
+//! don't run the target kernel and without privileges.
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    obj::{btf::Btf, Object, ParseError},
+    BpfError,
+};
+
+/// How bad a [`CheckDiagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckSeverity {
+    /// The object won't load on the target kernel.
+    Fatal,
+    /// The object loads, but part of it is skipped or may not work.
+    Warning,
+}
+
+/// A problem found by [`check_elf`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckDiagnostic {
+    /// How bad the problem is.
+    pub severity: CheckSeverity,
+    /// What the problem is about, eg `program "xdp_drop"`, `map "EVENTS"`
+    /// or `object`.
+    pub location: String,
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl CheckDiagnostic {
+    fn new(severity: CheckSeverity, location: String, message: String) -> CheckDiagnostic {
+        CheckDiagnostic {
+            severity,
+            location,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for CheckDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            CheckSeverity::Fatal => "error",
+            CheckSeverity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.location, self.message)
+    }
+}
+
+/// Checks the object `data` against `target_btf`, the BTF of the target
+/// kernel, eg parsed from a copy of its `/sys/kernel/btf/vmlinux`.
+///
+/// The checks include parsing the object, resolving the CO-RE relocations
+/// of every program against `target_btf` and checking that the target kernel
+/// knows the program and map types of the object. They catch the errors that
+/// can be predicted from BTF: programs can still fail verification, and
+/// checks that need features the target BTF doesn't describe are skipped
+/// with a warning. An empty result means no problem was found.
+///
+/// # Examples
+///
+/// ```no_run
+/// use aya::{check::{check_elf, CheckSeverity}, Btf, Endianness};
+///
+/// let data = std::fs::read("program.o")?;
+/// let btf = Btf::parse_file("target/vmlinux", Endianness::default())?;
+/// let diagnostics = check_elf(&data, &btf);
+/// for diagnostic in &diagnostics {
+///     eprintln!("{}", diagnostic);
+/// }
+/// if diagnostics.iter().any(|d| d.severity == CheckSeverity::Fatal) {
+///     std::process::exit(1);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn check_elf(data: &[u8], target_btf: &Btf) -> Vec<CheckDiagnostic> {
+    let mut diagnostics = Vec::new();
+    let object = "object".to_owned();
+
+    let mut obj = match Object::parse(data, &HashMap::new(), true) {
+        Ok(obj) => obj,
+        Err(e) => {
+            diagnostics.push(CheckDiagnostic::new(
+                CheckSeverity::Fatal,
+                object,
+                e.to_string(),
+            ));
+            return diagnostics;
+        }
+    };
+
+    for section in &obj.skipped_sections {
+        diagnostics.push(CheckDiagnostic::new(
+            CheckSeverity::Warning,
+            format!("section {:?}", section),
+            ParseError::UnrecognizedProgramSection {
+                section: section.clone(),
+            }
+            .to_string(),
+        ));
+    }
+
+    let _ = obj.relocate_btf_with(target_btf, |e| {
+        let location = match &e {
+            BpfError::RelocationError { function, .. } => format!("program {:?}", function),
+            _ => object.clone(),
+        };
+        // the error of a relocation is the cause of the RelocationError
+        let message = match &e {
+            BpfError::RelocationError { error, .. } => error.to_string(),
+            e => e.to_string(),
+        };
+        diagnostics.push(CheckDiagnostic::new(
+            CheckSeverity::Fatal,
+            location,
+            message,
+        ));
+        Ok(())
+    });
+
+    let mut programs = obj.programs.iter().collect::<Vec<_>>();
+    programs.sort_unstable_by_key(|(name, _)| name.as_str());
+    match target_btf.enum_values("bpf_prog_type") {
+        Some(prog_types) => {
+            for (name, program) in programs {
+                let prog_type = program.section.prog_type();
+                if !prog_types.contains(&(prog_type as i32)) {
+                    diagnostics.push(CheckDiagnostic::new(
+                        CheckSeverity::Fatal,
+                        format!("program {:?}", name),
+                        format!(
+                            "the target kernel doesn't support the program type {:?}",
+                            prog_type
+                        ),
+                    ));
+                }
+            }
+        }
+        None => diagnostics.push(CheckDiagnostic::new(
+            CheckSeverity::Warning,
+            object.clone(),
+            "the target BTF doesn't define `enum bpf_prog_type`, program types not checked"
+                .to_owned(),
+        )),
+    }
+
+    let mut maps = obj.maps.iter().collect::<Vec<_>>();
+    maps.sort_unstable_by_key(|(name, _)| name.as_str());
+    match target_btf.enum_values("bpf_map_type") {
+        Some(map_types) => {
+            for (name, map) in maps {
+                if !map_types.contains(&(map.def.map_type as i32)) {
+                    diagnostics.push(CheckDiagnostic::new(
+                        CheckSeverity::Fatal,
+                        format!("map {:?}", name),
+                        format!(
+                            "the target kernel doesn't support the map type {}",
+                            map.def.map_type
+                        ),
+                    ));
+                }
+            }
+        }
+        None => diagnostics.push(CheckDiagnostic::new(
+            CheckSeverity::Warning,
+            object,
+            "the target BTF doesn't define `enum bpf_map_type`, map types not checked".to_owned(),
+        )),
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bpf_map_def,
+        generated::{
+            bpf_map_type::{BPF_MAP_TYPE_ARRAY, BPF_MAP_TYPE_HASH},
+            bpf_prog_type::{BPF_PROG_TYPE_KPROBE, BPF_PROG_TYPE_XDP},
+            btf_enum,
+        },
+        obj::{
+            btf::BtfType,
+            fake::{insn, FakeElf, BPF_EXIT, BPF_MOV64_IMM},
+        },
+    };
+
+    fn xdp_pass() -> [crate::generated::bpf_insn; 2] {
+        [insn(BPF_MOV64_IMM, 2), insn(BPF_EXIT, 0)]
+    }
+
+    fn hash_map() -> bpf_map_def {
+        bpf_map_def {
+            map_type: BPF_MAP_TYPE_HASH as u32,
+            key_size: 4,
+            value_size: 4,
+            max_entries: 1,
+            ..Default::default()
+        }
+    }
+
+    // .BTF.ext with a single CO-RE relocation in `section`
+    fn btf_ext(section_name_off: u32, insn_off: u32) -> Vec<u8> {
+        let words: [u32; 15] = [
+            // magic, version and flags
+            0x01eb9f,
+            // hdr_len
+            32,
+            // func_info_off, func_info_len, line_info_off, line_info_len
+            0,
+            0,
+            0,
+            0,
+            // core_relo_off, core_relo_len
+            0,
+            28,
+            // rec_size, then the section and its number of records
+            16,
+            section_name_off,
+            1,
+            // bpf_core_relo: insn_off, type_id, access_str_off, kind
+            insn_off,
+            1,
+            0,
+            0,
+        ];
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    fn enum_type(btf: &mut Btf, name: &str, values: &[i32]) {
+        let name_off = btf.add_string(name.to_owned());
+        let members = values
+            .iter()
+            .map(|&val| btf_enum { name_off, val })
+            .collect();
+        btf.add_type(BtfType::new_enum(name_off, members));
+    }
+
+    #[test]
+    fn test_enum_values() {
+        let mut btf = Btf::new();
+        enum_type(&mut btf, "bpf_prog_type", &[0, BPF_PROG_TYPE_XDP as i32]);
+        enum_type(&mut btf, "bpf_map_type", &[BPF_MAP_TYPE_HASH as i32]);
+
+        assert_eq!(
+            btf.enum_values("bpf_prog_type"),
+            Some(vec![0, BPF_PROG_TYPE_XDP as i32])
+        );
+        assert_eq!(
+            btf.enum_values("bpf_map_type"),
+            Some(vec![BPF_MAP_TYPE_HASH as i32])
+        );
+        assert_eq!(btf.enum_values("bpf_attach_type"), None);
+    }
+
+    #[test]
+    fn test_check_unsupported_types() {
+        let data = FakeElf::new()
+            .program("xdp/pass", &xdp_pass())
+            .map("COUNTERS", hash_map())
+            .build();
+        let mut btf = Btf::new();
+        enum_type(&mut btf, "bpf_prog_type", &[BPF_PROG_TYPE_KPROBE as i32]);
+        enum_type(&mut btf, "bpf_map_type", &[BPF_MAP_TYPE_ARRAY as i32]);
+
+        let diagnostics = check_elf(&data, &btf);
+        assert_eq!(
+            diagnostics,
+            vec![
+                CheckDiagnostic::new(
+                    CheckSeverity::Fatal,
+                    "program \"pass\"".to_owned(),
+                    "the target kernel doesn't support the program type BPF_PROG_TYPE_XDP"
+                        .to_owned(),
+                ),
+                CheckDiagnostic::new(
+                    CheckSeverity::Fatal,
+                    "map \"COUNTERS\"".to_owned(),
+                    format!(
+                        "the target kernel doesn't support the map type {}",
+                        BPF_MAP_TYPE_HASH as u32
+                    ),
+                ),
+            ]
+        );
+
+        let mut btf = Btf::new();
+        enum_type(&mut btf, "bpf_prog_type", &[BPF_PROG_TYPE_XDP as i32]);
+        enum_type(&mut btf, "bpf_map_type", &[BPF_MAP_TYPE_HASH as i32]);
+        assert!(check_elf(&data, &btf).is_empty());
+    }
+
+    #[test]
+    fn test_check_relocation_failure() {
+        let mut local_btf = Btf::new();
+        let section_name_off = local_btf.add_string("xdp/pass".to_owned());
+        // the relocation points past the last instruction of the program
+        let data = FakeElf::new()
+            .section(".BTF", local_btf.to_bytes())
+            .section(".BTF.ext", btf_ext(section_name_off, 16))
+            .program("xdp/pass", &xdp_pass())
+            .build();
+        let mut btf = Btf::new();
+        enum_type(&mut btf, "bpf_prog_type", &[BPF_PROG_TYPE_XDP as i32]);
+        enum_type(&mut btf, "bpf_map_type", &[]);
+
+        let diagnostics = check_elf(&data, &btf);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, CheckSeverity::Fatal);
+        assert_eq!(diagnostics[0].location, "program \"pass\"");
+        assert!(
+            diagnostics[0]
+                .message
+                .starts_with("invalid instruction index #2"),
+            "{}",
+            diagnostics[0].message
+        );
+    }
+
+    #[test]
+    fn test_check_unknown_section() {
+        let data = FakeElf::new()
+            .program("xdp/pass", &xdp_pass())
+            .program("foo/bar", &xdp_pass())
+            .build();
+        let mut btf = Btf::new();
+        enum_type(&mut btf, "bpf_prog_type", &[BPF_PROG_TYPE_XDP as i32]);
+
+        let diagnostics = check_elf(&data, &btf);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, CheckSeverity::Warning);
+        assert_eq!(diagnostics[0].location, "section \"foo/bar\"");
+        // the BTF without `enum bpf_map_type`
+        assert_eq!(diagnostics[1].severity, CheckSeverity::Warning);
+        assert_eq!(diagnostics[1].location, "object");
+    }
+
+    #[test]
+    fn test_check_invalid_elf() {
+        let diagnostics = check_elf(b"not an elf", &Btf::new());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, CheckSeverity::Fatal);
+        assert_eq!(diagnostics[0].location, "object");
+    }
+}
@@ -38,13 +38,14 @@ extern crate lazy_static;
 extern crate bitflags;
 
 mod bpf;
+pub mod check;
 mod generated;
 pub mod maps;
 mod obj;
 pub mod programs;
-mod sys;
+pub mod sys;
 pub mod util;
 
 pub use bpf::*;
-pub use obj::btf::{Btf, BtfError};
+pub use obj::btf::{Btf, BtfError, BtfKind};
 pub use object::Endianness;
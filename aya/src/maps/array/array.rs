@@ -3,12 +3,13 @@ use std::{
     marker::PhantomData,
     mem,
     ops::{Deref, DerefMut},
+    os::unix::io::{AsRawFd, RawFd},
 };
 
 use crate::{
     generated::bpf_map_type::BPF_MAP_TYPE_ARRAY,
-    maps::{IterableMap, Map, MapError, MapRef, MapRefMut},
-    sys::{bpf_map_lookup_elem, bpf_map_update_elem},
+    maps::{IterableMap, Map, MapError, MapRef, MapRefMut, OwnedMap},
+    sys::{bpf_map_lookup_elem, bpf_map_update_batch, bpf_map_update_elem},
     Pod,
 };
 
@@ -92,6 +93,13 @@ impl<T: Deref<Target = Map>, V: Pod> Array<T, V> {
         value.ok_or(MapError::KeyNotFound)
     }
 
+    /// Returns an owned handle to the same array.
+    ///
+    /// See [`Map::try_clone`].
+    pub fn try_clone(&self) -> Result<Array<OwnedMap, V>, MapError> {
+        Array::new(self.inner.try_clone()?)
+    }
+
     /// An iterator over the elements of the array. The iterator item type is `Result<V,
     /// MapError>`.
     pub fn iter(&self) -> impl Iterator<Item = Result<V, MapError>> + '_ {
@@ -116,7 +124,7 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>, V: Pod> Array<T, V> {
     /// Returns [`MapError::OutOfBounds`] if `index` is out of bounds, [`MapError::SyscallError`]
     /// if `bpf_map_update_elem` fails.
     pub fn set(&mut self, index: u32, value: V, flags: u64) -> Result<(), MapError> {
-        let fd = self.inner.fd_or_err()?;
+        let fd = self.inner.writable_fd_or_err()?;
         self.check_bounds(index)?;
         bpf_map_update_elem(fd, &index, &value, flags).map_err(|(code, io_error)| {
             MapError::SyscallError {
@@ -127,6 +135,46 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>, V: Pod> Array<T, V> {
         })?;
         Ok(())
     }
+
+    /// Sets the values of the given elements with a single syscall.
+    ///
+    /// Returns the number of elements set. This is much faster than calling
+    /// [`set`](Array::set) for each element when initializing large arrays.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.6.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::OutOfBounds`] if any index is out of bounds, in
+    /// which case no element is set. Returns [`MapError::BatchError`] if
+    /// `bpf_map_update_batch` fails, with the number of elements that the
+    /// kernel set before failing.
+    pub fn update_batch<I: IntoIterator<Item = (u32, V)>>(
+        &mut self,
+        entries: I,
+    ) -> Result<usize, MapError> {
+        let fd = self.inner.writable_fd_or_err()?;
+        let (indices, values): (Vec<u32>, Vec<V>) = entries.into_iter().unzip();
+        for index in &indices {
+            self.check_bounds(*index)?;
+        }
+        if indices.is_empty() {
+            return Ok(0);
+        }
+
+        let mut count = indices.len() as u32;
+        bpf_map_update_batch(fd, &indices, &values, &mut count, 0).map_err(
+            |(code, io_error)| MapError::BatchError {
+                call: "bpf_map_update_batch".to_owned(),
+                count: count as usize,
+                code,
+                io_error,
+            },
+        )?;
+        Ok(count as usize)
+    }
 }
 
 impl<T: Deref<Target = Map>, V: Pod> IterableMap<u32, V> for Array<T, V> {
@@ -139,6 +187,13 @@ impl<T: Deref<Target = Map>, V: Pod> IterableMap<u32, V> for Array<T, V> {
     }
 }
 
+impl<T: Deref<Target = Map>, V: Pod> AsRawFd for Array<T, V> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
 impl<V: Pod> TryFrom<MapRef> for Array<MapRef, V> {
     type Error = MapError;
 
@@ -154,3 +209,82 @@ impl<V: Pod> TryFrom<MapRefMut> for Array<MapRefMut, V> {
         Array::new(a)
     }
 }
+
+impl<V: Pod> TryFrom<OwnedMap> for Array<OwnedMap, V> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<Array<OwnedMap, V>, MapError> {
+        Array::new(a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use libc::{E2BIG, EFAULT};
+
+    use crate::{
+        bpf_map_def,
+        generated::{bpf_attr, bpf_cmd},
+        obj,
+        sys::{override_syscall, Syscall},
+    };
+
+    use super::*;
+
+    fn new_map() -> Map {
+        Map {
+            obj: obj::Map {
+                def: bpf_map_def {
+                    map_type: BPF_MAP_TYPE_ARRAY as u32,
+                    key_size: 4,
+                    value_size: 4,
+                    max_entries: 16,
+                    ..Default::default()
+                },
+                section_index: 0,
+                data: Vec::new(),
+                kind: obj::MapKind::Other,
+            },
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn test_update_batch() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_BATCH,
+                attr,
+            } => {
+                let count = unsafe { attr.batch.count };
+                // the kernel writes the number of updated elements into the attr
+                let attr = attr as *const bpf_attr as *mut bpf_attr;
+                if count > 2 {
+                    unsafe { (*attr).batch.count = 2 };
+                    Err((-1, io::Error::from_raw_os_error(E2BIG)))
+                } else {
+                    Ok(0)
+                }
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+        let mut map = new_map();
+        let mut array = Array::<_, u32>::new(&mut map).unwrap();
+
+        assert!(matches!(array.update_batch(vec![]), Ok(0)));
+        assert!(matches!(array.update_batch(vec![(0, 1), (1, 2)]), Ok(2)));
+        assert!(matches!(
+            array.update_batch(vec![(0, 1), (1, 2), (2, 3)]),
+            Err(MapError::BatchError { count: 2, .. })
+        ));
+        assert!(matches!(
+            array.update_batch(vec![(0, 1), (16, 2)]),
+            Err(MapError::OutOfBounds { index: 16, .. })
+        ));
+    }
+}
@@ -3,11 +3,12 @@ use std::{
     marker::PhantomData,
     mem,
     ops::{Deref, DerefMut},
+    os::unix::io::{AsRawFd, RawFd},
 };
 
 use crate::{
     generated::bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY,
-    maps::{IterableMap, Map, MapError, MapRef, MapRefMut, PerCpuValues},
+    maps::{IterableMap, Map, MapError, MapRef, MapRefMut, OwnedMap, PerCpuValues},
     sys::{bpf_map_lookup_elem_per_cpu, bpf_map_update_elem_per_cpu},
     Pod,
 };
@@ -135,7 +136,7 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>, V: Pod> PerCpuArray<T, V>
     /// Returns [`MapError::OutOfBounds`] if `index` is out of bounds, [`MapError::SyscallError`]
     /// if `bpf_map_update_elem` fails.
     pub fn set(&mut self, index: u32, values: PerCpuValues<V>, flags: u64) -> Result<(), MapError> {
-        let fd = self.inner.fd_or_err()?;
+        let fd = self.inner.writable_fd_or_err()?;
         self.check_bounds(index)?;
         bpf_map_update_elem_per_cpu(fd, &index, &values, flags).map_err(|(code, io_error)| {
             MapError::SyscallError {
@@ -158,6 +159,13 @@ impl<T: Deref<Target = Map>, V: Pod> IterableMap<u32, PerCpuValues<V>> for PerCp
     }
 }
 
+impl<T: Deref<Target = Map>, V: Pod> AsRawFd for PerCpuArray<T, V> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
 impl<V: Pod> TryFrom<MapRef> for PerCpuArray<MapRef, V> {
     type Error = MapError;
 
@@ -173,3 +181,11 @@ impl<V: Pod> TryFrom<MapRefMut> for PerCpuArray<MapRefMut, V> {
         PerCpuArray::new(a)
     }
 }
+
+impl<V: Pod> TryFrom<OwnedMap> for PerCpuArray<OwnedMap, V> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<PerCpuArray<OwnedMap, V>, MapError> {
+        PerCpuArray::new(a)
+    }
+}
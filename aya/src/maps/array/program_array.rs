@@ -4,14 +4,14 @@ use std::{
     convert::TryFrom,
     mem,
     ops::{Deref, DerefMut},
-    os::unix::prelude::RawFd,
+    os::unix::prelude::{AsRawFd, RawFd},
 };
 
 use crate::{
     generated::bpf_map_type::BPF_MAP_TYPE_PROG_ARRAY,
-    maps::{Map, MapError, MapKeys, MapRef, MapRefMut},
+    maps::{Map, MapError, MapKeys, MapRef, MapRefMut, MapUpdateFlags, OwnedMap},
     programs::ProgramFd,
-    sys::{bpf_map_delete_elem, bpf_map_update_elem},
+    sys::{bpf_map_delete_elem, bpf_map_lookup_elem, bpf_map_update_elem, bpf_prog_get_fd_by_id},
 };
 
 /// An array of eBPF program file descriptors used as a jump table.
@@ -99,7 +99,7 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>> ProgramArray<T> {
     /// When an eBPF program calls `bpf_tail_call(ctx, prog_array, index)`, control
     /// flow will jump to `program`.
     pub fn set(&mut self, index: u32, program: impl ProgramFd, flags: u64) -> Result<(), MapError> {
-        let fd = self.inner.fd_or_err()?;
+        let fd = self.inner.writable_fd_or_err()?;
         self.check_bounds(index)?;
         let prog_fd = program.fd().ok_or(MapError::ProgramNotLoaded)?;
 
@@ -113,12 +113,74 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>> ProgramArray<T> {
         Ok(())
     }
 
+    /// Atomically replaces the program at the given index in the jump table.
+    ///
+    /// Unlike clearing an index and then calling [`set`](Self::set), tail calls
+    /// never observe an empty slot: they jump either to the previous program or
+    /// to `program`. If the index already pointed to a program, a new file
+    /// descriptor referencing the previous program is returned. The caller is
+    /// responsible for closing it.
+    ///
+    /// The replacement itself is atomic, but the lookup of the previous
+    /// program isn't: if the slot is modified concurrently, the returned fd
+    /// may not reference the program that was replaced.
+    pub fn atomic_swap(
+        &mut self,
+        index: u32,
+        program: impl ProgramFd,
+    ) -> Result<Option<RawFd>, MapError> {
+        let fd = self.inner.writable_fd_or_err()?;
+        self.check_bounds(index)?;
+        let prog_fd = program.fd().ok_or(MapError::ProgramNotLoaded)?;
+
+        // looking up a program array from user space returns the program id
+        let old_prog_id =
+            bpf_map_lookup_elem::<_, u32>(fd, &index, 0).map_err(|(code, io_error)| {
+                MapError::SyscallError {
+                    call: "bpf_map_lookup_elem".to_owned(),
+                    code,
+                    io_error,
+                }
+            })?;
+        // take a reference to the previous program before it gets replaced, so
+        // that it isn't unloaded if the jump table held the last reference
+        let old_prog_fd = match old_prog_id {
+            Some(id) => {
+                Some(
+                    bpf_prog_get_fd_by_id(id).map_err(|io_error| MapError::SyscallError {
+                        call: "bpf_prog_get_fd_by_id".to_owned(),
+                        code: -1,
+                        io_error,
+                    })?,
+                )
+            }
+            None => None,
+        };
+
+        // program arrays only accept BPF_ANY
+        if let Err((code, io_error)) =
+            bpf_map_update_elem(fd, &index, &prog_fd, MapUpdateFlags::ANY.bits())
+        {
+            if let Some(old_prog_fd) = old_prog_fd {
+                unsafe { libc::close(old_prog_fd) };
+            }
+            return Err(MapError::SyscallError {
+                call: "bpf_map_update_elem".to_owned(),
+                code,
+                io_error,
+            });
+        }
+
+        Ok(old_prog_fd)
+    }
+
     /// Clears the value at index in the jump table.
     ///
     /// Calling `bpf_tail_call(ctx, prog_array, index)` on an index that has been cleared returns an
-    /// error.
+    /// error. When replacing a program, prefer [`atomic_swap`](Self::atomic_swap): clearing the
+    /// index and then setting it again leaves a window during which tail calls fail.
     pub fn clear_index(&mut self, index: &u32) -> Result<(), MapError> {
-        let fd = self.inner.fd_or_err()?;
+        let fd = self.inner.writable_fd_or_err()?;
         self.check_bounds(*index)?;
         bpf_map_delete_elem(fd, index)
             .map(|_| ())
@@ -130,6 +192,13 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>> ProgramArray<T> {
     }
 }
 
+impl<T: Deref<Target = Map>> AsRawFd for ProgramArray<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
 impl TryFrom<MapRef> for ProgramArray<MapRef> {
     type Error = MapError;
 
@@ -145,3 +214,125 @@ impl TryFrom<MapRefMut> for ProgramArray<MapRefMut> {
         ProgramArray::new(a)
     }
 }
+
+impl TryFrom<OwnedMap> for ProgramArray<OwnedMap> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<ProgramArray<OwnedMap>, MapError> {
+        ProgramArray::new(a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use libc::{EFAULT, ENOENT};
+
+    use crate::{
+        bpf_map_def,
+        generated::{bpf_cmd, bpf_map_type::BPF_MAP_TYPE_PROG_ARRAY},
+        obj,
+        sys::{override_syscall, SysResult, Syscall},
+    };
+
+    use super::*;
+
+    struct FakeProgram(RawFd);
+
+    impl ProgramFd for FakeProgram {
+        fn fd(&self) -> Option<RawFd> {
+            Some(self.0)
+        }
+    }
+
+    fn new_map() -> Map {
+        Map {
+            obj: obj::Map {
+                def: bpf_map_def {
+                    map_type: BPF_MAP_TYPE_PROG_ARRAY as u32,
+                    key_size: 4,
+                    value_size: 4,
+                    max_entries: 8,
+                    ..Default::default()
+                },
+                section_index: 0,
+                data: Vec::new(),
+                kind: obj::MapKind::Other,
+            },
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        }
+    }
+
+    fn sys_error(value: i32) -> SysResult {
+        Err((-1, io::Error::from_raw_os_error(value)))
+    }
+
+    #[test]
+    fn test_atomic_swap_empty_slot() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                ..
+            } => sys_error(ENOENT),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } if unsafe { attr.__bindgen_anon_2.flags } == 0 => Ok(0),
+            _ => sys_error(EFAULT),
+        });
+
+        let mut map = new_map();
+        let mut prog_array = ProgramArray::new(&mut map).unwrap();
+        assert!(matches!(
+            prog_array.atomic_swap(1, FakeProgram(10)),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn test_atomic_swap_replace() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => {
+                let value = unsafe { attr.__bindgen_anon_2.__bindgen_anon_1.value } as *mut u32;
+                unsafe { *value = 7 };
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_GET_FD_BY_ID,
+                attr,
+            } if unsafe { attr.__bindgen_anon_6.__bindgen_anon_1.prog_id } == 7 => Ok(24),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } if unsafe { attr.__bindgen_anon_2.flags } == 0 => Ok(0),
+            _ => sys_error(EFAULT),
+        });
+
+        let mut map = new_map();
+        let mut prog_array = ProgramArray::new(&mut map).unwrap();
+        assert!(matches!(
+            prog_array.atomic_swap(1, FakeProgram(10)),
+            Ok(Some(24))
+        ));
+    }
+
+    #[test]
+    fn test_atomic_swap_out_of_bounds() {
+        let mut map = new_map();
+        let mut prog_array = ProgramArray::new(&mut map).unwrap();
+        assert!(matches!(
+            prog_array.atomic_swap(8, FakeProgram(10)),
+            Err(MapError::OutOfBounds {
+                index: 8,
+                max_entries: 8
+            })
+        ));
+    }
+}
@@ -0,0 +1,294 @@
+//! Cgroup local storage.
+use std::{
+    convert::TryFrom,
+    fs, io,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    os::unix::{
+        fs::MetadataExt,
+        io::{AsRawFd, RawFd},
+    },
+    path::Path,
+};
+
+use libc::fstat;
+
+use crate::{
+    generated::{bpf_attach_type, bpf_map_type::BPF_MAP_TYPE_CGROUP_STORAGE},
+    maps::{hash_map, Map, MapError, MapKeys, MapRef, MapRefMut, OwnedMap},
+    sys::bpf_map_lookup_elem,
+    Pod,
+};
+
+/// The key of a [`CgroupStorage`] map, `struct bpf_cgroup_storage_key`.
+///
+/// The kernel allocates a value for each cgroup a program using the map is
+/// attached to, identified by the inode id of the cgroup directory and by the
+/// attach type of the program. The inode id isn't the fd of the cgroup, so
+/// the key is built from the cgroup with
+/// [`from_cgroup_fd`](CgroupStorageKey::from_cgroup_fd) or
+/// [`from_cgroup_path`](CgroupStorageKey::from_cgroup_path). The attach types
+/// are the variants of [`bpf_attach_type`](crate::programs::bpf_attach_type).
+///
+/// Maps declared with a plain `u64` key, which since 5.9 shares the value of
+/// a cgroup between all the attach types, aren't supported: creating a
+/// [`CgroupStorage`] for them fails with [`MapError::InvalidKeySize`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use aya::{maps::CgroupStorageKey, programs::bpf_attach_type::BPF_CGROUP_INET_EGRESS};
+///
+/// let key = CgroupStorageKey::from_cgroup_path("/sys/fs/cgroup/unified", BPF_CGROUP_INET_EGRESS)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CgroupStorageKey {
+    /// The inode id of the cgroup directory.
+    pub cgroup_inode_id: u64,
+    /// The attach type of the program the storage belongs to.
+    pub attach_type: u32,
+    _pad: u32,
+}
+
+unsafe impl Pod for CgroupStorageKey {}
+
+impl CgroupStorageKey {
+    /// Creates the key of the storage of the cgroup with the given inode id, for
+    /// programs attached with `attach_type`.
+    pub fn new(cgroup_inode_id: u64, attach_type: bpf_attach_type) -> CgroupStorageKey {
+        CgroupStorageKey {
+            cgroup_inode_id,
+            attach_type: attach_type as u32,
+            _pad: 0,
+        }
+    }
+
+    /// Creates the key of the storage of the cgroup `cgroup_fd`, eg the fd of
+    /// the cgroup directory a program was attached to.
+    pub fn from_cgroup_fd(
+        cgroup_fd: RawFd,
+        attach_type: bpf_attach_type,
+    ) -> Result<CgroupStorageKey, io::Error> {
+        let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+        // Safety: libc wrapper
+        if unsafe { fstat(cgroup_fd, &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(CgroupStorageKey::new(stat.st_ino as u64, attach_type))
+    }
+
+    /// Creates the key of the storage of the cgroup at `path`, eg
+    /// `/sys/fs/cgroup/unified/my-service`.
+    pub fn from_cgroup_path<P: AsRef<Path>>(
+        path: P,
+        attach_type: bpf_attach_type,
+    ) -> Result<CgroupStorageKey, io::Error> {
+        let metadata = fs::metadata(path)?;
+        Ok(CgroupStorageKey::new(metadata.ino(), attach_type))
+    }
+}
+
+/// Per-cgroup storage that can be shared between eBPF programs and user
+/// space.
+///
+/// eBPF programs get the value of the cgroup they run for with
+/// `bpf_get_local_storage()`. The kernel creates the values when programs
+/// using the map are attached to cgroups and frees them when they're
+/// detached, so user space can only read and update existing values, using a
+/// [`CgroupStorageKey`] as the key.
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 4.19.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let bpf = aya::Bpf::load(&[])?;
+/// use aya::maps::{CgroupStorage, CgroupStorageKey};
+/// use std::convert::TryFrom;
+///
+/// let storage = CgroupStorage::<_, u64>::try_from(bpf.map("PACKETS")?)?;
+/// let program = bpf.program("count_egress").unwrap();
+/// let key = CgroupStorageKey::from_cgroup_path(
+///     "/sys/fs/cgroup/unified",
+///     program.expected_attach_type().unwrap(),
+/// )?;
+/// let packets = storage.get(&key, 0)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[doc(alias = "BPF_MAP_TYPE_CGROUP_STORAGE")]
+pub struct CgroupStorage<T: Deref<Target = Map>, V> {
+    inner: T,
+    _v: PhantomData<V>,
+}
+
+impl<T: Deref<Target = Map>, V: Pod> CgroupStorage<T, V> {
+    pub(crate) fn new(map: T) -> Result<CgroupStorage<T, V>, MapError> {
+        let map_type = map.obj.def.map_type;
+
+        // validate the map definition. Per-cpu storages aren't supported since
+        // their values can't be read as a single V.
+        if map_type != BPF_MAP_TYPE_CGROUP_STORAGE as u32 {
+            return Err(MapError::InvalidMapType { map_type });
+        }
+        hash_map::check_kv_size::<CgroupStorageKey, V>(&map)?;
+        let _ = map.fd_or_err()?;
+
+        Ok(CgroupStorage {
+            inner: map,
+            _v: PhantomData,
+        })
+    }
+
+    /// Returns a copy of the value stored for the given key.
+    pub fn get(&self, key: &CgroupStorageKey, flags: u64) -> Result<V, MapError> {
+        let fd = self.inner.deref().fd_or_err()?;
+        let value = bpf_map_lookup_elem(fd, key, flags).map_err(|(code, io_error)| {
+            MapError::SyscallError {
+                call: "bpf_map_lookup_elem".to_owned(),
+                code,
+                io_error,
+            }
+        })?;
+        value.ok_or(MapError::KeyNotFound)
+    }
+
+    /// An iterator visiting the keys of the cgroups that have a value, in
+    /// arbitrary order. The iterator element type is
+    /// `Result<CgroupStorageKey, MapError>`.
+    pub fn keys(&self) -> MapKeys<'_, CgroupStorageKey> {
+        MapKeys::new(&self.inner)
+    }
+}
+
+impl<T: DerefMut<Target = Map>, V: Pod> CgroupStorage<T, V> {
+    /// Updates the value stored for the given key.
+    ///
+    /// Values can't be created from user space, so this fails with `ENOENT`
+    /// if no program using the map is attached for `key`.
+    pub fn insert(&mut self, key: &CgroupStorageKey, value: V, flags: u64) -> Result<(), MapError> {
+        hash_map::insert(&mut self.inner, *key, value, flags)
+    }
+}
+
+impl<T: Deref<Target = Map>, V> AsRawFd for CgroupStorage<T, V> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
+impl<V: Pod> TryFrom<MapRef> for CgroupStorage<MapRef, V> {
+    type Error = MapError;
+
+    fn try_from(a: MapRef) -> Result<CgroupStorage<MapRef, V>, MapError> {
+        CgroupStorage::new(a)
+    }
+}
+
+impl<V: Pod> TryFrom<MapRefMut> for CgroupStorage<MapRefMut, V> {
+    type Error = MapError;
+
+    fn try_from(a: MapRefMut) -> Result<CgroupStorage<MapRefMut, V>, MapError> {
+        CgroupStorage::new(a)
+    }
+}
+
+impl<V: Pod> TryFrom<OwnedMap> for CgroupStorage<OwnedMap, V> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<CgroupStorage<OwnedMap, V>, MapError> {
+        CgroupStorage::new(a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use crate::{
+        bpf_map_def,
+        generated::{
+            bpf_attach_type::BPF_CGROUP_INET_EGRESS,
+            bpf_map_type::{BPF_MAP_TYPE_HASH, BPF_MAP_TYPE_PERCPU_CGROUP_STORAGE},
+        },
+        obj,
+    };
+
+    use super::*;
+
+    fn new_obj_map(map_type: u32) -> obj::Map {
+        obj::Map {
+            def: bpf_map_def {
+                map_type,
+                key_size: mem::size_of::<CgroupStorageKey>() as u32,
+                value_size: 8,
+                max_entries: 0,
+                ..Default::default()
+            },
+            section_index: 0,
+            data: Vec::new(),
+            kind: obj::MapKind::Other,
+        }
+    }
+
+    #[test]
+    fn test_key_layout() {
+        // struct bpf_cgroup_storage_key
+        assert_eq!(mem::size_of::<CgroupStorageKey>(), 16);
+        let key = CgroupStorageKey::new(42, BPF_CGROUP_INET_EGRESS);
+        assert_eq!(key.cgroup_inode_id, 42);
+        assert_eq!(key.attach_type, BPF_CGROUP_INET_EGRESS as u32);
+    }
+
+    #[test]
+    fn test_key_from_cgroup() {
+        let path = std::env::temp_dir();
+        let ino = fs::metadata(&path).unwrap().ino();
+
+        let key = CgroupStorageKey::from_cgroup_path(&path, BPF_CGROUP_INET_EGRESS).unwrap();
+        assert_eq!(key.cgroup_inode_id, ino);
+
+        let dir = File::open(&path).unwrap();
+        let key =
+            CgroupStorageKey::from_cgroup_fd(dir.as_raw_fd(), BPF_CGROUP_INET_EGRESS).unwrap();
+        assert_eq!(key, CgroupStorageKey::new(ino, BPF_CGROUP_INET_EGRESS));
+
+        assert!(CgroupStorageKey::from_cgroup_fd(-1, BPF_CGROUP_INET_EGRESS).is_err());
+    }
+
+    #[test]
+    fn test_try_from_wrong_map() {
+        for map_type in &[BPF_MAP_TYPE_HASH, BPF_MAP_TYPE_PERCPU_CGROUP_STORAGE] {
+            let map = Map {
+                obj: new_obj_map(*map_type as u32),
+                fd: Some(42),
+                pinned: false,
+                frozen: false,
+                name: None,
+            };
+
+            assert!(matches!(
+                CgroupStorage::<_, u64>::new(&map),
+                Err(MapError::InvalidMapType { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_new_ok() {
+        let map = Map {
+            obj: new_obj_map(BPF_MAP_TYPE_CGROUP_STORAGE as u32),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+
+        assert!(CgroupStorage::<_, u64>::new(&map).is_ok());
+    }
+}
@@ -1,16 +1,38 @@
 use std::{
+    collections::HashMap as StdHashMap,
     convert::TryFrom,
     marker::PhantomData,
+    mem,
     ops::{Deref, DerefMut},
+    os::unix::io::{AsRawFd, RawFd},
+    slice,
 };
 
+use libc::{EEXIST, EINVAL, ENOENT};
+
 use crate::{
-    generated::bpf_map_type::{BPF_MAP_TYPE_HASH, BPF_MAP_TYPE_LRU_HASH},
-    maps::{hash_map, IterableMap, Map, MapError, MapIter, MapKeys, MapRef, MapRefMut},
-    sys::bpf_map_lookup_elem,
+    generated::{
+        bpf_map_type::{BPF_MAP_TYPE_HASH, BPF_MAP_TYPE_LRU_HASH},
+        BPF_F_LOCK,
+    },
+    maps::{
+        hash_map, IterableMap, Map, MapError, MapIter, MapKeys, MapRef, MapRefMut, MapUpdateFlags,
+        OwnedMap,
+    },
+    sys::{
+        bpf_map_lookup_and_delete_elem, bpf_map_lookup_batch, bpf_map_lookup_elem,
+        bpf_map_update_batch,
+    },
     Pod,
 };
 
+// the maximum number of entries read by each bpf_map_lookup_batch call
+const LOOKUP_BATCH_SIZE: u32 = 1024;
+
+fn pod_bytes<T: Pod>(value: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+}
+
 /// A hash map that can be shared between eBPF programs and user space.
 ///
 /// # Minimum kernel version
@@ -73,6 +95,141 @@ impl<T: Deref<Target = Map>, K: Pod, V: Pod> HashMap<T, K, V> {
         value.ok_or(MapError::KeyNotFound)
     }
 
+    /// Returns copies of the values associated with `keys`, in the same
+    /// order, with `None` for the keys that aren't in the map.
+    ///
+    /// The kernel can't look up a list of keys in one call: `BPF_MAP_LOOKUP_BATCH`
+    /// copies the entries of the map in chunks instead. The map is read in
+    /// chunks of up to 1024 entries until every key has been
+    /// found or the whole map has been read, which takes far fewer syscalls
+    /// than calling [`get`](Self::get) for each key when looking up a large
+    /// part of the map. If the kernel doesn't support batch lookups, the keys
+    /// are looked up one by one.
+    ///
+    /// Entries that eBPF programs insert or remove during the lookup may or
+    /// may not be returned.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let bpf = aya::Bpf::load(&[])?;
+    /// use aya::maps::HashMap;
+    /// use std::convert::TryFrom;
+    ///
+    /// let counters = HashMap::<_, u32, u64>::try_from(bpf.map("COUNTERS")?)?;
+    /// for (key, count) in [1, 2, 3].iter().zip(counters.get_batch(&[1, 2, 3])?) {
+    ///     println!("{}: {:?}", key, count);
+    /// }
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn get_batch(&self, keys: &[K]) -> Result<Vec<Option<V>>, MapError> {
+        let fd = self.inner.deref().fd_or_err()?;
+        let mut values = vec![None; keys.len()];
+        if keys.is_empty() {
+            return Ok(values);
+        }
+
+        // the positions of each key, by the bytes of the key
+        let mut positions: StdHashMap<&[u8], Vec<usize>> = StdHashMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            positions.entry(pod_bytes(key)).or_default().push(i);
+        }
+
+        let batch_size = self.inner.obj.def.max_entries.clamp(1, LOOKUP_BATCH_SIZE);
+        let mut batch_keys: Vec<K> = vec![unsafe { mem::zeroed() }; batch_size as usize];
+        let mut batch_values: Vec<V> = vec![unsafe { mem::zeroed() }; batch_size as usize];
+        let mut in_batch = None;
+        let mut out_batch = 0u64;
+        while !positions.is_empty() {
+            let mut count = batch_size;
+            let ret = bpf_map_lookup_batch(
+                fd,
+                in_batch.as_ref(),
+                &mut out_batch,
+                &mut batch_keys,
+                &mut batch_values,
+                &mut count,
+            );
+            let done = match ret {
+                Ok(_) => false,
+                Err((_, io_error)) if io_error.raw_os_error() == Some(ENOENT) => true,
+                Err((_, io_error))
+                    if io_error.raw_os_error() == Some(EINVAL) && in_batch.is_none() =>
+                {
+                    return self.get_each(keys);
+                }
+                Err((code, io_error)) => {
+                    return Err(MapError::SyscallError {
+                        call: "bpf_map_lookup_batch".to_owned(),
+                        code,
+                        io_error,
+                    })
+                }
+            };
+
+            for (key, value) in batch_keys.iter().zip(&batch_values).take(count as usize) {
+                if let Some(indices) = positions.remove(pod_bytes(key)) {
+                    for i in indices {
+                        values[i] = Some(*value);
+                    }
+                }
+            }
+            if done {
+                break;
+            }
+            in_batch = Some(out_batch);
+        }
+
+        Ok(values)
+    }
+
+    fn get_each(&self, keys: &[K]) -> Result<Vec<Option<V>>, MapError> {
+        keys.iter()
+            .map(|key| match self.get(key, 0) {
+                Ok(value) => Ok(Some(value)),
+                Err(MapError::KeyNotFound) => Ok(None),
+                Err(e) => Err(e),
+            })
+            .collect()
+    }
+
+    /// Returns a copy of the value associated with the key, or `V::default()`
+    /// if the key isn't in the map.
+    ///
+    /// Errors other than [`MapError::KeyNotFound`] are propagated.
+    pub fn get_or_default(&self, key: &K) -> Result<V, MapError>
+    where
+        V: Default,
+    {
+        match self.get(key, 0) {
+            Err(MapError::KeyNotFound) => Ok(V::default()),
+            res => res,
+        }
+    }
+
+    /// Returns a copy of the value associated with the key, reading it while
+    /// holding the `bpf_spin_lock` embedded in the value.
+    ///
+    /// The lookup is done with `BPF_F_LOCK`, which the kernel only accepts if
+    /// the BTF of the map value contains a `struct bpf_spin_lock`. Returns
+    /// `None` if the key isn't in the map. See also [`SpinLockMap`].
+    ///
+    /// [`SpinLockMap`]: crate::maps::SpinLockMap
+    pub fn get_locked(&self, key: &K) -> Result<Option<V>, MapError> {
+        match self.get(key, BPF_F_LOCK as u64) {
+            Ok(value) => Ok(Some(value)),
+            Err(MapError::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns an owned handle to the same map.
+    ///
+    /// See [`Map::try_clone`].
+    pub fn try_clone(&self) -> Result<HashMap<OwnedMap, K, V>, MapError> {
+        HashMap::new(self.inner.try_clone()?)
+    }
+
     /// An iterator visiting all key-value pairs in arbitrary order. The
     /// iterator item type is `Result<(K, V), MapError>`.
     pub fn iter(&self) -> MapIter<'_, K, V, Self> {
@@ -84,6 +241,44 @@ impl<T: Deref<Target = Map>, K: Pod, V: Pod> HashMap<T, K, V> {
     pub fn keys(&self) -> MapKeys<'_, K> {
         MapKeys::new(&self.inner)
     }
+
+    /// Returns the maximum number of entries of the map.
+    ///
+    /// See [`Map::max_entries`].
+    pub fn max_entries(&self) -> u32 {
+        self.inner.max_entries()
+    }
+
+    /// Returns the number of entries in the map.
+    ///
+    /// The kernel doesn't report the number of entries of hash maps, so they
+    /// are counted by walking the keys with `bpf_map_get_next_key`, which
+    /// takes one syscall per entry. The walk isn't atomic: entries inserted
+    /// or removed by eBPF programs during the walk may or may not be
+    /// counted, and the kernel restarts the walk from the first key when the
+    /// last key seen is removed, so the count is capped at
+    /// [`max_entries`](HashMap::max_entries).
+    pub fn approximate_len(&self) -> Result<u32, MapError> {
+        // the kernel doesn't expose the number of entries, walking the keys is
+        // the only way to count them
+        let mut len = 0;
+        for key in self.keys() {
+            key?;
+            len += 1;
+            if len == self.max_entries() {
+                break;
+            }
+        }
+        Ok(len)
+    }
+
+    /// Returns `true` if the map contains no entries.
+    ///
+    /// Unlike [`approximate_len`](HashMap::approximate_len), this only looks
+    /// up the first key of the map.
+    pub fn is_empty(&self) -> Result<bool, MapError> {
+        self.keys().next().transpose().map(|key| key.is_none())
+    }
 }
 
 impl<T: DerefMut<Target = Map>, K: Pod, V: Pod> HashMap<T, K, V> {
@@ -92,10 +287,187 @@ impl<T: DerefMut<Target = Map>, K: Pod, V: Pod> HashMap<T, K, V> {
         hash_map::insert(&mut self.inner, key, value, flags)
     }
 
+    /// Inserts a key-value pair into the map, writing the value while holding
+    /// the `bpf_spin_lock` embedded in it.
+    ///
+    /// The value is inserted with [`MapUpdateFlags::LOCK`], which the kernel
+    /// only accepts if the BTF of the map value contains a
+    /// `struct bpf_spin_lock`. The lock field of `value` is ignored.
+    pub fn insert_locked(&mut self, key: K, value: V) -> Result<(), MapError> {
+        self.insert_with_flags(key, value, MapUpdateFlags::LOCK)
+    }
+
+    /// Inserts a key-value pair into the map using the given update `flags`.
+    pub fn insert_with_flags(
+        &mut self,
+        key: K,
+        value: V,
+        flags: MapUpdateFlags,
+    ) -> Result<(), MapError> {
+        hash_map::insert(&mut self.inner, key, value, flags.bits())
+    }
+
+    /// Inserts a key-value pair into the map only if the key doesn't exist yet.
+    ///
+    /// The check and the insertion are performed atomically by the kernel using
+    /// [`MapUpdateFlags::NOEXIST`]. Returns `true` if the value was inserted,
+    /// `false` if the key was already present.
+    pub fn insert_if_absent(&mut self, key: K, value: V) -> Result<bool, MapError> {
+        match self.insert_with_flags(key, value, MapUpdateFlags::NOEXIST) {
+            Ok(()) => Ok(true),
+            Err(MapError::SyscallError { io_error, .. })
+                if io_error.raw_os_error() == Some(EEXIST) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Updates the value associated with the key only if the key already exists.
+    ///
+    /// The check and the update are performed atomically by the kernel using
+    /// [`MapUpdateFlags::EXIST`]. Returns `true` if the value was updated,
+    /// `false` if the key was not present.
+    pub fn update_if_present(&mut self, key: K, value: V) -> Result<bool, MapError> {
+        match self.insert_with_flags(key, value, MapUpdateFlags::EXIST) {
+            Ok(()) => Ok(true),
+            Err(MapError::SyscallError { io_error, .. })
+                if io_error.raw_os_error() == Some(ENOENT) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns a copy of the value associated with the key, inserting
+    /// `V::default()` first if the key isn't in the map.
+    ///
+    /// The default value is inserted with [`MapUpdateFlags::NOEXIST`], so a
+    /// value inserted concurrently by an eBPF program or another process is
+    /// never overwritten: in that case the value that was inserted is
+    /// returned.
+    pub fn get_or_insert_default(&mut self, key: &K) -> Result<V, MapError>
+    where
+        V: Default,
+    {
+        match self.get(key, 0) {
+            Err(MapError::KeyNotFound) => {
+                if self.insert_if_absent(*key, V::default())? {
+                    Ok(V::default())
+                } else {
+                    self.get(key, 0)
+                }
+            }
+            res => res,
+        }
+    }
+
+    /// Copies the entries of `old_map` into this map, converting them with
+    /// `transform`.
+    ///
+    /// This is meant to carry the state of a map over to a map with a new
+    /// key or value layout, for example when upgrading an eBPF program.
+    /// `transform` is called for each entry of `old_map` and returns the
+    /// entry to insert, or `None` to skip it. The converted entries are
+    /// inserted with a single `bpf_map_update_batch` call, overwriting the
+    /// existing values. Returns the number of entries inserted.
+    ///
+    /// Call this before attaching the program that uses this map, so that the
+    /// program never runs with a map that hasn't been populated yet. Entries
+    /// that eBPF programs add to or remove from `old_map` while it's being
+    /// copied may or may not be migrated.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.6.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::BatchError`] if `bpf_map_update_batch` fails, with
+    /// the number of entries that the kernel inserted before failing.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let bpf = aya::Bpf::load(&[])?;
+    /// use aya::maps::HashMap;
+    /// use std::convert::TryFrom;
+    ///
+    /// let old = HashMap::<_, u32, u32>::try_from(bpf.map("COUNTERS_V1")?)?;
+    /// let mut new = HashMap::<_, u32, u64>::try_from(bpf.map_mut("COUNTERS_V2")?)?;
+    /// let migrated = new.migrate_from(&old, |key, count| Some((key, count as u64)))?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn migrate_from<U, K2, V2, F>(
+        &mut self,
+        old_map: &HashMap<U, K2, V2>,
+        transform: F,
+    ) -> Result<usize, MapError>
+    where
+        U: Deref<Target = Map>,
+        K2: Pod,
+        V2: Pod,
+        F: Fn(K2, V2) -> Option<(K, V)>,
+    {
+        let fd = self.inner.writable_fd_or_err()?;
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        for item in old_map.iter() {
+            let (key, value) = item?;
+            if let Some((key, value)) = transform(key, value) {
+                keys.push(key);
+                values.push(value);
+            }
+        }
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let mut count = keys.len() as u32;
+        bpf_map_update_batch(fd, &keys, &values, &mut count, 0).map_err(|(code, io_error)| {
+            MapError::BatchError {
+                call: "bpf_map_update_batch".to_owned(),
+                count: count as usize,
+                code,
+                io_error,
+            }
+        })?;
+        Ok(count as usize)
+    }
+
     /// Removes a key from the map.
     pub fn remove(&mut self, key: &K) -> Result<(), MapError> {
         hash_map::remove(&mut self.inner, key)
     }
+
+    /// Removes a key from the map, returning the value that was associated with it.
+    ///
+    /// The lookup and the removal are performed atomically by the kernel with a single
+    /// `BPF_MAP_LOOKUP_AND_DELETE_ELEM` command, so no other reader or writer can observe or
+    /// consume the value in between.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this method on hash maps is 5.14.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::KeyNotFound`] if the key does not exist, [`MapError::SyscallError`]
+    /// if `bpf_map_lookup_and_delete_elem` fails.
+    pub fn pop(&mut self, key: &K) -> Result<V, MapError> {
+        let fd = self.inner.writable_fd_or_err()?;
+        let value =
+            bpf_map_lookup_and_delete_elem(fd, Some(key), 0).map_err(|(code, io_error)| {
+                MapError::SyscallError {
+                    call: "bpf_map_lookup_and_delete_elem".to_owned(),
+                    code,
+                    io_error,
+                }
+            })?;
+        value.ok_or(MapError::KeyNotFound)
+    }
 }
 
 impl<T: Deref<Target = Map>, K: Pod, V: Pod> IterableMap<K, V> for HashMap<T, K, V> {
@@ -108,6 +480,13 @@ impl<T: Deref<Target = Map>, K: Pod, V: Pod> IterableMap<K, V> for HashMap<T, K,
     }
 }
 
+impl<T: Deref<Target = Map>, K, V> AsRawFd for HashMap<T, K, V> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
 impl<K: Pod, V: Pod> TryFrom<MapRef> for HashMap<MapRef, K, V> {
     type Error = MapError;
 
@@ -124,6 +503,14 @@ impl<K: Pod, V: Pod> TryFrom<MapRefMut> for HashMap<MapRefMut, K, V> {
     }
 }
 
+impl<K: Pod, V: Pod> TryFrom<OwnedMap> for HashMap<OwnedMap, K, V> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<HashMap<OwnedMap, K, V>, MapError> {
+        HashMap::new(a)
+    }
+}
+
 impl<'a, K: Pod, V: Pod> TryFrom<&'a Map> for HashMap<&'a Map, K, V> {
     type Error = MapError;
 
@@ -144,7 +531,7 @@ impl<'a, K: Pod, V: Pod> TryFrom<&'a mut Map> for HashMap<&'a mut Map, K, V> {
 mod tests {
     use std::io;
 
-    use libc::{EFAULT, ENOENT};
+    use libc::{EEXIST, EFAULT, EINVAL, ENOENT};
 
     use crate::{
         bpf_map_def,
@@ -183,6 +570,8 @@ mod tests {
             obj: new_obj_map(),
             fd: None,
             pinned: false,
+            frozen: false,
+            name: None,
         };
         assert!(matches!(
             HashMap::<_, u8, u32>::new(&map),
@@ -199,6 +588,8 @@ mod tests {
             obj: new_obj_map(),
             fd: None,
             pinned: false,
+            frozen: false,
+            name: None,
         };
         assert!(matches!(
             HashMap::<_, u32, u16>::new(&map),
@@ -226,6 +617,8 @@ mod tests {
             },
             fd: None,
             pinned: false,
+            frozen: false,
+            name: None,
         };
 
         assert!(matches!(
@@ -240,6 +633,8 @@ mod tests {
             obj: new_obj_map(),
             fd: None,
             pinned: false,
+            frozen: false,
+            name: None,
         };
 
         assert!(matches!(
@@ -254,6 +649,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
 
         assert!(HashMap::<_, u32, u32>::new(&mut map).is_ok());
@@ -265,10 +662,26 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         assert!(HashMap::<_, u32, u32>::try_from(&map).is_ok())
     }
 
+    #[test]
+    fn test_as_raw_fd() {
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
+        assert_eq!(hm.as_raw_fd(), 42);
+        assert_eq!(map.fd_or_err().unwrap(), 42);
+    }
+
     #[test]
     fn test_try_from_ok_lru() {
         let map = Map {
@@ -286,6 +699,8 @@ mod tests {
             },
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
 
         assert!(HashMap::<_, u32, u32>::try_from(&map).is_ok())
@@ -299,6 +714,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
 
@@ -322,12 +739,70 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
 
         assert!(hm.insert(1, 42, 0).is_ok());
     }
 
+    #[test]
+    fn test_insert_if_absent() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } if unsafe { attr.__bindgen_anon_2.flags } == MapUpdateFlags::NOEXIST.bits() => {
+                match bpf_key(attr) {
+                    Some(1) => Ok(1),
+                    _ => sys_error(EEXIST),
+                }
+            }
+            _ => sys_error(EFAULT),
+        });
+
+        let mut map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
+
+        assert!(matches!(hm.insert_if_absent(1, 42), Ok(true)));
+        assert!(matches!(hm.insert_if_absent(2, 42), Ok(false)));
+    }
+
+    #[test]
+    fn test_update_if_present() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } if unsafe { attr.__bindgen_anon_2.flags } == MapUpdateFlags::EXIST.bits() => {
+                match bpf_key(attr) {
+                    Some(1) => Ok(1),
+                    _ => sys_error(ENOENT),
+                }
+            }
+            _ => sys_error(EFAULT),
+        });
+
+        let mut map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
+
+        assert!(matches!(hm.update_if_present(1, 42), Ok(true)));
+        assert!(matches!(hm.update_if_present(2, 42), Ok(false)));
+    }
+
     #[test]
     fn test_remove_syscall_error() {
         override_syscall(|_| sys_error(EFAULT));
@@ -336,6 +811,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
 
@@ -359,6 +836,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
 
@@ -372,6 +851,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
 
@@ -394,12 +875,183 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
 
         assert!(matches!(hm.get(&1, 0), Err(MapError::KeyNotFound)));
     }
 
+    #[test]
+    fn test_get_locked() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } if unsafe { attr.__bindgen_anon_2.flags } == BPF_F_LOCK as u64 => lookup_elem(attr),
+            _ => sys_error(EFAULT),
+        });
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
+
+        assert!(matches!(hm.get_locked(&10), Ok(Some(100))));
+        assert!(matches!(hm.get_locked(&1), Ok(None)));
+    }
+
+    #[test]
+    fn test_insert_locked() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } if unsafe { attr.__bindgen_anon_2.flags } == BPF_F_LOCK as u64 => Ok(1),
+            _ => sys_error(EFAULT),
+        });
+        let mut map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
+
+        assert!(hm.insert_locked(1, 42).is_ok());
+    }
+
+    #[test]
+    fn test_get_or_default() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => lookup_elem(attr),
+            _ => sys_error(EFAULT),
+        });
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
+
+        assert!(matches!(hm.get_or_default(&10), Ok(100)));
+        assert!(matches!(hm.get_or_default(&1), Ok(0)));
+    }
+
+    #[test]
+    fn test_get_or_insert_default() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => lookup_elem(attr),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } if unsafe { attr.__bindgen_anon_2.flags } == MapUpdateFlags::NOEXIST.bits()
+                && bpf_key(attr) == Some(1) =>
+            {
+                Ok(1)
+            }
+            _ => sys_error(EFAULT),
+        });
+        let mut map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
+
+        assert!(matches!(hm.get_or_insert_default(&20), Ok(200)));
+        assert!(matches!(hm.get_or_insert_default(&1), Ok(0)));
+        assert!(matches!(
+            hm.get_or_insert_default(&2),
+            Err(MapError::SyscallError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_pop_syscall_error() {
+        override_syscall(|_| sys_error(EFAULT));
+
+        let mut map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
+
+        assert!(matches!(
+            hm.pop(&1),
+            Err(MapError::SyscallError { call, code: -1, io_error }) if call == "bpf_map_lookup_and_delete_elem" && io_error.raw_os_error() == Some(EFAULT)
+        ));
+    }
+
+    #[test]
+    fn test_pop_not_found() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_AND_DELETE_ELEM,
+                ..
+            } => sys_error(ENOENT),
+            _ => sys_error(EFAULT),
+        });
+
+        let mut map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
+
+        assert!(matches!(hm.pop(&1), Err(MapError::KeyNotFound)));
+    }
+
+    #[test]
+    fn test_pop_ok() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_AND_DELETE_ELEM,
+                attr,
+            } => match bpf_key(attr) {
+                Some(10) => {
+                    set_ret(attr, 100);
+                    Ok(1)
+                }
+                _ => sys_error(ENOENT),
+            },
+            _ => sys_error(EFAULT),
+        });
+
+        let mut map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
+
+        assert!(matches!(hm.pop(&10), Ok(100)));
+        assert!(matches!(hm.pop(&20), Err(MapError::KeyNotFound)));
+    }
+
     fn bpf_key<T: Copy>(attr: &bpf_attr) -> Option<T> {
         match unsafe { attr.__bindgen_anon_2.key } as *const T {
             p if p.is_null() => None,
@@ -430,6 +1082,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
         let keys = hm.keys().collect::<Result<Vec<_>, _>>();
@@ -474,6 +1128,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
 
@@ -481,6 +1137,51 @@ mod tests {
         assert_eq!(&keys, &[10, 20, 30])
     }
 
+    #[test]
+    fn test_approximate_len() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_GET_NEXT_KEY,
+                attr,
+            } => get_next_key(attr),
+            _ => sys_error(EFAULT),
+        });
+
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
+
+        assert_eq!(hm.approximate_len().unwrap(), 3);
+        assert!(!hm.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_is_empty() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_GET_NEXT_KEY,
+                ..
+            } => sys_error(ENOENT),
+            _ => sys_error(EFAULT),
+        });
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
+
+        assert!(hm.is_empty().unwrap());
+        assert_eq!(hm.approximate_len().unwrap(), 0);
+    }
+
     #[test]
     fn test_keys_error() {
         override_syscall(|call| match call {
@@ -502,6 +1203,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
 
@@ -532,12 +1235,145 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
         let items = hm.iter().collect::<Result<Vec<_>, _>>().unwrap();
         assert_eq!(&items, &[(10, 100), (20, 200), (30, 300)])
     }
 
+    #[test]
+    fn test_migrate_from() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_GET_NEXT_KEY,
+                attr,
+            } => get_next_key(attr),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => lookup_elem(attr),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_BATCH,
+                attr,
+            } => {
+                let batch = unsafe { &attr.batch };
+                let keys = unsafe {
+                    std::slice::from_raw_parts(batch.keys as *const u32, batch.count as usize)
+                };
+                let values = unsafe {
+                    std::slice::from_raw_parts(batch.values as *const u32, batch.count as usize)
+                };
+                if keys == [11, 31] && values == [200, 600] {
+                    Ok(0)
+                } else {
+                    sys_error(EFAULT)
+                }
+            }
+            _ => sys_error(EFAULT),
+        });
+        let old = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let mut new = Map {
+            obj: new_obj_map(),
+            fd: Some(43),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let old = HashMap::<_, u32, u32>::new(&old).unwrap();
+        let mut new = HashMap::<_, u32, u32>::new(&mut new).unwrap();
+
+        let migrated = new.migrate_from(&old, |key, value| {
+            if key == 20 {
+                None
+            } else {
+                Some((key + 1, value * 2))
+            }
+        });
+        assert!(matches!(migrated, Ok(2)));
+        assert!(matches!(new.migrate_from(&old, |_, _| None), Ok(0)));
+    }
+
+    #[test]
+    fn test_get_batch() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_BATCH,
+                attr,
+            } => {
+                let attr = attr as *const bpf_attr as *mut bpf_attr;
+                let batch = unsafe { &mut (*attr).batch };
+                let keys = batch.keys as *mut u32;
+                let values = batch.values as *mut u32;
+                // the map is read in two chunks, the end of the map is
+                // reported with ENOENT
+                let (entries, ret): (&[(u32, u32)], _) = if batch.in_batch == 0 {
+                    (&[(10, 100), (20, 200)], Ok(0))
+                } else if unsafe { *(batch.in_batch as *const u64) } == 2 {
+                    (&[(30, 300)], sys_error(ENOENT))
+                } else {
+                    return sys_error(EFAULT);
+                };
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    unsafe {
+                        *keys.add(i) = *key;
+                        *values.add(i) = *value;
+                    }
+                }
+                batch.count = entries.len() as u32;
+                unsafe { *(batch.out_batch as *mut u64) = 2 };
+                ret
+            }
+            _ => sys_error(EFAULT),
+        });
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
+
+        assert_eq!(
+            hm.get_batch(&[30, 40, 10, 30]).unwrap(),
+            vec![Some(300), None, Some(100), Some(300)]
+        );
+        assert!(hm.get_batch(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_batch_fallback() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_BATCH,
+                ..
+            } => sys_error(EINVAL),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => lookup_elem(attr),
+            _ => sys_error(EFAULT),
+        });
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
+
+        assert_eq!(hm.get_batch(&[20, 40]).unwrap(), vec![Some(200), None]);
+    }
+
     #[test]
     fn test_iter_key_deleted() {
         override_syscall(|call| match call {
@@ -565,6 +1401,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
 
@@ -599,6 +1437,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
 
@@ -639,6 +1479,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
 
@@ -9,9 +9,11 @@ use crate::{
 #[allow(clippy::module_inception)]
 mod hash_map;
 mod per_cpu_hash_map;
+mod spin_lock_map;
 
 pub use hash_map::*;
 pub use per_cpu_hash_map::*;
+pub use spin_lock_map::*;
 
 pub(crate) fn check_kv_size<K, V>(map: &Map) -> Result<(), MapError> {
     let size = mem::size_of::<K>();
@@ -28,7 +30,7 @@ pub(crate) fn check_kv_size<K, V>(map: &Map) -> Result<(), MapError> {
 }
 
 pub(crate) fn insert<K, V>(map: &mut Map, key: K, value: V, flags: u64) -> Result<(), MapError> {
-    let fd = map.fd_or_err()?;
+    let fd = map.writable_fd_or_err()?;
     bpf_map_update_elem(fd, &key, &value, flags).map_err(|(code, io_error)| {
         MapError::SyscallError {
             call: "bpf_map_update_elem".to_owned(),
@@ -41,7 +43,7 @@ pub(crate) fn insert<K, V>(map: &mut Map, key: K, value: V, flags: u64) -> Resul
 }
 
 pub(crate) fn remove<K>(map: &mut Map, key: &K) -> Result<(), MapError> {
-    let fd = map.fd_or_err()?;
+    let fd = map.writable_fd_or_err()?;
     bpf_map_delete_elem(fd, key)
         .map(|_| ())
         .map_err(|(code, io_error)| MapError::SyscallError {
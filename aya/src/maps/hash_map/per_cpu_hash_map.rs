@@ -1,14 +1,19 @@
 //! Per-CPU hash map.
 use std::{
+    collections::HashMap,
     convert::TryFrom,
+    hash::Hash,
+    iter::Sum,
     marker::PhantomData,
     ops::{Deref, DerefMut},
+    os::unix::io::{AsRawFd, RawFd},
 };
 
 use crate::{
     generated::bpf_map_type::{BPF_MAP_TYPE_LRU_PERCPU_HASH, BPF_MAP_TYPE_PERCPU_HASH},
     maps::{
-        hash_map, IterableMap, Map, MapError, MapIter, MapKeys, MapRef, MapRefMut, PerCpuValues,
+        hash_map, IterableMap, Map, MapError, MapIter, MapKeys, MapRef, MapRefMut, OwnedMap,
+        PerCpuValues,
     },
     sys::{bpf_map_lookup_elem_per_cpu, bpf_map_update_elem_per_cpu},
     Pod,
@@ -85,6 +90,52 @@ impl<T: Deref<Target = Map>, K: Pod, V: Pod> PerCpuHashMap<T, K, V> {
         values.ok_or(MapError::KeyNotFound)
     }
 
+    /// Reads the values associated with the key and combines them with `f`.
+    ///
+    /// The values are read one CPU at a time by the kernel, so the read is not
+    /// atomic: eBPF programs running on other CPUs may update their slots
+    /// while the values are being copied.
+    pub fn aggregate<R, F: Fn(PerCpuValues<V>) -> R>(&self, key: &K, f: F) -> Result<R, MapError> {
+        self.get(key, 0).map(f)
+    }
+
+    /// Returns the sum of the values - one for each CPU - associated with the
+    /// key.
+    ///
+    /// See [`aggregate`](Self::aggregate) for the atomicity caveats.
+    pub fn sum_values(&self, key: &K) -> Result<V, MapError>
+    where
+        V: Sum,
+    {
+        self.aggregate(key, |values| values.iter().copied().sum())
+    }
+
+    /// Returns the largest of the values - one for each CPU - associated with
+    /// the key.
+    ///
+    /// See [`aggregate`](Self::aggregate) for the atomicity caveats.
+    pub fn max_value(&self, key: &K) -> Result<V, MapError>
+    where
+        V: Ord,
+    {
+        // there's always one value per possible CPU, so the max always exists
+        self.aggregate(key, |values| values.iter().copied().max().unwrap())
+    }
+
+    /// Returns the sum of the per-CPU values of every key in the map.
+    ///
+    /// Keys are visited one at a time, so the result is not a consistent
+    /// snapshot of the map. See also [`aggregate`](Self::aggregate).
+    pub fn sum_all(&self) -> Result<HashMap<K, V>, MapError>
+    where
+        K: Eq + Hash,
+        V: Sum,
+    {
+        self.iter()
+            .map(|item| item.map(|(key, values)| (key, values.iter().copied().sum())))
+            .collect()
+    }
+
     /// An iterator visiting all key-value pairs in arbitrary order. The
     /// iterator item type is `Result<(K, PerCpuValues<V>), MapError>`.
     pub fn iter(&self) -> MapIter<'_, K, PerCpuValues<V>, Self> {
@@ -129,7 +180,7 @@ impl<T: DerefMut<Target = Map>, K: Pod, V: Pod> PerCpuHashMap<T, K, V> {
     /// # Ok::<(), Error>(())
     /// ```
     pub fn insert(&mut self, key: K, values: PerCpuValues<V>, flags: u64) -> Result<(), MapError> {
-        let fd = self.inner.fd_or_err()?;
+        let fd = self.inner.writable_fd_or_err()?;
         bpf_map_update_elem_per_cpu(fd, &key, &values, flags).map_err(|(code, io_error)| {
             MapError::SyscallError {
                 call: "bpf_map_update_elem".to_owned(),
@@ -159,6 +210,13 @@ impl<T: Deref<Target = Map>, K: Pod, V: Pod> IterableMap<K, PerCpuValues<V>>
     }
 }
 
+impl<T: Deref<Target = Map>, K: Pod, V: Pod> AsRawFd for PerCpuHashMap<T, K, V> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
 impl<K: Pod, V: Pod> TryFrom<MapRef> for PerCpuHashMap<MapRef, K, V> {
     type Error = MapError;
 
@@ -175,6 +233,14 @@ impl<K: Pod, V: Pod> TryFrom<MapRefMut> for PerCpuHashMap<MapRefMut, K, V> {
     }
 }
 
+impl<K: Pod, V: Pod> TryFrom<OwnedMap> for PerCpuHashMap<OwnedMap, K, V> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<PerCpuHashMap<OwnedMap, K, V>, MapError> {
+        PerCpuHashMap::new(a)
+    }
+}
+
 impl<'a, K: Pod, V: Pod> TryFrom<&'a Map> for PerCpuHashMap<&'a Map, K, V> {
     type Error = MapError;
 
@@ -190,3 +256,133 @@ impl<'a, K: Pod, V: Pod> TryFrom<&'a mut Map> for PerCpuHashMap<&'a mut Map, K,
         PerCpuHashMap::new(a)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use libc::{EFAULT, ENOENT};
+
+    use crate::{
+        bpf_map_def,
+        generated::{bpf_attr, bpf_cmd, bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH},
+        obj,
+        sys::{override_syscall, SysResult, Syscall},
+        util::nr_cpus,
+    };
+
+    use super::*;
+
+    fn new_obj_map() -> obj::Map {
+        obj::Map {
+            def: bpf_map_def {
+                map_type: BPF_MAP_TYPE_PERCPU_HASH as u32,
+                key_size: 4,
+                value_size: 4,
+                max_entries: 1024,
+                ..Default::default()
+            },
+            section_index: 0,
+            data: Vec::new(),
+            kind: obj::MapKind::Other,
+        }
+    }
+
+    fn sys_error(value: i32) -> SysResult {
+        Err((-1, io::Error::from_raw_os_error(value)))
+    }
+
+    fn bpf_key(attr: &bpf_attr) -> Option<u32> {
+        match unsafe { attr.__bindgen_anon_2.key } as *const u32 {
+            p if p.is_null() => None,
+            p => Some(unsafe { *p }),
+        }
+    }
+
+    fn get_next_key(attr: &bpf_attr) -> SysResult {
+        let next = match bpf_key(attr) {
+            None => 10,
+            Some(10) => 20,
+            Some(20) => return sys_error(ENOENT),
+            Some(_) => return sys_error(EFAULT),
+        };
+        let key = unsafe { attr.__bindgen_anon_2.__bindgen_anon_1.next_key } as *mut u32;
+        unsafe { *key = next };
+
+        Ok(1)
+    }
+
+    // the value for CPU `n` is `key + n`, values are padded to 8 bytes
+    fn lookup_elem(attr: &bpf_attr) -> SysResult {
+        let key = match bpf_key(attr) {
+            Some(key @ 10) | Some(key @ 20) => key,
+            Some(_) => return sys_error(ENOENT),
+            None => return sys_error(EFAULT),
+        };
+        let values = unsafe { attr.__bindgen_anon_2.__bindgen_anon_1.value } as *mut u64;
+        for cpu in 0..nr_cpus().unwrap() {
+            unsafe { *values.add(cpu) = (key + cpu as u32) as u64 };
+        }
+
+        Ok(1)
+    }
+
+    fn override_lookups() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_GET_NEXT_KEY,
+                attr,
+            } => get_next_key(attr),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => lookup_elem(attr),
+            _ => sys_error(EFAULT),
+        });
+    }
+
+    #[test]
+    fn test_aggregate() {
+        override_lookups();
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let hm = PerCpuHashMap::<_, u32, u32>::new(&map).unwrap();
+        let nr_cpus = nr_cpus().unwrap() as u32;
+
+        assert_eq!(
+            hm.aggregate(&10, |values| values.len()).unwrap(),
+            nr_cpus as usize
+        );
+        assert_eq!(
+            hm.sum_values(&10).unwrap(),
+            10 * nr_cpus + nr_cpus * (nr_cpus - 1) / 2
+        );
+        assert_eq!(hm.max_value(&20).unwrap(), 20 + nr_cpus - 1);
+        assert!(matches!(hm.sum_values(&30), Err(MapError::KeyNotFound)));
+    }
+
+    #[test]
+    fn test_sum_all() {
+        override_lookups();
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let hm = PerCpuHashMap::<_, u32, u32>::new(&map).unwrap();
+        let nr_cpus = nr_cpus().unwrap() as u32;
+        let extra = nr_cpus * (nr_cpus - 1) / 2;
+
+        let totals = hm.sum_all().unwrap();
+        assert_eq!(totals.len(), 2);
+        assert_eq!(totals[&10], 10 * nr_cpus + extra);
+        assert_eq!(totals[&20], 20 * nr_cpus + extra);
+    }
+}
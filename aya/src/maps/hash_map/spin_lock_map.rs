@@ -0,0 +1,120 @@
+use std::{
+    convert::TryFrom,
+    ops::{Deref, DerefMut},
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use crate::{
+    maps::{HashMap, Map, MapError, MapKeys, MapRef, MapRefMut, OwnedMap},
+    Pod,
+};
+
+/// A hash map whose values are protected by a `bpf_spin_lock`.
+///
+/// eBPF programs serialize the access to the values of the map with
+/// `bpf_spin_lock()` and `bpf_spin_unlock()`. [`SpinLockMap`] wraps a
+/// [`HashMap`] and only exposes the locked lookup and update operations, so
+/// that user space never reads or writes a value without holding its lock.
+///
+/// The map value must contain a `struct bpf_spin_lock` field and the object
+/// must be compiled with BTF, since the kernel finds the lock through the
+/// BTF of the map value. Otherwise the kernel rejects the locked operations
+/// with `EINVAL`. The lock field of the values read by user space is always
+/// zero and the one of the values written by user space is ignored.
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 5.1.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let bpf = aya::Bpf::load(&[])?;
+/// use aya::maps::SpinLockMap;
+/// use std::convert::TryFrom;
+///
+/// #[derive(Clone, Copy)]
+/// #[repr(C)]
+/// struct Counter {
+///     lock: u32,
+///     count: u64,
+/// }
+///
+/// unsafe impl aya::Pod for Counter {}
+///
+/// let mut counters = SpinLockMap::<_, u32, Counter>::try_from(bpf.map_mut("COUNTERS")?)?;
+/// if let Some(counter) = counters.get(&0)? {
+///     counters.insert(0, Counter { count: counter.count + 1, ..counter })?;
+/// }
+/// # Ok::<(), aya::BpfError>(())
+/// ```
+pub struct SpinLockMap<T: Deref<Target = Map>, K, V> {
+    inner: HashMap<T, K, V>,
+}
+
+impl<T: Deref<Target = Map>, K: Pod, V: Pod> SpinLockMap<T, K, V> {
+    pub(crate) fn new(map: T) -> Result<SpinLockMap<T, K, V>, MapError> {
+        Ok(SpinLockMap {
+            inner: HashMap::new(map)?,
+        })
+    }
+
+    /// Returns a copy of the value associated with the key, read while
+    /// holding its lock.
+    ///
+    /// See [`HashMap::get_locked`].
+    pub fn get(&self, key: &K) -> Result<Option<V>, MapError> {
+        self.inner.get_locked(key)
+    }
+
+    /// An iterator visiting all keys in arbitrary order. The iterator element
+    /// type is `Result<K, MapError>`.
+    pub fn keys(&self) -> MapKeys<'_, K> {
+        self.inner.keys()
+    }
+}
+
+impl<T: DerefMut<Target = Map>, K: Pod, V: Pod> SpinLockMap<T, K, V> {
+    /// Inserts a key-value pair into the map, writing the value while
+    /// holding its lock.
+    ///
+    /// See [`HashMap::insert_locked`].
+    pub fn insert(&mut self, key: K, value: V) -> Result<(), MapError> {
+        self.inner.insert_locked(key, value)
+    }
+
+    /// Removes a key from the map.
+    pub fn remove(&mut self, key: &K) -> Result<(), MapError> {
+        self.inner.remove(key)
+    }
+}
+
+impl<T: Deref<Target = Map>, K, V> AsRawFd for SpinLockMap<T, K, V> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl<K: Pod, V: Pod> TryFrom<MapRef> for SpinLockMap<MapRef, K, V> {
+    type Error = MapError;
+
+    fn try_from(a: MapRef) -> Result<SpinLockMap<MapRef, K, V>, MapError> {
+        SpinLockMap::new(a)
+    }
+}
+
+impl<K: Pod, V: Pod> TryFrom<MapRefMut> for SpinLockMap<MapRefMut, K, V> {
+    type Error = MapError;
+
+    fn try_from(a: MapRefMut) -> Result<SpinLockMap<MapRefMut, K, V>, MapError> {
+        SpinLockMap::new(a)
+    }
+}
+
+impl<K: Pod, V: Pod> TryFrom<OwnedMap> for SpinLockMap<OwnedMap, K, V> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<SpinLockMap<OwnedMap, K, V>, MapError> {
+        SpinLockMap::new(a)
+    }
+}
@@ -1,10 +1,17 @@
 //! A LPM Trie.
-use std::{convert::TryFrom, marker::PhantomData, mem, ops::Deref};
+use std::{
+    convert::TryFrom,
+    marker::PhantomData,
+    mem,
+    ops::Deref,
+    os::unix::io::{AsRawFd, RawFd},
+};
 
 use crate::{
     generated::bpf_map_type::BPF_MAP_TYPE_LPM_TRIE,
-    maps::{IterableMap, Map, MapError, MapRef, MapRefMut},
+    maps::{IterableMap, Map, MapError, MapKeys, MapRef, MapRefMut, OwnedMap},
     sys::{bpf_map_delete_elem, bpf_map_lookup_elem, bpf_map_update_elem},
+    util::bytes_of,
     Pod,
 };
 
@@ -142,9 +149,58 @@ impl<T: Deref<Target = Map>, K: Pod, V: Pod> LpmTrie<T, K, V> {
         value.ok_or(MapError::KeyNotFound)
     }
 
+    /// Returns the prefix length and a copy of the value of the longest
+    /// prefix matching `data`, or `None` if no prefix matches.
+    ///
+    /// The kernel lookup doesn't return the key that matched, so the prefix
+    /// length is found by iterating the keys of the trie and this is O(n) in
+    /// the number of prefixes. Use [`get`](Self::get) when only the value is
+    /// needed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let bpf = aya::Bpf::load(&[])?;
+    /// use aya::maps::lpm_trie::LpmTrie;
+    /// use std::convert::TryFrom;
+    /// use std::net::Ipv4Addr;
+    ///
+    /// let trie = LpmTrie::<_, u32, u32>::try_from(bpf.map("ROUTES")?)?;
+    /// let addr = u32::from(Ipv4Addr::new(10, 1, 2, 3)).to_be();
+    /// if let Some((prefix_len, next_hop)) = trie.longest_match(&addr)? {
+    ///     println!("matched a /{} route to {}", prefix_len, next_hop);
+    /// }
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn longest_match(&self, data: &K) -> Result<Option<(u32, V)>, MapError> {
+        let mut longest = None;
+        for key in MapKeys::<Key<K>>::new(&self.inner) {
+            let key = key?;
+            let (prefix_len, key_data) = (key.prefix_len, key.data);
+            // Safety: K is Pod
+            let matches =
+                unsafe { prefix_matches(bytes_of(&key_data), bytes_of(data), prefix_len) };
+            if matches && longest.map(|len| prefix_len > len).unwrap_or(true) {
+                longest = Some(prefix_len);
+            }
+        }
+
+        let prefix_len = match longest {
+            Some(prefix_len) => prefix_len,
+            None => return Ok(None),
+        };
+        // the longest prefix is the only one matching a lookup with its length
+        match self.get(&Key::new(prefix_len, *data), 0) {
+            Ok(value) => Ok(Some((prefix_len, value))),
+            // the prefix was removed since the keys were read
+            Err(MapError::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Inserts a key value pair into the map.
     pub fn insert(&self, key: &Key<K>, value: V, flags: u64) -> Result<(), MapError> {
-        let fd = self.inner.deref().fd_or_err()?;
+        let fd = self.inner.deref().writable_fd_or_err()?;
         bpf_map_update_elem(fd, key, &value, flags).map_err(|(code, io_error)| {
             MapError::SyscallError {
                 call: "bpf_map_update_elem".to_owned(),
@@ -160,7 +216,7 @@ impl<T: Deref<Target = Map>, K: Pod, V: Pod> LpmTrie<T, K, V> {
     ///
     /// Both the prefix and data must match exactly - this method does not do a longest prefix match.
     pub fn remove(&self, key: &Key<K>) -> Result<(), MapError> {
-        let fd = self.inner.deref().fd_or_err()?;
+        let fd = self.inner.deref().writable_fd_or_err()?;
         bpf_map_delete_elem(fd, key)
             .map(|_| ())
             .map_err(|(code, io_error)| MapError::SyscallError {
@@ -171,6 +227,23 @@ impl<T: Deref<Target = Map>, K: Pod, V: Pod> LpmTrie<T, K, V> {
     }
 }
 
+// returns whether the first `prefix_len` bits of `a` and `b` are equal
+fn prefix_matches(a: &[u8], b: &[u8], prefix_len: u32) -> bool {
+    let bytes = prefix_len as usize / 8;
+    let bits = prefix_len % 8;
+    if bytes > a.len() || a[..bytes] != b[..bytes] {
+        return false;
+    }
+    if bits == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - bits);
+    match (a.get(bytes), b.get(bytes)) {
+        (Some(a), Some(b)) => a & mask == b & mask,
+        _ => false,
+    }
+}
+
 impl<T: Deref<Target = Map>, K: Pod, V: Pod> IterableMap<K, V> for LpmTrie<T, K, V> {
     fn map(&self) -> &Map {
         &self.inner
@@ -182,6 +255,13 @@ impl<T: Deref<Target = Map>, K: Pod, V: Pod> IterableMap<K, V> for LpmTrie<T, K,
     }
 }
 
+impl<T: Deref<Target = Map>, K, V> AsRawFd for LpmTrie<T, K, V> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
 impl<K: Pod, V: Pod> TryFrom<MapRef> for LpmTrie<MapRef, K, V> {
     type Error = MapError;
 
@@ -198,6 +278,14 @@ impl<K: Pod, V: Pod> TryFrom<MapRefMut> for LpmTrie<MapRefMut, K, V> {
     }
 }
 
+impl<K: Pod, V: Pod> TryFrom<OwnedMap> for LpmTrie<OwnedMap, K, V> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<LpmTrie<OwnedMap, K, V>, MapError> {
+        LpmTrie::new(a)
+    }
+}
+
 impl<'a, K: Pod, V: Pod> TryFrom<&'a Map> for LpmTrie<&'a Map, K, V> {
     type Error = MapError;
 
@@ -220,7 +308,7 @@ mod tests {
     use crate::{
         bpf_map_def,
         generated::{
-            bpf_cmd,
+            bpf_attr, bpf_cmd,
             bpf_map_type::{BPF_MAP_TYPE_LPM_TRIE, BPF_MAP_TYPE_PERF_EVENT_ARRAY},
         },
         obj,
@@ -254,6 +342,8 @@ mod tests {
             obj: new_obj_map(),
             fd: None,
             pinned: false,
+            frozen: false,
+            name: None,
         };
         assert!(matches!(
             LpmTrie::<_, u16, u32>::new(&map),
@@ -270,6 +360,8 @@ mod tests {
             obj: new_obj_map(),
             fd: None,
             pinned: false,
+            frozen: false,
+            name: None,
         };
         assert!(matches!(
             LpmTrie::<_, u32, u16>::new(&map),
@@ -297,6 +389,8 @@ mod tests {
             },
             fd: None,
             pinned: false,
+            frozen: false,
+            name: None,
         };
 
         assert!(matches!(
@@ -311,6 +405,8 @@ mod tests {
             obj: new_obj_map(),
             fd: None,
             pinned: false,
+            frozen: false,
+            name: None,
         };
 
         assert!(matches!(
@@ -325,6 +421,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
 
         assert!(LpmTrie::<_, u32, u32>::new(&mut map).is_ok());
@@ -336,6 +434,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         assert!(LpmTrie::<_, u32, u32>::try_from(&map).is_ok())
     }
@@ -348,6 +448,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let trie = LpmTrie::<_, u32, u32>::new(&mut map).unwrap();
         let ipaddr = Ipv4Addr::new(8, 8, 8, 8);
@@ -372,6 +474,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
 
         let trie = LpmTrie::<_, u32, u32>::new(&mut map).unwrap();
@@ -388,6 +492,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let trie = LpmTrie::<_, u32, u32>::new(&mut map).unwrap();
         let ipaddr = Ipv4Addr::new(8, 8, 8, 8);
@@ -412,6 +518,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let trie = LpmTrie::<_, u32, u32>::new(&mut map).unwrap();
         let ipaddr = Ipv4Addr::new(8, 8, 8, 8);
@@ -426,6 +534,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let trie = LpmTrie::<_, u32, u32>::new(&map).unwrap();
         let ipaddr = Ipv4Addr::new(8, 8, 8, 8);
@@ -450,6 +560,8 @@ mod tests {
             obj: new_obj_map(),
             fd: Some(42),
             pinned: false,
+            frozen: false,
+            name: None,
         };
         let trie = LpmTrie::<_, u32, u32>::new(&map).unwrap();
         let ipaddr = Ipv4Addr::new(8, 8, 8, 8);
@@ -457,4 +569,109 @@ mod tests {
 
         assert!(matches!(trie.get(&key, 0), Err(MapError::KeyNotFound)));
     }
+
+    #[test]
+    fn test_prefix_matches() {
+        assert!(prefix_matches(&[10, 1, 2, 3], &[10, 1, 9, 9], 16));
+        assert!(!prefix_matches(&[10, 1, 2, 3], &[10, 1, 9, 9], 24));
+        assert!(prefix_matches(&[10, 0b1000_0000], &[10, 0b1011_1111], 9));
+        assert!(!prefix_matches(&[10, 0b1000_0000], &[10, 0b0011_1111], 9));
+        assert!(prefix_matches(&[1], &[2], 0));
+        assert!(!prefix_matches(&[1], &[1], 9));
+    }
+
+    fn ipv4(a: u8, b: u8, c: u8, d: u8) -> u32 {
+        u32::from(Ipv4Addr::new(a, b, c, d)).to_be()
+    }
+
+    fn prefixes() -> [Key<u32>; 4] {
+        [
+            Key::new(8, ipv4(10, 0, 0, 0)),
+            Key::new(16, ipv4(10, 1, 0, 0)),
+            Key::new(24, ipv4(10, 1, 2, 0)),
+            Key::new(16, ipv4(192, 168, 0, 0)),
+        ]
+    }
+
+    fn get_next_key(attr: &bpf_attr) -> SysResult {
+        let prefixes = prefixes();
+        let key = unsafe { attr.__bindgen_anon_2.key } as *const Key<u32>;
+        let next = if key.is_null() {
+            0
+        } else {
+            let key = unsafe { *key };
+            let (prefix_len, data) = (key.prefix_len, key.data);
+            prefixes
+                .iter()
+                .position(|k| k.prefix_len == prefix_len && k.data == data)
+                .unwrap()
+                + 1
+        };
+        if next == prefixes.len() {
+            return sys_error(ENOENT);
+        }
+        let next_key = unsafe { attr.__bindgen_anon_2.__bindgen_anon_1.next_key } as *mut Key<u32>;
+        unsafe { *next_key = prefixes[next] };
+        Ok(0)
+    }
+
+    fn lookup(attr: &bpf_attr) -> SysResult {
+        let key = unsafe { *(attr.__bindgen_anon_2.key as *const Key<u32>) };
+        let (lookup_len, data) = (key.prefix_len, key.data);
+        // the value of each prefix is its length
+        let found = prefixes()
+            .iter()
+            .filter(|k| {
+                let (prefix_len, prefix) = (k.prefix_len, k.data);
+                prefix_len <= lookup_len
+                    && unsafe { prefix_matches(bytes_of(&prefix), bytes_of(&data), prefix_len) }
+            })
+            .map(|k| k.prefix_len)
+            .max();
+        match found {
+            Some(value) => {
+                let ret = unsafe { attr.__bindgen_anon_2.__bindgen_anon_1.value } as *mut u32;
+                unsafe { *ret = value };
+                Ok(0)
+            }
+            None => sys_error(ENOENT),
+        }
+    }
+
+    #[test]
+    fn test_longest_match() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_GET_NEXT_KEY,
+                attr,
+            } => get_next_key(attr),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => lookup(attr),
+            _ => sys_error(EFAULT),
+        });
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let trie = LpmTrie::<_, u32, u32>::new(&map).unwrap();
+
+        assert_eq!(
+            trie.longest_match(&ipv4(10, 1, 2, 3)).unwrap(),
+            Some((24, 24))
+        );
+        assert_eq!(
+            trie.longest_match(&ipv4(10, 1, 9, 9)).unwrap(),
+            Some((16, 16))
+        );
+        assert_eq!(
+            trie.longest_match(&ipv4(10, 9, 9, 9)).unwrap(),
+            Some((8, 8))
+        );
+        assert_eq!(trie.longest_match(&ipv4(11, 1, 2, 3)).unwrap(), None);
+    }
 }
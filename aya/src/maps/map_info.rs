@@ -0,0 +1,535 @@
+//! Information about loaded maps.
+use std::{ffi::CString, fmt, os::unix::io::RawFd, path::Path};
+
+use libc::ENOENT;
+use object::Endianness;
+
+use crate::{
+    generated::{bpf_map_info, bpf_map_type::*},
+    maps::{Map, MapError},
+    obj::btf::Btf,
+    sys::{
+        bpf_btf_get_fd_by_id, bpf_get_object, bpf_map_get_fd_by_id, bpf_map_get_info_by_fd,
+        bpf_map_get_next_id, btf_obj_get_data, ids,
+    },
+};
+
+// map types that are newer than the generated bindings
+const BPF_MAP_TYPE_USER_RINGBUF: u32 = 31;
+const BPF_MAP_TYPE_CGRP_STORAGE: u32 = 32;
+const BPF_MAP_TYPE_ARENA: u32 = 33;
+
+/// The type of a map, as defined by the `BPF_MAP_TYPE_*` constants of the
+/// kernel.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BpfMapType {
+    /// `BPF_MAP_TYPE_UNSPEC`
+    Unspec = BPF_MAP_TYPE_UNSPEC as u32,
+    /// `BPF_MAP_TYPE_HASH`
+    Hash = BPF_MAP_TYPE_HASH as u32,
+    /// `BPF_MAP_TYPE_ARRAY`
+    Array = BPF_MAP_TYPE_ARRAY as u32,
+    /// `BPF_MAP_TYPE_PROG_ARRAY`
+    ProgramArray = BPF_MAP_TYPE_PROG_ARRAY as u32,
+    /// `BPF_MAP_TYPE_PERF_EVENT_ARRAY`
+    PerfEventArray = BPF_MAP_TYPE_PERF_EVENT_ARRAY as u32,
+    /// `BPF_MAP_TYPE_PERCPU_HASH`
+    PerCpuHash = BPF_MAP_TYPE_PERCPU_HASH as u32,
+    /// `BPF_MAP_TYPE_PERCPU_ARRAY`
+    PerCpuArray = BPF_MAP_TYPE_PERCPU_ARRAY as u32,
+    /// `BPF_MAP_TYPE_STACK_TRACE`
+    StackTrace = BPF_MAP_TYPE_STACK_TRACE as u32,
+    /// `BPF_MAP_TYPE_CGROUP_ARRAY`
+    CgroupArray = BPF_MAP_TYPE_CGROUP_ARRAY as u32,
+    /// `BPF_MAP_TYPE_LRU_HASH`
+    LruHash = BPF_MAP_TYPE_LRU_HASH as u32,
+    /// `BPF_MAP_TYPE_LRU_PERCPU_HASH`
+    LruPerCpuHash = BPF_MAP_TYPE_LRU_PERCPU_HASH as u32,
+    /// `BPF_MAP_TYPE_LPM_TRIE`
+    LpmTrie = BPF_MAP_TYPE_LPM_TRIE as u32,
+    /// `BPF_MAP_TYPE_ARRAY_OF_MAPS`
+    ArrayOfMaps = BPF_MAP_TYPE_ARRAY_OF_MAPS as u32,
+    /// `BPF_MAP_TYPE_HASH_OF_MAPS`
+    HashOfMaps = BPF_MAP_TYPE_HASH_OF_MAPS as u32,
+    /// `BPF_MAP_TYPE_DEVMAP`
+    DevMap = BPF_MAP_TYPE_DEVMAP as u32,
+    /// `BPF_MAP_TYPE_SOCKMAP`
+    SockMap = BPF_MAP_TYPE_SOCKMAP as u32,
+    /// `BPF_MAP_TYPE_CPUMAP`
+    CpuMap = BPF_MAP_TYPE_CPUMAP as u32,
+    /// `BPF_MAP_TYPE_XSKMAP`
+    XskMap = BPF_MAP_TYPE_XSKMAP as u32,
+    /// `BPF_MAP_TYPE_SOCKHASH`
+    SockHash = BPF_MAP_TYPE_SOCKHASH as u32,
+    /// `BPF_MAP_TYPE_CGROUP_STORAGE`
+    CgroupStorage = BPF_MAP_TYPE_CGROUP_STORAGE as u32,
+    /// `BPF_MAP_TYPE_REUSEPORT_SOCKARRAY`
+    ReuseportSockArray = BPF_MAP_TYPE_REUSEPORT_SOCKARRAY as u32,
+    /// `BPF_MAP_TYPE_PERCPU_CGROUP_STORAGE`
+    PerCpuCgroupStorage = BPF_MAP_TYPE_PERCPU_CGROUP_STORAGE as u32,
+    /// `BPF_MAP_TYPE_QUEUE`
+    Queue = BPF_MAP_TYPE_QUEUE as u32,
+    /// `BPF_MAP_TYPE_STACK`
+    Stack = BPF_MAP_TYPE_STACK as u32,
+    /// `BPF_MAP_TYPE_SK_STORAGE`
+    SkStorage = BPF_MAP_TYPE_SK_STORAGE as u32,
+    /// `BPF_MAP_TYPE_DEVMAP_HASH`
+    DevMapHash = BPF_MAP_TYPE_DEVMAP_HASH as u32,
+    /// `BPF_MAP_TYPE_STRUCT_OPS`
+    StructOps = BPF_MAP_TYPE_STRUCT_OPS as u32,
+    /// `BPF_MAP_TYPE_RINGBUF`
+    RingBuf = BPF_MAP_TYPE_RINGBUF as u32,
+    /// `BPF_MAP_TYPE_INODE_STORAGE`
+    InodeStorage = BPF_MAP_TYPE_INODE_STORAGE as u32,
+    /// `BPF_MAP_TYPE_TASK_STORAGE`
+    TaskStorage = BPF_MAP_TYPE_TASK_STORAGE as u32,
+    /// `BPF_MAP_TYPE_BLOOM_FILTER`
+    BloomFilter = BPF_MAP_TYPE_BLOOM_FILTER as u32,
+    /// `BPF_MAP_TYPE_USER_RINGBUF`
+    UserRingBuf = BPF_MAP_TYPE_USER_RINGBUF,
+    /// `BPF_MAP_TYPE_CGRP_STORAGE`
+    CgrpStorage = BPF_MAP_TYPE_CGRP_STORAGE,
+    /// `BPF_MAP_TYPE_ARENA`
+    Arena = BPF_MAP_TYPE_ARENA,
+}
+
+impl BpfMapType {
+    const ALL: [BpfMapType; 34] = [
+        BpfMapType::Unspec,
+        BpfMapType::Hash,
+        BpfMapType::Array,
+        BpfMapType::ProgramArray,
+        BpfMapType::PerfEventArray,
+        BpfMapType::PerCpuHash,
+        BpfMapType::PerCpuArray,
+        BpfMapType::StackTrace,
+        BpfMapType::CgroupArray,
+        BpfMapType::LruHash,
+        BpfMapType::LruPerCpuHash,
+        BpfMapType::LpmTrie,
+        BpfMapType::ArrayOfMaps,
+        BpfMapType::HashOfMaps,
+        BpfMapType::DevMap,
+        BpfMapType::SockMap,
+        BpfMapType::CpuMap,
+        BpfMapType::XskMap,
+        BpfMapType::SockHash,
+        BpfMapType::CgroupStorage,
+        BpfMapType::ReuseportSockArray,
+        BpfMapType::PerCpuCgroupStorage,
+        BpfMapType::Queue,
+        BpfMapType::Stack,
+        BpfMapType::SkStorage,
+        BpfMapType::DevMapHash,
+        BpfMapType::StructOps,
+        BpfMapType::RingBuf,
+        BpfMapType::InodeStorage,
+        BpfMapType::TaskStorage,
+        BpfMapType::BloomFilter,
+        BpfMapType::UserRingBuf,
+        BpfMapType::CgrpStorage,
+        BpfMapType::Arena,
+    ];
+
+    /// Returns the map type with the given `BPF_MAP_TYPE_*` value, or `None`
+    /// if the value is unknown.
+    pub fn from_u32(value: u32) -> Option<BpfMapType> {
+        BpfMapType::ALL
+            .iter()
+            .copied()
+            .find(|ty| *ty as u32 == value)
+    }
+
+    /// Returns `true` if maps of this type store a value per CPU.
+    pub fn is_per_cpu(self) -> bool {
+        matches!(
+            self,
+            BpfMapType::PerCpuHash
+                | BpfMapType::PerCpuArray
+                | BpfMapType::LruPerCpuHash
+                | BpfMapType::PerCpuCgroupStorage
+        )
+    }
+
+    /// Returns `true` if the values of maps of this type can be read from
+    /// user space with `BPF_MAP_LOOKUP_ELEM`.
+    ///
+    /// Maps of file descriptors, like [`BpfMapType::PerfEventArray`], and
+    /// ring buffers can only be accessed by eBPF programs.
+    pub fn supports_lookup(self) -> bool {
+        !matches!(
+            self,
+            BpfMapType::Unspec
+                | BpfMapType::PerfEventArray
+                | BpfMapType::CgroupArray
+                | BpfMapType::XskMap
+                | BpfMapType::RingBuf
+                | BpfMapType::UserRingBuf
+                | BpfMapType::Arena
+        )
+    }
+}
+
+impl fmt::Display for BpfMapType {
+    /// Formats the map type the way `bpftool` does, for example `percpu_hash`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BpfMapType::Unspec => "unspec",
+            BpfMapType::Hash => "hash",
+            BpfMapType::Array => "array",
+            BpfMapType::ProgramArray => "prog_array",
+            BpfMapType::PerfEventArray => "perf_event_array",
+            BpfMapType::PerCpuHash => "percpu_hash",
+            BpfMapType::PerCpuArray => "percpu_array",
+            BpfMapType::StackTrace => "stack_trace",
+            BpfMapType::CgroupArray => "cgroup_array",
+            BpfMapType::LruHash => "lru_hash",
+            BpfMapType::LruPerCpuHash => "lru_percpu_hash",
+            BpfMapType::LpmTrie => "lpm_trie",
+            BpfMapType::ArrayOfMaps => "array_of_maps",
+            BpfMapType::HashOfMaps => "hash_of_maps",
+            BpfMapType::DevMap => "devmap",
+            BpfMapType::SockMap => "sockmap",
+            BpfMapType::CpuMap => "cpumap",
+            BpfMapType::XskMap => "xskmap",
+            BpfMapType::SockHash => "sockhash",
+            BpfMapType::CgroupStorage => "cgroup_storage",
+            BpfMapType::ReuseportSockArray => "reuseport_sockarray",
+            BpfMapType::PerCpuCgroupStorage => "percpu_cgroup_storage",
+            BpfMapType::Queue => "queue",
+            BpfMapType::Stack => "stack",
+            BpfMapType::SkStorage => "sk_storage",
+            BpfMapType::DevMapHash => "devmap_hash",
+            BpfMapType::StructOps => "struct_ops",
+            BpfMapType::RingBuf => "ringbuf",
+            BpfMapType::InodeStorage => "inode_storage",
+            BpfMapType::TaskStorage => "task_storage",
+            BpfMapType::BloomFilter => "bloom_filter",
+            BpfMapType::UserRingBuf => "user_ringbuf",
+            BpfMapType::CgrpStorage => "cgrp_storage",
+            BpfMapType::Arena => "arena",
+        })
+    }
+}
+
+/// Provides information about a loaded map, like name, id and type.
+pub struct MapInfo(pub(super) bpf_map_info);
+
+impl MapInfo {
+    pub(crate) fn from_fd(fd: RawFd) -> Result<MapInfo, MapError> {
+        let info = bpf_map_get_info_by_fd(fd).map_err(|io_error| MapError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            code: -1,
+            io_error,
+        })?;
+        Ok(MapInfo(info))
+    }
+
+    /// Returns information about the loaded map with the given id.
+    pub fn from_id(id: u32) -> Result<MapInfo, MapError> {
+        let fd = bpf_map_get_fd_by_id(id).map_err(|io_error| MapError::SyscallError {
+            call: "bpf_map_get_fd_by_id".to_owned(),
+            code: -1,
+            io_error,
+        })?;
+        let info = MapInfo::from_fd(fd);
+        unsafe { libc::close(fd) };
+        info
+    }
+
+    /// Returns information about the map pinned at `path` in bpffs.
+    pub fn from_pinned<P: AsRef<Path>>(path: P) -> Result<MapInfo, MapError> {
+        let path_string =
+            CString::new(path.as_ref().to_string_lossy().into_owned()).map_err(|e| {
+                MapError::InvalidPinPath {
+                    error: e.to_string(),
+                }
+            })?;
+        let fd =
+            bpf_get_object(&path_string).map_err(|(code, io_error)| MapError::SyscallError {
+                call: "bpf_obj_get".to_owned(),
+                code,
+                io_error,
+            })? as RawFd;
+        let info = MapInfo::from_fd(fd);
+        unsafe { libc::close(fd) };
+        info
+    }
+
+    /// The name of the map, limited to 16 bytes.
+    pub fn name(&self) -> &[u8] {
+        let length = self
+            .0
+            .name
+            .iter()
+            .rposition(|ch| *ch != 0)
+            .map(|pos| pos + 1)
+            .unwrap_or(0);
+
+        // the name field is a [c_char; 16] and c_char may be signed or
+        // unsigned depending on the platform
+        unsafe { std::slice::from_raw_parts(self.0.name.as_ptr() as *const _, length) }
+    }
+
+    /// The name of the map as a &str. If the name isn't valid unicode, None
+    /// is returned.
+    pub fn name_as_str(&self) -> Option<&str> {
+        std::str::from_utf8(self.name()).ok()
+    }
+
+    /// The id of the map. Each map has a unique id.
+    pub fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    /// The type of the map, or `None` if the kernel reports a type that is
+    /// unknown to aya. See [`map_type_raw`](MapInfo::map_type_raw).
+    pub fn map_type(&self) -> Option<BpfMapType> {
+        BpfMapType::from_u32(self.0.type_)
+    }
+
+    /// The `BPF_MAP_TYPE_*` value of the type of the map.
+    pub fn map_type_raw(&self) -> u32 {
+        self.0.type_
+    }
+
+    /// The size of the keys of the map, in bytes.
+    pub fn key_size(&self) -> u32 {
+        self.0.key_size
+    }
+
+    /// The size of the values of the map, in bytes.
+    pub fn value_size(&self) -> u32 {
+        self.0.value_size
+    }
+
+    /// The maximum number of entries of the map.
+    pub fn max_entries(&self) -> u32 {
+        self.0.max_entries
+    }
+
+    /// The `BPF_F_*` flags the map was created with.
+    pub fn map_flags(&self) -> u32 {
+        self.0.map_flags
+    }
+
+    /// Returns `true` if the map stores a value per CPU.
+    ///
+    /// See [`BpfMapType::is_per_cpu`].
+    pub fn is_per_cpu(&self) -> bool {
+        self.map_type().map(BpfMapType::is_per_cpu).unwrap_or(false)
+    }
+
+    /// Returns `true` if the values of the map can be read from user space.
+    ///
+    /// See [`BpfMapType::supports_lookup`].
+    pub fn supports_lookup(&self) -> bool {
+        self.map_type()
+            .map(BpfMapType::supports_lookup)
+            .unwrap_or(false)
+    }
+
+    /// Returns `true` if the values of the map contain a `struct bpf_timer`.
+    ///
+    /// The timers are found in the BTF the map was created with, which is
+    /// read from the kernel. Maps created without BTF for their values can't
+    /// contain timers, the verifier rejects the programs using them.
+    pub fn has_timer(&self) -> Result<bool, MapError> {
+        if self.0.btf_id == 0 || self.0.btf_value_type_id == 0 {
+            return Ok(false);
+        }
+
+        let btf_fd =
+            bpf_btf_get_fd_by_id(self.0.btf_id).map_err(|io_error| MapError::SyscallError {
+                call: "bpf_btf_get_fd_by_id".to_owned(),
+                code: -1,
+                io_error,
+            })?;
+        let data = btf_obj_get_data(btf_fd);
+        unsafe { libc::close(btf_fd) };
+        let data = data.map_err(|io_error| MapError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            code: -1,
+            io_error,
+        })?;
+
+        Btf::parse(&data, Endianness::default())
+            .and_then(|btf| btf.contains_struct(self.0.btf_value_type_id, "bpf_timer"))
+            .map_err(|error| MapError::BtfError {
+                name: self.name_as_str().unwrap_or_default().to_owned(),
+                error,
+            })
+    }
+}
+
+/// Returns information about all the maps currently loaded in the kernel.
+///
+/// The maps are enumerated by id, including the maps created by other
+/// processes. Maps that are released while iterating are skipped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use aya::maps::loaded_maps;
+///
+/// for info in loaded_maps() {
+///     let info = info?;
+///     println!("{}: {:?} {:?}", info.id(), info.name_as_str(), info.map_type());
+/// }
+/// # Ok::<(), aya::maps::MapError>(())
+/// ```
+pub fn loaded_maps() -> impl Iterator<Item = Result<MapInfo, MapError>> {
+    ids(bpf_map_get_next_id).filter_map(|id| {
+        let info = id
+            .map_err(|io_error| MapError::SyscallError {
+                call: "bpf_map_get_next_id".to_owned(),
+                code: -1,
+                io_error,
+            })
+            .and_then(MapInfo::from_id);
+        match info {
+            // the map was released after its id was returned
+            Err(MapError::SyscallError { io_error, .. })
+                if io_error.raw_os_error() == Some(ENOENT) =>
+            {
+                None
+            }
+            info => Some(info),
+        }
+    })
+}
+
+impl Map {
+    /// Returns information about the map as reported by the kernel.
+    ///
+    /// Returns [`MapError::NotCreated`] if the map hasn't been created yet.
+    pub fn info(&self) -> Result<MapInfo, MapError> {
+        MapInfo::from_fd(self.fd_or_err()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use libc::EFAULT;
+
+    use crate::{
+        bpf_map_def,
+        generated::{bpf_btf_info, bpf_cmd, btf_member},
+        obj::{self, btf::BtfType},
+        sys::{override_syscall, Syscall},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_from_u32() {
+        for ty in BpfMapType::ALL.iter() {
+            assert_eq!(BpfMapType::from_u32(*ty as u32), Some(*ty));
+        }
+        assert_eq!(
+            BpfMapType::from_u32(BPF_MAP_TYPE_PERCPU_HASH as u32),
+            Some(BpfMapType::PerCpuHash)
+        );
+        assert_eq!(BpfMapType::from_u32(1000), None);
+        assert_eq!(BpfMapType::PerCpuHash.to_string(), "percpu_hash");
+    }
+
+    #[test]
+    fn test_info() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_map_info) };
+                info.type_ = BPF_MAP_TYPE_PERCPU_ARRAY as u32;
+                info.id = 7;
+                info.max_entries = 16;
+                info.name[..3].copy_from_slice(&[b'f' as _, b'o' as _, b'o' as _]);
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let map = Map {
+            obj: obj::Map {
+                def: bpf_map_def {
+                    map_type: BPF_MAP_TYPE_PERCPU_ARRAY as u32,
+                    ..Default::default()
+                },
+                section_index: 0,
+                data: Vec::new(),
+                kind: obj::MapKind::Other,
+            },
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let info = map.info().unwrap();
+        assert_eq!(info.id(), 7);
+        assert_eq!(info.name_as_str(), Some("foo"));
+        assert_eq!(info.map_type(), Some(BpfMapType::PerCpuArray));
+        assert_eq!(info.max_entries(), 16);
+        assert!(info.is_per_cpu());
+        assert!(info.supports_lookup());
+    }
+
+    // returns BTF defining `struct elem { struct bpf_timer timer; }` and the
+    // id of `struct elem`
+    fn timer_btf() -> (Btf, u32) {
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("bpf_timer".to_string());
+        let timer_type_id = btf.add_type(BtfType::new_struct(name_offset, Vec::new(), 16));
+        let members = vec![btf_member {
+            name_off: btf.add_string("timer".to_string()),
+            type_: timer_type_id,
+            offset: 0,
+        }];
+        let name_offset = btf.add_string("elem".to_string());
+        let elem_type_id = btf.add_type(BtfType::new_struct(name_offset, members, 16));
+        (btf, elem_type_id)
+    }
+
+    #[test]
+    fn test_has_timer() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_BTF_GET_FD_BY_ID,
+                attr,
+            } if unsafe { attr.__bindgen_anon_6.__bindgen_anon_1.btf_id } == 3 => Ok(10_000),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } if unsafe { attr.info.bpf_fd } == 10_000 => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_btf_info) };
+                let data = timer_btf().0.to_bytes();
+                if info.btf_size as usize >= data.len() {
+                    unsafe {
+                        std::slice::from_raw_parts_mut(info.btf as *mut u8, data.len())
+                            .copy_from_slice(&data)
+                    };
+                }
+                info.btf_size = data.len() as u32;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut info = unsafe { std::mem::zeroed::<bpf_map_info>() };
+        assert!(!MapInfo(info).has_timer().unwrap());
+
+        info.btf_id = 3;
+        info.btf_value_type_id = timer_btf().1;
+        assert!(MapInfo(info).has_timer().unwrap());
+
+        info.btf_id = 4;
+        assert!(matches!(
+            MapInfo(info).has_timer(),
+            Err(MapError::SyscallError { call, .. }) if call == "bpf_btf_get_fd_by_id"
+        ));
+    }
+}
@@ -33,15 +33,30 @@
 //! versa. Because of that, all map values must be plain old data and therefore
 //! implement the [Pod] trait.
 use std::{
-    convert::TryFrom, ffi::CString, io, marker::PhantomData, mem, ops::Deref, os::unix::io::RawFd,
-    path::Path, ptr,
+    convert::TryFrom,
+    ffi::CString,
+    io,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    os::unix::io::RawFd,
+    path::Path,
+    ptr,
 };
 use thiserror::Error;
 
 use crate::{
-    generated::bpf_map_type,
-    obj,
-    sys::{bpf_create_map, bpf_get_object, bpf_map_get_next_key, bpf_pin_object},
+    bpf::load_btf,
+    bpf_map_def,
+    generated::{bpf_map_type, BPF_ANY, BPF_EXIST, BPF_F_LOCK, BPF_NOEXIST},
+    obj::{
+        self,
+        btf::{Btf, BtfError},
+    },
+    sys::{
+        bpf_create_map, bpf_get_object, bpf_map_freeze, bpf_map_get_fd_by_id,
+        bpf_map_get_info_by_fd, bpf_map_get_next_key, bpf_pin_object,
+    },
     util::nr_cpus,
     Pod,
 };
@@ -49,22 +64,32 @@ use crate::{
 mod map_lock;
 
 pub mod array;
+pub mod cgroup_storage;
 pub mod hash_map;
 pub mod lpm_trie;
+pub mod map_info;
 pub mod perf;
 pub mod queue;
+pub mod ring_buf;
+pub mod sk_storage;
 pub mod sock;
 pub mod stack;
 pub mod stack_trace;
+pub mod xdp;
 
 pub use array::{Array, PerCpuArray, ProgramArray};
-pub use hash_map::{HashMap, PerCpuHashMap};
+pub use cgroup_storage::{CgroupStorage, CgroupStorageKey};
+pub use hash_map::{HashMap, PerCpuHashMap, SpinLockMap};
+pub use map_info::{loaded_maps, BpfMapType, MapInfo};
 pub use map_lock::*;
 pub use perf::PerfEventArray;
 pub use queue::Queue;
-pub use sock::{SockHash, SockMap};
+pub use ring_buf::{ConsumeDecision, RingBuf, RingBufRecord};
+pub use sk_storage::SkStorageMap;
+pub use sock::{SockHash, SockMap, SockMapLink};
 pub use stack::Stack;
 pub use stack_trace::StackTraceMap;
+pub use xdp::{CpuMap, DevMap};
 
 #[derive(Error, Debug)]
 /// Errors occuring from working with Maps
@@ -174,10 +199,21 @@ pub enum MapError {
     #[error("element not found")]
     ElementNotFound,
 
+    /// The map is frozen and can't be updated from user space
+    #[error("the map is frozen")]
+    Frozen,
+
     /// Progam Not Loaded
     #[error("the program is not loaded")]
     ProgramNotLoaded,
 
+    /// The program set in a map entry has the wrong type
+    #[error("invalid program type {prog_type}")]
+    InvalidProgramType {
+        /// The program type
+        prog_type: u32,
+    },
+
     /// Syscall failed
     #[error("the `{call}` syscall failed with code {code}")]
     SyscallError {
@@ -190,6 +226,30 @@ pub enum MapError {
         io_error: io::Error,
     },
 
+    /// A batch operation failed after processing some of the elements
+    #[error("the `{call}` syscall failed with code {code} after processing {count} elements")]
+    BatchError {
+        /// Syscall Name
+        call: String,
+        /// Number of elements processed before the failure
+        count: usize,
+        /// Error code
+        code: libc::c_long,
+        #[source]
+        /// Original io::Error
+        io_error: io::Error,
+    },
+
+    /// Loading the BTF of the map failed
+    #[error("failed to load the BTF of map `{name}`")]
+    BtfError {
+        /// Map name
+        name: String,
+        #[source]
+        /// Original BtfError
+        error: BtfError,
+    },
+
     /// Map is borrowed mutably
     #[error("map `{name}` is borrowed mutably")]
     BorrowError {
@@ -205,6 +265,111 @@ pub enum MapError {
     },
 }
 
+bitflags! {
+    /// Flags passed to map update operations.
+    #[derive(Default)]
+    pub struct MapUpdateFlags: u64 {
+        /// Create a new element or update an existing one.
+        const ANY = BPF_ANY as u64;
+        /// Create a new element only if it doesn't exist.
+        const NOEXIST = BPF_NOEXIST as u64;
+        /// Update an existing element only.
+        const EXIST = BPF_EXIST as u64;
+        /// Update a spin-lock annotated element while holding its lock.
+        const LOCK = BPF_F_LOCK as u64;
+    }
+}
+
+/// Options used when creating a map.
+///
+/// # Example
+///
+/// ```no_run
+/// use aya::{maps::MapCreateOptions, BpfLoader};
+///
+/// let bpf = BpfLoader::new()
+///     .map_create_options("RX_QUEUES", MapCreateOptions::new().numa_node(1))
+///     .load_file("file.o")?;
+/// # Ok::<(), aya::BpfError>(())
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct MapCreateOptions {
+    pub(crate) numa_node: Option<u32>,
+    pub(crate) inner_map_fd: Option<RawFd>,
+    pub(crate) token_fd: Option<RawFd>,
+    // the BTF of the object loaded by BpfLoader
+    pub(crate) btf_fd: Option<RawFd>,
+    // the BTF set with with_btf, loaded when the map is created
+    pub(crate) btf_data: Option<Vec<u8>>,
+    pub(crate) btf_key_type_id: u32,
+    pub(crate) btf_value_type_id: u32,
+}
+
+impl MapCreateOptions {
+    /// Creates a new set of options using the kernel defaults.
+    pub fn new() -> MapCreateOptions {
+        MapCreateOptions::default()
+    }
+
+    /// Allocates the map on the NUMA node `id`.
+    ///
+    /// This sets `BPF_F_NUMA_NODE` on the map. The kernel silently ignores the
+    /// node on non-NUMA systems.
+    pub fn numa_node(mut self, id: u32) -> MapCreateOptions {
+        self.numa_node = Some(id);
+        self
+    }
+
+    /// Uses `fd` as the prototype for the maps stored in a map-in-map.
+    pub fn inner_map_fd(mut self, fd: RawFd) -> MapCreateOptions {
+        self.inner_map_fd = Some(fd);
+        self
+    }
+
+    /// Annotates the map with the types named `key_type_name` and
+    /// `value_type_name` in `btf`.
+    ///
+    /// Features like `bpf_spin_lock` and `bpf_timer` values require the
+    /// kernel to know the BTF types of the map. [`BpfLoader`](crate::BpfLoader)
+    /// already annotates the maps declared with `BPF_ANNOTATE_KV_PAIR` using
+    /// the BTF of the object, this overrides those annotations. `btf` is
+    /// loaded into the kernel when the map is created, so it must only
+    /// contain types supported by the running kernel. The kernel fails to
+    /// create the map if the sizes of the types don't match the key and
+    /// value sizes of the map.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BtfError::UnknownBtfTypeName`] if `btf` doesn't contain a
+    /// struct, union, typedef, integer, enum or float type with one of the
+    /// names.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{maps::MapCreateOptions, Btf, BpfLoader, Endianness};
+    ///
+    /// let btf = Btf::parse_file("types.btf", Endianness::default())?;
+    /// let options = MapCreateOptions::new().with_btf(&btf, "u32", "struct_with_lock")?;
+    /// let bpf = BpfLoader::new()
+    ///     .map_create_options("LOCKED", options)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn with_btf(
+        mut self,
+        btf: &Btf,
+        key_type_name: &str,
+        value_type_name: &str,
+    ) -> Result<MapCreateOptions, BtfError> {
+        self.btf_key_type_id = btf.id_by_type_name(key_type_name)?;
+        self.btf_value_type_id = btf.id_by_type_name(value_type_name)?;
+        self.btf_data = Some(btf.to_bytes());
+        self.btf_fd = None;
+        Ok(self)
+    }
+}
+
 /// A generic handle to a BPF map.
 ///
 /// You should never need to use this unless you're implementing a new map type.
@@ -214,23 +379,88 @@ pub struct Map {
     pub(crate) fd: Option<RawFd>,
     /// Indicates if this map has been pinned to bpffs
     pub pinned: bool,
+    pub(crate) frozen: bool,
+    pub(crate) name: Option<String>,
 }
 
 impl Map {
+    /// Creates the BTF map `map_name` defined in the `.maps` section of
+    /// `btf`, eg the BTF of a compiled eBPF object, without loading the
+    /// object.
+    ///
+    /// The type, size and flags of the map are read from the definition of
+    /// its variable, a struct declared with the `__uint()` and `__type()`
+    /// macros of libbpf. The map is created as is: it isn't pinned even if
+    /// the definition sets `pinning`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::BtfError`] if the definition can't be found or
+    /// parsed, and [`MapError::CreateError`] if creating the map fails.
+    pub fn from_btf_map_def(btf: &Btf, map_name: &str) -> Result<Map, MapError> {
+        let def = btf
+            .btf_map_def(map_name)
+            .map_err(|error| MapError::BtfError {
+                name: map_name.into(),
+                error,
+            })?;
+        let mut map = Map {
+            obj: obj::Map {
+                def,
+                section_index: 0,
+                data: Vec::new(),
+                kind: obj::MapKind::Other,
+            },
+            fd: None,
+            pinned: false,
+            frozen: false,
+            name: Some(map_name.to_owned()),
+        };
+        map.create(map_name)?;
+        Ok(map)
+    }
+
     /// Creates a new map with the provided `name`
     pub fn create(&mut self, name: &str) -> Result<RawFd, MapError> {
+        self.create_with_options(name, &MapCreateOptions::default())
+    }
+
+    /// Creates a new map with the provided `name` and creation `options`.
+    pub fn create_with_options(
+        &mut self,
+        name: &str,
+        options: &MapCreateOptions,
+    ) -> Result<RawFd, MapError> {
         if self.fd.is_some() {
             return Err(MapError::AlreadyCreated { name: name.into() });
         }
 
         let c_name = CString::new(name).map_err(|_| MapError::InvalidName { name: name.into() })?;
 
-        let fd = bpf_create_map(&c_name, &self.obj.def).map_err(|(code, io_error)| {
-            MapError::CreateError {
-                name: name.into(),
-                code,
-                io_error,
-            }
+        // the map keeps a reference to its BTF, so the BTF set with
+        // `MapCreateOptions::with_btf` is only loaded for the creation
+        let loaded_btf_fd = match &options.btf_data {
+            Some(data) => Some(load_btf(data.clone(), options.token_fd).map_err(|error| {
+                MapError::BtfError {
+                    name: name.into(),
+                    error,
+                }
+            })?),
+            None => None,
+        };
+        let ret = bpf_create_map(
+            &c_name,
+            &self.obj.def,
+            options,
+            loaded_btf_fd.or(options.btf_fd),
+        );
+        if let Some(fd) = loaded_btf_fd {
+            unsafe { libc::close(fd) };
+        }
+        let fd = ret.map_err(|(code, io_error)| MapError::CreateError {
+            name: name.into(),
+            code,
+            io_error,
         })? as RawFd;
 
         self.fd = Some(fd);
@@ -261,20 +491,240 @@ impl Map {
             io_error,
         })? as RawFd;
 
+        // the typed wrappers check the key and value sizes against the
+        // definition in the object, so make sure that the pinned map matches it
+        match self.check_map_info(fd) {
+            // the pinned map may have been created with a different size
+            Ok(max_entries) => self.obj.def.max_entries = max_entries,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+        }
         self.fd = Some(fd);
 
         Ok(fd)
     }
 
+    // returns the max_entries of the map
+    fn check_map_info(&self, fd: RawFd) -> Result<u32, MapError> {
+        let info = bpf_map_get_info_by_fd(fd).map_err(|io_error| MapError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            code: -1,
+            io_error,
+        })?;
+        let def = &self.obj.def;
+        if info.type_ != def.map_type {
+            return Err(MapError::InvalidMapType {
+                map_type: info.type_,
+            });
+        }
+        if info.key_size != def.key_size {
+            return Err(MapError::InvalidKeySize {
+                size: info.key_size as usize,
+                expected: def.key_size as usize,
+            });
+        }
+        if info.value_size != def.value_size {
+            return Err(MapError::InvalidValueSize {
+                size: info.value_size as usize,
+                expected: def.value_size as usize,
+            });
+        }
+        Ok(info.max_entries)
+    }
+
+    /// Returns the name of the map.
+    ///
+    /// Maps loaded by [`Bpf`](crate::Bpf) have the name of the map in the
+    /// object file. Maps opened with [`Map::from_id`] have the name reported
+    /// by the kernel, which is truncated to 15 bytes.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     /// Returns the [`bpf_map_type`] of this map
     pub fn map_type(&self) -> Result<bpf_map_type, MapError> {
         bpf_map_type::try_from(self.obj.def.map_type)
     }
 
-    pub(crate) fn fd_or_err(&self) -> Result<RawFd, MapError> {
+    /// Returns the maximum number of entries of the map.
+    ///
+    /// The value comes from the map definition, or from the kernel for maps
+    /// opened from a pin or an id, and is cached: no syscall is made.
+    pub fn max_entries(&self) -> u32 {
+        self.obj.def.max_entries
+    }
+
+    /// Returns the size of the keys of the map, in bytes.
+    pub fn key_size(&self) -> u32 {
+        self.obj.def.key_size
+    }
+
+    /// Returns the size of the values of the map, in bytes.
+    pub fn value_size(&self) -> u32 {
+        self.obj.def.value_size
+    }
+
+    /// Returns the file descriptor of the map, or [`MapError::NotCreated`] if
+    /// the map hasn't been created.
+    ///
+    /// This is a low level escape hatch to pass the map to code that works
+    /// with the raw fd, like C libraries. The fd is owned by the map and is
+    /// closed when the map is dropped. The typed maps, like
+    /// [`HashMap`](crate::maps::HashMap), implement [`AsRawFd`] instead.
+    ///
+    /// [`AsRawFd`]: std::os::unix::io::AsRawFd
+    pub fn fd_or_err(&self) -> Result<RawFd, MapError> {
         self.fd.ok_or(MapError::NotCreated)
     }
 
+    /// Returns the fd of the map, or [`MapError::Frozen`] if the map can't be
+    /// updated from user space.
+    pub(crate) fn writable_fd_or_err(&self) -> Result<RawFd, MapError> {
+        if self.frozen {
+            return Err(MapError::Frozen);
+        }
+        self.fd_or_err()
+    }
+
+    /// Freezes the map, making it read-only from user space.
+    ///
+    /// eBPF programs can still update a frozen map. Once the map is frozen,
+    /// the update and delete operations of the typed maps return
+    /// [`MapError::Frozen`] without issuing any syscall.
+    ///
+    /// Freezing is permanent: it applies to the map itself, not to the file
+    /// descriptor, so closing the fd and reopening the map - for example from
+    /// its pinned path in bpffs - doesn't unfreeze it.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.2.
+    pub fn freeze(&mut self) -> Result<(), MapError> {
+        let fd = self.fd_or_err()?;
+        bpf_map_freeze(fd).map_err(|(code, io_error)| MapError::SyscallError {
+            call: "bpf_map_freeze".to_owned(),
+            code,
+            io_error,
+        })?;
+        self.frozen = true;
+        Ok(())
+    }
+
+    /// Returns `true` if the map has been frozen with [`freeze`](Self::freeze).
+    ///
+    /// The `.rodata` maps of eBPF programs are frozen once they're loaded.
+    /// The kernel doesn't report whether a map is frozen, so this returns
+    /// `false` for a frozen map reopened from bpffs. Updating such a map fails
+    /// with [`MapError::SyscallError`] instead.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Returns an owned handle to the same map.
+    ///
+    /// The file descriptor of the map is duplicated, so the returned handle
+    /// isn't tied to the lifetime of the [`Bpf`](crate::Bpf) instance or of
+    /// the [`MapRef`] or [`MapRefMut`] it was cloned from. The duplicated file
+    /// descriptor is closed when the [`OwnedMap`] is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let bpf = aya::Bpf::load(&[])?;
+    /// use aya::maps::HashMap;
+    /// use std::convert::TryFrom;
+    ///
+    /// let map = bpf.map("COUNTERS")?.try_clone()?;
+    /// let counters = HashMap::<_, u32, u64>::try_from(map)?;
+    /// // counters can now be moved into a task
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn try_clone(&self) -> Result<OwnedMap, MapError> {
+        let fd = self.fd_or_err()?;
+        let new_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if new_fd < 0 {
+            return Err(MapError::SyscallError {
+                call: "fcntl".to_owned(),
+                code: new_fd as libc::c_long,
+                io_error: io::Error::last_os_error(),
+            });
+        }
+
+        Ok(OwnedMap {
+            map: Map {
+                obj: self.obj.clone(),
+                fd: Some(new_fd),
+                pinned: self.pinned,
+                frozen: self.frozen,
+                name: self.name.clone(),
+            },
+        })
+    }
+
+    /// Opens the loaded map with the given id.
+    ///
+    /// The definition of the map is queried from the kernel, so the returned
+    /// handle can be converted to the typed map matching the type, key and
+    /// value sizes of the map, like the maps of a [`Bpf`](crate::Bpf)
+    /// instance. Opening a map by id requires `CAP_SYS_ADMIN`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use aya::maps::{loaded_maps, HashMap, Map};
+    /// use std::convert::TryFrom;
+    ///
+    /// for info in loaded_maps() {
+    ///     let info = info?;
+    ///     if info.name_as_str() == Some("COUNTERS") {
+    ///         let counters = HashMap::<_, u32, u64>::try_from(Map::from_id(info.id())?)?;
+    ///     }
+    /// }
+    /// # Ok::<(), aya::maps::MapError>(())
+    /// ```
+    pub fn from_id(id: u32) -> Result<OwnedMap, MapError> {
+        let fd = bpf_map_get_fd_by_id(id).map_err(|io_error| MapError::SyscallError {
+            call: "bpf_map_get_fd_by_id".to_owned(),
+            code: -1,
+            io_error,
+        })?;
+        let info = match bpf_map_get_info_by_fd(fd) {
+            Ok(info) => info,
+            Err(io_error) => {
+                unsafe { libc::close(fd) };
+                return Err(MapError::SyscallError {
+                    call: "bpf_obj_get_info_by_fd".to_owned(),
+                    code: -1,
+                    io_error,
+                });
+            }
+        };
+
+        Ok(OwnedMap {
+            map: Map {
+                obj: obj::Map {
+                    def: bpf_map_def {
+                        map_type: info.type_,
+                        key_size: info.key_size,
+                        value_size: info.value_size,
+                        max_entries: info.max_entries,
+                        map_flags: info.map_flags,
+                        ..Default::default()
+                    },
+                    section_index: 0,
+                    data: Vec::new(),
+                    kind: obj::MapKind::Other,
+                },
+                fd: Some(fd),
+                pinned: false,
+                frozen: false,
+                name: MapInfo(info).name_as_str().map(String::from),
+            },
+        })
+    }
+
     pub(crate) fn pin<P: AsRef<Path>>(&mut self, name: &str, path: P) -> Result<(), MapError> {
         if self.pinned {
             return Err(MapError::AlreadyPinned { name: name.into() });
@@ -305,6 +755,29 @@ impl Drop for Map {
     }
 }
 
+/// An owned BPF map, created with [`Map::try_clone`] or [`Map::from_id`].
+///
+/// Unlike [`MapRef`] and [`MapRefMut`], an `OwnedMap` doesn't borrow the map
+/// from a [`Bpf`](crate::Bpf) instance and can be converted to any typed map.
+#[derive(Debug)]
+pub struct OwnedMap {
+    map: Map,
+}
+
+impl Deref for OwnedMap {
+    type Target = Map;
+
+    fn deref(&self) -> &Map {
+        &self.map
+    }
+}
+
+impl DerefMut for OwnedMap {
+    fn deref_mut(&mut self) -> &mut Map {
+        &mut self.map
+    }
+}
+
 /// An iterable map
 pub trait IterableMap<K: Pod, V> {
     /// Get a generic map handle
@@ -549,8 +1022,9 @@ mod tests {
     use libc::EFAULT;
 
     use crate::{
-        bpf_map_def,
-        generated::{bpf_cmd, bpf_map_type::BPF_MAP_TYPE_HASH},
+        generated::{
+            bpf_cmd, bpf_map_info, bpf_map_type::BPF_MAP_TYPE_HASH, BPF_F_NUMA_NODE, BPF_F_TOKEN_FD,
+        },
         obj::MapKind,
         sys::{override_syscall, Syscall},
     };
@@ -577,6 +1051,8 @@ mod tests {
             obj: new_obj_map(),
             fd: None,
             pinned: false,
+            frozen: false,
+            name: None,
         }
     }
 
@@ -599,6 +1075,68 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_create_with_inner_map() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_CREATE,
+                attr,
+            } if unsafe { attr.__bindgen_anon_1.inner_map_fd } == 24 => Ok(42),
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut map = new_map();
+        let options = MapCreateOptions::new().inner_map_fd(24);
+        assert!(matches!(map.create_with_options("foo", &options), Ok(42)));
+        assert_eq!(map.fd, Some(42));
+    }
+
+    #[test]
+    fn test_create_with_numa_node() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_CREATE,
+                attr,
+            } if unsafe {
+                attr.__bindgen_anon_1.map_flags & BPF_F_NUMA_NODE != 0
+                    && attr.__bindgen_anon_1.numa_node == 1
+            } =>
+            {
+                Ok(42)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut map = new_map();
+        let options = MapCreateOptions::new().numa_node(1);
+        assert!(matches!(map.create_with_options("foo", &options), Ok(42)));
+        assert_eq!(map.fd, Some(42));
+    }
+
+    #[test]
+    fn test_create_with_token() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_CREATE,
+                attr,
+            } if unsafe {
+                attr.__bindgen_anon_1.map_flags & BPF_F_TOKEN_FD != 0
+                    && attr.__bindgen_anon_1.map_token_fd == 7
+            } =>
+            {
+                Ok(42)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut map = new_map();
+        let options = MapCreateOptions {
+            token_fd: Some(7),
+            ..Default::default()
+        };
+        assert!(matches!(map.create_with_options("foo", &options), Ok(42)));
+    }
+
     #[test]
     fn test_create_failed() {
         override_syscall(|_| Err((-42, io::Error::from_raw_os_error(EFAULT))));
@@ -618,4 +1156,163 @@ mod tests {
         }
         assert_eq!(map.fd, None);
     }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_open_pinned_size_mismatch() {
+        override_syscall(|call| match call {
+            // an fd that is surely not open, so closing it is harmless
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET,
+                ..
+            } => Ok(10_000),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_map_info) };
+                info.type_ = BPF_MAP_TYPE_HASH as u32;
+                info.key_size = 4;
+                info.value_size = 8;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut map = new_map();
+        assert!(matches!(
+            map.open_pinned("foo", "/sys/fs/bpf"),
+            Err(MapError::InvalidValueSize {
+                size: 8,
+                expected: 4
+            })
+        ));
+        assert_eq!(map.fd, None);
+    }
+
+    #[test]
+    fn test_maps_are_send_sync() {
+        assert_send_sync::<Map>();
+        assert_send_sync::<MapRef>();
+        assert_send_sync::<MapRefMut>();
+        assert_send_sync::<OwnedMap>();
+        assert_send_sync::<HashMap<MapRefMut, u32, u32>>();
+        assert_send_sync::<PerCpuHashMap<MapRefMut, u32, u32>>();
+        assert_send_sync::<Array<MapRefMut, u32>>();
+        assert_send_sync::<PerCpuArray<MapRefMut, u32>>();
+        assert_send_sync::<ProgramArray<MapRefMut>>();
+        assert_send_sync::<PerfEventArray<MapRefMut>>();
+        assert_send_sync::<perf::PerfEventArrayBuffer<MapRefMut>>();
+        assert_send_sync::<Queue<MapRefMut, u32>>();
+        assert_send_sync::<Stack<MapRefMut, u32>>();
+        assert_send_sync::<StackTraceMap<MapRefMut>>();
+        assert_send_sync::<SockMap<MapRefMut>>();
+        assert_send_sync::<SockHash<MapRefMut, u32>>();
+        assert_send_sync::<SkStorageMap<MapRefMut, u32>>();
+        assert_send_sync::<CgroupStorage<MapRefMut, u32>>();
+        assert_send_sync::<lpm_trie::LpmTrie<MapRefMut, u32, u32>>();
+        assert_send_sync::<CpuMap<MapRefMut>>();
+        assert_send_sync::<DevMap<MapRefMut>>();
+    }
+
+    #[test]
+    fn test_map_ref_across_threads() {
+        let lock = MapLock::new(Map {
+            obj: new_obj_map(),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        });
+        let map = HashMap::<_, u32, u32>::try_from(lock.try_write().ok().unwrap()).unwrap();
+
+        // the map is locked until the handle is dropped by the other thread
+        assert!(lock.try_read().is_err());
+        std::thread::spawn(move || drop(map)).join().unwrap();
+        assert!(lock.try_write().is_ok());
+    }
+
+    #[test]
+    fn test_try_clone() {
+        use std::{fs::File, os::unix::io::IntoRawFd};
+
+        assert!(matches!(new_map().try_clone(), Err(MapError::NotCreated)));
+
+        let fd = File::open("/dev/null").unwrap().into_raw_fd();
+        let map = Map {
+            obj: new_obj_map(),
+            fd: Some(fd),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let owned = map.try_clone().unwrap();
+        assert!(owned.fd.is_some());
+        assert_ne!(owned.fd, map.fd);
+        assert_eq!(owned.obj.def, map.obj.def);
+
+        // the clone stays valid after the original is dropped
+        drop(map);
+        let owned_fd = owned.fd.unwrap();
+        assert!(unsafe { libc::fcntl(owned_fd, libc::F_GETFD) } >= 0);
+        let hm = HashMap::<_, u32, u32>::try_from(owned).unwrap();
+        assert!(hm.try_clone().is_ok());
+    }
+    #[test]
+    fn test_freeze() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_FREEZE,
+                ..
+            } => Ok(0),
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut map = new_map();
+        assert!(matches!(map.freeze(), Err(MapError::NotCreated)));
+
+        map.fd = Some(42);
+        assert!(!map.is_frozen());
+        map.freeze().unwrap();
+        assert!(map.is_frozen());
+
+        // updates are rejected without reaching the (failing) syscall
+        let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
+        assert!(matches!(hm.insert(1, 42, 0), Err(MapError::Frozen)));
+        assert!(matches!(hm.remove(&1), Err(MapError::Frozen)));
+    }
+
+    #[test]
+    fn test_from_id() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_GET_FD_BY_ID,
+                attr,
+            } => Ok(10_000 + unsafe { attr.__bindgen_anon_6.__bindgen_anon_1.map_id } as i64),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_map_info) };
+                info.id = unsafe { attr.info.bpf_fd } - 10_000;
+                info.type_ = BPF_MAP_TYPE_HASH as u32;
+                info.key_size = 4;
+                info.value_size = 8;
+                info.max_entries = 16;
+                info.name[..3].copy_from_slice(&[b'f' as _, b'o' as _, b'o' as _]);
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let map = Map::from_id(7).unwrap();
+        assert_eq!(map.fd, Some(10_007));
+        assert_eq!(map.name(), Some("foo"));
+        assert_eq!(map.info().unwrap().id(), 7);
+        assert_eq!(map.max_entries(), 16);
+        assert_eq!(map.key_size(), 4);
+        assert_eq!(map.value_size(), 8);
+        assert!(HashMap::<_, u32, u64>::try_from(map).is_ok());
+    }
 }
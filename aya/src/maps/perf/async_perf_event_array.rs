@@ -13,7 +13,7 @@ use tokio::io::unix::AsyncFd;
 
 use crate::maps::{
     perf::{Events, PerfBufferError, PerfEventArray, PerfEventArrayBuffer},
-    Map, MapError, MapRefMut,
+    Map, MapError, MapRefMut, OwnedMap,
 };
 
 /// A `Future` based map that can be used to receive events from eBPF programs using the linux
@@ -198,6 +198,12 @@ impl<T: DerefMut<Target = Map>> AsyncPerfEventArrayBuffer<T> {
     }
 }
 
+impl<T: DerefMut<Target = Map>> AsRawFd for AsyncPerfEventArray<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.perf_map.as_raw_fd()
+    }
+}
+
 impl TryFrom<MapRefMut> for AsyncPerfEventArray<MapRefMut> {
     type Error = MapError;
 
@@ -205,3 +211,11 @@ impl TryFrom<MapRefMut> for AsyncPerfEventArray<MapRefMut> {
         AsyncPerfEventArray::new(a)
     }
 }
+
+impl TryFrom<OwnedMap> for AsyncPerfEventArray<OwnedMap> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<AsyncPerfEventArray<OwnedMap>, MapError> {
+        AsyncPerfEventArray::new(a)
+    }
+}
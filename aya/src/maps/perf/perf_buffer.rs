@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     ffi::c_void,
     io, mem,
     os::unix::io::{AsRawFd, RawFd},
@@ -15,7 +16,9 @@ use crate::{
         perf_event_header, perf_event_mmap_page,
         perf_event_type::{PERF_RECORD_LOST, PERF_RECORD_SAMPLE},
     },
+    maps::stack_trace::{StackFrame, UserSymbolizer},
     sys::{perf_event_ioctl, perf_event_open_bpf},
+    util::kernel_symbols,
     PERF_EVENT_IOC_DISABLE, PERF_EVENT_IOC_ENABLE,
 };
 
@@ -79,11 +82,126 @@ pub struct Events {
     pub lost: usize,
 }
 
+/// A sample read along with the callchain of the program that sent it.
+///
+/// Returned by
+/// [`PerfEventArrayBuffer::read_records`](crate::maps::perf::PerfEventArrayBuffer::read_records)
+/// for buffers opened with
+/// [`PerfEventArray::open_buffer_with_stack_trace`](crate::maps::PerfEventArray::open_buffer_with_stack_trace).
+#[derive(Debug, Default)]
+pub struct PerfEventRecord {
+    /// The data sent by the program.
+    pub data: BytesMut,
+    /// The instruction pointers of the callchain, innermost first.
+    ///
+    /// The kernel separates the kernel and user space parts of the
+    /// callchain with the `PERF_CONTEXT_KERNEL` and `PERF_CONTEXT_USER`
+    /// markers, which are kept as is.
+    pub callchain: Vec<u64>,
+}
+
+// the markers the kernel inserts in callchains, see `enum perf_callchain_context`
+const PERF_CONTEXT_KERNEL: u64 = -128i64 as u64;
+const PERF_CONTEXT_USER: u64 = -512i64 as u64;
+const PERF_CONTEXT_MAX: u64 = -4095i64 as u64;
+
+impl PerfEventRecord {
+    /// Creates a record whose data buffer can hold samples of up to
+    /// `capacity` bytes.
+    pub fn with_capacity(capacity: usize) -> PerfEventRecord {
+        PerfEventRecord {
+            data: BytesMut::with_capacity(capacity),
+            callchain: Vec::new(),
+        }
+    }
+
+    /// Resolves the callchain of the sample sent from the process `pid`.
+    ///
+    /// Kernel frames are resolved to kernel symbols with
+    /// [`kernel_symbols`](crate::util::kernel_symbols). User space frames
+    /// are resolved to the ELF symbols of the binary or library they belong
+    /// to, like in
+    /// [`StackTraceMap::get_user_trace`](crate::maps::StackTraceMap::get_user_trace):
+    /// the process must still be running, and the frames in stripped
+    /// binaries are left unresolved. The context markers are skipped.
+    pub fn symbolize_callchain(&self, pid: u32) -> Result<Vec<StackFrame>, io::Error> {
+        let has_context = |context| self.callchain.contains(&context);
+        let kernel = if has_context(PERF_CONTEXT_KERNEL) {
+            kernel_symbols()?
+        } else {
+            BTreeMap::new()
+        };
+        let mut user = if has_context(PERF_CONTEXT_USER) {
+            Some(UserSymbolizer::new(pid)?)
+        } else {
+            None
+        };
+        Ok(resolve_callchain(&self.callchain, &kernel, |ip| {
+            user.as_mut()?.resolve(ip).symbol
+        }))
+    }
+}
+
+fn resolve_callchain(
+    callchain: &[u64],
+    kernel: &BTreeMap<u64, String>,
+    mut resolve_user: impl FnMut(u64) -> Option<String>,
+) -> Vec<StackFrame> {
+    let mut user = false;
+    let mut frames = Vec::new();
+    for ip in callchain.iter().copied() {
+        match ip {
+            PERF_CONTEXT_KERNEL => user = false,
+            PERF_CONTEXT_USER => user = true,
+            ip if ip >= PERF_CONTEXT_MAX => {}
+            ip => frames.push(StackFrame {
+                ip,
+                symbol_name: if user {
+                    resolve_user(ip)
+                } else {
+                    kernel.range(..=ip).next_back().map(|(_, s)| s.clone())
+                },
+            }),
+        }
+    }
+    frames
+}
+
+// the output buffers of PerfBuffer::read_events
+pub(crate) trait SampleBuf {
+    fn data(&mut self) -> &mut BytesMut;
+
+    // the callchain of the sample, if the caller wants it
+    fn callchain(&mut self) -> Option<&mut Vec<u64>>;
+}
+
+impl SampleBuf for BytesMut {
+    fn data(&mut self) -> &mut BytesMut {
+        self
+    }
+
+    fn callchain(&mut self) -> Option<&mut Vec<u64>> {
+        None
+    }
+}
+
+impl SampleBuf for PerfEventRecord {
+    fn data(&mut self) -> &mut BytesMut {
+        &mut self.data
+    }
+
+    fn callchain(&mut self) -> Option<&mut Vec<u64>> {
+        Some(&mut self.callchain)
+    }
+}
+
 pub(crate) struct PerfBuffer {
     buf: AtomicPtr<perf_event_mmap_page>,
     size: usize,
     page_size: usize,
     fd: RawFd,
+    // whether the samples start with a callchain
+    callchain: bool,
 }
 
 impl PerfBuffer {
@@ -91,12 +209,23 @@ impl PerfBuffer {
         cpu_id: u32,
         page_size: usize,
         page_count: usize,
+    ) -> Result<PerfBuffer, PerfBufferError> {
+        PerfBuffer::open_with_max_stack(cpu_id, page_size, page_count, None)
+    }
+
+    // opens a buffer whose samples include the callchain of the program if
+    // `max_stack` is set
+    pub(crate) fn open_with_max_stack(
+        cpu_id: u32,
+        page_size: usize,
+        page_count: usize,
+        max_stack: Option<u16>,
     ) -> Result<PerfBuffer, PerfBufferError> {
         if !page_count.is_power_of_two() {
             return Err(PerfBufferError::InvalidPageCount { page_count });
         }
 
-        let fd = perf_event_open_bpf(cpu_id as i32)
+        let fd = perf_event_open_bpf(cpu_id as i32, max_stack)
             .map_err(|(_, io_error)| PerfBufferError::OpenError { io_error })?
             as RawFd;
         let size = page_size * page_count;
@@ -121,6 +250,7 @@ impl PerfBuffer {
             fd,
             size,
             page_size,
+            callchain: max_stack.is_some(),
         };
 
         perf_event_ioctl(fd, PERF_EVENT_IOC_ENABLE, 0)
@@ -136,9 +266,9 @@ impl PerfBuffer {
         head != tail
     }
 
-    pub(crate) fn read_events(
+    pub(crate) fn read_events<B: SampleBuf>(
         &mut self,
-        buffers: &mut [BytesMut],
+        buffers: &mut [B],
     ) -> Result<Events, PerfBufferError> {
         if buffers.is_empty() {
             return Err(PerfBufferError::NoBuffers);
@@ -151,6 +281,9 @@ impl PerfBuffer {
 
         let fill_buf = |start_off, base, mmap_size, out_buf: &mut [u8]| {
             let len = out_buf.len();
+            if len == 0 {
+                return;
+            }
 
             let end = (start_off + len) % mmap_size;
             let start = start_off % mmap_size;
@@ -170,27 +303,36 @@ impl PerfBuffer {
             }
         };
 
-        let read_event = |event_start, event_type, base, buf: &mut BytesMut| {
-            let sample_size = match event_type {
-                x if x == PERF_RECORD_SAMPLE as u32 || x == PERF_RECORD_LOST as u32 => {
-                    let mut size = [0u8; mem::size_of::<u32>()];
-                    fill_buf(
-                        event_start + mem::size_of::<perf_event_header>(),
-                        base,
-                        self.size,
-                        &mut size,
-                    );
-                    u32::from_ne_bytes(size)
-                }
-                _ => return Ok(None),
-            } as usize;
-
-            let sample_start =
-                (event_start + mem::size_of::<perf_event_header>() + mem::size_of::<u32>())
-                    % self.size;
-
+        let read_event = |event_start, event_type, base, buf: &mut B| {
             match event_type {
                 x if x == PERF_RECORD_SAMPLE as u32 => {
+                    let mut offset = event_start + mem::size_of::<perf_event_header>();
+                    if self.callchain {
+                        let mut nr = [0u8; mem::size_of::<u64>()];
+                        fill_buf(offset, base, self.size, &mut nr);
+                        offset += mem::size_of::<u64>();
+                        let nr = u64::from_ne_bytes(nr) as usize;
+                        if let Some(callchain) = buf.callchain() {
+                            callchain.clear();
+                            callchain.resize(nr, 0);
+                            // Safety: any bytes are valid u64s
+                            let ips = unsafe {
+                                slice::from_raw_parts_mut(
+                                    callchain.as_mut_ptr() as *mut u8,
+                                    nr * mem::size_of::<u64>(),
+                                )
+                            };
+                            fill_buf(offset, base, self.size, ips);
+                        }
+                        offset += nr * mem::size_of::<u64>();
+                    }
+
+                    let mut size = [0u8; mem::size_of::<u32>()];
+                    fill_buf(offset, base, self.size, &mut size);
+                    let sample_size = u32::from_ne_bytes(size) as usize;
+                    let sample_start = offset + mem::size_of::<u32>();
+
+                    let buf = buf.data();
                     buf.clear();
                     if sample_size > buf.capacity() {
                         return Err(PerfBufferError::MoreSpaceNeeded { size: sample_size });
@@ -365,7 +507,7 @@ mod tests {
 
         let mut buf = PerfBuffer::open(1, PAGE_SIZE, 1).unwrap();
         assert!(matches!(
-            buf.read_events(&mut []),
+            buf.read_events::<BytesMut>(&mut []),
             Err(PerfBufferError::NoBuffers)
         ))
     }
@@ -595,4 +737,96 @@ mod tests {
         assert_eq!(events, Events { lost: 0, read: 1 });
         assert_eq!(u64_from_buf(&out_bufs[0]), 0xBAADCAFECAFEBABE);
     }
+
+    #[repr(C)]
+    #[derive(Debug)]
+    struct CallchainSample {
+        header: perf_event_header,
+        nr: u64,
+        ips: [u64; 3],
+        size: u32,
+        value: u32,
+    }
+
+    fn callchain_sample() -> CallchainSample {
+        CallchainSample {
+            header: perf_event_header {
+                type_: PERF_RECORD_SAMPLE as u32,
+                misc: 0,
+                size: mem::size_of::<CallchainSample>() as u16,
+            },
+            nr: 3,
+            ips: [
+                PERF_CONTEXT_KERNEL,
+                0xffff_ffff_8100_0010,
+                0xffff_ffff_8100_0020,
+            ],
+            size: 4,
+            value: 0xCAFEBABE,
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_read_callchain_sample() {
+        let mut mmapped_buf = MMappedBuf {
+            data: [0; PAGE_SIZE * 2],
+        };
+        fake_mmap(&mut mmapped_buf);
+        let mut buf = PerfBuffer::open_with_max_stack(1, PAGE_SIZE, 1, Some(8)).unwrap();
+
+        write(&mut mmapped_buf, 0, callchain_sample());
+
+        let mut records = [PerfEventRecord::with_capacity(4)];
+        let events = buf.read_events(&mut records).unwrap();
+        assert_eq!(events, Events { lost: 0, read: 1 });
+        assert_eq!(u32_from_buf(&records[0].data), 0xCAFEBABE);
+        assert_eq!(
+            records[0].callchain,
+            [
+                PERF_CONTEXT_KERNEL,
+                0xffff_ffff_8100_0010,
+                0xffff_ffff_8100_0020
+            ]
+        );
+
+        // plain buffers only get the data
+        mmapped_buf.mmap_page.data_tail = 0;
+        write(&mut mmapped_buf, 0, callchain_sample());
+        let mut out_bufs = [BytesMut::with_capacity(4)];
+        let events = buf.read_events(&mut out_bufs).unwrap();
+        assert_eq!(events, Events { lost: 0, read: 1 });
+        assert_eq!(u32_from_buf(&out_bufs[0]), 0xCAFEBABE);
+    }
+
+    #[test]
+    fn test_resolve_callchain() {
+        let kernel = vec![(0xffff_ffff_8100_0000, "vfs_read".to_owned())]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let callchain = [
+            PERF_CONTEXT_KERNEL,
+            0xffff_ffff_8100_0010,
+            PERF_CONTEXT_USER,
+            0x40_1000,
+            0x10,
+        ];
+
+        let user = |ip| match ip {
+            0x40_0000..=0x40_ffff => Some("main".to_owned()),
+            _ => None,
+        };
+        let frames = resolve_callchain(&callchain, &kernel, user)
+            .into_iter()
+            .map(|frame| (frame.ip, frame.symbol_name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            frames,
+            [
+                (0xffff_ffff_8100_0010, Some("vfs_read".to_owned())),
+                (0x40_1000, Some("main".to_owned())),
+                (0x10, None),
+            ]
+        );
+    }
 }
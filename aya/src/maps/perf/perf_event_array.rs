@@ -13,8 +13,8 @@ use bytes::BytesMut;
 use crate::{
     generated::bpf_map_type::BPF_MAP_TYPE_PERF_EVENT_ARRAY,
     maps::{
-        perf::{Events, PerfBuffer, PerfBufferError},
-        Map, MapError, MapRefMut,
+        perf::{Events, PerfBuffer, PerfBufferError, PerfEventRecord},
+        Map, MapError, MapRefMut, OwnedMap,
     },
     sys::bpf_map_update_elem,
     util::page_size,
@@ -57,6 +57,25 @@ impl<T: DerefMut<Target = Map>> PerfEventArrayBuffer<T> {
     pub fn read_events(&mut self, out_bufs: &mut [BytesMut]) -> Result<Events, PerfBufferError> {
         self.buf.read_events(out_bufs)
     }
+
+    /// Reads events along with their callchain from the buffer.
+    ///
+    /// This works like [`read_events`](Self::read_events), filling the
+    /// data and the callchain of each record in order. The callchains are
+    /// only available for buffers opened with
+    /// [`PerfEventArray::open_buffer_with_stack_trace`], they are left
+    /// empty otherwise. [`read_events`](Self::read_events) can be used on
+    /// those buffers as well, and drops the callchains.
+    ///
+    /// # Errors
+    ///
+    /// See [`read_events`](Self::read_events).
+    pub fn read_records(
+        &mut self,
+        records: &mut [PerfEventRecord],
+    ) -> Result<Events, PerfBufferError> {
+        self.buf.read_events(records)
+    }
 }
 
 impl<T: DerefMut<Target = Map>> AsRawFd for PerfEventArrayBuffer<T> {
@@ -181,6 +200,14 @@ impl<T: DerefMut<Target = Map>> PerfEventArray<T> {
         })
     }
 
+    /// Returns an owned handle to the same array.
+    ///
+    /// The buffers opened with the original array keep working. See
+    /// [`Map::try_clone`].
+    pub fn try_clone(&self) -> Result<PerfEventArray<OwnedMap>, MapError> {
+        PerfEventArray::new(self.map.try_clone()?)
+    }
+
     /// Opens the perf buffer at the given index.
     ///
     /// The returned buffer will receive all the events eBPF programs send at the given index.
@@ -202,6 +229,67 @@ impl<T: DerefMut<Target = Map>> PerfEventArray<T> {
             _map: self.map.clone(),
         })
     }
+
+    /// Opens the perf buffer at the given index, with the samples including
+    /// the callchain of the program that sent them.
+    ///
+    /// The kernel records the stack of the program when it calls
+    /// `bpf_perf_event_output()`, at most `stack_depth` frames deep, before
+    /// the data of the sample. Read the samples with
+    /// [`PerfEventArrayBuffer::read_records`] to get the callchains.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to set the stack depth is 4.8.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let bpf = aya::Bpf::load(&[])?;
+    /// use aya::maps::{perf::PerfEventRecord, PerfEventArray};
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut perf_array = PerfEventArray::try_from(bpf.map_mut("ALLOCS")?)?;
+    /// let mut buf = perf_array.open_buffer_with_stack_trace(0, None, 32)?;
+    /// let mut records = (0..10)
+    ///     .map(|_| PerfEventRecord::with_capacity(64))
+    ///     .collect::<Vec<_>>();
+    /// let events = buf.read_records(&mut records)?;
+    /// for record in &records[..events.read] {
+    ///     for frame in record.symbolize_callchain(1234)? {
+    ///         println!("{:#x} {:?}", frame.ip, frame.symbol_name);
+    ///     }
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn open_buffer_with_stack_trace(
+        &mut self,
+        index: u32,
+        page_count: Option<usize>,
+        stack_depth: u16,
+    ) -> Result<PerfEventArrayBuffer<T>, PerfBufferError> {
+        let map_fd = self.map.fd_or_err().unwrap();
+        let buf = PerfBuffer::open_with_max_stack(
+            index,
+            self.page_size,
+            page_count.unwrap_or(2),
+            Some(stack_depth),
+        )?;
+        bpf_map_update_elem(map_fd, &index, &buf.as_raw_fd(), 0)
+            .map_err(|(_, io_error)| io_error)?;
+
+        Ok(PerfEventArrayBuffer {
+            buf,
+            _map: self.map.clone(),
+        })
+    }
+}
+
+impl<T: DerefMut<Target = Map>> AsRawFd for PerfEventArray<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.map.fd_or_err().unwrap()
+    }
 }
 
 impl TryFrom<MapRefMut> for PerfEventArray<MapRefMut> {
@@ -211,3 +299,11 @@ impl TryFrom<MapRefMut> for PerfEventArray<MapRefMut> {
         PerfEventArray::new(a)
     }
 }
+
+impl TryFrom<OwnedMap> for PerfEventArray<OwnedMap> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<PerfEventArray<OwnedMap>, MapError> {
+        PerfEventArray::new(a)
+    }
+}
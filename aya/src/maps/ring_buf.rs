@@ -0,0 +1,713 @@
+//! A ring buffer shared with eBPF programs.
+use std::{
+    convert::TryFrom,
+    ffi::c_void,
+    io, mem,
+    ops::Deref,
+    os::unix::io::{AsRawFd, RawFd},
+    ptr, slice,
+    sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering},
+};
+
+use libc::{munmap, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
+
+use crate::{
+    generated::bpf_map_type::BPF_MAP_TYPE_RINGBUF,
+    maps::{Map, MapError, MapRef, MapRefMut, OwnedMap},
+    util::page_size,
+};
+
+// see `enum { BPF_RINGBUF_BUSY_BIT, ... }` in include/uapi/linux/bpf.h
+const BPF_RINGBUF_BUSY_BIT: u32 = 1 << 31;
+const BPF_RINGBUF_DISCARD_BIT: u32 = 1 << 30;
+const BPF_RINGBUF_HDR_SZ: usize = 8;
+
+/// A ring buffer used to send data from eBPF programs to user space.
+///
+/// eBPF programs write records with `bpf_ringbuf_output()` or
+/// `bpf_ringbuf_reserve()` and `bpf_ringbuf_submit()`. Unlike
+/// [`PerfEventArray`](crate::maps::PerfEventArray), a single buffer is shared
+/// by all the CPUs, so records are received in the order they were committed.
+///
+/// [`RingBuf`] doesn't depend on any async runtime: register
+/// [`epoll_fd`](RingBuf::epoll_fd) with `epoll`, `mio` or any other event
+/// loop, and call [`consume_pending`](RingBuf::consume_pending) when it
+/// becomes readable.
+///
+/// # Wakeups
+///
+/// The kernel has no consumer-side wakeup threshold for ring buffers: the
+/// eBPF program decides whether committing a record wakes up the consumer.
+/// By default the consumer is woken up when it has caught up with the
+/// producer, which minimizes latency but can cause a wakeup per record under
+/// high load. Programs can pass `BPF_RB_NO_WAKEUP` to skip the wakeup of most
+/// records and `BPF_RB_FORCE_WAKEUP` once enough data is pending, trading
+/// latency for throughput since each wakeup then reads several records.
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 5.8.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let bpf = aya::Bpf::load(&[])?;
+/// use aya::maps::RingBuf;
+/// use std::convert::TryFrom;
+///
+/// let mut ring_buf = RingBuf::try_from(bpf.map_mut("EVENTS")?)?;
+/// let fd = ring_buf.epoll_fd();
+/// // register fd with epoll_ctl(), then each time it becomes readable:
+/// ring_buf.consume_pending(|record| {
+///     println!("received {} bytes", record.len());
+/// });
+/// # Ok::<(), aya::BpfError>(())
+/// ```
+#[doc(alias = "BPF_MAP_TYPE_RINGBUF")]
+pub struct RingBuf<T: Deref<Target = Map>> {
+    _map: T,
+    fd: RawFd,
+    consumer: AtomicPtr<c_void>,
+    producer: AtomicPtr<c_void>,
+    size: usize,
+    page_size: usize,
+}
+
+impl<T: Deref<Target = Map>> RingBuf<T> {
+    fn new(map: T) -> Result<RingBuf<T>, MapError> {
+        let map_type = map.obj.def.map_type;
+        if map_type != BPF_MAP_TYPE_RINGBUF as u32 {
+            return Err(MapError::InvalidMapType { map_type });
+        }
+        let fd = map.fd_or_err()?;
+        let size = map.obj.def.max_entries as usize;
+        let page_size = page_size();
+
+        // the consumer position page is the only one user space can write to
+        let consumer = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                page_size,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if consumer == MAP_FAILED {
+            return Err(mmap_error());
+        }
+
+        // the producer position page is followed by the data pages, which the
+        // kernel maps twice in a row so that records never wrap around
+        let producer = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                page_size + 2 * size,
+                PROT_READ,
+                MAP_SHARED,
+                fd,
+                page_size as libc::off_t,
+            )
+        };
+        if producer == MAP_FAILED {
+            let error = mmap_error();
+            unsafe { munmap(consumer, page_size) };
+            return Err(error);
+        }
+
+        Ok(RingBuf {
+            _map: map,
+            fd,
+            consumer: AtomicPtr::new(consumer),
+            producer: AtomicPtr::new(producer),
+            size,
+            page_size,
+        })
+    }
+
+    /// Returns the file descriptor of the ring buffer.
+    ///
+    /// The file descriptor becomes readable when records are available and
+    /// can be registered with `epoll_ctl()` or any event loop built on it.
+    pub fn epoll_fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Returns the file descriptor of the underlying map.
+    ///
+    /// This is the same file descriptor as [`epoll_fd`](RingBuf::epoll_fd),
+    /// exposed so that other libraries can operate on the map.
+    pub fn map_fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Returns the size in bytes of the data area of the ring buffer, which
+    /// is the `max_entries` of the map.
+    pub fn capacity_bytes(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the position up to which the kernel has reserved records.
+    ///
+    /// Positions only ever increase: the offset in the data area is the
+    /// position modulo [`capacity_bytes`](RingBuf::capacity_bytes).
+    pub fn producer_position(&self) -> u64 {
+        let producer = self.producer.load(Ordering::SeqCst);
+        unsafe { &*(producer as *const AtomicUsize) }.load(Ordering::Acquire) as u64
+    }
+
+    /// Returns the position up to which records have been consumed.
+    pub fn consumer_position(&self) -> u64 {
+        let consumer = self.consumer.load(Ordering::SeqCst);
+        unsafe { &*(consumer as *const AtomicUsize) }.load(Ordering::Acquire) as u64
+    }
+
+    /// Returns the number of bytes reserved by the kernel and not yet
+    /// consumed, including the record headers and the records that are
+    /// still being written.
+    ///
+    /// The positions are read without locking while the kernel may be
+    /// producing, so the value is a snapshot meant for monitoring.
+    pub fn used_bytes(&self) -> usize {
+        // read the consumer position first so that used <= capacity
+        let cons = self.consumer_position();
+        let prod = self.producer_position();
+        used_bytes(cons, prod)
+    }
+
+    /// Returns [`used_bytes`](RingBuf::used_bytes) as a percentage of
+    /// [`capacity_bytes`](RingBuf::capacity_bytes).
+    ///
+    /// A ring buffer that stays close to 100% drops records: eBPF programs
+    /// fail to reserve space until user space catches up.
+    pub fn utilization_percent(&self) -> f64 {
+        utilization_percent(self.used_bytes(), self.size)
+    }
+
+    /// Empties the ring buffer, dropping all the records that haven't been
+    /// consumed yet.
+    ///
+    /// The producer position lives in a page only the kernel can write to,
+    /// so the positions can't be rewound to 0. Instead the consumer position
+    /// is moved to the producer position, which leaves the ring buffer in the
+    /// same empty state, for example after a consumer crashed in the middle
+    /// of processing records.
+    ///
+    /// This is only safe when no eBPF program is writing to the ring buffer:
+    /// records reserved but not yet committed are dropped as well, and the
+    /// kernel would overwrite them once they're committed. It's intended for
+    /// testing and crash recovery.
+    pub fn reset(&mut self) {
+        let consumer = self.consumer.load(Ordering::SeqCst);
+        let producer = self.producer.load(Ordering::SeqCst);
+        unsafe {
+            reset(
+                &*(consumer as *const AtomicUsize),
+                &*(producer as *const AtomicUsize),
+            )
+        }
+    }
+
+    /// Processes the records available in the ring buffer without blocking.
+    ///
+    /// Calls `f` for each committed record and returns the number of records
+    /// processed. Only the records committed when the call starts are
+    /// consumed, so the method returns after at most one round of the ring
+    /// even if eBPF programs keep producing. Consumption also stops at the
+    /// first record that is still reserved but not yet committed. Discarded
+    /// records are skipped and not counted.
+    pub fn consume_pending<F: FnMut(&[u8])>(&mut self, f: F) -> usize {
+        let consumer = self.consumer.load(Ordering::SeqCst);
+        let producer = self.producer.load(Ordering::SeqCst);
+        unsafe {
+            consume(
+                &*(consumer as *const AtomicUsize),
+                &*(producer as *const AtomicUsize),
+                (producer as *const u8).add(self.page_size),
+                self.size - 1,
+                f,
+            )
+        }
+    }
+
+    /// Returns the next committed record without blocking.
+    ///
+    /// Returns `None` if the ring buffer is empty or if the next record is
+    /// still reserved but not yet committed. Discarded records are skipped.
+    /// The record is released back to the kernel when the returned
+    /// [`RingBufRecord`] is dropped.
+    pub fn try_next(&mut self) -> Option<RingBufRecord<'_>> {
+        let consumer = self.consumer.load(Ordering::SeqCst);
+        let producer = self.producer.load(Ordering::SeqCst);
+        unsafe {
+            let producer_pos = &*(producer as *const AtomicUsize);
+            next_record(
+                &*(consumer as *const AtomicUsize),
+                producer_pos.load(Ordering::Acquire),
+                (producer as *const u8).add(self.page_size),
+                self.size - 1,
+            )
+        }
+    }
+
+    /// Returns the data of the next committed record without consuming it.
+    ///
+    /// Returns `None` if the ring buffer is empty or if the next record is
+    /// still reserved but not yet committed. The record stays in the ring
+    /// buffer, so the next call to `peek`, [`try_next`](RingBuf::try_next)
+    /// or any other read returns it again.
+    pub fn peek(&mut self) -> Option<&[u8]> {
+        let record = self.try_next()?;
+        let data = record.data;
+        // don't advance the consumer position
+        mem::forget(record);
+        Some(data)
+    }
+
+    /// Calls `f` for each committed record until the ring buffer is empty or
+    /// `f` returns [`ConsumeDecision::Discard`], and returns the number of
+    /// records committed.
+    ///
+    /// The record passed to `f` is only released to the kernel if `f`
+    /// returns [`ConsumeDecision::Commit`]. Otherwise it's left in the ring
+    /// buffer and returned again by the next read, which lets `f` stop, for
+    /// example because a downstream queue is full, without losing data.
+    pub fn consume_until_empty<F: FnMut(&[u8]) -> ConsumeDecision>(&mut self, mut f: F) -> usize {
+        let mut count = 0;
+        while let Some(record) = self.try_next() {
+            match f(&record) {
+                ConsumeDecision::Commit => count += 1,
+                ConsumeDecision::Discard => {
+                    mem::forget(record);
+                    break;
+                }
+            }
+        }
+        count
+    }
+
+    /// Calls `f` for each record returned by [`try_next`](RingBuf::try_next)
+    /// until it returns `None`, and returns the number of records processed.
+    ///
+    /// Unlike [`consume_pending`](RingBuf::consume_pending), records
+    /// committed while draining are processed too, so this method doesn't
+    /// return as long as eBPF programs produce records faster than `f`
+    /// processes them.
+    pub fn drain<F: FnMut(RingBufRecord<'_>)>(&mut self, mut f: F) -> usize {
+        let mut count = 0;
+        while let Some(record) = self.try_next() {
+            f(record);
+            count += 1;
+        }
+        count
+    }
+}
+
+/// Returned by the closure passed to
+/// [`consume_until_empty`](RingBuf::consume_until_empty) to decide what
+/// happens to a record.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConsumeDecision {
+    /// The record has been processed: release it to the kernel and move on to
+    /// the next one.
+    Commit,
+    /// Keep the record in the ring buffer and stop. The same record is
+    /// returned again by the next read.
+    Discard,
+}
+
+/// A record read from a [`RingBuf`].
+///
+/// The record dereferences to its data. The ring buffer space used by the
+/// record is released to the kernel when it's dropped.
+pub struct RingBufRecord<'a> {
+    data: &'a [u8],
+    consumer_pos: &'a AtomicUsize,
+    next_pos: usize,
+}
+
+impl Deref for RingBufRecord<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl Drop for RingBufRecord<'_> {
+    fn drop(&mut self) {
+        self.consumer_pos.store(self.next_pos, Ordering::Release);
+    }
+}
+
+impl<T: Deref<Target = Map>> AsRawFd for RingBuf<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl<T: Deref<Target = Map>> Drop for RingBuf<T> {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.consumer.load(Ordering::SeqCst), self.page_size);
+            munmap(
+                self.producer.load(Ordering::SeqCst),
+                self.page_size + 2 * self.size,
+            );
+        }
+    }
+}
+
+impl TryFrom<MapRef> for RingBuf<MapRef> {
+    type Error = MapError;
+
+    fn try_from(a: MapRef) -> Result<RingBuf<MapRef>, MapError> {
+        RingBuf::new(a)
+    }
+}
+
+impl TryFrom<MapRefMut> for RingBuf<MapRefMut> {
+    type Error = MapError;
+
+    fn try_from(a: MapRefMut) -> Result<RingBuf<MapRefMut>, MapError> {
+        RingBuf::new(a)
+    }
+}
+
+impl TryFrom<OwnedMap> for RingBuf<OwnedMap> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<RingBuf<OwnedMap>, MapError> {
+        RingBuf::new(a)
+    }
+}
+
+fn mmap_error() -> MapError {
+    MapError::SyscallError {
+        call: "mmap".to_owned(),
+        code: -1,
+        io_error: io::Error::last_os_error(),
+    }
+}
+
+/// Consumes the records between `consumer_pos` and the value `producer_pos`
+/// has when the function is called.
+///
+/// # Safety
+///
+/// See [`next_record`].
+unsafe fn consume<F: FnMut(&[u8])>(
+    consumer_pos: &AtomicUsize,
+    producer_pos: &AtomicUsize,
+    data: *const u8,
+    mask: usize,
+    mut f: F,
+) -> usize {
+    let mut consumed = 0;
+    let prod = producer_pos.load(Ordering::Acquire);
+    while let Some(record) = next_record(consumer_pos, prod, data, mask) {
+        f(&record);
+        consumed += 1;
+    }
+
+    consumed
+}
+
+fn used_bytes(consumer_pos: u64, producer_pos: u64) -> usize {
+    producer_pos.saturating_sub(consumer_pos) as usize
+}
+
+fn utilization_percent(used: usize, capacity: usize) -> f64 {
+    if capacity == 0 {
+        return 0.0;
+    }
+    used as f64 / capacity as f64 * 100.0
+}
+
+// moves the consumer position to the producer position
+fn reset(consumer_pos: &AtomicUsize, producer_pos: &AtomicUsize) {
+    consumer_pos.store(producer_pos.load(Ordering::Acquire), Ordering::Release);
+}
+
+/// Returns the first committed record between `consumer_pos` and `prod`,
+/// skipping discarded records.
+///
+/// # Safety
+///
+/// `data` must point to the ring buffer data and every committed record must
+/// be readable without wrapping around `mask + 1`.
+unsafe fn next_record<'a>(
+    consumer_pos: &'a AtomicUsize,
+    prod: usize,
+    data: *const u8,
+    mask: usize,
+) -> Option<RingBufRecord<'a>> {
+    let mut cons = consumer_pos.load(Ordering::Acquire);
+    while cons < prod {
+        let offset = cons & mask;
+        let header = &*(data.add(offset) as *const AtomicU32);
+        let len = header.load(Ordering::Acquire);
+        if len & BPF_RINGBUF_BUSY_BIT != 0 {
+            break;
+        }
+
+        let size = (len & !BPF_RINGBUF_DISCARD_BIT) as usize;
+        // records are 8 byte aligned
+        let next_pos = cons + ((size + BPF_RINGBUF_HDR_SZ + 7) & !7);
+        if len & BPF_RINGBUF_DISCARD_BIT == 0 {
+            return Some(RingBufRecord {
+                data: slice::from_raw_parts(data.add(offset + BPF_RINGBUF_HDR_SZ), size),
+                consumer_pos,
+                next_pos,
+            });
+        }
+
+        cons = next_pos;
+        consumer_pos.store(cons, Ordering::Release);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bpf_map_def, generated::bpf_map_type::BPF_MAP_TYPE_HASH, obj};
+
+    use super::*;
+
+    fn write_record(data: &mut [u8], offset: usize, len: u32, payload: &[u8]) -> usize {
+        data[offset..offset + 4].copy_from_slice(&len.to_ne_bytes());
+        data[offset + BPF_RINGBUF_HDR_SZ..offset + BPF_RINGBUF_HDR_SZ + payload.len()]
+            .copy_from_slice(payload);
+        offset + ((payload.len() + BPF_RINGBUF_HDR_SZ + 7) & !7)
+    }
+
+    #[test]
+    fn test_wrong_map() {
+        let map = Map {
+            obj: obj::Map {
+                def: bpf_map_def {
+                    map_type: BPF_MAP_TYPE_HASH as u32,
+                    max_entries: 4096,
+                    ..Default::default()
+                },
+                section_index: 0,
+                data: Vec::new(),
+                kind: obj::MapKind::Other,
+            },
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+
+        assert!(matches!(
+            RingBuf::new(&map),
+            Err(MapError::InvalidMapType { .. })
+        ));
+    }
+
+    #[test]
+    fn test_consume() {
+        // u64s keep the headers aligned for AtomicU32
+        let mut buf = vec![0u64; 16];
+        let data = unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 128) };
+        let mut end = write_record(data, 0, 3, b"foo");
+        end = write_record(data, end, 4 | BPF_RINGBUF_DISCARD_BIT, b"skip");
+        end = write_record(data, end, 6, b"barbaz");
+        let busy = write_record(data, end, 3 | BPF_RINGBUF_BUSY_BIT, b"qux");
+
+        let consumer_pos = AtomicUsize::new(0);
+        let producer_pos = AtomicUsize::new(busy);
+        let mut records = Vec::new();
+        let consumed = unsafe {
+            consume(
+                &consumer_pos,
+                &producer_pos,
+                data.as_ptr(),
+                127,
+                |record: &[u8]| records.push(record.to_vec()),
+            )
+        };
+
+        assert_eq!(consumed, 2);
+        assert_eq!(records, vec![b"foo".to_vec(), b"barbaz".to_vec()]);
+        // the busy record stays in the ring
+        assert_eq!(consumer_pos.load(Ordering::SeqCst), end);
+    }
+
+    #[test]
+    fn test_used_bytes() {
+        assert_eq!(used_bytes(4096, 4096), 0);
+        assert_eq!(used_bytes(4096, 5120), 1024);
+        // a consumer position read after a reset can be ahead of a stale
+        // producer position
+        assert_eq!(used_bytes(5120, 4096), 0);
+        assert_eq!(utilization_percent(1024, 4096), 25.0);
+        assert_eq!(utilization_percent(0, 0), 0.0);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut buf = vec![0u64; 16];
+        let data = unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 128) };
+        let end = write_record(data, 0, 3, b"foo");
+        let busy = write_record(data, end, 3 | BPF_RINGBUF_BUSY_BIT, b"bar");
+
+        let consumer_pos = AtomicUsize::new(0);
+        let producer_pos = AtomicUsize::new(busy);
+        reset(&consumer_pos, &producer_pos);
+        assert_eq!(consumer_pos.load(Ordering::SeqCst), busy);
+        assert!(unsafe { next_record(&consumer_pos, busy, data.as_ptr(), 127) }.is_none());
+    }
+
+    // a ring buffer backed by anonymous mappings instead of the pages of a
+    // map, so that the tests can write records to it
+    fn fake_ring_buf(map: &Map) -> RingBuf<&Map> {
+        let page_size = page_size();
+        let size = 128;
+        let mmap = |len| {
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    PROT_READ | PROT_WRITE,
+                    MAP_SHARED | libc::MAP_ANONYMOUS,
+                    -1,
+                    0,
+                )
+            };
+            assert_ne!(ptr, MAP_FAILED);
+            ptr
+        };
+        RingBuf {
+            _map: map,
+            fd: 42,
+            consumer: AtomicPtr::new(mmap(page_size)),
+            producer: AtomicPtr::new(mmap(page_size + 2 * size)),
+            size,
+            page_size,
+        }
+    }
+
+    // writes the records to the ring buffer and commits them
+    fn produce(ring_buf: &mut RingBuf<&Map>, records: &[(u32, &[u8])]) {
+        let producer = ring_buf.producer.load(Ordering::SeqCst);
+        let data = unsafe {
+            slice::from_raw_parts_mut((producer as *mut u8).add(ring_buf.page_size), ring_buf.size)
+        };
+        let mut end = 0;
+        for (len, payload) in records {
+            end = write_record(data, end, *len, payload);
+        }
+        unsafe { &*(producer as *const AtomicUsize) }.store(end, Ordering::SeqCst);
+    }
+
+    fn ring_buf_map() -> Map {
+        Map {
+            obj: obj::Map {
+                def: bpf_map_def {
+                    map_type: BPF_MAP_TYPE_RINGBUF as u32,
+                    max_entries: 128,
+                    ..Default::default()
+                },
+                section_index: 0,
+                data: Vec::new(),
+                kind: obj::MapKind::Other,
+            },
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn test_peek() {
+        let map = ring_buf_map();
+        let mut ring_buf = fake_ring_buf(&map);
+        assert!(ring_buf.peek().is_none());
+
+        produce(
+            &mut ring_buf,
+            &[
+                (3, b"foo"),
+                (4 | BPF_RINGBUF_DISCARD_BIT, b"skip"),
+                (6, b"barbaz"),
+            ],
+        );
+        assert_eq!(ring_buf.peek(), Some(&b"foo"[..]));
+        // peeking doesn't advance the consumer position
+        assert_eq!(ring_buf.peek(), Some(&b"foo"[..]));
+        assert_eq!(ring_buf.consumer_position(), 0);
+
+        drop(ring_buf.try_next());
+        // the discarded record is skipped
+        assert_eq!(ring_buf.peek(), Some(&b"barbaz"[..]));
+        assert_eq!(ring_buf.consumer_position(), 32);
+    }
+
+    #[test]
+    fn test_consume_until_empty() {
+        let map = ring_buf_map();
+        let mut ring_buf = fake_ring_buf(&map);
+        produce(
+            &mut ring_buf,
+            &[
+                (3, b"foo"),
+                (4 | BPF_RINGBUF_DISCARD_BIT, b"skip"),
+                (3, b"bar"),
+                (3, b"baz"),
+            ],
+        );
+
+        let mut seen = Vec::new();
+        let committed = ring_buf.consume_until_empty(|record| {
+            seen.push(record.to_vec());
+            if record == b"bar" {
+                ConsumeDecision::Discard
+            } else {
+                ConsumeDecision::Commit
+            }
+        });
+        assert_eq!(committed, 1);
+        assert_eq!(seen, vec![b"foo".to_vec(), b"bar".to_vec()]);
+        // bar is left in the ring buffer, after the skipped record
+        assert_eq!(ring_buf.consumer_position(), 32);
+
+        seen.clear();
+        let committed = ring_buf.consume_until_empty(|record| {
+            seen.push(record.to_vec());
+            ConsumeDecision::Commit
+        });
+        assert_eq!(committed, 2);
+        assert_eq!(seen, vec![b"bar".to_vec(), b"baz".to_vec()]);
+        assert_eq!(ring_buf.consumer_position(), ring_buf.producer_position());
+        assert_eq!(ring_buf.used_bytes(), 0);
+    }
+
+    #[test]
+    fn test_next_record() {
+        let mut buf = vec![0u64; 16];
+        let data = unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 128) };
+        let first = write_record(data, 0, 3, b"foo");
+        let mut end = write_record(data, first, 4 | BPF_RINGBUF_DISCARD_BIT, b"skip");
+        end = write_record(data, end, 6, b"barbaz");
+
+        let consumer_pos = AtomicUsize::new(0);
+        let record = unsafe { next_record(&consumer_pos, end, data.as_ptr(), 127) }.unwrap();
+        assert_eq!(&*record, b"foo");
+        // the record is only released when dropped
+        assert_eq!(consumer_pos.load(Ordering::SeqCst), 0);
+        drop(record);
+        assert_eq!(consumer_pos.load(Ordering::SeqCst), first);
+
+        let record = unsafe { next_record(&consumer_pos, end, data.as_ptr(), 127) }.unwrap();
+        assert_eq!(&*record, b"barbaz");
+        drop(record);
+        assert_eq!(consumer_pos.load(Ordering::SeqCst), end);
+
+        assert!(unsafe { next_record(&consumer_pos, end, data.as_ptr(), 127) }.is_none());
+    }
+}
@@ -0,0 +1,245 @@
+//! Socket local storage.
+use std::{
+    convert::TryFrom,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use crate::{
+    generated::bpf_map_type::BPF_MAP_TYPE_SK_STORAGE,
+    maps::{hash_map, Map, MapError, MapRef, MapRefMut, OwnedMap},
+    sys::bpf_map_lookup_elem,
+    Pod,
+};
+
+/// Per-socket storage that can be shared between eBPF programs and user
+/// space.
+///
+/// eBPF programs store values for a socket with `bpf_sk_storage_get()`. User
+/// space accesses the same values using the fd of the socket as the key. The
+/// kernel frees the value stored for a socket when the socket is closed.
+///
+/// The kernel only creates socket storage maps that have BTF key and value
+/// types. Maps defined in the `.maps` section of C programs carry their BTF.
+/// For maps that don't, such as the `SkStorage` map of `aya-bpf`, pass the
+/// key and value types with
+/// [`MapCreateOptions::with_btf`](crate::maps::MapCreateOptions::with_btf) and
+/// [`BpfLoader::map_create_options`](crate::BpfLoader::map_create_options).
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 5.2.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let bpf = aya::Bpf::load(&[])?;
+/// use aya::maps::SkStorageMap;
+/// use std::convert::TryFrom;
+/// use std::net::TcpStream;
+///
+/// let mut storage = SkStorageMap::<_, u64>::try_from(bpf.map_mut("SOCKET_BYTES")?)?;
+///
+/// let client = TcpStream::connect("127.0.0.1:1234")?;
+/// storage.insert(&client, 0, 0)?;
+/// let bytes = storage.get(&client, 0)?.unwrap_or(0);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[doc(alias = "BPF_MAP_TYPE_SK_STORAGE")]
+pub struct SkStorageMap<T: Deref<Target = Map>, V> {
+    inner: T,
+    _v: PhantomData<V>,
+}
+
+impl<T: Deref<Target = Map>, V: Pod> SkStorageMap<T, V> {
+    pub(crate) fn new(map: T) -> Result<SkStorageMap<T, V>, MapError> {
+        let map_type = map.obj.def.map_type;
+
+        // validate the map definition
+        if map_type != BPF_MAP_TYPE_SK_STORAGE as u32 {
+            return Err(MapError::InvalidMapType { map_type });
+        }
+        hash_map::check_kv_size::<RawFd, V>(&map)?;
+        let _ = map.fd_or_err()?;
+
+        Ok(SkStorageMap {
+            inner: map,
+            _v: PhantomData,
+        })
+    }
+
+    /// Returns a copy of the value stored for the given socket, or `None` if
+    /// no value is stored.
+    pub fn get<I: AsRawFd>(&self, socket: &I, flags: u64) -> Result<Option<V>, MapError> {
+        let fd = self.inner.deref().fd_or_err()?;
+        bpf_map_lookup_elem(fd, &socket.as_raw_fd(), flags).map_err(|(code, io_error)| {
+            MapError::SyscallError {
+                call: "bpf_map_lookup_elem".to_owned(),
+                code,
+                io_error,
+            }
+        })
+    }
+}
+
+impl<T: DerefMut<Target = Map>, V: Pod> SkStorageMap<T, V> {
+    /// Stores a value for the given socket.
+    pub fn insert<I: AsRawFd>(&mut self, socket: &I, value: V, flags: u64) -> Result<(), MapError> {
+        hash_map::insert(&mut self.inner, socket.as_raw_fd(), value, flags)
+    }
+
+    /// Deletes the value stored for the given socket.
+    pub fn delete<I: AsRawFd>(&mut self, socket: &I) -> Result<(), MapError> {
+        hash_map::remove(&mut self.inner, &socket.as_raw_fd())
+    }
+}
+
+impl<T: Deref<Target = Map>, V> AsRawFd for SkStorageMap<T, V> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
+impl<V: Pod> TryFrom<MapRef> for SkStorageMap<MapRef, V> {
+    type Error = MapError;
+
+    fn try_from(a: MapRef) -> Result<SkStorageMap<MapRef, V>, MapError> {
+        SkStorageMap::new(a)
+    }
+}
+
+impl<V: Pod> TryFrom<MapRefMut> for SkStorageMap<MapRefMut, V> {
+    type Error = MapError;
+
+    fn try_from(a: MapRefMut) -> Result<SkStorageMap<MapRefMut, V>, MapError> {
+        SkStorageMap::new(a)
+    }
+}
+
+impl<V: Pod> TryFrom<OwnedMap> for SkStorageMap<OwnedMap, V> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<SkStorageMap<OwnedMap, V>, MapError> {
+        SkStorageMap::new(a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use libc::{EFAULT, ENOENT};
+
+    use crate::{
+        bpf_map_def,
+        generated::{
+            bpf_attr, bpf_cmd,
+            bpf_map_type::{BPF_MAP_TYPE_HASH, BPF_MAP_TYPE_SK_STORAGE},
+        },
+        obj,
+        sys::{override_syscall, SysResult, Syscall},
+    };
+
+    use super::*;
+
+    struct FakeSocket(RawFd);
+
+    impl AsRawFd for FakeSocket {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0
+        }
+    }
+
+    fn new_obj_map(map_type: u32) -> obj::Map {
+        obj::Map {
+            def: bpf_map_def {
+                map_type,
+                key_size: 4,
+                value_size: 8,
+                max_entries: 0,
+                ..Default::default()
+            },
+            section_index: 0,
+            data: Vec::new(),
+            kind: obj::MapKind::Other,
+        }
+    }
+
+    fn sys_error(value: i32) -> SysResult {
+        Err((-1, io::Error::from_raw_os_error(value)))
+    }
+
+    fn lookup_elem(attr: &bpf_attr) -> SysResult {
+        let key = unsafe { *(attr.__bindgen_anon_2.key as *const RawFd) };
+        if key != 7 {
+            return sys_error(ENOENT);
+        }
+        let value = unsafe { attr.__bindgen_anon_2.__bindgen_anon_1.value } as *mut u64;
+        unsafe { *value = 42 };
+
+        Ok(0)
+    }
+
+    #[test]
+    fn test_try_from_wrong_map() {
+        let map = Map {
+            obj: new_obj_map(BPF_MAP_TYPE_HASH as u32),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+
+        assert!(matches!(
+            SkStorageMap::<_, u64>::new(&map),
+            Err(MapError::InvalidMapType { .. })
+        ));
+    }
+
+    #[test]
+    fn test_get() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => lookup_elem(attr),
+            _ => sys_error(EFAULT),
+        });
+
+        let map = Map {
+            obj: new_obj_map(BPF_MAP_TYPE_SK_STORAGE as u32),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let storage = SkStorageMap::<_, u64>::new(&map).unwrap();
+
+        assert!(matches!(storage.get(&FakeSocket(7), 0), Ok(Some(42))));
+        assert!(matches!(storage.get(&FakeSocket(8), 0), Ok(None)));
+    }
+
+    #[test]
+    fn test_delete() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_DELETE_ELEM,
+                attr,
+            } if unsafe { *(attr.__bindgen_anon_2.key as *const RawFd) } == 7 => Ok(1),
+            _ => sys_error(EFAULT),
+        });
+
+        let mut map = Map {
+            obj: new_obj_map(BPF_MAP_TYPE_SK_STORAGE as u32),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let mut storage = SkStorageMap::<_, u64>::new(&mut map).unwrap();
+
+        assert!(storage.delete(&FakeSocket(7)).is_ok());
+    }
+}
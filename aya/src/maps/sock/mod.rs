@@ -4,7 +4,12 @@ mod sock_map;
 
 use std::os::unix::io::RawFd;
 
-use crate::maps::MapError;
+use crate::{
+    generated::bpf_attach_type,
+    maps::MapError,
+    programs::{Link, ProgAttachLink, ProgramError},
+    sys::bpf_prog_attach,
+};
 
 pub use sock_hash::SockHash;
 pub use sock_map::SockMap;
@@ -14,3 +19,35 @@ pub trait SocketMap {
     /// Returns a [`Result`] containg the map fd or an error if there is none
     fn fd_or_err(&self) -> Result<RawFd, MapError>;
 }
+
+/// A program attached to a socket map.
+///
+/// The program is detached from the map when the link is dropped.
+#[derive(Debug)]
+pub struct SockMapLink {
+    inner: ProgAttachLink,
+}
+
+// attaches the sk_skb program `prog_fd` to the socket map `map_fd`
+pub(crate) fn attach_sk_skb(
+    map_fd: RawFd,
+    prog_fd: RawFd,
+    attach_type: bpf_attach_type,
+) -> Result<SockMapLink, MapError> {
+    bpf_prog_attach(prog_fd, map_fd, attach_type, 0).map_err(|(code, io_error)| {
+        MapError::SyscallError {
+            call: "bpf_prog_attach".to_owned(),
+            code,
+            io_error,
+        }
+    })?;
+    Ok(SockMapLink {
+        inner: ProgAttachLink::new(prog_fd, map_fd, attach_type),
+    })
+}
+
+impl Link for SockMapLink {
+    fn detach(&mut self) -> Result<(), ProgramError> {
+        self.inner.detach()
+    }
+}
@@ -9,6 +9,7 @@ use crate::{
     generated::bpf_map_type::BPF_MAP_TYPE_SOCKHASH,
     maps::{
         hash_map, sock::SocketMap, IterableMap, Map, MapError, MapIter, MapKeys, MapRef, MapRefMut,
+        OwnedMap,
     },
     sys::bpf_map_lookup_elem,
     Pod,
@@ -86,7 +87,14 @@ impl<T: Deref<Target = Map>, K: Pod> SockHash<T, K> {
         })
     }
 
-    /// Returns the fd of the socket stored at the given key.
+    /// Returns the value stored at the given key.
+    ///
+    /// Looking up a socket from userspace doesn't return a file descriptor
+    /// that can be used to operate on the socket. Kernels before 5.10 reject
+    /// the lookup, while newer kernels return the socket cookie when the map
+    /// value is 8 bytes wide, so this is mostly useful to check whether a key
+    /// is present in the map. Returns [`MapError::KeyNotFound`] if the key
+    /// isn't present.
     pub fn get(&self, key: &K, flags: u64) -> Result<RawFd, MapError> {
         let fd = self.inner.deref().fd_or_err()?;
         let value = bpf_map_lookup_elem(fd, key, flags).map_err(|(code, io_error)| {
@@ -107,6 +115,9 @@ impl<T: Deref<Target = Map>, K: Pod> SockHash<T, K> {
 
     /// An iterator visiting all keys in arbitrary order. The iterator element
     /// type is `Result<K, MapError>`.
+    ///
+    /// This can be used to enumerate the connections currently stored in the
+    /// map.
     pub fn keys(&self) -> MapKeys<'_, K> {
         MapKeys::new(&self.inner)
     }
@@ -140,6 +151,13 @@ impl<T: DerefMut<Target = Map>, K: Pod> SocketMap for SockHash<T, K> {
     }
 }
 
+impl<T: Deref<Target = Map>, K> AsRawFd for SockHash<T, K> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
 impl<K: Pod> TryFrom<MapRef> for SockHash<MapRef, K> {
     type Error = MapError;
 
@@ -155,3 +173,11 @@ impl<K: Pod> TryFrom<MapRefMut> for SockHash<MapRefMut, K> {
         SockHash::new(a)
     }
 }
+
+impl<K: Pod> TryFrom<OwnedMap> for SockHash<OwnedMap, K> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<SockHash<OwnedMap, K>, MapError> {
+        SockHash::new(a)
+    }
+}
@@ -8,8 +8,14 @@ use std::{
 };
 
 use crate::{
-    generated::bpf_map_type::BPF_MAP_TYPE_SOCKMAP,
-    maps::{sock::SocketMap, Map, MapError, MapKeys, MapRef, MapRefMut},
+    generated::{
+        bpf_attach_type::{BPF_SK_SKB_STREAM_PARSER, BPF_SK_SKB_STREAM_VERDICT},
+        bpf_map_type::BPF_MAP_TYPE_SOCKMAP,
+    },
+    maps::{
+        sock::{attach_sk_skb, SockMapLink, SocketMap},
+        Map, MapError, MapKeys, MapRef, MapRefMut, OwnedMap,
+    },
     sys::{bpf_map_delete_elem, bpf_map_update_elem},
 };
 
@@ -75,6 +81,29 @@ impl<T: Deref<Target = Map>> SockMap<T> {
         MapKeys::new(&self.inner)
     }
 
+    /// Attaches the stream verdict program `prog_fd` to the map.
+    ///
+    /// The verdict program decides what happens to the messages received by
+    /// the sockets in the map, typically redirecting them to another socket
+    /// with `bpf_sk_redirect_map()`. The program is detached when the
+    /// returned link is dropped. [`SkSkb::attach`] attaches programs the
+    /// same way.
+    ///
+    /// [`SkSkb::attach`]: crate::programs::SkSkb::attach
+    pub fn attach_stream_verdict(&self, prog_fd: RawFd) -> Result<SockMapLink, MapError> {
+        attach_sk_skb(self.inner.fd_or_err()?, prog_fd, BPF_SK_SKB_STREAM_VERDICT)
+    }
+
+    /// Attaches the stream parser program `prog_fd` to the map.
+    ///
+    /// The parser returns the length of the next message in the stream of
+    /// the sockets in the map and is used together with a stream verdict
+    /// program, see [`attach_stream_verdict`](Self::attach_stream_verdict).
+    /// The program is detached when the returned link is dropped.
+    pub fn attach_stream_parser(&self, prog_fd: RawFd) -> Result<SockMapLink, MapError> {
+        attach_sk_skb(self.inner.fd_or_err()?, prog_fd, BPF_SK_SKB_STREAM_PARSER)
+    }
+
     fn check_bounds(&self, index: u32) -> Result<(), MapError> {
         let max_entries = self.inner.obj.def.max_entries;
         if index >= self.inner.obj.def.max_entries {
@@ -88,7 +117,7 @@ impl<T: Deref<Target = Map>> SockMap<T> {
 impl<T: Deref<Target = Map> + DerefMut<Target = Map>> SockMap<T> {
     /// Stores a socket into the map.
     pub fn set<I: AsRawFd>(&mut self, index: u32, socket: &I, flags: u64) -> Result<(), MapError> {
-        let fd = self.inner.fd_or_err()?;
+        let fd = self.inner.writable_fd_or_err()?;
         self.check_bounds(index)?;
         bpf_map_update_elem(fd, &index, &socket.as_raw_fd(), flags).map_err(
             |(code, io_error)| MapError::SyscallError {
@@ -102,7 +131,7 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>> SockMap<T> {
 
     /// Removes the socket stored at `index` from the map.
     pub fn clear_index(&mut self, index: &u32) -> Result<(), MapError> {
-        let fd = self.inner.fd_or_err()?;
+        let fd = self.inner.writable_fd_or_err()?;
         self.check_bounds(*index)?;
         bpf_map_delete_elem(fd, index)
             .map(|_| ())
@@ -120,6 +149,13 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>> SocketMap for SockMap<T> {
     }
 }
 
+impl<T: Deref<Target = Map>> AsRawFd for SockMap<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
 impl TryFrom<MapRef> for SockMap<MapRef> {
     type Error = MapError;
 
@@ -135,3 +171,11 @@ impl TryFrom<MapRefMut> for SockMap<MapRefMut> {
         SockMap::new(a)
     }
 }
+
+impl TryFrom<OwnedMap> for SockMap<OwnedMap> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<SockMap<OwnedMap>, MapError> {
+        SockMap::new(a)
+    }
+}
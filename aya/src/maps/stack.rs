@@ -4,11 +4,12 @@ use std::{
     marker::PhantomData,
     mem,
     ops::{Deref, DerefMut},
+    os::unix::io::{AsRawFd, RawFd},
 };
 
 use crate::{
     generated::bpf_map_type::BPF_MAP_TYPE_STACK,
-    maps::{Map, MapError, MapRef, MapRefMut},
+    maps::{Map, MapError, MapRef, MapRefMut, OwnedMap},
     sys::{bpf_map_lookup_and_delete_elem, bpf_map_update_elem},
     Pod,
 };
@@ -80,7 +81,7 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>, V: Pod> Stack<T, V> {
     /// Returns [`MapError::ElementNotFound`] if the stack is empty, [`MapError::SyscallError`]
     /// if `bpf_map_lookup_and_delete_elem` fails.
     pub fn pop(&mut self, flags: u64) -> Result<V, MapError> {
-        let fd = self.inner.fd_or_err()?;
+        let fd = self.inner.writable_fd_or_err()?;
 
         let value = bpf_map_lookup_and_delete_elem::<u32, _>(fd, None, flags).map_err(
             |(code, io_error)| MapError::SyscallError {
@@ -98,7 +99,7 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>, V: Pod> Stack<T, V> {
     ///
     /// [`MapError::SyscallError`] if `bpf_map_update_elem` fails.
     pub fn push(&mut self, value: V, flags: u64) -> Result<(), MapError> {
-        let fd = self.inner.fd_or_err()?;
+        let fd = self.inner.writable_fd_or_err()?;
         bpf_map_update_elem(fd, &0, &value, flags).map_err(|(code, io_error)| {
             MapError::SyscallError {
                 call: "bpf_map_update_elem".to_owned(),
@@ -110,6 +111,13 @@ impl<T: Deref<Target = Map> + DerefMut<Target = Map>, V: Pod> Stack<T, V> {
     }
 }
 
+impl<T: Deref<Target = Map>, V: Pod> AsRawFd for Stack<T, V> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
 impl<V: Pod> TryFrom<MapRef> for Stack<MapRef, V> {
     type Error = MapError;
 
@@ -125,3 +133,11 @@ impl<V: Pod> TryFrom<MapRefMut> for Stack<MapRefMut, V> {
         Stack::new(a)
     }
 }
+
+impl<V: Pod> TryFrom<OwnedMap> for Stack<OwnedMap, V> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<Stack<OwnedMap, V>, MapError> {
+        Stack::new(a)
+    }
+}
@@ -2,13 +2,22 @@
 //!
 //! See [`StackTraceMap`] for documentation and examples.
 use std::{
-    collections::BTreeMap, convert::TryFrom, fs, io, mem, ops::Deref, path::Path, str::FromStr,
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    fs, io, mem,
+    ops::Deref,
+    os::unix::io::{AsRawFd, RawFd},
+    path::Path,
+    str::FromStr,
 };
 
+use object::{Object, ObjectSegment, ObjectSymbol, SymbolKind};
+
 use crate::{
     generated::bpf_map_type::BPF_MAP_TYPE_STACK_TRACE,
-    maps::{IterableMap, Map, MapError, MapIter, MapKeys, MapRef, MapRefMut},
+    maps::{IterableMap, Map, MapError, MapIter, MapKeys, MapRef, MapRefMut, OwnedMap},
     sys::bpf_map_lookup_elem_ptr,
+    util::{kernel_symbols, process_mapping_ranges, ProcessMapping},
 };
 
 /// A hash map of kernel or user space stack traces.
@@ -16,7 +25,8 @@ use crate::{
 /// Stack trace maps can be used to store stack traces captured by eBPF programs, which can be
 /// useful for profiling, to associate a trace to an event, etc. You can capture traces calling
 /// `stack_id = bpf_get_stackid(ctx, map, flags)` from eBPF, and then you can retrieve the traces
-/// from their stack ids.
+/// from their stack ids. Pass `BPF_F_USER_STACK` in `flags` to capture the user space stack of
+/// the current task instead of the kernel stack.
 ///
 /// # Minimum kernel version
 ///
@@ -49,7 +59,8 @@ use crate::{
 ///
 /// // here we resolve symbol names using kernel symbols. If this was a user space stack (for
 /// // example captured from a uprobe), you'd have to load the symbols using some other mechanism
-/// // (eg loading the target binary debuginfo)
+/// // (eg loading the target binary debuginfo), or use `aya::util::process_mappings` to find
+/// // the binary each frame belongs to
 /// for frame in stack_trace.resolve(&ksyms).frames() {
 ///     println!(
 ///         "{:#x} {}",
@@ -138,6 +149,84 @@ impl<T: Deref<Target = Map>> StackTraceMap<T> {
         })
     }
 
+    /// Returns the kernel stack trace with the given stack_id, with its frames
+    /// resolved to kernel symbols from `/proc/kallsyms`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`get`](StackTraceMap::get), or
+    /// [`MapError::SyscallError`] if the kernel symbols can't be loaded.
+    pub fn get_kernel_trace(&self, stack_id: &u32) -> Result<Vec<KernelFrame>, MapError> {
+        let trace = self.get(stack_id, 0)?;
+        let ksyms = kernel_symbols().map_err(|io_error| proc_error("kernel_symbols", io_error))?;
+
+        Ok(trace
+            .frames
+            .iter()
+            .map(|frame| {
+                let (symbol, offset) = match ksyms.range(..=frame.ip).next_back() {
+                    Some((addr, name)) => (Some(name.clone()), frame.ip - addr),
+                    None => (None, 0),
+                };
+                KernelFrame {
+                    ip: frame.ip,
+                    symbol,
+                    offset,
+                }
+            })
+            .collect())
+    }
+
+    /// Returns the user space stack trace with the given stack_id, captured
+    /// from the process `pid`.
+    ///
+    /// The frames are resolved to the binary or library they belong to with
+    /// `/proc/<pid>/maps`, then to the ELF symbols of that file. The process
+    /// must still be running, and frames in stripped binaries are only
+    /// resolved to their file.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`get`](StackTraceMap::get), or
+    /// [`MapError::SyscallError`] if the mappings of the process can't be read.
+    pub fn get_user_trace(&self, stack_id: &u32, pid: u32) -> Result<Vec<UserFrame>, MapError> {
+        let trace = self.get(stack_id, 0)?;
+        let mut symbolizer = UserSymbolizer::new(pid)
+            .map_err(|io_error| proc_error("process_mappings", io_error))?;
+
+        Ok(trace
+            .frames
+            .iter()
+            .map(|frame| symbolizer.resolve(frame.ip))
+            .collect())
+    }
+
+    /// Returns the stack trace with the given stack_id, with each frame
+    /// resolved either to a symbol of the process `pid` or to a kernel
+    /// symbol.
+    ///
+    /// Frames in the executable mappings of the process are resolved like
+    /// in [`get_user_trace`](StackTraceMap::get_user_trace), the others to
+    /// kernel symbols like in [`get_kernel_trace`](StackTraceMap::get_kernel_trace).
+    /// This is useful when the eBPF program doesn't record whether the trace
+    /// was captured with `BPF_F_USER_STACK`.
+    pub fn get_combined_trace(&self, stack_id: &u32, pid: u32) -> Result<StackTrace, MapError> {
+        let mut trace = self.get(stack_id, 0)?;
+        let ksyms = kernel_symbols().map_err(|io_error| proc_error("kernel_symbols", io_error))?;
+        let mut symbolizer = UserSymbolizer::new(pid)
+            .map_err(|io_error| proc_error("process_mappings", io_error))?;
+
+        for frame in trace.frames.iter_mut() {
+            frame.symbol_name = if symbolizer.mapping(frame.ip).is_some() {
+                symbolizer.resolve(frame.ip).symbol
+            } else {
+                ksyms.range(..=frame.ip).next_back().map(|(_, s)| s.clone())
+            };
+        }
+
+        Ok(trace)
+    }
+
     /// An iterator visiting all (`stack_id`, `stack_trace`) pairs in arbitrary order. The
     /// iterator item type is `Result<(u32, StackTrace), MapError>`.
     pub fn iter(&self) -> MapIter<'_, u32, StackTrace, Self> {
@@ -161,6 +250,13 @@ impl<T: Deref<Target = Map>> IterableMap<u32, StackTrace> for StackTraceMap<T> {
     }
 }
 
+impl<T: Deref<Target = Map>> AsRawFd for StackTraceMap<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
 impl TryFrom<MapRef> for StackTraceMap<MapRef> {
     type Error = MapError;
 
@@ -177,6 +273,14 @@ impl TryFrom<MapRefMut> for StackTraceMap<MapRefMut> {
     }
 }
 
+impl TryFrom<OwnedMap> for StackTraceMap<OwnedMap> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<StackTraceMap<OwnedMap>, MapError> {
+        StackTraceMap::new(a)
+    }
+}
+
 impl<'a, T: Deref<Target = Map>> IntoIterator for &'a StackTraceMap<T> {
     type Item = Result<(u32, StackTrace), MapError>;
     type IntoIter = MapIter<'a, u32, StackTrace, StackTraceMap<T>>;
@@ -200,7 +304,9 @@ impl StackTrace {
     ///
     /// You can use [`util::kernel_symbols()`](crate::util::kernel_symbols) to load kernel symbols. For
     /// user-space traces you need to provide the symbols, for example loading
-    /// them from debug info.
+    /// them from debug info. [`util::process_mappings()`](crate::util::process_mappings)
+    /// can be used to resolve user-space frames to the binary or library they
+    /// belong to.
     pub fn resolve(&mut self, symbols: &BTreeMap<u64, String>) -> &StackTrace {
         for frame in self.frames.iter_mut() {
             frame.symbol_name = symbols
@@ -229,9 +335,188 @@ pub struct StackFrame {
     pub symbol_name: Option<String>,
 }
 
+/// A kernel stack frame, returned by [`StackTraceMap::get_kernel_trace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelFrame {
+    /// The instruction pointer of this frame.
+    pub ip: u64,
+    /// The kernel symbol containing `ip`, if any.
+    pub symbol: Option<String>,
+    /// The offset of `ip` from the start of `symbol`, or 0 if the symbol
+    /// wasn't found.
+    pub offset: u64,
+}
+
+/// A user space stack frame, returned by [`StackTraceMap::get_user_trace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserFrame {
+    /// The instruction pointer of this frame.
+    pub ip: u64,
+    /// The path of the binary or library mapped at `ip`, if any.
+    pub path: Option<String>,
+    /// The ELF symbol containing `ip`, if any.
+    pub symbol: Option<String>,
+    /// The offset of `ip` from the start of `symbol`. If the symbol wasn't
+    /// found, the offset of `ip` in the file at `path`, which can be passed to
+    /// tools like `addr2line`, or 0 if `ip` isn't in a file backed mapping.
+    pub offset: u64,
+}
+
+// Resolves the addresses of a process to the symbols of the mapped files,
+// parsing each file at most once.
+pub(crate) struct UserSymbolizer {
+    mappings: Vec<ProcessMapping>,
+    objects: HashMap<String, Option<ObjectSymbols>>,
+}
+
+impl UserSymbolizer {
+    pub(crate) fn new(pid: u32) -> Result<UserSymbolizer, io::Error> {
+        let mappings = process_mapping_ranges(pid)?;
+        Ok(UserSymbolizer {
+            mappings,
+            objects: HashMap::new(),
+        })
+    }
+
+    fn mapping(&self, ip: u64) -> Option<&ProcessMapping> {
+        self.mappings.iter().find(|m| ip >= m.start && ip < m.end)
+    }
+
+    pub(crate) fn resolve(&mut self, ip: u64) -> UserFrame {
+        let (path, file_offset) = match self.mapping(ip) {
+            Some(m) => (m.path.clone(), ip - m.start + m.offset),
+            None => {
+                return UserFrame {
+                    ip,
+                    path: None,
+                    symbol: None,
+                    offset: 0,
+                }
+            }
+        };
+        let symbol = self
+            .objects
+            .entry(path.clone())
+            // binaries that were deleted or can't be read are left unresolved
+            .or_insert_with(|| {
+                fs::read(&path)
+                    .ok()
+                    .and_then(|data| ObjectSymbols::parse(&data))
+            })
+            .as_ref()
+            .and_then(|object| object.resolve(file_offset));
+        let (symbol, offset) = match symbol {
+            Some((name, offset)) => (Some(name), offset),
+            None => (None, file_offset),
+        };
+
+        UserFrame {
+            ip,
+            path: Some(path),
+            symbol,
+            offset,
+        }
+    }
+}
+
+// The function symbols of an ELF file and the segments needed to translate
+// file offsets to their addresses.
+struct ObjectSymbols {
+    // (address, file offset, size)
+    segments: Vec<(u64, u64, u64)>,
+    symbols: BTreeMap<u64, String>,
+}
+
+impl ObjectSymbols {
+    fn parse(data: &[u8]) -> Option<ObjectSymbols> {
+        let obj = object::read::File::parse(data).ok()?;
+        let segments = obj
+            .segments()
+            .map(|segment| {
+                let (offset, size) = segment.file_range();
+                (segment.address(), offset, size)
+            })
+            .collect();
+        let symbols = obj
+            .symbols()
+            .chain(obj.dynamic_symbols())
+            .filter(|sym| sym.kind() == SymbolKind::Text && sym.address() != 0)
+            .filter_map(|sym| Some((sym.address(), sym.name().ok()?.to_owned())))
+            .collect();
+
+        Some(ObjectSymbols { segments, symbols })
+    }
+
+    // Returns the symbol containing the given file offset and the offset
+    // from its start.
+    fn resolve(&self, file_offset: u64) -> Option<(String, u64)> {
+        let address = self
+            .segments
+            .iter()
+            .find(|(_, offset, size)| file_offset >= *offset && file_offset < offset + size)
+            .map(|(address, offset, _)| file_offset - offset + address)?;
+        self.symbols
+            .range(..=address)
+            .next_back()
+            .map(|(sym_addr, name)| (name.clone(), address - sym_addr))
+    }
+}
+
+fn proc_error(call: &str, io_error: io::Error) -> MapError {
+    MapError::SyscallError {
+        call: call.to_owned(),
+        code: -1,
+        io_error,
+    }
+}
+
 fn sysctl<T: FromStr>(key: &str) -> Result<T, io::Error> {
     let val = fs::read_to_string(Path::new("/proc/sys").join(key))?;
     val.trim()
         .parse::<T>()
         .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, val))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_symbols_resolve() {
+        let object = ObjectSymbols {
+            segments: vec![(0x401000, 0x1000, 0x2000)],
+            symbols: vec![
+                (0x401000, "_start".to_owned()),
+                (0x401100, "main".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        assert_eq!(object.resolve(0x1010), Some(("_start".to_owned(), 0x10)));
+        assert_eq!(object.resolve(0x1180), Some(("main".to_owned(), 0x80)));
+        // outside of the loaded segments
+        assert_eq!(object.resolve(0x4000), None);
+    }
+
+    #[test]
+    fn test_resolve_self() {
+        // the test binary defines this function
+        let ip = test_resolve_self as fn() as usize as u64;
+        let mut symbolizer = UserSymbolizer::new(std::process::id()).unwrap();
+        let frame = symbolizer.resolve(ip);
+        assert!(frame.path.is_some());
+        assert!(frame.symbol.unwrap().contains("test_resolve_self"));
+        assert_eq!(frame.offset, 0);
+
+        assert_eq!(
+            symbolizer.resolve(0),
+            UserFrame {
+                ip: 0,
+                path: None,
+                symbol: None,
+                offset: 0,
+            }
+        );
+    }
+}
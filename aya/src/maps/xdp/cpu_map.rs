@@ -0,0 +1,357 @@
+//! An array of CPUs that XDP programs can redirect packets to.
+use std::{
+    convert::TryFrom,
+    ops::{Deref, DerefMut},
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use crate::{
+    generated::bpf_map_type::BPF_MAP_TYPE_CPUMAP,
+    maps::{xdp, Map, MapError, MapRef, MapRefMut, OwnedMap},
+};
+
+/// An array of CPUs.
+///
+/// XDP programs can redirect packets to the CPUs of a `CpuMap` with
+/// `bpf_redirect_map()`. Each entry is the CPU at the same index, with a
+/// queue of the given size. The redirected packets are processed by the
+/// network stack on that CPU.
+///
+/// An entry can also run an XDP program on the packets redirected to it, see
+/// [`Xdp::attach_to_cpumap`](crate::programs::Xdp::attach_to_cpumap).
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 4.15, or 5.9
+/// for running programs on the entries.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let bpf = aya::Bpf::load(&[])?;
+/// use aya::{maps::CpuMap, util::nr_cpus};
+/// use std::convert::TryFrom;
+///
+/// let mut cpus = CpuMap::try_from(bpf.map_mut("CPUS")?)?;
+/// for cpu in 0..nr_cpus()? as u32 {
+///     cpus.set(cpu, 2048, None, 0)?;
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[doc(alias = "BPF_MAP_TYPE_CPUMAP")]
+pub struct CpuMap<T: Deref<Target = Map>> {
+    inner: T,
+}
+
+/// An entry of a [`CpuMap`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CpuMapValue {
+    /// The size of the queue of the CPU.
+    pub queue_size: u32,
+    /// The id of the program run on the redirected packets, if any.
+    pub prog_id: Option<u32>,
+}
+
+impl<T: Deref<Target = Map>> CpuMap<T> {
+    fn new(map: T) -> Result<CpuMap<T>, MapError> {
+        let map_type = map.obj.def.map_type;
+        if map_type != BPF_MAP_TYPE_CPUMAP as u32 {
+            return Err(MapError::InvalidMapType { map_type });
+        }
+        xdp::check_map(&map)?;
+
+        Ok(CpuMap { inner: map })
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> u32 {
+        self.inner.obj.def.max_entries
+    }
+
+    /// Returns the entry of the CPU at `cpu_index`.
+    pub fn get(&self, cpu_index: u32, flags: u64) -> Result<CpuMapValue, MapError> {
+        xdp::check_bounds(&self.inner, cpu_index)?;
+        let (queue_size, prog_id) = xdp::get(&self.inner, cpu_index, flags)?;
+        Ok(CpuMapValue {
+            queue_size,
+            prog_id,
+        })
+    }
+}
+
+impl<T: Deref<Target = Map> + DerefMut<Target = Map>> CpuMap<T> {
+    /// Sets the queue size of the CPU at `cpu_index`, and optionally the XDP
+    /// program to run on the packets redirected to it.
+    ///
+    /// `program` must be the fd of an [`Xdp`](crate::programs::Xdp) program
+    /// loaded from a `xdp/cpumap` section. Setting a program requires a map
+    /// whose values have room for it, with a `value_size` of 8.
+    pub fn set(
+        &mut self,
+        cpu_index: u32,
+        queue_size: u32,
+        program: Option<RawFd>,
+        flags: u64,
+    ) -> Result<(), MapError> {
+        xdp::check_bounds(&self.inner, cpu_index)?;
+        xdp::set(&self.inner, cpu_index, queue_size, program, flags)
+    }
+
+    /// Sets the queue size of the CPU at `cpu_index` and the XDP program to
+    /// run on the packets redirected to it.
+    ///
+    /// This is the same as [`set`](CpuMap::set) with a program, except that
+    /// `prog_fd` is checked to be an XDP program first.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::InvalidProgramType`] if `prog_fd` isn't an XDP
+    /// program.
+    pub fn set_with_bpf_prog(
+        &mut self,
+        cpu_index: u32,
+        queue_size: u32,
+        prog_fd: RawFd,
+    ) -> Result<(), MapError> {
+        xdp::check_bounds(&self.inner, cpu_index)?;
+        xdp::check_program(prog_fd)?;
+        xdp::set(&self.inner, cpu_index, queue_size, Some(prog_fd), 0)
+    }
+}
+
+impl<T: Deref<Target = Map>> AsRawFd for CpuMap<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
+impl TryFrom<MapRef> for CpuMap<MapRef> {
+    type Error = MapError;
+
+    fn try_from(a: MapRef) -> Result<CpuMap<MapRef>, MapError> {
+        CpuMap::new(a)
+    }
+}
+
+impl TryFrom<MapRefMut> for CpuMap<MapRefMut> {
+    type Error = MapError;
+
+    fn try_from(a: MapRefMut) -> Result<CpuMap<MapRefMut>, MapError> {
+        CpuMap::new(a)
+    }
+}
+
+impl TryFrom<OwnedMap> for CpuMap<OwnedMap> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<CpuMap<OwnedMap>, MapError> {
+        CpuMap::new(a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    use libc::{EFAULT, ENOENT};
+
+    use crate::{
+        bpf_map_def,
+        generated::{
+            bpf_attr, bpf_cmd,
+            bpf_map_type::{BPF_MAP_TYPE_CPUMAP, BPF_MAP_TYPE_DEVMAP},
+            bpf_prog_info,
+            bpf_prog_type::{BPF_PROG_TYPE_KPROBE, BPF_PROG_TYPE_XDP},
+        },
+        obj,
+        sys::{override_syscall, SysResult, Syscall},
+    };
+
+    use super::*;
+
+    fn new_obj_map(map_type: u32, value_size: u32) -> obj::Map {
+        obj::Map {
+            def: bpf_map_def {
+                map_type,
+                key_size: 4,
+                value_size,
+                max_entries: 4,
+                ..Default::default()
+            },
+            section_index: 0,
+            data: Vec::new(),
+            kind: obj::MapKind::Other,
+        }
+    }
+
+    fn new_map(value_size: u32) -> Map {
+        Map {
+            obj: new_obj_map(BPF_MAP_TYPE_CPUMAP as u32, value_size),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        }
+    }
+
+    fn sys_error(value: i32) -> SysResult {
+        Err((-1, io::Error::from_raw_os_error(value)))
+    }
+
+    fn key(attr: &bpf_attr) -> u32 {
+        unsafe { *(attr.__bindgen_anon_2.key as *const u32) }
+    }
+
+    fn value(attr: &bpf_attr) -> *mut [u32; 2] {
+        unsafe { attr.__bindgen_anon_2.__bindgen_anon_1.value as *mut [u32; 2] }
+    }
+
+    #[test]
+    fn test_try_from_wrong_map() {
+        let map = Map {
+            obj: new_obj_map(BPF_MAP_TYPE_DEVMAP as u32, 8),
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        assert!(matches!(
+            CpuMap::new(&map),
+            Err(MapError::InvalidMapType { .. })
+        ));
+    }
+
+    #[test]
+    fn test_wrong_value_size() {
+        let map = new_map(2);
+        assert!(matches!(
+            CpuMap::new(&map),
+            Err(MapError::InvalidValueSize { size: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_set_with_program() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } if key(attr) == 1 && unsafe { *value(attr) } == [2048, 7] => Ok(1),
+            _ => sys_error(EFAULT),
+        });
+
+        let mut map = new_map(8);
+        let mut cpus = CpuMap::new(&mut map).unwrap();
+        assert!(cpus.set(1, 2048, Some(7), 0).is_ok());
+        assert!(matches!(
+            cpus.set(4, 2048, None, 0),
+            Err(MapError::OutOfBounds { index: 4, .. })
+        ));
+    }
+
+    // the entry stored by test_set_with_bpf_prog, the queue size in the low
+    // 32 bits and the program in the high 32 bits
+    static ENTRY: AtomicU64 = AtomicU64::new(0);
+
+    #[test]
+    fn test_set_with_bpf_prog() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = if unsafe { attr.info.bpf_fd } == 7 {
+                    BPF_PROG_TYPE_XDP
+                } else {
+                    BPF_PROG_TYPE_KPROBE
+                } as u32;
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } if key(attr) == 1 => {
+                let [queue_size, prog] = unsafe { *value(attr) };
+                ENTRY.store((prog as u64) << 32 | queue_size as u64, Ordering::SeqCst);
+                Ok(1)
+            }
+            // the kernel returns the id of the program in place of its fd,
+            // use the fd as the id
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } if key(attr) == 1 => {
+                let entry = ENTRY.load(Ordering::SeqCst);
+                unsafe { *value(attr) = [entry as u32, (entry >> 32) as u32] };
+                Ok(1)
+            }
+            _ => sys_error(EFAULT),
+        });
+
+        let mut map = new_map(8);
+        let mut cpus = CpuMap::new(&mut map).unwrap();
+        assert!(cpus.set_with_bpf_prog(1, 2048, 7).is_ok());
+        assert_eq!(
+            cpus.get(1, 0).unwrap(),
+            CpuMapValue {
+                queue_size: 2048,
+                prog_id: Some(7)
+            }
+        );
+        assert!(matches!(
+            cpus.set_with_bpf_prog(1, 2048, 8),
+            Err(MapError::InvalidProgramType { prog_type }) if prog_type == BPF_PROG_TYPE_KPROBE as u32
+        ));
+    }
+
+    #[test]
+    fn test_set_program_without_room() {
+        let mut map = new_map(4);
+        let mut cpus = CpuMap::new(&mut map).unwrap();
+        assert!(matches!(
+            cpus.set(1, 2048, Some(7), 0),
+            Err(MapError::InvalidValueSize {
+                size: 4,
+                expected: 8
+            })
+        ));
+    }
+
+    #[test]
+    fn test_get() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => match key(attr) {
+                0 => {
+                    unsafe { *value(attr) = [1024, 0] };
+                    Ok(1)
+                }
+                1 => {
+                    unsafe { *value(attr) = [2048, 33] };
+                    Ok(1)
+                }
+                _ => sys_error(ENOENT),
+            },
+            _ => sys_error(EFAULT),
+        });
+
+        let map = new_map(8);
+        let cpus = CpuMap::new(&map).unwrap();
+        assert_eq!(
+            cpus.get(0, 0).unwrap(),
+            CpuMapValue {
+                queue_size: 1024,
+                prog_id: None
+            }
+        );
+        assert_eq!(cpus.get(1, 0).unwrap().prog_id, Some(33));
+        assert!(matches!(cpus.get(2, 0), Err(MapError::KeyNotFound)));
+    }
+}
@@ -0,0 +1,273 @@
+//! An array of network devices that XDP programs can redirect packets to.
+use std::{
+    convert::TryFrom,
+    ops::{Deref, DerefMut},
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use crate::{
+    generated::bpf_map_type::BPF_MAP_TYPE_DEVMAP,
+    maps::{xdp, Map, MapError, MapRef, MapRefMut, OwnedMap},
+};
+
+/// An array of network devices.
+///
+/// XDP programs can redirect packets to the network devices of a `DevMap`
+/// with `bpf_redirect_map()`. Each entry holds the index of the interface
+/// the packets are transmitted on.
+///
+/// An entry can also run an XDP program on the packets redirected to it,
+/// before they are transmitted, see
+/// [`Xdp::attach_to_devmap`](crate::programs::Xdp::attach_to_devmap).
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 4.14, or 5.8
+/// for running programs on the entries.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let bpf = aya::Bpf::load(&[])?;
+/// use aya::maps::DevMap;
+/// use std::convert::TryFrom;
+///
+/// let mut devices = DevMap::try_from(bpf.map_mut("DEVICES")?)?;
+/// // redirect the packets sent to index 0 to the interface with index 2
+/// devices.set(0, 2, None, 0)?;
+/// # Ok::<(), aya::BpfError>(())
+/// ```
+#[doc(alias = "BPF_MAP_TYPE_DEVMAP")]
+pub struct DevMap<T: Deref<Target = Map>> {
+    inner: T,
+}
+
+/// An entry of a [`DevMap`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DevMapValue {
+    /// The index of the interface packets are transmitted on.
+    pub ifindex: u32,
+    /// The id of the program run on the redirected packets, if any.
+    pub prog_id: Option<u32>,
+}
+
+impl<T: Deref<Target = Map>> DevMap<T> {
+    fn new(map: T) -> Result<DevMap<T>, MapError> {
+        let map_type = map.obj.def.map_type;
+        if map_type != BPF_MAP_TYPE_DEVMAP as u32 {
+            return Err(MapError::InvalidMapType { map_type });
+        }
+        xdp::check_map(&map)?;
+
+        Ok(DevMap { inner: map })
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> u32 {
+        self.inner.obj.def.max_entries
+    }
+
+    /// Returns the entry at `index`.
+    pub fn get(&self, index: u32, flags: u64) -> Result<DevMapValue, MapError> {
+        xdp::check_bounds(&self.inner, index)?;
+        let (ifindex, prog_id) = xdp::get(&self.inner, index, flags)?;
+        Ok(DevMapValue { ifindex, prog_id })
+    }
+}
+
+impl<T: Deref<Target = Map> + DerefMut<Target = Map>> DevMap<T> {
+    /// Sets the interface of the entry at `index`, and optionally the XDP
+    /// program to run on the packets redirected to it.
+    ///
+    /// `program` must be the fd of an [`Xdp`](crate::programs::Xdp) program
+    /// loaded from a `xdp/devmap` section. Setting a program requires a map
+    /// whose values have room for it, with a `value_size` of 8.
+    pub fn set(
+        &mut self,
+        index: u32,
+        ifindex: u32,
+        program: Option<RawFd>,
+        flags: u64,
+    ) -> Result<(), MapError> {
+        xdp::check_bounds(&self.inner, index)?;
+        xdp::set(&self.inner, index, ifindex, program, flags)
+    }
+
+    /// Sets the interface of the entry at `index` and the XDP program to run
+    /// on the packets redirected to it.
+    ///
+    /// This is the same as [`set`](DevMap::set) with a program, except that
+    /// `prog_fd` is checked to be an XDP program first.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::InvalidProgramType`] if `prog_fd` isn't an XDP
+    /// program.
+    pub fn set_with_bpf_prog(
+        &mut self,
+        index: u32,
+        ifindex: u32,
+        prog_fd: RawFd,
+    ) -> Result<(), MapError> {
+        xdp::check_bounds(&self.inner, index)?;
+        xdp::check_program(prog_fd)?;
+        xdp::set(&self.inner, index, ifindex, Some(prog_fd), 0)
+    }
+}
+
+impl<T: Deref<Target = Map>> AsRawFd for DevMap<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        // the fd is checked when the map is created
+        self.inner.fd_or_err().unwrap()
+    }
+}
+
+impl TryFrom<MapRef> for DevMap<MapRef> {
+    type Error = MapError;
+
+    fn try_from(a: MapRef) -> Result<DevMap<MapRef>, MapError> {
+        DevMap::new(a)
+    }
+}
+
+impl TryFrom<MapRefMut> for DevMap<MapRefMut> {
+    type Error = MapError;
+
+    fn try_from(a: MapRefMut) -> Result<DevMap<MapRefMut>, MapError> {
+        DevMap::new(a)
+    }
+}
+
+impl TryFrom<OwnedMap> for DevMap<OwnedMap> {
+    type Error = MapError;
+
+    fn try_from(a: OwnedMap) -> Result<DevMap<OwnedMap>, MapError> {
+        DevMap::new(a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    use libc::EFAULT;
+
+    use crate::{
+        bpf_map_def,
+        generated::{
+            bpf_attr, bpf_cmd,
+            bpf_map_type::{BPF_MAP_TYPE_CPUMAP, BPF_MAP_TYPE_DEVMAP},
+            bpf_prog_info,
+            bpf_prog_type::{BPF_PROG_TYPE_KPROBE, BPF_PROG_TYPE_XDP},
+        },
+        obj,
+        sys::{override_syscall, SysResult, Syscall},
+    };
+
+    use super::*;
+
+    fn new_map(map_type: u32, value_size: u32) -> Map {
+        Map {
+            obj: obj::Map {
+                def: bpf_map_def {
+                    map_type,
+                    key_size: 4,
+                    value_size,
+                    max_entries: 4,
+                    ..Default::default()
+                },
+                section_index: 0,
+                data: Vec::new(),
+                kind: obj::MapKind::Other,
+            },
+            fd: Some(42),
+            pinned: false,
+            frozen: false,
+            name: None,
+        }
+    }
+
+    fn sys_error(value: i32) -> SysResult {
+        Err((-1, io::Error::from_raw_os_error(value)))
+    }
+
+    fn key(attr: &bpf_attr) -> u32 {
+        unsafe { *(attr.__bindgen_anon_2.key as *const u32) }
+    }
+
+    fn value(attr: &bpf_attr) -> *mut [u32; 2] {
+        unsafe { attr.__bindgen_anon_2.__bindgen_anon_1.value as *mut [u32; 2] }
+    }
+
+    #[test]
+    fn test_try_from_wrong_map() {
+        let map = new_map(BPF_MAP_TYPE_CPUMAP as u32, 8);
+        assert!(matches!(
+            DevMap::new(&map),
+            Err(MapError::InvalidMapType { .. })
+        ));
+    }
+
+    // the entry stored by test_set_with_bpf_prog, the ifindex in the low 32
+    // bits and the program in the high 32 bits
+    static ENTRY: AtomicU64 = AtomicU64::new(0);
+
+    #[test]
+    fn test_set_with_bpf_prog() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = if unsafe { attr.info.bpf_fd } == 7 {
+                    BPF_PROG_TYPE_XDP
+                } else {
+                    BPF_PROG_TYPE_KPROBE
+                } as u32;
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } if key(attr) == 1 => {
+                let [ifindex, prog] = unsafe { *value(attr) };
+                ENTRY.store((prog as u64) << 32 | ifindex as u64, Ordering::SeqCst);
+                Ok(1)
+            }
+            // the kernel returns the id of the program in place of its fd,
+            // use the fd as the id
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } if key(attr) == 1 => {
+                let entry = ENTRY.load(Ordering::SeqCst);
+                unsafe { *value(attr) = [entry as u32, (entry >> 32) as u32] };
+                Ok(1)
+            }
+            _ => sys_error(EFAULT),
+        });
+
+        let mut map = new_map(BPF_MAP_TYPE_DEVMAP as u32, 8);
+        let mut devices = DevMap::new(&mut map).unwrap();
+        assert!(devices.set_with_bpf_prog(1, 3, 7).is_ok());
+        assert_eq!(
+            devices.get(1, 0).unwrap(),
+            DevMapValue {
+                ifindex: 3,
+                prog_id: Some(7)
+            }
+        );
+        assert!(matches!(
+            devices.set_with_bpf_prog(1, 3, 8),
+            Err(MapError::InvalidProgramType { prog_type }) if prog_type == BPF_PROG_TYPE_KPROBE as u32
+        ));
+        assert!(matches!(
+            devices.set_with_bpf_prog(4, 3, 7),
+            Err(MapError::OutOfBounds { index: 4, .. })
+        ));
+    }
+}
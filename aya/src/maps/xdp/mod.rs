@@ -0,0 +1,108 @@
+//! XDP redirect maps.
+mod cpu_map;
+mod dev_map;
+
+use std::{mem, os::unix::io::RawFd};
+
+use crate::{
+    generated::bpf_prog_type::BPF_PROG_TYPE_XDP,
+    maps::{Map, MapError},
+    sys::{bpf_map_lookup_elem, bpf_map_update_elem, bpf_obj_get_info_by_fd},
+};
+
+pub use cpu_map::{CpuMap, CpuMapValue};
+pub use dev_map::{DevMap, DevMapValue};
+
+// The values of cpumaps and devmaps are either a single u32 (queue size or
+// ifindex), or a u32 followed by the fd of the program to run on redirected
+// packets. Lookups return the id of the program in place of the fd.
+fn check_map(map: &Map) -> Result<(), MapError> {
+    let expected = mem::size_of::<u32>();
+    let size = map.obj.def.key_size as usize;
+    if size != expected {
+        return Err(MapError::InvalidKeySize { size, expected });
+    }
+
+    let size = map.obj.def.value_size as usize;
+    if size != mem::size_of::<u32>() && size != mem::size_of::<[u32; 2]>() {
+        return Err(MapError::InvalidValueSize {
+            size,
+            expected: mem::size_of::<[u32; 2]>(),
+        });
+    }
+    let _fd = map.fd_or_err()?;
+
+    Ok(())
+}
+
+fn has_program(map: &Map) -> bool {
+    map.obj.def.value_size as usize == mem::size_of::<[u32; 2]>()
+}
+
+fn check_bounds(map: &Map, index: u32) -> Result<(), MapError> {
+    let max_entries = map.obj.def.max_entries;
+    if index >= max_entries {
+        Err(MapError::OutOfBounds { index, max_entries })
+    } else {
+        Ok(())
+    }
+}
+
+// the kernel only checks the type of the program when the entry is updated,
+// checking it first gives a clearer error than EINVAL
+fn check_program(prog_fd: RawFd) -> Result<(), MapError> {
+    let info = bpf_obj_get_info_by_fd(prog_fd).map_err(|io_error| MapError::SyscallError {
+        call: "bpf_obj_get_info_by_fd".to_owned(),
+        code: -1,
+        io_error,
+    })?;
+    if info.type_ != BPF_PROG_TYPE_XDP as u32 {
+        return Err(MapError::InvalidProgramType {
+            prog_type: info.type_,
+        });
+    }
+    Ok(())
+}
+
+fn get(map: &Map, index: u32, flags: u64) -> Result<(u32, Option<u32>), MapError> {
+    let fd = map.fd_or_err()?;
+    let value = if has_program(map) {
+        bpf_map_lookup_elem::<_, [u32; 2]>(fd, &index, flags)
+            .map(|v| v.map(|[value, prog_id]| (value, Some(prog_id).filter(|id| *id != 0))))
+    } else {
+        bpf_map_lookup_elem::<_, u32>(fd, &index, flags).map(|v| v.map(|value| (value, None)))
+    }
+    .map_err(|(code, io_error)| MapError::SyscallError {
+        call: "bpf_map_lookup_elem".to_owned(),
+        code,
+        io_error,
+    })?;
+    value.ok_or(MapError::KeyNotFound)
+}
+
+fn set(
+    map: &Map,
+    index: u32,
+    value: u32,
+    program: Option<RawFd>,
+    flags: u64,
+) -> Result<(), MapError> {
+    let fd = map.writable_fd_or_err()?;
+    let ret = if has_program(map) {
+        // the kernel only looks up fds > 0
+        bpf_map_update_elem(fd, &index, &[value, program.unwrap_or(-1) as u32], flags)
+    } else if program.is_some() {
+        return Err(MapError::InvalidValueSize {
+            size: map.obj.def.value_size as usize,
+            expected: mem::size_of::<[u32; 2]>(),
+        });
+    } else {
+        bpf_map_update_elem(fd, &index, &value, flags)
+    };
+    ret.map_err(|(code, io_error)| MapError::SyscallError {
+        call: "bpf_map_update_elem".to_owned(),
+        code,
+        io_error,
+    })?;
+    Ok(())
+}
@@ -2,7 +2,7 @@ use std::{
     borrow::Cow,
     collections::HashMap,
     convert::TryInto,
-    ffi::{c_void, CStr, CString},
+    ffi::{CStr, CString},
     fs, io, mem,
     path::{Path, PathBuf},
     ptr,
@@ -15,6 +15,7 @@ use object::Endianness;
 use thiserror::Error;
 
 use crate::{
+    bpf::{bpf_map_def, PinningType},
     generated::{btf_enum, btf_ext_header, btf_func_linkage, btf_header, btf_member},
     obj::btf::{relocation::Relocation, BtfKind, BtfType},
     util::bytes_of,
@@ -125,6 +126,13 @@ pub enum BtfError {
         type_id: u32,
     },
 
+    /// the size of a BTF type doesn't fit in a u32
+    #[error("the size of BTF type `{type_id}` overflows")]
+    TypeSizeOverflow {
+        /// type id
+        type_id: u32,
+    },
+
     /// Loading the btf failed
     #[error("the BPF_BTF_LOAD syscall failed. Verifier output: {verifier_log}")]
     LoadError {
@@ -256,6 +264,39 @@ impl Btf {
         })
     }
 
+    // Parses the split BTF of a kernel module, whose type ids and string
+    // offsets continue the ones of `base`, the BTF of the kernel. The
+    // returned BTF contains both the types of `base` and of the module.
+    pub(crate) fn parse_split(
+        data: &[u8],
+        base: &Btf,
+        endianness: Endianness,
+    ) -> Result<Btf, BtfError> {
+        let split = Btf::parse(data, endianness)?;
+        let mut btf = base.clone();
+        btf.strings.extend_from_slice(&split.strings);
+        btf.header.str_len += split.header.str_len;
+        for ty in split.types.types.into_iter().skip(1) {
+            btf.types.push(ty);
+        }
+        btf.header.type_len += split.header.type_len;
+        btf.header.str_off += split.header.type_len;
+        Ok(btf)
+    }
+
+    /// Loads the BTF metadata of the kernel module `module` from
+    /// `/sys/kernel/btf/<module>`.
+    ///
+    /// `base` must be the BTF of the running kernel, eg loaded with
+    /// [`Btf::from_sys_fs`], since the BTF of modules only contains the
+    /// types that are missing from it. The returned BTF contains the types
+    /// of both.
+    pub fn from_sys_fs_module(module: &str, base: &Btf) -> Result<Btf, BtfError> {
+        let path = Path::new("/sys/kernel/btf").join(module);
+        let data = fs::read(&path).map_err(|error| BtfError::FileError { path, error })?;
+        Btf::parse_split(&data, base, Endianness::default())
+    }
+
     fn read_type_info(
         header: &btf_header,
         data: &[u8],
@@ -352,9 +393,158 @@ impl Btf {
         })
     }
 
-    pub(crate) fn type_size(&self, root_type_id: u32) -> Result<usize, BtfError> {
+    // returns the values of the enum `name`, eg `bpf_prog_type` in the BTF of
+    // the kernel
+    pub(crate) fn enum_values(&self, name: &str) -> Option<Vec<i32>> {
+        let type_id = self.id_by_type_name_kind(name, BtfKind::Enum).ok()?;
+        match self.type_by_id(type_id).ok()? {
+            BtfType::Enum(_, members) => Some(members.iter().map(|m| m.val).collect()),
+            _ => None,
+        }
+    }
+
+    // looks up the named types that can be used as map keys and values
+    pub(crate) fn id_by_type_name(&self, name: &str) -> Result<u32, BtfError> {
+        for kind in [
+            BtfKind::Struct,
+            BtfKind::Union,
+            BtfKind::Typedef,
+            BtfKind::Int,
+            BtfKind::Enum,
+            BtfKind::Float,
+        ] {
+            if let Ok(type_id) = self.id_by_type_name_kind(name, kind) {
+                return Ok(type_id);
+            }
+        }
+
+        Err(BtfError::UnknownBtfTypeName {
+            type_name: name.to_string(),
+        })
+    }
+
+    // Returns the key and value type ids of a legacy map annotated with
+    // BPF_ANNOTATE_KV_PAIR, which defines a `struct ____btf_map_<name>`
+    // whose `key` and `value` members have the types of the map.
+    pub(crate) fn map_kv_type_ids(&self, map_name: &str) -> Option<(u32, u32)> {
+        let type_id = self
+            .id_by_type_name_kind(&format!("____btf_map_{}", map_name), BtfKind::Struct)
+            .ok()?;
+        let members = match self.type_by_id(type_id).ok()? {
+            BtfType::Struct(_, members) => members,
+            _ => return None,
+        };
+
+        let mut key_type_id = None;
+        let mut value_type_id = None;
+        for member in members {
+            match self.string_at(member.name_off).ok()?.as_ref() {
+                "key" => key_type_id = Some(member.type_),
+                "value" => value_type_id = Some(member.type_),
+                _ => {}
+            }
+        }
+
+        Some((key_type_id?, value_type_id?))
+    }
+
+    // Returns the definition of a BTF map, a variable of the `.maps` section
+    // whose type is a struct like the ones defined by libbpf's
+    // `__uint(name, val)` and `__type(name, val)` macros: integer members are
+    // `int (*name)[val]` and the key and value members point to their type.
+    pub(crate) fn btf_map_def(&self, map_name: &str) -> Result<bpf_map_def, BtfError> {
+        let datasec_id = self.id_by_type_name_kind(".maps", BtfKind::DataSec)?;
+        let vars = match self.type_by_id(datasec_id)? {
+            BtfType::DataSec(_, vars) => vars,
+            _ => {
+                return Err(BtfError::UnexpectedBtfType {
+                    type_id: datasec_id,
+                })
+            }
+        };
+
+        let mut def_type_id = None;
+        for var in vars {
+            let ty = self.type_by_id(var.type_)?;
+            match ty {
+                BtfType::Var(var_ty, _) => {
+                    if self.type_name(ty)?.as_deref() == Some(map_name) {
+                        // Safety: union
+                        def_type_id = Some(unsafe { var_ty.__bindgen_anon_1.type_ });
+                        break;
+                    }
+                }
+                _ => return Err(BtfError::InvalidDatasec),
+            }
+        }
+        let def_type_id = def_type_id.ok_or_else(|| BtfError::UnknownBtfTypeName {
+            type_name: map_name.to_string(),
+        })?;
+
+        let def_type_id = self.resolve_type(def_type_id)?;
+        let members = match self.type_by_id(def_type_id)? {
+            BtfType::Struct(_, members) => members,
+            _ => {
+                return Err(BtfError::UnexpectedBtfType {
+                    type_id: def_type_id,
+                })
+            }
+        };
+
+        let mut def = bpf_map_def::default();
+        for member in members {
+            match self.string_at(member.name_off)?.as_ref() {
+                "type" => def.map_type = self.map_def_uint(member.type_)?,
+                "key_size" => def.key_size = self.map_def_uint(member.type_)?,
+                "value_size" => def.value_size = self.map_def_uint(member.type_)?,
+                "max_entries" => def.max_entries = self.map_def_uint(member.type_)?,
+                "map_flags" => def.map_flags = self.map_def_uint(member.type_)?,
+                "pinning" => {
+                    def.pinning = match self.map_def_uint(member.type_)? {
+                        0 => PinningType::None,
+                        _ => PinningType::ByName,
+                    }
+                }
+                "key" => def.key_size = self.type_size(self.map_def_pointee(member.type_)?)?,
+                "value" => def.value_size = self.type_size(self.map_def_pointee(member.type_)?)?,
+                _ => {}
+            }
+        }
+
+        Ok(def)
+    }
+
+    // returns the type a member of a BTF map definition points to
+    fn map_def_pointee(&self, type_id: u32) -> Result<u32, BtfError> {
+        let type_id = self.resolve_type(type_id)?;
+        match self.type_by_id(type_id)? {
+            // Safety: union
+            BtfType::Ptr(ty) => Ok(unsafe { ty.__bindgen_anon_1.type_ }),
+            _ => Err(BtfError::UnexpectedBtfType { type_id }),
+        }
+    }
+
+    // returns the value of an `int (*name)[val]` member of a BTF map definition
+    fn map_def_uint(&self, type_id: u32) -> Result<u32, BtfError> {
+        let array_id = self.resolve_type(self.map_def_pointee(type_id)?)?;
+        match self.type_by_id(array_id)? {
+            BtfType::Array(_, array) => Ok(array.nelems),
+            _ => Err(BtfError::UnexpectedBtfType { type_id: array_id }),
+        }
+    }
+
+    /// Returns the size in bytes of the type `root_type_id`.
+    ///
+    /// Typedefs and `const`, `volatile` and `restrict` qualifiers are
+    /// resolved to the type they refer to, arrays are sized from their
+    /// element type and pointers are always 8 bytes, the size of pointers in
+    /// eBPF. Functions and forward declarations have no size and return
+    /// [`BtfError::UnexpectedBtfType`]. Sizes that don't fit in a `u32`, eg
+    /// the size of a corrupted array type, return
+    /// [`BtfError::TypeSizeOverflow`].
+    pub fn type_size(&self, root_type_id: u32) -> Result<u32, BtfError> {
         let mut type_id = root_type_id;
-        let mut n_elems = 1;
+        let mut n_elems: u32 = 1;
         for _ in 0..MAX_RESOLVE_DEPTH {
             let ty = self.types.type_by_id(type_id)?;
 
@@ -367,9 +557,9 @@ impl Btf {
                 | DataSec(ty, _)
                 | Float(ty) => {
                     // Safety: union
-                    unsafe { ty.__bindgen_anon_1.size as usize }
+                    unsafe { ty.__bindgen_anon_1.size }
                 }
-                Ptr(_) => mem::size_of::<*const c_void>(), // FIXME
+                Ptr(_) => 8,
                 Typedef(ty)
                 | Volatile(ty)
                 | Const(ty)
@@ -382,7 +572,12 @@ impl Btf {
                     continue;
                 }
                 Array(_, array) => {
-                    n_elems *= array.nelems as usize;
+                    n_elems =
+                        n_elems
+                            .checked_mul(array.nelems)
+                            .ok_or(BtfError::TypeSizeOverflow {
+                                type_id: root_type_id,
+                            })?;
                     type_id = array.type_;
                     continue;
                 }
@@ -391,7 +586,9 @@ impl Btf {
                 }
             };
 
-            return Ok(size * n_elems);
+            return size.checked_mul(n_elems).ok_or(BtfError::TypeSizeOverflow {
+                type_id: root_type_id,
+            });
         }
 
         Err(BtfError::MaximumTypeDepthReached {
@@ -399,6 +596,49 @@ impl Btf {
         })
     }
 
+    /// Returns the size in bytes of the type of kind `kind` called `name`.
+    ///
+    /// This can be used to check that a struct has the size user space
+    /// expects, eg `btf.type_size_by_name("task_struct", BtfKind::Struct)`.
+    /// See [`type_size`](Btf::type_size).
+    pub fn type_size_by_name(&self, name: &str, kind: BtfKind) -> Result<u32, BtfError> {
+        self.type_size(self.id_by_type_name_kind(name, kind)?)
+    }
+
+    // Returns true if the type `root_type_id` is the struct `struct_name` or
+    // contains it, in one of its members or in the members and elements of
+    // the structs, unions and arrays it's made of.
+    pub(crate) fn contains_struct(
+        &self,
+        root_type_id: u32,
+        struct_name: &str,
+    ) -> Result<bool, BtfError> {
+        let mut pending = vec![(root_type_id, 0)];
+        while let Some((type_id, depth)) = pending.pop() {
+            if depth >= MAX_RESOLVE_DEPTH {
+                return Err(BtfError::MaximumTypeDepthReached {
+                    type_id: root_type_id,
+                });
+            }
+            let ty = self.type_by_id(self.resolve_type(type_id)?)?;
+            match ty {
+                BtfType::Struct(_, members) => {
+                    if self.type_name(ty)?.as_deref() == Some(struct_name) {
+                        return Ok(true);
+                    }
+                    pending.extend(members.iter().map(|m| (m.type_, depth + 1)));
+                }
+                BtfType::Union(_, members) => {
+                    pending.extend(members.iter().map(|m| (m.type_, depth + 1)))
+                }
+                BtfType::Array(_, array) => pending.push((array.type_, depth + 1)),
+                _ => {}
+            }
+        }
+
+        Ok(false)
+    }
+
     pub(crate) fn to_bytes(&self) -> Vec<u8> {
         // Safety: btf_header is POD
         let mut buf = unsafe { bytes_of::<btf_header>(&self.header).to_vec() };
@@ -1359,4 +1599,210 @@ mod tests {
         let raw = btf.to_bytes();
         Btf::parse(&raw, Endianness::default()).unwrap();
     }
+
+    #[test]
+    fn test_type_size() {
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("int".to_string());
+        let int_type_id = btf.add_type(BtfType::new_int(name_offset, 4, 0, 0));
+        let name_offset = btf.add_string("int_t".to_string());
+        let typedef_id = btf.add_type(BtfType::new_typedef(name_offset, int_type_id));
+        let const_id = btf.add_type(BtfType::new_const(typedef_id));
+        let array_id = btf.add_type(BtfType::new_array(0, const_id, int_type_id, 3));
+        let ptr_id = btf.add_type(BtfType::new_ptr(0, array_id));
+        let name_offset = btf.add_string("foo".to_string());
+        let struct_id = btf.add_type(BtfType::new_struct(name_offset, Vec::new(), 24));
+        let proto_id = btf.add_type(BtfType::new_func_proto(Vec::new(), int_type_id));
+
+        assert_eq!(btf.type_size(int_type_id).unwrap(), 4);
+        assert_eq!(btf.type_size(typedef_id).unwrap(), 4);
+        assert_eq!(btf.type_size(const_id).unwrap(), 4);
+        assert_eq!(btf.type_size(array_id).unwrap(), 12);
+        assert_eq!(btf.type_size(ptr_id).unwrap(), 8);
+        assert_eq!(btf.type_size(struct_id).unwrap(), 24);
+        assert!(matches!(
+            btf.type_size(proto_id),
+            Err(BtfError::UnexpectedBtfType { .. })
+        ));
+        assert_eq!(btf.type_size_by_name("foo", BtfKind::Struct).unwrap(), 24);
+        assert!(btf.type_size_by_name("foo", BtfKind::Union).is_err());
+
+        let huge_id = btf.add_type(BtfType::new_array(0, struct_id, int_type_id, u32::MAX));
+        assert!(matches!(
+            btf.type_size(huge_id),
+            Err(BtfError::TypeSizeOverflow { type_id }) if type_id == huge_id
+        ));
+        let huge_id = btf.add_type(BtfType::new_array(0, huge_id, int_type_id, 2));
+        assert!(matches!(
+            btf.type_size(huge_id),
+            Err(BtfError::TypeSizeOverflow { type_id }) if type_id == huge_id
+        ));
+    }
+
+    #[test]
+    fn test_parse_split() {
+        let mut base = Btf::new();
+        let name_offset = base.add_string("int".to_string());
+        let int_type_id = base.add_type(BtfType::new_int(name_offset, 4, 0, 0));
+
+        // the split BTF has its own header and string section, whose
+        // offsets continue the ones of the base
+        let mut split = Btf::new();
+        split.strings.clear();
+        split.header.str_len = 0;
+        let base_str_len = base.strings.len() as u32;
+        let name_offset = split.add_string("nvme_probe".to_string()) + base_str_len;
+        let proto = split.add_type(BtfType::new_func_proto(Vec::new(), int_type_id));
+        let proto = proto + int_type_id;
+        split.add_type(BtfType::new_func(
+            name_offset,
+            proto,
+            btf_func_linkage::BTF_FUNC_GLOBAL,
+        ));
+
+        let btf = Btf::parse_split(&split.to_bytes(), &base, Endianness::default()).unwrap();
+        let func_id = btf
+            .id_by_type_name_kind("nvme_probe", BtfKind::Func)
+            .unwrap();
+        assert_eq!(func_id, 3);
+        assert!(matches!(btf.type_by_id(proto), Ok(BtfType::FuncProto(..))));
+        assert_eq!(btf.id_by_type_name_kind("int", BtfKind::Int).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_map_kv_type_ids() {
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("int".to_string());
+        let int_type_id = btf.add_type(BtfType::new_int(name_offset, 4, BTF_INT_SIGNED, 0));
+        let name_offset = btf.add_string("value_t".to_string());
+        let value_type_id = btf.add_type(BtfType::new_typedef(name_offset, int_type_id));
+
+        let members = vec![
+            btf_member {
+                name_off: btf.add_string("key".to_string()),
+                type_: int_type_id,
+                offset: 0,
+            },
+            btf_member {
+                name_off: btf.add_string("value".to_string()),
+                type_: value_type_id,
+                offset: 32,
+            },
+        ];
+        let name_offset = btf.add_string("____btf_map_COUNTERS".to_string());
+        btf.add_type(BtfType::new_struct(name_offset, members, 8));
+
+        assert_eq!(
+            btf.map_kv_type_ids("COUNTERS"),
+            Some((int_type_id, value_type_id))
+        );
+        assert_eq!(btf.map_kv_type_ids("OTHER"), None);
+        assert_eq!(btf.id_by_type_name("value_t").unwrap(), value_type_id);
+        assert!(matches!(
+            btf.id_by_type_name("missing"),
+            Err(BtfError::UnknownBtfTypeName { .. })
+        ));
+    }
+
+    #[test]
+    fn test_btf_map_def() {
+        // struct {
+        //     __uint(type, BPF_MAP_TYPE_HASH);
+        //     __uint(max_entries, 1024);
+        //     __type(key, int);
+        //     __type(value, long);
+        // } COUNTERS SEC(".maps");
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("int".to_string());
+        let int_type_id = btf.add_type(BtfType::new_int(name_offset, 4, BTF_INT_SIGNED, 0));
+        let name_offset = btf.add_string("long".to_string());
+        let long_type_id = btf.add_type(BtfType::new_int(name_offset, 8, BTF_INT_SIGNED, 0));
+
+        let uint_member = |btf: &mut Btf, name: &str, val| {
+            let array_type_id = btf.add_type(BtfType::new_array(0, int_type_id, int_type_id, val));
+            btf_member {
+                name_off: btf.add_string(name.to_string()),
+                type_: btf.add_type(BtfType::new_ptr(0, array_type_id)),
+                offset: 0,
+            }
+        };
+        let mut members = vec![
+            uint_member(&mut btf, "type", 1),
+            uint_member(&mut btf, "max_entries", 1024),
+        ];
+        for (name, type_id) in [("key", int_type_id), ("value", long_type_id)] {
+            members.push(btf_member {
+                name_off: btf.add_string(name.to_string()),
+                type_: btf.add_type(BtfType::new_ptr(0, type_id)),
+                offset: 0,
+            });
+        }
+        let struct_type_id = btf.add_type(BtfType::new_struct(0, members, 32));
+
+        let name_offset = btf.add_string("COUNTERS".to_string());
+        let var_type_id = btf.add_type(BtfType::new_var(
+            name_offset,
+            struct_type_id,
+            BTF_VAR_STATIC,
+        ));
+        let name_offset = btf.add_string(".maps".to_string());
+        let variables = vec![btf_var_secinfo {
+            type_: var_type_id,
+            offset: 0,
+            size: 32,
+        }];
+        btf.add_type(BtfType::new_datasec(name_offset, variables, 32));
+
+        let def = btf.btf_map_def("COUNTERS").unwrap();
+        assert_eq!(def.map_type, 1);
+        assert_eq!(def.max_entries, 1024);
+        assert_eq!(def.key_size, 4);
+        assert_eq!(def.value_size, 8);
+        assert_eq!(def.pinning, PinningType::None);
+        assert!(matches!(
+            btf.btf_map_def("OTHER"),
+            Err(BtfError::UnknownBtfTypeName { type_name }) if type_name == "OTHER"
+        ));
+    }
+
+    #[test]
+    fn test_contains_struct() {
+        // struct bpf_timer { __u64 opaque[2]; };
+        // struct elem { struct bpf_timer timer; __u64 fired; };
+        // typedef struct elem elems_t[4];
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("__u64".to_string());
+        let u64_type_id = btf.add_type(BtfType::new_int(name_offset, 8, 0, 0));
+        let array_type_id = btf.add_type(BtfType::new_array(0, u64_type_id, u64_type_id, 2));
+        let members = vec![btf_member {
+            name_off: btf.add_string("opaque".to_string()),
+            type_: array_type_id,
+            offset: 0,
+        }];
+        let name_offset = btf.add_string("bpf_timer".to_string());
+        let timer_type_id = btf.add_type(BtfType::new_struct(name_offset, members, 16));
+        let members = vec![
+            btf_member {
+                name_off: btf.add_string("timer".to_string()),
+                type_: timer_type_id,
+                offset: 0,
+            },
+            btf_member {
+                name_off: btf.add_string("fired".to_string()),
+                type_: u64_type_id,
+                offset: 128,
+            },
+        ];
+        let name_offset = btf.add_string("elem".to_string());
+        let elem_type_id = btf.add_type(BtfType::new_struct(name_offset, members, 24));
+        let array_type_id = btf.add_type(BtfType::new_array(0, elem_type_id, u64_type_id, 4));
+        let name_offset = btf.add_string("elems_t".to_string());
+        let typedef_type_id = btf.add_type(BtfType::new_typedef(name_offset, array_type_id));
+
+        assert!(btf.contains_struct(timer_type_id, "bpf_timer").unwrap());
+        assert!(btf.contains_struct(elem_type_id, "bpf_timer").unwrap());
+        assert!(btf.contains_struct(typedef_type_id, "bpf_timer").unwrap());
+        assert!(!btf.contains_struct(u64_type_id, "bpf_timer").unwrap());
+        assert!(!btf.contains_struct(elem_type_id, "bpf_spin_lock").unwrap());
+    }
 }
@@ -7,4 +7,5 @@ mod types;
 pub use btf::*;
 pub(crate) use info::*;
 pub use relocation::RelocationError;
+pub use types::BtfKind;
 pub(crate) use types::*;
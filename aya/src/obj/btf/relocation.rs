@@ -153,6 +153,17 @@ impl Relocation {
 
 impl Object {
     pub fn relocate_btf(&mut self, target_btf: &Btf) -> Result<(), BpfError> {
+        self.relocate_btf_with(target_btf, Err)
+    }
+
+    // Relocates every program, passing the relocation error of each program
+    // that can't be relocated to `on_error`. Stops at the first error
+    // returned by `on_error`.
+    pub(crate) fn relocate_btf_with<F: FnMut(BpfError) -> Result<(), BpfError>>(
+        &mut self,
+        target_btf: &Btf,
+        mut on_error: F,
+    ) -> Result<(), BpfError> {
         let (local_btf, btf_ext) = match (&self.btf, &self.btf_ext) {
             (Some(btf), Some(btf_ext)) => (btf, btf_ext),
             _ => return Ok(()),
@@ -178,13 +189,11 @@ impl Object {
             match relocate_btf_program(program, relos, local_btf, target_btf, &mut candidates_cache)
             {
                 Ok(_) => {}
-                Err(ErrorWrapper::BtfError(e)) => return Err(e.into()),
-                Err(ErrorWrapper::RelocationError(error)) => {
-                    return Err(BpfError::RelocationError {
-                        function: section_name.to_owned(),
-                        error: Box::new(error),
-                    })
-                }
+                Err(ErrorWrapper::BtfError(e)) => on_error(e.into())?,
+                Err(ErrorWrapper::RelocationError(error)) => on_error(BpfError::RelocationError {
+                    function: section_name.to_owned(),
+                    error: Box::new(error),
+                })?,
             }
         }
 
@@ -428,7 +437,7 @@ fn match_candidate<'target>(
                         name: None,
                     });
                     target_spec.bit_offset +=
-                        accessor.index * candidate.btf.type_size(target_id)? * 8;
+                        accessor.index * candidate.btf.type_size(target_id)? as usize * 8;
                 }
             }
         }
@@ -618,7 +627,7 @@ impl<'a> AccessSpec<'a> {
                     index: parts[0],
                     name: None,
                 }];
-                let mut bit_offset = accessors[0].index as usize * btf.type_size(type_id)?;
+                let mut bit_offset = accessors[0].index as usize * btf.type_size(type_id)? as usize;
                 for index in parts.iter().skip(1).cloned() {
                     type_id = btf.resolve_type(type_id)?;
                     let ty = btf.type_by_id(type_id)?;
@@ -678,7 +687,7 @@ impl<'a> AccessSpec<'a> {
                                 index,
                                 name: None,
                             });
-                            let size = btf.type_size(type_id)?;
+                            let size = btf.type_size(type_id)? as usize;
                             bit_offset += index * size * 8;
                         }
                         rel_kind => {
@@ -928,11 +937,11 @@ impl ComputedRelocation {
             return match rel.kind {
                 FieldByteOffset => Ok(ComputedRelocationValue {
                     value: (spec.bit_offset / 8) as u32,
-                    size: spec.btf.type_size(accessor.type_id)? as u32,
+                    size: spec.btf.type_size(accessor.type_id)?,
                     type_id: Some(accessor.type_id),
                 }),
                 FieldByteSize => Ok(ComputedRelocationValue {
-                    value: spec.btf.type_size(accessor.type_id)? as u32,
+                    value: spec.btf.type_size(accessor.type_id)?,
                     size: 0,
                     type_id: Some(accessor.type_id),
                 }),
@@ -987,7 +996,7 @@ impl ComputedRelocation {
                 byte_off = bit_off / 8 / byte_size * byte_size;
             }
         } else {
-            byte_size = spec.btf.type_size(member_type_id)? as u32;
+            byte_size = spec.btf.type_size(member_type_id)?;
             bit_size = byte_size * 8;
             byte_off = spec.bit_offset as u32 / 8;
         }
@@ -1045,7 +1054,7 @@ impl ComputedRelocation {
                 Some(target_spec) => match rel.kind {
                     TypeIdTarget => target_spec.root_type_id,
                     TypeExists => 1,
-                    TypeSize => target_spec.btf.type_size(target_spec.root_type_id)? as u32,
+                    TypeSize => target_spec.btf.type_size(target_spec.root_type_id)?,
                     _ => panic!("bug! this should not be reached"),
                 },
                 // FIXME in the case of TypeIdTarget and TypeSize this should probably fail the
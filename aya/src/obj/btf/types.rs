@@ -41,27 +41,48 @@ pub(crate) enum BtfType {
     TypeTag(btf_type),
 }
 
+/// The kind of a BTF type.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(u32)]
-pub(crate) enum BtfKind {
+#[non_exhaustive]
+pub enum BtfKind {
+    /// Unknown type, type id 0 is `void`
     Unknown = BTF_KIND_UNKN,
+    /// Integer
     Int = BTF_KIND_INT,
+    /// Floating point number
     Float = BTF_KIND_FLOAT,
+    /// Pointer
     Ptr = BTF_KIND_PTR,
+    /// Array
     Array = BTF_KIND_ARRAY,
+    /// Struct
     Struct = BTF_KIND_STRUCT,
+    /// Union
     Union = BTF_KIND_UNION,
+    /// Enum
     Enum = BTF_KIND_ENUM,
+    /// Forward declaration of a struct or union
     Fwd = BTF_KIND_FWD,
+    /// Typedef
     Typedef = BTF_KIND_TYPEDEF,
+    /// `volatile` qualifier
     Volatile = BTF_KIND_VOLATILE,
+    /// `const` qualifier
     Const = BTF_KIND_CONST,
+    /// `restrict` qualifier
     Restrict = BTF_KIND_RESTRICT,
+    /// Function
     Func = BTF_KIND_FUNC,
+    /// Function prototype
     FuncProto = BTF_KIND_FUNC_PROTO,
+    /// Variable
     Var = BTF_KIND_VAR,
+    /// Data section
     DataSec = BTF_KIND_DATASEC,
+    /// Declaration tag
     DeclTag = BTF_KIND_DECL_TAG,
+    /// Type tag
     TypeTag = BTF_KIND_TYPE_TAG,
 }
 
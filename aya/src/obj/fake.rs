@@ -0,0 +1,292 @@
+//! A builder of minimal eBPF ELF objects, so that the tests of the parser and
+//! of the loader don't need a compiler or checked-in binaries.
+use std::mem;
+
+use crate::{bpf_map_def, generated::bpf_insn, util::bytes_of};
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_REL: u32 = 9;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+const EM_BPF: u16 = 247;
+const R_BPF_64_64: u64 = 1;
+
+const EHDR_SIZE: usize = 64;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+const REL_SIZE: usize = 16;
+
+/// `r0 = imm`
+pub(crate) const BPF_MOV64_IMM: u8 = 0xb7;
+/// `r_dst = imm64`, takes two instructions. Map relocations point to it.
+pub(crate) const BPF_LD_IMM64: u8 = 0x18;
+/// `exit`
+pub(crate) const BPF_EXIT: u8 = 0x95;
+
+/// Returns an instruction without registers or offset.
+pub(crate) fn insn(code: u8, imm: i32) -> bpf_insn {
+    bpf_insn {
+        code,
+        _bitfield_align_1: [],
+        _bitfield_1: bpf_insn::new_bitfield_1(0, 0),
+        off: 0,
+        imm,
+    }
+}
+
+struct FakeSection {
+    name: String,
+    flags: u64,
+    data: Vec<u8>,
+    // (offset, symbol index)
+    relocations: Vec<(u64, usize)>,
+}
+
+struct FakeSymbol {
+    name: String,
+    kind: u8,
+    // the index of the section in `FakeElf::sections`, `None` if undefined
+    section: Option<usize>,
+    size: u64,
+}
+
+/// A little-endian, relocatable eBPF ELF object.
+///
+/// The sections are written in the order they're added, followed by the
+/// relocation sections, the symbol table and the string table. Every symbol
+/// is global.
+#[derive(Default)]
+pub(crate) struct FakeElf {
+    sections: Vec<FakeSection>,
+    symbols: Vec<FakeSymbol>,
+}
+
+impl FakeElf {
+    pub(crate) fn new() -> FakeElf {
+        FakeElf::default()
+    }
+
+    fn add_section(&mut self, name: &str, flags: u64, data: Vec<u8>) -> usize {
+        self.sections.push(FakeSection {
+            name: name.to_owned(),
+            flags,
+            data,
+            relocations: Vec::new(),
+        });
+        self.sections.len() - 1
+    }
+
+    fn add_symbol(&mut self, name: &str, kind: u8, section: Option<usize>, size: u64) -> usize {
+        self.symbols.push(FakeSymbol {
+            name: name.to_owned(),
+            kind,
+            section,
+            size,
+        });
+        self.symbols.len() - 1
+    }
+
+    /// Adds a section with arbitrary content, eg `.BTF`.
+    pub(crate) fn section(&mut self, name: &str, data: Vec<u8>) -> &mut FakeElf {
+        self.add_section(name, 0, data);
+        self
+    }
+
+    /// Adds the `license` section.
+    pub(crate) fn license(&mut self, license: &str) -> &mut FakeElf {
+        let mut data = license.as_bytes().to_vec();
+        data.push(0);
+        self.add_section("license", SHF_ALLOC | SHF_WRITE, data);
+        self
+    }
+
+    /// Adds the `maps/NAME` section containing `def` and a symbol for it.
+    pub(crate) fn map(&mut self, name: &str, def: bpf_map_def) -> &mut FakeElf {
+        // Safety: bpf_map_def is POD
+        let data = unsafe { bytes_of(&def) }.to_vec();
+        let index = self.add_section(&format!("maps/{}", name), SHF_ALLOC | SHF_WRITE, data);
+        self.add_symbol(
+            name,
+            STT_OBJECT,
+            Some(index),
+            mem::size_of::<bpf_map_def>() as u64,
+        );
+        self
+    }
+
+    /// Adds the program section `section`, eg `xdp/pass`, and a function
+    /// symbol for it.
+    pub(crate) fn program(&mut self, section: &str, insns: &[bpf_insn]) -> &mut FakeElf {
+        let data = insns
+            .iter()
+            // Safety: bpf_insn is POD
+            .flat_map(|ins| unsafe { bytes_of(ins) }.to_vec())
+            .collect::<Vec<_>>();
+        let size = data.len() as u64;
+        let index = self.add_section(section, SHF_ALLOC | SHF_EXECINSTR, data);
+        let name = section.rsplit('/').next().unwrap();
+        self.add_symbol(name, STT_FUNC, Some(index), size);
+        self
+    }
+
+    /// Relocates the instruction `ins_index` of the program `section` to the
+    /// symbol `symbol`, adding an undefined symbol if there's no symbol with
+    /// that name.
+    pub(crate) fn relocation(
+        &mut self,
+        section: &str,
+        ins_index: usize,
+        symbol: &str,
+    ) -> &mut FakeElf {
+        let symbol = match self.symbols.iter().position(|s| s.name == symbol) {
+            Some(index) => index,
+            None => self.add_symbol(symbol, STT_NOTYPE, None, 0),
+        };
+        let section = self
+            .sections
+            .iter_mut()
+            .find(|s| s.name == section)
+            .expect("unknown section");
+        section
+            .relocations
+            .push(((ins_index * mem::size_of::<bpf_insn>()) as u64, symbol));
+        self
+    }
+
+    /// Writes the object.
+    pub(crate) fn build(&self) -> Vec<u8> {
+        let mut strtab = vec![0u8];
+        let mut add_string = |s: &str| {
+            let offset = strtab.len() as u32;
+            strtab.extend_from_slice(s.as_bytes());
+            strtab.push(0);
+            offset
+        };
+
+        // section indices: 0 is the null section, then the sections in the
+        // order they were added, the relocation sections, .symtab and .strtab
+        let rel_sections = self
+            .sections
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.relocations.is_empty())
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let symtab_index = 1 + self.sections.len() + rel_sections.len();
+        let strtab_index = symtab_index + 1;
+
+        // (name, type, flags, data, link, info, entsize)
+        let mut headers = Vec::new();
+        for section in &self.sections {
+            headers.push((
+                add_string(&section.name),
+                SHT_PROGBITS,
+                section.flags,
+                section.data.clone(),
+                0,
+                0,
+                0,
+            ));
+        }
+        for &i in &rel_sections {
+            let section = &self.sections[i];
+            let mut data = Vec::new();
+            for &(offset, symbol) in &section.relocations {
+                data.extend_from_slice(&offset.to_le_bytes());
+                // symbol 0 is the null symbol
+                let info = ((symbol as u64 + 1) << 32) | R_BPF_64_64;
+                data.extend_from_slice(&info.to_le_bytes());
+            }
+            headers.push((
+                add_string(&format!(".rel{}", section.name)),
+                SHT_REL,
+                0,
+                data,
+                symtab_index as u32,
+                (i + 1) as u32,
+                REL_SIZE as u64,
+            ));
+        }
+
+        let mut symtab = vec![0u8; SYM_SIZE];
+        for symbol in &self.symbols {
+            symtab.extend_from_slice(&add_string(&symbol.name).to_le_bytes());
+            symtab.push((STB_GLOBAL << 4) | symbol.kind);
+            symtab.push(0);
+            let shndx = symbol.section.map(|i| i + 1).unwrap_or(0) as u16;
+            symtab.extend_from_slice(&shndx.to_le_bytes());
+            symtab.extend_from_slice(&0u64.to_le_bytes());
+            symtab.extend_from_slice(&symbol.size.to_le_bytes());
+        }
+        let symtab_name = add_string(".symtab");
+        let strtab_name = add_string(".strtab");
+        headers.push((
+            symtab_name,
+            SHT_SYMTAB,
+            0,
+            symtab,
+            strtab_index as u32,
+            // the index of the first global symbol
+            1,
+            SYM_SIZE as u64,
+        ));
+        headers.push((strtab_name, SHT_STRTAB, 0, strtab, 0, 0, 0));
+
+        let mut data = vec![0u8; EHDR_SIZE];
+        let mut shdrs = vec![0u8; SHDR_SIZE];
+        for (name, sh_type, flags, section_data, link, info, entsize) in headers {
+            // align the sections on 8 bytes
+            data.resize((data.len() + 7) & !7, 0);
+            let offset = data.len() as u64;
+            data.extend_from_slice(&section_data);
+
+            shdrs.extend_from_slice(&name.to_le_bytes());
+            shdrs.extend_from_slice(&sh_type.to_le_bytes());
+            shdrs.extend_from_slice(&flags.to_le_bytes());
+            shdrs.extend_from_slice(&0u64.to_le_bytes());
+            shdrs.extend_from_slice(&offset.to_le_bytes());
+            shdrs.extend_from_slice(&(section_data.len() as u64).to_le_bytes());
+            shdrs.extend_from_slice(&link.to_le_bytes());
+            shdrs.extend_from_slice(&info.to_le_bytes());
+            shdrs.extend_from_slice(&8u64.to_le_bytes());
+            shdrs.extend_from_slice(&entsize.to_le_bytes());
+        }
+        data.resize((data.len() + 7) & !7, 0);
+        let shoff = data.len() as u64;
+        data.extend_from_slice(&shdrs);
+
+        let shnum = (shdrs.len() / SHDR_SIZE) as u16;
+        let mut ehdr = Vec::with_capacity(EHDR_SIZE);
+        // ELFCLASS64, ELFDATA2LSB, EV_CURRENT
+        ehdr.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1]);
+        ehdr.resize(16, 0);
+        // ET_REL
+        ehdr.extend_from_slice(&1u16.to_le_bytes());
+        ehdr.extend_from_slice(&EM_BPF.to_le_bytes());
+        ehdr.extend_from_slice(&1u32.to_le_bytes());
+        // entry and program headers
+        ehdr.extend_from_slice(&0u64.to_le_bytes());
+        ehdr.extend_from_slice(&0u64.to_le_bytes());
+        ehdr.extend_from_slice(&shoff.to_le_bytes());
+        ehdr.extend_from_slice(&0u32.to_le_bytes());
+        ehdr.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+        ehdr.extend_from_slice(&0u16.to_le_bytes());
+        ehdr.extend_from_slice(&0u16.to_le_bytes());
+        ehdr.extend_from_slice(&(SHDR_SIZE as u16).to_le_bytes());
+        ehdr.extend_from_slice(&shnum.to_le_bytes());
+        ehdr.extend_from_slice(&(strtab_index as u16).to_le_bytes());
+        data[..EHDR_SIZE].copy_from_slice(&ehdr);
+
+        data
+    }
+}
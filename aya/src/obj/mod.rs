@@ -1,6 +1,9 @@
 pub(crate) mod btf;
+#[cfg(test)]
+pub(crate) mod fake;
 mod relocation;
 
+use log::{debug, warn};
 use object::{
     read::{Object as ElfObject, ObjectSection, Section as ObjSection},
     Endianness, ObjectSymbol, ObjectSymbolTable, RelocationTarget, SectionIndex, SectionKind,
@@ -19,9 +22,12 @@ use relocation::*;
 
 use crate::{
     bpf_map_def,
-    generated::{bpf_insn, bpf_map_type::BPF_MAP_TYPE_ARRAY, BPF_F_RDONLY_PROG},
+    generated::{
+        bpf_attach_type, bpf_insn, bpf_map_type::BPF_MAP_TYPE_ARRAY, bpf_prog_type,
+        BPF_F_RDONLY_PROG,
+    },
     obj::btf::{Btf, BtfError, BtfExt},
-    BpfError,
+    BpfError, ParsedMap,
 };
 use std::slice::from_raw_parts_mut;
 
@@ -48,6 +54,11 @@ pub struct Object {
     // BTF VAR type so the offsets can be fixed up
     pub(crate) symbol_offset_by_name: HashMap<String, u64>,
     pub(crate) text_section_index: Option<usize>,
+    // the program sections skipped because of their unknown program type
+    pub(crate) skipped_sections: Vec<String>,
+    // the definitions of the maps defined more than once, which were replaced
+    // in `maps` by a later definition with the same name
+    pub(crate) redefined_maps: Vec<(String, bpf_map_def)>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -107,9 +118,12 @@ pub enum ProgramSection {
     KProbe { name: String },
     UProbe { name: String },
     URetProbe { name: String },
+    Usdt { name: String },
     TracePoint { name: String },
     SocketFilter { name: String },
     Xdp { name: String },
+    XdpCpuMap { name: String },
+    XdpDevMap { name: String },
     SkMsg { name: String },
     SkSkbStreamParser { name: String },
     SkSkbStreamVerdict { name: String },
@@ -118,6 +132,21 @@ pub enum ProgramSection {
     CgroupSkb { name: String },
     CgroupSkbIngress { name: String },
     CgroupSkbEgress { name: String },
+    CgroupSockCreate { name: String },
+    CgroupSockPostBind4 { name: String },
+    CgroupSockPostBind6 { name: String },
+    CgroupSockRelease { name: String },
+    CgroupSockAddrConnect4 { name: String },
+    CgroupSockAddrConnect6 { name: String },
+    CgroupSockAddrBind4 { name: String },
+    CgroupSockAddrBind6 { name: String },
+    CgroupSockAddrSendmsg4 { name: String },
+    CgroupSockAddrSendmsg6 { name: String },
+    CgroupSockAddrRecvmsg4 { name: String },
+    CgroupSockAddrRecvmsg6 { name: String },
+    CgroupSockAddrGetpeername4 { name: String },
+    CgroupSockAddrGetpeername6 { name: String },
+    CgroupDevice { name: String },
     LircMode2 { name: String },
     PerfEvent { name: String },
     RawTracePoint { name: String },
@@ -126,6 +155,7 @@ pub enum ProgramSection {
     FEntry { name: String },
     FExit { name: String },
     Extension { name: String },
+    Iter { name: String },
 }
 
 impl ProgramSection {
@@ -135,9 +165,12 @@ impl ProgramSection {
             ProgramSection::KProbe { name } => name,
             ProgramSection::UProbe { name } => name,
             ProgramSection::URetProbe { name } => name,
+            ProgramSection::Usdt { name } => name,
             ProgramSection::TracePoint { name } => name,
             ProgramSection::SocketFilter { name } => name,
             ProgramSection::Xdp { name } => name,
+            ProgramSection::XdpCpuMap { name } => name,
+            ProgramSection::XdpDevMap { name } => name,
             ProgramSection::SkMsg { name } => name,
             ProgramSection::SkSkbStreamParser { name } => name,
             ProgramSection::SkSkbStreamVerdict { name } => name,
@@ -146,6 +179,21 @@ impl ProgramSection {
             ProgramSection::CgroupSkb { name } => name,
             ProgramSection::CgroupSkbIngress { name } => name,
             ProgramSection::CgroupSkbEgress { name } => name,
+            ProgramSection::CgroupSockCreate { name } => name,
+            ProgramSection::CgroupSockPostBind4 { name } => name,
+            ProgramSection::CgroupSockPostBind6 { name } => name,
+            ProgramSection::CgroupSockRelease { name } => name,
+            ProgramSection::CgroupSockAddrConnect4 { name } => name,
+            ProgramSection::CgroupSockAddrConnect6 { name } => name,
+            ProgramSection::CgroupSockAddrBind4 { name } => name,
+            ProgramSection::CgroupSockAddrBind6 { name } => name,
+            ProgramSection::CgroupSockAddrSendmsg4 { name } => name,
+            ProgramSection::CgroupSockAddrSendmsg6 { name } => name,
+            ProgramSection::CgroupSockAddrRecvmsg4 { name } => name,
+            ProgramSection::CgroupSockAddrRecvmsg6 { name } => name,
+            ProgramSection::CgroupSockAddrGetpeername4 { name } => name,
+            ProgramSection::CgroupSockAddrGetpeername6 { name } => name,
+            ProgramSection::CgroupDevice { name } => name,
             ProgramSection::LircMode2 { name } => name,
             ProgramSection::PerfEvent { name } => name,
             ProgramSection::RawTracePoint { name } => name,
@@ -154,6 +202,88 @@ impl ProgramSection {
             ProgramSection::FEntry { name } => name,
             ProgramSection::FExit { name } => name,
             ProgramSection::Extension { name } => name,
+            ProgramSection::Iter { name } => name,
+        }
+    }
+
+    pub(crate) fn prog_type(&self) -> bpf_prog_type {
+        use crate::generated::bpf_prog_type::*;
+        match self {
+            ProgramSection::KRetProbe { .. }
+            | ProgramSection::KProbe { .. }
+            | ProgramSection::UProbe { .. }
+            | ProgramSection::URetProbe { .. }
+            | ProgramSection::Usdt { .. } => BPF_PROG_TYPE_KPROBE,
+            ProgramSection::TracePoint { .. } => BPF_PROG_TYPE_TRACEPOINT,
+            ProgramSection::SocketFilter { .. } => BPF_PROG_TYPE_SOCKET_FILTER,
+            ProgramSection::Xdp { .. }
+            | ProgramSection::XdpCpuMap { .. }
+            | ProgramSection::XdpDevMap { .. } => BPF_PROG_TYPE_XDP,
+            ProgramSection::SkMsg { .. } => BPF_PROG_TYPE_SK_MSG,
+            ProgramSection::SkSkbStreamParser { .. }
+            | ProgramSection::SkSkbStreamVerdict { .. } => BPF_PROG_TYPE_SK_SKB,
+            ProgramSection::SockOps { .. } => BPF_PROG_TYPE_SOCK_OPS,
+            ProgramSection::SchedClassifier { .. } => BPF_PROG_TYPE_SCHED_CLS,
+            ProgramSection::CgroupSkb { .. }
+            | ProgramSection::CgroupSkbIngress { .. }
+            | ProgramSection::CgroupSkbEgress { .. } => BPF_PROG_TYPE_CGROUP_SKB,
+            ProgramSection::CgroupSockCreate { .. }
+            | ProgramSection::CgroupSockPostBind4 { .. }
+            | ProgramSection::CgroupSockPostBind6 { .. }
+            | ProgramSection::CgroupSockRelease { .. } => BPF_PROG_TYPE_CGROUP_SOCK,
+            ProgramSection::CgroupSockAddrConnect4 { .. }
+            | ProgramSection::CgroupSockAddrConnect6 { .. }
+            | ProgramSection::CgroupSockAddrBind4 { .. }
+            | ProgramSection::CgroupSockAddrBind6 { .. }
+            | ProgramSection::CgroupSockAddrSendmsg4 { .. }
+            | ProgramSection::CgroupSockAddrSendmsg6 { .. }
+            | ProgramSection::CgroupSockAddrRecvmsg4 { .. }
+            | ProgramSection::CgroupSockAddrRecvmsg6 { .. }
+            | ProgramSection::CgroupSockAddrGetpeername4 { .. }
+            | ProgramSection::CgroupSockAddrGetpeername6 { .. } => BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+            ProgramSection::CgroupDevice { .. } => BPF_PROG_TYPE_CGROUP_DEVICE,
+            ProgramSection::LircMode2 { .. } => BPF_PROG_TYPE_LIRC_MODE2,
+            ProgramSection::PerfEvent { .. } => BPF_PROG_TYPE_PERF_EVENT,
+            ProgramSection::RawTracePoint { .. } => BPF_PROG_TYPE_RAW_TRACEPOINT,
+            ProgramSection::Lsm { .. } => BPF_PROG_TYPE_LSM,
+            ProgramSection::BtfTracePoint { .. }
+            | ProgramSection::FEntry { .. }
+            | ProgramSection::FExit { .. }
+            | ProgramSection::Iter { .. } => BPF_PROG_TYPE_TRACING,
+            ProgramSection::Extension { .. } => BPF_PROG_TYPE_EXT,
+        }
+    }
+
+    /// Returns the attach type the kernel expects the program to be loaded
+    /// with, for the sections that imply one.
+    pub(crate) fn expected_attach_type(&self) -> Option<bpf_attach_type> {
+        use crate::generated::bpf_attach_type::*;
+        match self {
+            ProgramSection::Xdp { .. } => Some(BPF_XDP),
+            ProgramSection::XdpCpuMap { .. } => Some(BPF_XDP_CPUMAP),
+            ProgramSection::XdpDevMap { .. } => Some(BPF_XDP_DEVMAP),
+            ProgramSection::CgroupSkbIngress { .. } => Some(BPF_CGROUP_INET_INGRESS),
+            ProgramSection::CgroupSkbEgress { .. } => Some(BPF_CGROUP_INET_EGRESS),
+            ProgramSection::CgroupSockCreate { .. } => Some(BPF_CGROUP_INET_SOCK_CREATE),
+            ProgramSection::CgroupSockPostBind4 { .. } => Some(BPF_CGROUP_INET4_POST_BIND),
+            ProgramSection::CgroupSockPostBind6 { .. } => Some(BPF_CGROUP_INET6_POST_BIND),
+            ProgramSection::CgroupSockRelease { .. } => Some(BPF_CGROUP_INET_SOCK_RELEASE),
+            ProgramSection::CgroupSockAddrConnect4 { .. } => Some(BPF_CGROUP_INET4_CONNECT),
+            ProgramSection::CgroupSockAddrConnect6 { .. } => Some(BPF_CGROUP_INET6_CONNECT),
+            ProgramSection::CgroupSockAddrBind4 { .. } => Some(BPF_CGROUP_INET4_BIND),
+            ProgramSection::CgroupSockAddrBind6 { .. } => Some(BPF_CGROUP_INET6_BIND),
+            ProgramSection::CgroupSockAddrSendmsg4 { .. } => Some(BPF_CGROUP_UDP4_SENDMSG),
+            ProgramSection::CgroupSockAddrSendmsg6 { .. } => Some(BPF_CGROUP_UDP6_SENDMSG),
+            ProgramSection::CgroupSockAddrRecvmsg4 { .. } => Some(BPF_CGROUP_UDP4_RECVMSG),
+            ProgramSection::CgroupSockAddrRecvmsg6 { .. } => Some(BPF_CGROUP_UDP6_RECVMSG),
+            ProgramSection::CgroupSockAddrGetpeername4 { .. } => Some(BPF_CGROUP_INET4_GETPEERNAME),
+            ProgramSection::CgroupSockAddrGetpeername6 { .. } => Some(BPF_CGROUP_INET6_GETPEERNAME),
+            ProgramSection::Lsm { .. } => Some(BPF_LSM_MAC),
+            ProgramSection::BtfTracePoint { .. } => Some(BPF_TRACE_RAW_TP),
+            ProgramSection::FEntry { .. } => Some(BPF_TRACE_FENTRY),
+            ProgramSection::FExit { .. } => Some(BPF_TRACE_FEXIT),
+            ProgramSection::Iter { .. } => Some(BPF_TRACE_ITER),
+            _ => None,
         }
     }
 }
@@ -178,7 +308,14 @@ impl FromStr for ProgramSection {
             "kretprobe" => KRetProbe { name },
             "uprobe" => UProbe { name },
             "uretprobe" => URetProbe { name },
-            "xdp" => Xdp { name },
+            "usdt" => Usdt { name },
+            "xdp" => match &*name {
+                "cpumap" => XdpCpuMap { name },
+                "devmap" => XdpDevMap { name },
+                _ => Xdp { name },
+            },
+            "xdp/cpumap" => XdpCpuMap { name },
+            "xdp/devmap" => XdpDevMap { name },
             "tp_btf" => BtfTracePoint { name },
             _ if kind.starts_with("tracepoint") || kind.starts_with("tp") => {
                 // tracepoint sections are named `tracepoint/category/event_name`,
@@ -213,8 +350,38 @@ impl FromStr for ProgramSection {
             "cgroup_skb/ingress" => CgroupSkbIngress { name },
             "cgroup_skb/egress" => CgroupSkbEgress { name },
             "cgroup/skb" => CgroupSkb { name },
+            "cgroup/sock" | "cgroup/sock_create" => CgroupSockCreate { name },
+            "cgroup/post_bind4" => CgroupSockPostBind4 { name },
+            "cgroup/post_bind6" => CgroupSockPostBind6 { name },
+            "cgroup/sock_release" => CgroupSockRelease { name },
+            "cgroup/connect4" => CgroupSockAddrConnect4 { name },
+            "cgroup/connect6" => CgroupSockAddrConnect6 { name },
+            "cgroup/bind4" => CgroupSockAddrBind4 { name },
+            "cgroup/bind6" => CgroupSockAddrBind6 { name },
+            "cgroup/sendmsg4" => CgroupSockAddrSendmsg4 { name },
+            "cgroup/sendmsg6" => CgroupSockAddrSendmsg6 { name },
+            "cgroup/recvmsg4" => CgroupSockAddrRecvmsg4 { name },
+            "cgroup/recvmsg6" => CgroupSockAddrRecvmsg6 { name },
+            "cgroup/getpeername4" => CgroupSockAddrGetpeername4 { name },
+            "cgroup/getpeername6" => CgroupSockAddrGetpeername6 { name },
+            "cgroup/dev" => CgroupDevice { name },
             "cgroup" => match &*name {
                 "skb" => CgroupSkb { name },
+                "sock" | "sock_create" => CgroupSockCreate { name },
+                "post_bind4" => CgroupSockPostBind4 { name },
+                "post_bind6" => CgroupSockPostBind6 { name },
+                "sock_release" => CgroupSockRelease { name },
+                "connect4" => CgroupSockAddrConnect4 { name },
+                "connect6" => CgroupSockAddrConnect6 { name },
+                "bind4" => CgroupSockAddrBind4 { name },
+                "bind6" => CgroupSockAddrBind6 { name },
+                "sendmsg4" => CgroupSockAddrSendmsg4 { name },
+                "sendmsg6" => CgroupSockAddrSendmsg6 { name },
+                "recvmsg4" => CgroupSockAddrRecvmsg4 { name },
+                "recvmsg6" => CgroupSockAddrRecvmsg6 { name },
+                "getpeername4" => CgroupSockAddrGetpeername4 { name },
+                "getpeername6" => CgroupSockAddrGetpeername6 { name },
+                "dev" => CgroupDevice { name },
                 _ => {
                     return Err(ParseError::InvalidProgramSection {
                         section: section.to_owned(),
@@ -228,8 +395,9 @@ impl FromStr for ProgramSection {
             "fentry" => FEntry { name },
             "fexit" => FExit { name },
             "freplace" => Extension { name },
+            "iter" => Iter { name },
             _ => {
-                return Err(ParseError::InvalidProgramSection {
+                return Err(ParseError::UnrecognizedProgramSection {
                     section: section.to_owned(),
                 })
             }
@@ -238,7 +406,66 @@ impl FromStr for ProgramSection {
 }
 
 impl Object {
-    pub(crate) fn parse(data: &[u8]) -> Result<Object, BpfError> {
+    /// Classifies a program from the name of its ELF section.
+    ///
+    /// Returns the program type, the attach type the program is expected to
+    /// be loaded with if the section implies one, and the attach target
+    /// encoded in the section, eg `try_to_wake_up` for
+    /// `kprobe/try_to_wake_up`. Returns `None` if the section isn't a program
+    /// section known to aya.
+    pub fn section_program_type(
+        name: &str,
+    ) -> Option<(bpf_prog_type, Option<bpf_attach_type>, String)> {
+        let section = name.parse::<ProgramSection>().ok()?;
+        Some((
+            section.prog_type(),
+            section.expected_attach_type(),
+            section.name().to_owned(),
+        ))
+    }
+
+    /// Returns the definitions of the maps of the object, including the
+    /// maps backing its `.bss`, `.data` and `.rodata` sections.
+    pub(crate) fn extract_maps(&self) -> HashMap<String, ParsedMap> {
+        self.maps
+            .iter()
+            .map(|(name, map)| {
+                let (btf_key_type, btf_value_type) = self
+                    .btf
+                    .as_ref()
+                    .and_then(|btf| {
+                        let (key_id, value_id) = btf.map_kv_type_ids(name)?;
+                        let type_name =
+                            |id| btf.type_by_id(id).ok().and_then(|ty| btf.err_type_name(ty));
+                        Some((type_name(key_id), type_name(value_id)))
+                    })
+                    .unwrap_or((None, None));
+                let def = &map.def;
+                let parsed = ParsedMap {
+                    map_type: def.map_type,
+                    key_size: def.key_size,
+                    value_size: def.value_size,
+                    max_entries: def.max_entries,
+                    flags: def.map_flags,
+                    btf_key_type,
+                    btf_value_type,
+                };
+                (name.clone(), parsed)
+            })
+            .collect()
+    }
+
+    /// Parses `data`, renaming the sections listed in `renames` before their
+    /// kind and program type are derived from their name.
+    ///
+    /// Program sections whose name doesn't start with a known program type
+    /// are skipped with a warning if `allow_unknown_sections` is true, and
+    /// fail with [`ParseError::UnrecognizedProgramSection`] otherwise.
+    pub(crate) fn parse(
+        data: &[u8],
+        renames: &HashMap<&str, &str>,
+        allow_unknown_sections: bool,
+    ) -> Result<Object, BpfError> {
         let obj = object::read::File::parse(data).map_err(ParseError::ElfError)?;
         let endianness = obj.endianness();
 
@@ -287,6 +514,17 @@ impl Object {
             bpf_obj.parse_section(Section::try_from(&s)?)?;
             if let Some(s) = obj.section_by_name(".BTF.ext") {
                 bpf_obj.parse_section(Section::try_from(&s)?)?;
+            } else {
+                debug!("no .BTF.ext section, programs are loaded without func and line info");
+            }
+        }
+
+        for (old_name, _) in renames.iter() {
+            if obj.section_by_name(old_name).is_none() {
+                return Err(ParseError::SectionNotFound {
+                    name: old_name.to_string(),
+                }
+                .into());
             }
         }
 
@@ -297,7 +535,19 @@ impl Object {
                 }
             }
 
-            bpf_obj.parse_section(Section::try_from(&s)?)?;
+            let mut section = Section::try_from(&s)?;
+            if let Some(new_name) = renames.get(section.name) {
+                section.rename(new_name, s.kind());
+            }
+            match bpf_obj.parse_section(section) {
+                Err(BpfError::ParseError(ParseError::UnrecognizedProgramSection { section }))
+                    if allow_unknown_sections =>
+                {
+                    warn!("skipping section `{}`: unknown program type", section);
+                    bpf_obj.skipped_sections.push(section);
+                }
+                res => res?,
+            }
         }
 
         Ok(bpf_obj)
@@ -318,9 +568,45 @@ impl Object {
             section_sizes: HashMap::new(),
             symbol_offset_by_name: HashMap::new(),
             text_section_index: None,
+            skipped_sections: Vec::new(),
+            redefined_maps: Vec::new(),
         }
     }
 
+    /// Checks that the maps defined more than once in the object, eg in the
+    /// `maps/NAME` sections of several compilation units, all have the same
+    /// type, key size, value size and max entries.
+    ///
+    /// Programs referencing any of the definitions all use the same map, so
+    /// definitions that don't match would make some of them use a map with
+    /// an unexpected layout.
+    pub fn verify_maps_consistent(&self) -> Result<(), ParseError> {
+        for (name, first_def) in &self.redefined_maps {
+            let def = &self.maps[name].def;
+            let layout =
+                |def: &bpf_map_def| (def.map_type, def.key_size, def.value_size, def.max_entries);
+            if layout(first_def) != layout(def) {
+                let parsed = |def: &bpf_map_def| {
+                    Box::new(ParsedMap {
+                        map_type: def.map_type,
+                        key_size: def.key_size,
+                        value_size: def.value_size,
+                        max_entries: def.max_entries,
+                        flags: def.map_flags,
+                        btf_key_type: None,
+                        btf_value_type: None,
+                    })
+                };
+                return Err(ParseError::InconsistentMapDefinition {
+                    map_name: name.clone(),
+                    first_def: parsed(first_def),
+                    conflicting_def: parsed(def),
+                });
+            }
+        }
+        Ok(())
+    }
+
     pub fn patch_map_data(&mut self, globals: HashMap<&str, &[u8]>) -> Result<(), ParseError> {
         let symbols: HashMap<String, &Symbol> = self
             .symbols_by_index
@@ -387,8 +673,8 @@ impl Object {
 
         let (func_info, line_info, func_info_rec_size, line_info_rec_size) =
             if let Some(btf_ext) = &self.btf_ext {
-                let func_info = btf_ext.func_info.get(section.name);
-                let line_info = btf_ext.line_info.get(section.name);
+                let func_info = btf_ext.func_info.get(section.elf_name);
+                let line_info = btf_ext.line_info.get(section.elf_name);
                 (
                     func_info,
                     line_info,
@@ -458,14 +744,14 @@ impl Object {
                     let bytes_offset = offset as u32 / INS_SIZE as u32;
                     let section_size_bytes = sym.size as u32 / INS_SIZE as u32;
 
-                    let mut func_info = btf_ext.func_info.get(section.name);
+                    let mut func_info = btf_ext.func_info.get(section.elf_name);
                     func_info.func_info = func_info
                         .func_info
                         .into_iter()
                         .filter(|f| f.insn_off == bytes_offset)
                         .collect();
 
-                    let mut line_info = btf_ext.line_info.get(section.name);
+                    let mut line_info = btf_ext.line_info.get(section.elf_name);
                     line_info.line_info = line_info
                         .line_info
                         .into_iter()
@@ -532,7 +818,7 @@ impl Object {
             parts.push(parts[0]);
         }
         self.section_sizes
-            .insert(section.name.to_owned(), section.size);
+            .insert(section.elf_name.to_owned(), section.size);
         match section.kind {
             BpfSectionKind::Data => {
                 self.maps
@@ -543,8 +829,12 @@ impl Object {
             BpfSectionKind::BtfExt => self.parse_btf_ext(&section)?,
             BpfSectionKind::Maps => {
                 let name = section.name.splitn(2, '/').last().unwrap();
-                self.maps
-                    .insert(name.to_string(), parse_map(&section, name)?);
+                if let Some(previous) = self
+                    .maps
+                    .insert(name.to_string(), parse_map(&section, name)?)
+                {
+                    self.redefined_maps.push((name.to_string(), previous.def));
+                }
             }
             BpfSectionKind::Program => {
                 let program = self.parse_program(&section)?;
@@ -598,12 +888,25 @@ pub enum ParseError {
     #[error("invalid program section `{section}`")]
     InvalidProgramSection { section: String },
 
+    #[error("unrecognized program section `{section}`: unknown program type")]
+    UnrecognizedProgramSection { section: String },
+
+    #[error("section `{name}` not found")]
+    SectionNotFound { name: String },
+
     #[error("invalid program code")]
     InvalidProgramCode,
 
     #[error("error parsing map `{name}`")]
     InvalidMapDefinition { name: String },
 
+    #[error("map `{map_name}` is defined more than once with different definitions: {first_def:?} and {conflicting_def:?}")]
+    InconsistentMapDefinition {
+        map_name: String,
+        first_def: Box<ParsedMap>,
+        conflicting_def: Box<ParsedMap>,
+    },
+
     #[error("two or more symbols in section `{section_index}` have the same address {address:#X}")]
     SymbolTableConflict { section_index: usize, address: u64 },
 
@@ -666,6 +969,19 @@ impl BpfSectionKind {
             BpfSectionKind::Undefined
         }
     }
+
+    fn from_obj_section(name: &str, kind: SectionKind, size: u64) -> BpfSectionKind {
+        match BpfSectionKind::from_name(name) {
+            BpfSectionKind::Undefined => {
+                if kind == SectionKind::Text && size > 0 {
+                    BpfSectionKind::Program
+                } else {
+                    BpfSectionKind::Undefined
+                }
+            }
+            k => k,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -674,11 +990,20 @@ struct Section<'a> {
     kind: BpfSectionKind,
     address: u64,
     name: &'a str,
+    // the name of the section in the ELF file, which BTF refers to
+    elf_name: &'a str,
     data: &'a [u8],
     size: u64,
     relocations: Vec<Relocation>,
 }
 
+impl<'a> Section<'a> {
+    fn rename(&mut self, name: &'a str, kind: SectionKind) {
+        self.name = name;
+        self.kind = BpfSectionKind::from_obj_section(name, kind, self.size);
+    }
+}
+
 impl<'data, 'file, 'a> TryFrom<&'a ObjSection<'data, 'file>> for Section<'a> {
     type Error = ParseError;
 
@@ -689,21 +1014,13 @@ impl<'data, 'file, 'a> TryFrom<&'a ObjSection<'data, 'file>> for Section<'a> {
             source,
         };
         let name = section.name().map_err(map_err)?;
-        let kind = match BpfSectionKind::from_name(name) {
-            BpfSectionKind::Undefined => {
-                if section.kind() == SectionKind::Text && section.size() > 0 {
-                    BpfSectionKind::Program
-                } else {
-                    BpfSectionKind::Undefined
-                }
-            }
-            k => k,
-        };
+        let kind = BpfSectionKind::from_obj_section(name, section.kind(), section.size());
         Ok(Section {
             index,
             kind,
             address: section.address(),
             name,
+            elf_name: name,
             data: section.data().map_err(map_err)?,
             size: section.size(),
             relocations: section
@@ -768,6 +1085,22 @@ pub enum KernelVersion {
     Any,
 }
 
+impl KernelVersion {
+    /// Returns the `kern_version` passed to `BPF_PROG_LOAD`, the version of
+    /// the running kernel for programs that work with any kernel.
+    pub(crate) fn as_u32(self) -> u32 {
+        match self {
+            KernelVersion::Any => {
+                let (major, minor, patch) = crate::sys::kernel_version().unwrap();
+                // like LINUX_VERSION_CODE, whose patch level is clamped to
+                // 255 since 4.9.256
+                (major << 16) + (minor << 8) + patch.min(255)
+            }
+            KernelVersion::Version(v) => v,
+        }
+    }
+}
+
 impl From<KernelVersion> for u32 {
     fn from(version: KernelVersion) -> u32 {
         match version {
@@ -857,6 +1190,7 @@ mod tests {
             kind,
             address: 0,
             name,
+            elf_name: name,
             data,
             size: data.len() as u64,
             relocations: Vec::new(),
@@ -878,10 +1212,47 @@ mod tests {
         unsafe { crate::util::bytes_of(val) }
     }
 
+    #[test]
+    fn test_parse_fake_elf() {
+        use fake::{insn, FakeElf, BPF_EXIT, BPF_LD_IMM64};
+
+        let data = FakeElf::new()
+            .license("Dual MIT/GPL")
+            .map(
+                "foo",
+                bpf_map_def {
+                    map_type: 1,
+                    key_size: 4,
+                    value_size: 8,
+                    max_entries: 16,
+                    ..Default::default()
+                },
+            )
+            .program(
+                "kprobe/bar",
+                &[insn(BPF_LD_IMM64, 0), insn(0, 0), insn(BPF_EXIT, 0)],
+            )
+            .relocation("kprobe/bar", 0, "foo")
+            .build();
+        let obj = Object::parse(&data, &HashMap::new(), false).unwrap();
+
+        assert_eq!(obj.license.to_str().unwrap(), "Dual MIT/GPL");
+        let map = &obj.maps["foo"];
+        assert_eq!(map.def.value_size, 8);
+        assert_eq!(map.def.max_entries, 16);
+        let program = &obj.programs["bar"];
+        assert_matches!(program.section, ProgramSection::KProbe { .. });
+        assert_eq!(program.function.instructions.len(), 3);
+        let relocations = &obj.relocations[&program.function.section_index];
+        let symbol = &obj.symbols_by_index[&relocations[&0].symbol_index];
+        assert_eq!(symbol.name.as_deref(), Some("foo"));
+        assert_eq!(symbol.section_index, Some(map.section_index));
+    }
+
     #[test]
     fn test_parse_generic_error() {
         assert!(matches!(
-            Object::parse(&b"foo"[..]),
+            Object::parse(&b"foo"[..], &HashMap::new(), true),
             Err(BpfError::ParseError(ParseError::ElfError(_)))
         ))
     }
@@ -1130,6 +1501,53 @@ mod tests {
             Ok(())
         );
         assert!(obj.maps.get("foo").is_some());
+
+        let maps = obj.extract_maps();
+        assert_eq!(
+            maps.get("foo"),
+            Some(&ParsedMap {
+                map_type: 1,
+                key_size: 2,
+                value_size: 3,
+                max_entries: 4,
+                flags: 5,
+                btf_key_type: None,
+                btf_value_type: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_verify_maps_consistent() {
+        let mut obj = fake_obj();
+        let defs = [(10, 0), (10, 1), (20, 0)]
+            .iter()
+            .map(|(max_entries, map_flags)| bpf_map_def {
+                map_type: 1,
+                key_size: 4,
+                value_size: 8,
+                max_entries: *max_entries,
+                map_flags: *map_flags,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let sections = defs
+            .iter()
+            .map(|def| fake_section(BpfSectionKind::Maps, "maps/foo", bytes_of(def)))
+            .collect::<Vec<_>>();
+        let mut sections = sections.into_iter();
+
+        obj.parse_section(sections.next().unwrap()).unwrap();
+        assert!(obj.verify_maps_consistent().is_ok());
+        // the flags aren't part of the layout
+        obj.parse_section(sections.next().unwrap()).unwrap();
+        assert!(obj.verify_maps_consistent().is_ok());
+        obj.parse_section(sections.next().unwrap()).unwrap();
+        assert_matches!(
+            obj.verify_maps_consistent(),
+            Err(ParseError::InconsistentMapDefinition { map_name, first_def, conflicting_def })
+                if map_name == "foo" && first_def.max_entries == 10 && conflicting_def.max_entries == 20
+        );
     }
 
     #[test]
@@ -1212,6 +1630,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_section_usdt() {
+        let mut obj = fake_obj();
+
+        assert_matches!(
+            obj.parse_section(fake_section(
+                BpfSectionKind::Program,
+                "usdt/foo",
+                bytes_of(&fake_ins())
+            )),
+            Ok(())
+        );
+        assert_matches!(
+            obj.programs.get("foo"),
+            Some(Program {
+                section: ProgramSection::Usdt { .. },
+                ..
+            })
+        );
+    }
+
     #[test]
     fn test_parse_section_trace_point() {
         let mut obj = fake_obj();
@@ -1270,6 +1709,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_program_section_errors() {
+        assert_matches!(
+            ProgramSection::from_str("xdp_v2/foo"),
+            Err(ParseError::UnrecognizedProgramSection { section }) if section == "xdp_v2/foo"
+        );
+        assert_matches!(
+            ProgramSection::from_str("sk_skb/foo"),
+            Err(ParseError::InvalidProgramSection { .. })
+        );
+    }
+
+    #[test]
+    fn test_parse_renamed_section() {
+        let mut obj = fake_obj();
+
+        let ins = fake_ins();
+        let mut section = fake_section(BpfSectionKind::Program, "xdp_v2/foo", bytes_of(&ins));
+        assert_matches!(
+            obj.parse_section(fake_section(
+                BpfSectionKind::Program,
+                "xdp_v2/foo",
+                bytes_of(&fake_ins())
+            )),
+            Err(BpfError::ParseError(
+                ParseError::UnrecognizedProgramSection { .. }
+            ))
+        );
+
+        section.rename("xdp/foo", SectionKind::Text);
+        assert_eq!(section.elf_name, "xdp_v2/foo");
+        assert_matches!(obj.parse_section(section), Ok(()));
+        assert_matches!(
+            obj.programs.get("foo"),
+            Some(Program {
+                section: ProgramSection::Xdp { .. },
+                ..
+            })
+        );
+        assert!(obj.section_sizes.contains_key("xdp_v2/foo"));
+    }
+
     #[test]
     fn test_parse_section_xdp() {
         let mut obj = fake_obj();
@@ -1291,6 +1772,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_section_xdp_cpumap_devmap() {
+        let mut obj = fake_obj();
+
+        assert_matches!(
+            obj.parse_section(fake_section(
+                BpfSectionKind::Program,
+                "xdp/cpumap/foo",
+                bytes_of(&fake_ins())
+            )),
+            Ok(())
+        );
+        assert_matches!(
+            obj.programs.get("foo"),
+            Some(Program {
+                section: ProgramSection::XdpCpuMap { .. },
+                ..
+            })
+        );
+
+        assert_matches!(
+            obj.parse_section(fake_section(
+                BpfSectionKind::Program,
+                "xdp/devmap/bar",
+                bytes_of(&fake_ins())
+            )),
+            Ok(())
+        );
+        assert_matches!(
+            obj.programs.get("bar"),
+            Some(Program {
+                section: ProgramSection::XdpDevMap { .. },
+                ..
+            })
+        );
+    }
+
     #[test]
     fn test_parse_section_raw_tp() {
         let mut obj = fake_obj();
@@ -1538,6 +2056,106 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_section_cgroup_sock_create_unnamed() {
+        let mut obj = fake_obj();
+
+        assert_matches!(
+            obj.parse_section(fake_section(
+                BpfSectionKind::Program,
+                "cgroup/sock_create",
+                bytes_of(&fake_ins())
+            )),
+            Ok(())
+        );
+        assert_matches!(
+            obj.programs.get("sock_create"),
+            Some(Program {
+                section: ProgramSection::CgroupSockCreate { .. },
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_section_cgroup_device() {
+        let mut obj = fake_obj();
+
+        assert_matches!(
+            obj.parse_section(fake_section(
+                BpfSectionKind::Program,
+                "cgroup/dev",
+                bytes_of(&fake_ins())
+            )),
+            Ok(())
+        );
+        assert_matches!(
+            obj.programs.get("dev"),
+            Some(Program {
+                section: ProgramSection::CgroupDevice { .. },
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_section_cgroup_sock_post_bind4_named() {
+        let mut obj = fake_obj();
+
+        assert_matches!(
+            obj.parse_section(fake_section(
+                BpfSectionKind::Program,
+                "cgroup/post_bind4/foo",
+                bytes_of(&fake_ins())
+            )),
+            Ok(())
+        );
+        assert_matches!(
+            obj.programs.get("foo"),
+            Some(Program {
+                section: ProgramSection::CgroupSockPostBind4 { .. },
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_section_cgroup_sock_addr() {
+        let mut obj = fake_obj();
+
+        assert_matches!(
+            obj.parse_section(fake_section(
+                BpfSectionKind::Program,
+                "cgroup/connect6/foo",
+                bytes_of(&fake_ins())
+            )),
+            Ok(())
+        );
+        assert_matches!(
+            obj.programs.get("foo"),
+            Some(Program {
+                section: ProgramSection::CgroupSockAddrConnect6 { .. },
+                ..
+            })
+        );
+
+        assert_matches!(
+            obj.parse_section(fake_section(
+                BpfSectionKind::Program,
+                "cgroup/getpeername4",
+                bytes_of(&fake_ins())
+            )),
+            Ok(())
+        );
+        assert_matches!(
+            obj.programs.get("getpeername4"),
+            Some(Program {
+                section: ProgramSection::CgroupSockAddrGetpeername4 { .. },
+                ..
+            })
+        );
+    }
+
     #[test]
     fn test_patch_map_data() {
         let mut obj = fake_obj();
@@ -1578,4 +2196,208 @@ mod tests {
         let map = obj.maps.get(".rodata").unwrap();
         assert_eq!(test_data, map.data);
     }
+
+    #[test]
+    fn test_section_program_type() {
+        use crate::generated::{bpf_attach_type::*, bpf_prog_type::*};
+
+        let sections = [
+            ("kprobe/foo", BPF_PROG_TYPE_KPROBE, None, "foo"),
+            ("kretprobe/foo", BPF_PROG_TYPE_KPROBE, None, "foo"),
+            ("uprobe/foo", BPF_PROG_TYPE_KPROBE, None, "foo"),
+            ("uretprobe/foo", BPF_PROG_TYPE_KPROBE, None, "foo"),
+            ("usdt/foo", BPF_PROG_TYPE_KPROBE, None, "foo"),
+            (
+                "tracepoint/syscalls/sys_enter_open",
+                BPF_PROG_TYPE_TRACEPOINT,
+                None,
+                "syscalls/sys_enter_open",
+            ),
+            (
+                "tp/syscalls/sys_enter_open",
+                BPF_PROG_TYPE_TRACEPOINT,
+                None,
+                "syscalls/sys_enter_open",
+            ),
+            ("socket/foo", BPF_PROG_TYPE_SOCKET_FILTER, None, "foo"),
+            ("xdp/foo", BPF_PROG_TYPE_XDP, Some(BPF_XDP), "foo"),
+            (
+                "xdp/cpumap/foo",
+                BPF_PROG_TYPE_XDP,
+                Some(BPF_XDP_CPUMAP),
+                "foo",
+            ),
+            (
+                "xdp/devmap/foo",
+                BPF_PROG_TYPE_XDP,
+                Some(BPF_XDP_DEVMAP),
+                "foo",
+            ),
+            ("sk_msg/foo", BPF_PROG_TYPE_SK_MSG, None, "foo"),
+            (
+                "sk_skb/stream_parser/foo",
+                BPF_PROG_TYPE_SK_SKB,
+                None,
+                "foo",
+            ),
+            (
+                "sk_skb/stream_verdict/foo",
+                BPF_PROG_TYPE_SK_SKB,
+                None,
+                "foo",
+            ),
+            ("sockops/foo", BPF_PROG_TYPE_SOCK_OPS, None, "foo"),
+            ("classifier/foo", BPF_PROG_TYPE_SCHED_CLS, None, "foo"),
+            (
+                "cgroup_skb/ingress/foo",
+                BPF_PROG_TYPE_CGROUP_SKB,
+                Some(BPF_CGROUP_INET_INGRESS),
+                "foo",
+            ),
+            (
+                "cgroup_skb/egress/foo",
+                BPF_PROG_TYPE_CGROUP_SKB,
+                Some(BPF_CGROUP_INET_EGRESS),
+                "foo",
+            ),
+            ("cgroup/skb/foo", BPF_PROG_TYPE_CGROUP_SKB, None, "foo"),
+            (
+                "cgroup/sock_create/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK,
+                Some(BPF_CGROUP_INET_SOCK_CREATE),
+                "foo",
+            ),
+            (
+                "cgroup/post_bind4/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK,
+                Some(BPF_CGROUP_INET4_POST_BIND),
+                "foo",
+            ),
+            (
+                "cgroup/post_bind6/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK,
+                Some(BPF_CGROUP_INET6_POST_BIND),
+                "foo",
+            ),
+            (
+                "cgroup/sock_release/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK,
+                Some(BPF_CGROUP_INET_SOCK_RELEASE),
+                "foo",
+            ),
+            (
+                "cgroup/connect4/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                Some(BPF_CGROUP_INET4_CONNECT),
+                "foo",
+            ),
+            (
+                "cgroup/connect6/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                Some(BPF_CGROUP_INET6_CONNECT),
+                "foo",
+            ),
+            (
+                "cgroup/bind4/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                Some(BPF_CGROUP_INET4_BIND),
+                "foo",
+            ),
+            (
+                "cgroup/bind6/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                Some(BPF_CGROUP_INET6_BIND),
+                "foo",
+            ),
+            (
+                "cgroup/sendmsg4/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                Some(BPF_CGROUP_UDP4_SENDMSG),
+                "foo",
+            ),
+            (
+                "cgroup/sendmsg6/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                Some(BPF_CGROUP_UDP6_SENDMSG),
+                "foo",
+            ),
+            (
+                "cgroup/recvmsg4/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                Some(BPF_CGROUP_UDP4_RECVMSG),
+                "foo",
+            ),
+            (
+                "cgroup/recvmsg6/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                Some(BPF_CGROUP_UDP6_RECVMSG),
+                "foo",
+            ),
+            (
+                "cgroup/getpeername4/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                Some(BPF_CGROUP_INET4_GETPEERNAME),
+                "foo",
+            ),
+            (
+                "cgroup/getpeername6/foo",
+                BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                Some(BPF_CGROUP_INET6_GETPEERNAME),
+                "foo",
+            ),
+            ("cgroup/dev/foo", BPF_PROG_TYPE_CGROUP_DEVICE, None, "foo"),
+            ("lirc_mode2/foo", BPF_PROG_TYPE_LIRC_MODE2, None, "foo"),
+            ("perf_event/foo", BPF_PROG_TYPE_PERF_EVENT, None, "foo"),
+            ("raw_tp/foo", BPF_PROG_TYPE_RAW_TRACEPOINT, None, "foo"),
+            (
+                "raw_tracepoint/foo",
+                BPF_PROG_TYPE_RAW_TRACEPOINT,
+                None,
+                "foo",
+            ),
+            (
+                "lsm/file_open",
+                BPF_PROG_TYPE_LSM,
+                Some(BPF_LSM_MAC),
+                "file_open",
+            ),
+            (
+                "tp_btf/sched_switch",
+                BPF_PROG_TYPE_TRACING,
+                Some(BPF_TRACE_RAW_TP),
+                "sched_switch",
+            ),
+            (
+                "fentry/foo",
+                BPF_PROG_TYPE_TRACING,
+                Some(BPF_TRACE_FENTRY),
+                "foo",
+            ),
+            (
+                "fexit/foo",
+                BPF_PROG_TYPE_TRACING,
+                Some(BPF_TRACE_FEXIT),
+                "foo",
+            ),
+            ("freplace/foo", BPF_PROG_TYPE_EXT, None, "foo"),
+            (
+                "iter/task",
+                BPF_PROG_TYPE_TRACING,
+                Some(BPF_TRACE_ITER),
+                "task",
+            ),
+        ];
+        for (section, prog_type, attach_type, target) in sections {
+            assert_eq!(
+                Object::section_program_type(section),
+                Some((prog_type, attach_type, target.to_owned())),
+                "{}",
+                section
+            );
+        }
+
+        assert_eq!(Object::section_program_type("license"), None);
+        assert_eq!(Object::section_program_type("maps/foo"), None);
+        assert_eq!(Object::section_program_type("sk_skb/foo"), None);
+    }
 }
@@ -0,0 +1,377 @@
+use std::{io, mem, os::unix::io::RawFd};
+
+use bitflags;
+use libc::{close, fstat, openat, O_CLOEXEC, O_DIRECTORY, O_RDONLY};
+use thiserror::Error;
+
+use crate::{
+    generated::{
+        bpf_attach_type, BPF_F_ALLOW_MULTI, BPF_F_ALLOW_OVERRIDE, BPF_F_QUERY_EFFECTIVE,
+        BPF_F_REPLACE,
+    },
+    programs::{query, LinkRef, ProgAttachLink, ProgramData, ProgramError, ProgramInfo},
+    sys::bpf_prog_attach_replace,
+};
+
+/// The type returned when attaching a cgroup program with
+/// [`CgroupAttachFlags`] fails.
+#[derive(Debug, Error)]
+pub enum CgroupError {
+    /// [`CgroupAttachFlags::REPLACE`] was used without
+    /// [`CgroupAttachFlags::ALLOW_MULTI`].
+    #[error("BPF_F_REPLACE requires BPF_F_ALLOW_MULTI")]
+    ReplaceRequiresMulti,
+
+    /// [`CgroupAttachFlags::REPLACE`] was used without the fd of the program
+    /// to replace.
+    #[error("BPF_F_REPLACE requires the fd of the program to replace")]
+    MissingReplaceFd,
+
+    /// [`CgroupAttachFlags::ALLOW_OVERRIDE`] and
+    /// [`CgroupAttachFlags::ALLOW_MULTI`] were used together.
+    #[error("BPF_F_ALLOW_OVERRIDE and BPF_F_ALLOW_MULTI are mutually exclusive")]
+    OverrideWithMulti,
+}
+
+bitflags! {
+    /// Flags passed to the `attach_with_flags()` method of the cgroup programs.
+    ///
+    /// Without any flag, only one program can be attached to a cgroup for a
+    /// given attach type, and the programs of the descendant cgroups can't
+    /// override it.
+    ///
+    /// Unlike `attach()`, `attach_with_flags()` always uses `bpf_prog_attach`:
+    /// the links created by `bpf_link_create` are always attached as if
+    /// [`ALLOW_MULTI`](CgroupAttachFlags::ALLOW_MULTI) was set and take no
+    /// flags. Its `replace_fd` argument is the fd of the attached program that
+    /// is atomically replaced when the flags contain
+    /// [`REPLACE`](CgroupAttachFlags::REPLACE), and is ignored otherwise.
+    ///
+    /// # Errors
+    ///
+    /// `attach_with_flags()` returns [`CgroupError::ReplaceRequiresMulti`] if
+    /// the flags contain `REPLACE` but not `ALLOW_MULTI`,
+    /// [`CgroupError::MissingReplaceFd`] if they contain `REPLACE` and
+    /// `replace_fd` is `None` and [`CgroupError::OverrideWithMulti`] if they
+    /// contain both `ALLOW_OVERRIDE` and `ALLOW_MULTI`. The kernel also fails
+    /// with `EPERM` if the flags don't match the ones of the programs already
+    /// attached to the cgroup.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use `REPLACE` is 5.6.
+    #[derive(Default)]
+    pub struct CgroupAttachFlags: u32 {
+        /// Allow the programs attached to descendant cgroups to override this
+        /// program.
+        const ALLOW_OVERRIDE = BPF_F_ALLOW_OVERRIDE;
+        /// Allow multiple programs to be attached to the cgroup. The programs
+        /// of the cgroup and of its ancestors all run.
+        const ALLOW_MULTI = BPF_F_ALLOW_MULTI;
+        /// Atomically replace an attached program. Requires `ALLOW_MULTI`.
+        const REPLACE = BPF_F_REPLACE;
+    }
+}
+
+// mirrors the validation done by the kernel in __cgroup_bpf_attach(), so
+// that invalid combinations are reported with a meaningful error instead of
+// EINVAL
+fn check_flags(flags: CgroupAttachFlags, replace_fd: Option<RawFd>) -> Result<(), CgroupError> {
+    if flags.contains(CgroupAttachFlags::ALLOW_OVERRIDE | CgroupAttachFlags::ALLOW_MULTI) {
+        return Err(CgroupError::OverrideWithMulti);
+    }
+    if flags.contains(CgroupAttachFlags::REPLACE) {
+        if !flags.contains(CgroupAttachFlags::ALLOW_MULTI) {
+            return Err(CgroupError::ReplaceRequiresMulti);
+        }
+        if replace_fd.is_none() {
+            return Err(CgroupError::MissingReplaceFd);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn attach_with_flags(
+    data: &mut ProgramData,
+    cgroup_fd: RawFd,
+    attach_type: bpf_attach_type,
+    flags: CgroupAttachFlags,
+    replace_fd: Option<RawFd>,
+) -> Result<LinkRef, ProgramError> {
+    let prog_fd = data.fd_or_err()?;
+    check_flags(flags, replace_fd)?;
+    // the replace fd is only read by the kernel when BPF_F_REPLACE is set
+    let replace_fd = replace_fd.filter(|_| flags.contains(CgroupAttachFlags::REPLACE));
+
+    bpf_prog_attach_replace(prog_fd, cgroup_fd, attach_type, flags.bits(), replace_fd).map_err(
+        |(_, io_error)| ProgramError::SyscallError {
+            call: "bpf_prog_attach".to_owned(),
+            io_error,
+        },
+    )?;
+
+    Ok(data.link(ProgAttachLink::new(prog_fd, cgroup_fd, attach_type)))
+}
+
+/// Returns information about the programs attached to `cgroup_fd` with the
+/// given attach type, including the programs attached by other processes.
+///
+/// If `effective` is `true`, the query is done with `BPF_F_QUERY_EFFECTIVE`
+/// and also returns the programs attached to the ancestors of the cgroup that
+/// run for it, in the order they run. The attach types are the variants of
+/// [`bpf_attach_type`](crate::programs::bpf_attach_type).
+pub fn cgroup_attached_programs(
+    cgroup_fd: RawFd,
+    attach_type: bpf_attach_type,
+    effective: bool,
+) -> Result<Vec<ProgramInfo>, ProgramError> {
+    let query_flags = if effective { BPF_F_QUERY_EFFECTIVE } else { 0 };
+    query(cgroup_fd, attach_type, query_flags, &mut None)?
+        .into_iter()
+        .map(ProgramInfo::from_id)
+        .collect()
+}
+
+/// The programs attached to a cgroup, returned by [`CgroupHierarchyIter`].
+pub struct CgroupPrograms {
+    /// The inode id of the cgroup directory.
+    pub cgroup_inode_id: u64,
+    /// The distance from the cgroup the iteration started from, which has
+    /// depth 0.
+    pub depth: usize,
+    /// The programs attached directly to the cgroup.
+    pub programs: Vec<ProgramInfo>,
+}
+
+/// An iterator over the programs attached to a cgroup and to each of its
+/// ancestors, like `bpftool cgroup tree` but walking towards the root.
+///
+/// The iterator goes up the hierarchy by opening the `..` directory of each
+/// cgroup, and stops after the root of the cgroup filesystem. The item type
+/// is `Result<CgroupPrograms, ProgramError>` and the iteration stops after the
+/// first error.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let bpf = aya::Bpf::load(&[])?;
+/// use std::{fs::File, os::unix::io::AsRawFd};
+/// use aya::programs::CgroupHierarchyIter;
+///
+/// let attach_type = bpf.program("count_egress").unwrap().expected_attach_type().unwrap();
+/// let cgroup = File::open("/sys/fs/cgroup/unified/my-service")?;
+/// for level in CgroupHierarchyIter::new(cgroup.as_raw_fd(), attach_type) {
+///     let level = level?;
+///     println!("{} {}", level.cgroup_inode_id, level.programs.len());
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct CgroupHierarchyIter {
+    // the cgroup of the next item, and whether the iterator opened its fd
+    current: Option<(RawFd, bool)>,
+    attach_type: bpf_attach_type,
+    depth: usize,
+}
+
+impl CgroupHierarchyIter {
+    /// Creates an iterator starting from the cgroup `cgroup_fd`, eg the fd of
+    /// a cgroup directory. The fd isn't closed by the iterator.
+    pub fn new(cgroup_fd: RawFd, attach_type: bpf_attach_type) -> CgroupHierarchyIter {
+        CgroupHierarchyIter {
+            current: Some((cgroup_fd, false)),
+            attach_type,
+            depth: 0,
+        }
+    }
+
+    fn step(&mut self, fd: RawFd) -> Result<CgroupPrograms, ProgramError> {
+        let stat = fd_stat(fd)?;
+        let programs = cgroup_attached_programs(fd, self.attach_type, false)?;
+
+        // Safety: libc wrapper
+        let parent = unsafe {
+            openat(
+                fd,
+                "..\0".as_ptr() as *const _,
+                O_RDONLY | O_DIRECTORY | O_CLOEXEC,
+            )
+        };
+        if parent < 0 {
+            return Err(ProgramError::SyscallError {
+                call: "openat".to_owned(),
+                io_error: io::Error::last_os_error(),
+            });
+        }
+        // the parent of the root of the cgroup filesystem is on another
+        // device, or is the root itself when the filesystem is mounted at /
+        match fd_stat(parent) {
+            Ok(parent_stat)
+                if parent_stat.st_dev == stat.st_dev && parent_stat.st_ino != stat.st_ino =>
+            {
+                self.current = Some((parent, true))
+            }
+            _ => unsafe {
+                close(parent);
+            },
+        }
+
+        let level = CgroupPrograms {
+            cgroup_inode_id: stat.st_ino as _,
+            depth: self.depth,
+            programs,
+        };
+        self.depth += 1;
+        Ok(level)
+    }
+}
+
+impl Iterator for CgroupHierarchyIter {
+    type Item = Result<CgroupPrograms, ProgramError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // step() only sets the next cgroup if it succeeds
+        let (fd, owned) = self.current.take()?;
+        let ret = self.step(fd);
+        if owned {
+            unsafe { close(fd) };
+        }
+        Some(ret)
+    }
+}
+
+impl Drop for CgroupHierarchyIter {
+    fn drop(&mut self) {
+        if let Some((fd, true)) = self.current.take() {
+            // Safety: libc wrapper
+            unsafe { close(fd) };
+        }
+    }
+}
+
+fn fd_stat(fd: RawFd) -> Result<libc::stat, ProgramError> {
+    let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+    // Safety: libc wrapper
+    if unsafe { fstat(fd, &mut stat) } != 0 {
+        return Err(ProgramError::SyscallError {
+            call: "fstat".to_owned(),
+            io_error: io::Error::last_os_error(),
+        });
+    }
+    Ok(stat)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::MetadataExt, os::unix::io::AsRawFd};
+
+    use libc::EFAULT;
+
+    use crate::{
+        generated::{bpf_attach_type::BPF_CGROUP_INET_EGRESS, bpf_attr, bpf_cmd, bpf_prog_info},
+        sys::{override_syscall, Syscall},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_check_flags() {
+        assert!(check_flags(CgroupAttachFlags::empty(), None).is_ok());
+        assert!(check_flags(CgroupAttachFlags::ALLOW_MULTI, None).is_ok());
+        assert!(check_flags(
+            CgroupAttachFlags::ALLOW_MULTI | CgroupAttachFlags::REPLACE,
+            Some(42)
+        )
+        .is_ok());
+        assert!(matches!(
+            check_flags(CgroupAttachFlags::REPLACE, Some(42)),
+            Err(CgroupError::ReplaceRequiresMulti)
+        ));
+        assert!(matches!(
+            check_flags(
+                CgroupAttachFlags::ALLOW_MULTI | CgroupAttachFlags::REPLACE,
+                None
+            ),
+            Err(CgroupError::MissingReplaceFd)
+        ));
+        assert!(matches!(
+            check_flags(
+                CgroupAttachFlags::ALLOW_OVERRIDE | CgroupAttachFlags::ALLOW_MULTI,
+                None
+            ),
+            Err(CgroupError::OverrideWithMulti)
+        ));
+    }
+
+    // reports program 7 as attached, and program 8 too for effective queries
+    fn override_query() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_QUERY,
+                attr,
+            } => {
+                let ids = unsafe { attr.query.prog_ids } as *mut u32;
+                let cnt = if unsafe { attr.query.query_flags } == BPF_F_QUERY_EFFECTIVE {
+                    unsafe { *ids.add(1) = 8 };
+                    2
+                } else {
+                    1
+                };
+                unsafe { *ids = 7 };
+                let attr = attr as *const bpf_attr as *mut bpf_attr;
+                unsafe { (*attr).query.prog_cnt = cnt };
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_GET_FD_BY_ID,
+                attr,
+            } => Ok(10_000 + unsafe { attr.__bindgen_anon_6.__bindgen_anon_1.prog_id } as i64),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let fd = unsafe { attr.info.bpf_fd };
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.id = fd - 10_000;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+    }
+
+    #[test]
+    fn test_cgroup_attached_programs() {
+        override_query();
+        let ids = |effective| {
+            cgroup_attached_programs(42, BPF_CGROUP_INET_EGRESS, effective)
+                .unwrap()
+                .iter()
+                .map(|p| p.id())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(false), vec![7]);
+        assert_eq!(ids(true), vec![7, 8]);
+    }
+
+    #[test]
+    fn test_hierarchy_iter() {
+        override_query();
+        // any directory works since the queries are mocked
+        let path = std::env::temp_dir();
+        let dir = fs::File::open(&path).unwrap();
+        let levels = CgroupHierarchyIter::new(dir.as_raw_fd(), BPF_CGROUP_INET_EGRESS)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            levels[0].cgroup_inode_id,
+            fs::metadata(&path).unwrap().ino()
+        );
+        for (depth, level) in levels.iter().enumerate() {
+            assert_eq!(level.depth, depth);
+            assert_eq!(level.programs.len(), 1);
+        }
+        // the iterator must not close the fd it was created with
+        assert!(dir.metadata().is_ok());
+    }
+}
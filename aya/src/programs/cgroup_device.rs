@@ -0,0 +1,502 @@
+use std::{
+    ffi::CString,
+    os::unix::prelude::{AsRawFd, RawFd},
+};
+
+use bitflags;
+
+use crate::{
+    generated::{
+        bpf_attach_type::BPF_CGROUP_DEVICE, bpf_insn, bpf_prog_type::BPF_PROG_TYPE_CGROUP_DEVICE,
+    },
+    obj::{self, Function, KernelVersion, ProgramSection},
+    programs::{
+        cgroup, load_program, query, query_attached_programs, CgroupAttachFlags, FdLink, LinkRef,
+        ProgAttachLink, ProgramData, ProgramError, ProgramInfo,
+    },
+    sys::{bpf_link_create, bpf_prog_attach, kernel_version},
+    VerifierLogLevel,
+};
+
+/// A program used to control access to devices in a cgroup.
+///
+/// [`CgroupDevice`] programs are attached to a [cgroup] and are called when a
+/// process in the cgroup creates a device node or opens a device for reading
+/// or writing. The program receives the type, major and minor numbers of the
+/// device and the requested access, and returns 1 to allow it or 0 to deny
+/// it. Programs are defined in the `cgroup/dev` ELF section.
+///
+/// Simple allowlists and denylists can also be expressed with a
+/// [`CgroupDevicePolicy`], which doesn't require writing an eBPF program.
+///
+/// [cgroup]: https://man7.org/linux/man-pages/man7/cgroups.7.html
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 4.15.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[derive(thiserror::Error, Debug)]
+/// # enum Error {
+/// #     #[error(transparent)]
+/// #     IO(#[from] std::io::Error),
+/// #     #[error(transparent)]
+/// #     Map(#[from] aya::maps::MapError),
+/// #     #[error(transparent)]
+/// #     Program(#[from] aya::programs::ProgramError),
+/// #     #[error(transparent)]
+/// #     Bpf(#[from] aya::BpfError)
+/// # }
+/// # let mut bpf = aya::Bpf::load(&[])?;
+/// use std::fs::File;
+/// use std::convert::TryInto;
+/// use aya::programs::CgroupDevice;
+///
+/// let cgroup = File::open("/sys/fs/cgroup/unified")?;
+/// let program: &mut CgroupDevice = bpf.program_mut("cgroup_dev").unwrap().try_into()?;
+/// program.load()?;
+/// program.attach(cgroup)?;
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Debug)]
+#[doc(alias = "BPF_PROG_TYPE_CGROUP_DEVICE")]
+pub struct CgroupDevice {
+    pub(crate) data: ProgramData,
+}
+
+impl CgroupDevice {
+    /// Loads the program inside the kernel.
+    ///
+    /// See also [`Program::load`](crate::programs::Program::load).
+    pub fn load(&mut self) -> Result<(), ProgramError> {
+        load_program(BPF_PROG_TYPE_CGROUP_DEVICE, &mut self.data)
+    }
+
+    /// Returns the ids of the programs attached to `cgroup`, including the
+    /// programs attached by other processes.
+    pub fn query_prog_ids<T: AsRawFd>(cgroup: T) -> Result<Vec<u32>, ProgramError> {
+        query(cgroup, BPF_CGROUP_DEVICE, 0, &mut None)
+    }
+
+    /// Returns information about the programs attached to `cgroup`,
+    /// including the programs attached by other processes.
+    pub fn query_programs<T: AsRawFd>(cgroup: T) -> Result<Vec<ProgramInfo>, ProgramError> {
+        query_attached_programs(cgroup, BPF_CGROUP_DEVICE)
+    }
+
+    /// Attaches the program to the given cgroup.
+    pub fn attach<T: AsRawFd>(&mut self, cgroup: T) -> Result<LinkRef, ProgramError> {
+        let prog_fd = self.data.fd_or_err()?;
+        let cgroup_fd = cgroup.as_raw_fd();
+
+        let k_ver = kernel_version().unwrap();
+        if k_ver >= (5, 7, 0) {
+            let link_fd = bpf_link_create(prog_fd, cgroup_fd, BPF_CGROUP_DEVICE, None, 0).map_err(
+                |(_, io_error)| ProgramError::SyscallError {
+                    call: "bpf_link_create".to_owned(),
+                    io_error,
+                },
+            )? as RawFd;
+            Ok(self.data.link(FdLink { fd: Some(link_fd) }))
+        } else {
+            bpf_prog_attach(prog_fd, cgroup_fd, BPF_CGROUP_DEVICE, 0).map_err(
+                |(_, io_error)| ProgramError::SyscallError {
+                    call: "bpf_prog_attach".to_owned(),
+                    io_error,
+                },
+            )?;
+
+            Ok(self
+                .data
+                .link(ProgAttachLink::new(prog_fd, cgroup_fd, BPF_CGROUP_DEVICE)))
+        }
+    }
+
+    /// Attaches the program to the given cgroup with the given attach
+    /// `flags`.
+    ///
+    /// See [`CgroupAttachFlags`] for how the program is attached, the meaning
+    /// of `replace_fd` and the errors returned.
+    pub fn attach_with_flags<T: AsRawFd>(
+        &mut self,
+        cgroup: T,
+        flags: CgroupAttachFlags,
+        replace_fd: Option<RawFd>,
+    ) -> Result<LinkRef, ProgramError> {
+        cgroup::attach_with_flags(
+            &mut self.data,
+            cgroup.as_raw_fd(),
+            BPF_CGROUP_DEVICE,
+            flags,
+            replace_fd,
+        )
+    }
+}
+
+/// The type of a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeviceType {
+    /// A block device.
+    Block,
+    /// A character device.
+    Char,
+}
+
+impl DeviceType {
+    fn bpf_devcg_dev(self) -> i32 {
+        match self {
+            DeviceType::Block => BPF_DEVCG_DEV_BLOCK,
+            DeviceType::Char => BPF_DEVCG_DEV_CHAR,
+        }
+    }
+}
+
+// from enum bpf_devcg_dev_type and enum bpf_devcg_acc_type in uapi/linux/bpf.h
+const BPF_DEVCG_DEV_BLOCK: i32 = 1;
+const BPF_DEVCG_DEV_CHAR: i32 = 2;
+const BPF_DEVCG_ACC_MKNOD: u32 = 1;
+const BPF_DEVCG_ACC_READ: u32 = 2;
+const BPF_DEVCG_ACC_WRITE: u32 = 4;
+
+bitflags! {
+    /// The kinds of access to a device checked by a [`CgroupDevicePolicy`].
+    pub struct DeviceAccess: u32 {
+        /// Creating a device node with `mknod`.
+        const MKNOD = BPF_DEVCG_ACC_MKNOD;
+        /// Opening the device for reading.
+        const READ = BPF_DEVCG_ACC_READ;
+        /// Opening the device for writing.
+        const WRITE = BPF_DEVCG_ACC_WRITE;
+    }
+}
+
+#[derive(Clone, Debug)]
+struct DeviceRule {
+    allow: bool,
+    device_type: Option<DeviceType>,
+    major: Option<u32>,
+    minor: Option<u32>,
+    access: DeviceAccess,
+}
+
+/// An allowlist or denylist of devices, compiled into a [`CgroupDevice`]
+/// program.
+///
+/// Rules are checked in the order they are added and the first rule matching
+/// the device and the requested access decides whether access is allowed. If
+/// no rule matches, the default of the policy applies. A `None` device type,
+/// major or minor number matches any value. An allow rule only matches if all
+/// the requested kinds of access are included in its [`DeviceAccess`], while
+/// a deny rule matches if any of them is.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use aya::programs::{CgroupDevicePolicy, DeviceAccess, DeviceType};
+///
+/// // deny everything but /dev/null and /dev/zero
+/// let mut program = CgroupDevicePolicy::deny_all()
+///     .allow(Some(DeviceType::Char), Some(1), Some(3), DeviceAccess::all())
+///     .allow(Some(DeviceType::Char), Some(1), Some(5), DeviceAccess::READ)
+///     .load()?;
+/// program.attach(File::open("/sys/fs/cgroup/unified/sandbox")?)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct CgroupDevicePolicy {
+    default_allow: bool,
+    rules: Vec<DeviceRule>,
+}
+
+impl CgroupDevicePolicy {
+    /// Creates a policy allowing access to the devices no rule matches.
+    pub fn allow_all() -> CgroupDevicePolicy {
+        CgroupDevicePolicy {
+            default_allow: true,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Creates a policy denying access to the devices no rule matches.
+    pub fn deny_all() -> CgroupDevicePolicy {
+        CgroupDevicePolicy {
+            default_allow: false,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Adds a rule allowing `access` to the matching devices.
+    pub fn allow(
+        mut self,
+        device_type: Option<DeviceType>,
+        major: Option<u32>,
+        minor: Option<u32>,
+        access: DeviceAccess,
+    ) -> CgroupDevicePolicy {
+        self.rules.push(DeviceRule {
+            allow: true,
+            device_type,
+            major,
+            minor,
+            access,
+        });
+        self
+    }
+
+    /// Adds a rule denying `access` to the matching devices.
+    pub fn deny(
+        mut self,
+        device_type: Option<DeviceType>,
+        major: Option<u32>,
+        minor: Option<u32>,
+        access: DeviceAccess,
+    ) -> CgroupDevicePolicy {
+        self.rules.push(DeviceRule {
+            allow: false,
+            device_type,
+            major,
+            minor,
+            access,
+        });
+        self
+    }
+
+    /// Compiles the policy and loads the resulting program inside the kernel.
+    pub fn load(&self) -> Result<CgroupDevice, ProgramError> {
+        let mut program = CgroupDevice {
+            data: ProgramData {
+                name: Some("aya_dev_policy".to_owned()),
+                obj: obj::Program {
+                    license: CString::new("GPL").unwrap(),
+                    kernel_version: KernelVersion::Any,
+                    section: ProgramSection::CgroupDevice {
+                        name: "aya_dev_policy".to_owned(),
+                    },
+                    function: Function {
+                        address: 0,
+                        name: "aya_dev_policy".to_owned(),
+                        section_index: object::SectionIndex(0),
+                        section_offset: 0,
+                        instructions: self.instructions(),
+                        func_info: Default::default(),
+                        line_info: Default::default(),
+                        func_info_rec_size: 0,
+                        line_info_rec_size: 0,
+                    },
+                },
+                fd: None,
+                links: Vec::new(),
+                expected_attach_type: None,
+                attach_btf_obj_fd: None,
+                attach_btf_id: None,
+                attach_prog_fd: None,
+                attach_prog_id: None,
+                attach_func_name: None,
+                prog_ifindex: None,
+                prog_flags: 0,
+                attach_cookie: None,
+                btf_fd: None,
+                log_level: VerifierLogLevel::Off,
+                log_buf_size: 0,
+                verifier_log: None,
+                token_fd: None,
+                kern_version: None,
+            },
+        };
+        program.load()?;
+
+        Ok(program)
+    }
+
+    fn instructions(&self) -> Vec<bpf_insn> {
+        // r1 points to struct bpf_cgroup_dev_ctx { access_type, major, minor }
+        // where the low 16 bits of access_type hold the device type and the
+        // high 16 bits the requested access
+        let mut insns = vec![
+            insn(BPF_LDX | BPF_W | BPF_MEM, 2, 1, 0, 0),
+            insn(BPF_ALU64 | BPF_MOV | BPF_X, 3, 2, 0, 0),
+            insn(BPF_ALU64 | BPF_AND | BPF_K, 2, 0, 0, 0xffff),
+            insn(BPF_ALU64 | BPF_RSH | BPF_K, 3, 0, 0, 16),
+            insn(BPF_LDX | BPF_W | BPF_MEM, 4, 1, 0, 4),
+            insn(BPF_LDX | BPF_W | BPF_MEM, 5, 1, 0, 8),
+        ];
+
+        for rule in &self.rules {
+            // each check is a jump to the next rule, optionally preceded by
+            // the instructions computing the value it tests
+            let mut checks = Vec::new();
+            if let Some(device_type) = rule.device_type {
+                checks.push((
+                    Vec::new(),
+                    insn(
+                        BPF_JMP | BPF_JNE | BPF_K,
+                        2,
+                        0,
+                        0,
+                        device_type.bpf_devcg_dev(),
+                    ),
+                ));
+            }
+            if rule.access != DeviceAccess::all() {
+                if rule.allow {
+                    // skip the rule if any access outside of the rule is requested
+                    let other = (DeviceAccess::all() - rule.access).bits() as i32;
+                    checks.push((Vec::new(), insn(BPF_JMP | BPF_JSET | BPF_K, 3, 0, 0, other)));
+                } else {
+                    // skip the rule if none of the access in the rule is requested
+                    checks.push((
+                        vec![
+                            insn(BPF_ALU64 | BPF_MOV | BPF_X, 6, 3, 0, 0),
+                            insn(
+                                BPF_ALU64 | BPF_AND | BPF_K,
+                                6,
+                                0,
+                                0,
+                                rule.access.bits() as i32,
+                            ),
+                        ],
+                        insn(BPF_JMP | BPF_JEQ | BPF_K, 6, 0, 0, 0),
+                    ));
+                }
+            }
+            if let Some(major) = rule.major {
+                checks.push((
+                    Vec::new(),
+                    insn(BPF_JMP | BPF_JNE | BPF_K, 4, 0, 0, major as i32),
+                ));
+            }
+            if let Some(minor) = rule.minor {
+                checks.push((
+                    Vec::new(),
+                    insn(BPF_JMP | BPF_JNE | BPF_K, 5, 0, 0, minor as i32),
+                ));
+            }
+
+            // each jump skips the remaining checks and the verdict
+            let mut remaining = checks
+                .iter()
+                .map(|(prefix, _)| prefix.len() + 1)
+                .sum::<usize>()
+                + 2;
+            for (prefix, mut jump) in checks {
+                remaining -= prefix.len() + 1;
+                insns.extend(prefix);
+                jump.off = remaining as i16;
+                insns.push(jump);
+            }
+            insns.push(insn(
+                BPF_ALU64 | BPF_MOV | BPF_K,
+                0,
+                0,
+                0,
+                rule.allow as i32,
+            ));
+            insns.push(insn(BPF_JMP | BPF_EXIT, 0, 0, 0, 0));
+        }
+
+        insns.push(insn(
+            BPF_ALU64 | BPF_MOV | BPF_K,
+            0,
+            0,
+            0,
+            self.default_allow as i32,
+        ));
+        insns.push(insn(BPF_JMP | BPF_EXIT, 0, 0, 0, 0));
+
+        insns
+    }
+}
+
+const BPF_LDX: u8 = 0x01;
+const BPF_ALU64: u8 = 0x07;
+const BPF_JMP: u8 = 0x05;
+const BPF_W: u8 = 0x00;
+const BPF_MEM: u8 = 0x60;
+const BPF_K: u8 = 0x00;
+const BPF_X: u8 = 0x08;
+const BPF_MOV: u8 = 0xb0;
+const BPF_AND: u8 = 0x50;
+const BPF_RSH: u8 = 0x70;
+const BPF_JEQ: u8 = 0x10;
+const BPF_JNE: u8 = 0x50;
+const BPF_JSET: u8 = 0x40;
+const BPF_EXIT: u8 = 0x90;
+
+fn insn(code: u8, dst_reg: u8, src_reg: u8, off: i16, imm: i32) -> bpf_insn {
+    bpf_insn {
+        code,
+        _bitfield_align_1: [],
+        _bitfield_1: bpf_insn::new_bitfield_1(dst_reg, src_reg),
+        off,
+        imm,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_instructions() {
+        let policy = CgroupDevicePolicy::deny_all().allow(
+            Some(DeviceType::Char),
+            Some(1),
+            None,
+            DeviceAccess::READ,
+        );
+        let insns = policy.instructions();
+
+        // 6 to read the context, 3 checks, the verdict and the default
+        assert_eq!(insns.len(), 6 + 3 + 2 + 2);
+        let check = |i: usize| {
+            let insn = &insns[i];
+            (insn.code, insn.dst_reg(), insn.off, insn.imm)
+        };
+        assert_eq!(
+            check(6),
+            (BPF_JMP | BPF_JNE | BPF_K, 2, 4, BPF_DEVCG_DEV_CHAR)
+        );
+        assert_eq!(
+            check(7),
+            (
+                BPF_JMP | BPF_JSET | BPF_K,
+                3,
+                3,
+                (BPF_DEVCG_ACC_MKNOD | BPF_DEVCG_ACC_WRITE) as i32
+            )
+        );
+        assert_eq!(check(8), (BPF_JMP | BPF_JNE | BPF_K, 4, 2, 1));
+        assert_eq!(check(9), (BPF_ALU64 | BPF_MOV | BPF_K, 0, 0, 1));
+        assert_eq!(check(11), (BPF_ALU64 | BPF_MOV | BPF_K, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_deny_rule_instructions() {
+        let policy = CgroupDevicePolicy::allow_all().deny(None, Some(8), None, DeviceAccess::WRITE);
+        let insns = policy.instructions();
+
+        // 6 to read the context, 2 checks with 2 extra instructions, the
+        // verdict and the default
+        assert_eq!(insns.len(), 6 + 4 + 2 + 2);
+        let check = |i: usize| {
+            let insn = &insns[i];
+            (insn.code, insn.dst_reg(), insn.off, insn.imm)
+        };
+        assert_eq!(
+            check(7),
+            (
+                BPF_ALU64 | BPF_AND | BPF_K,
+                6,
+                0,
+                BPF_DEVCG_ACC_WRITE as i32
+            )
+        );
+        assert_eq!(check(8), (BPF_JMP | BPF_JEQ | BPF_K, 6, 3, 0));
+        assert_eq!(check(9), (BPF_JMP | BPF_JNE | BPF_K, 4, 2, 8));
+        assert_eq!(check(10), (BPF_ALU64 | BPF_MOV | BPF_K, 0, 0, 0));
+        assert_eq!(check(12), (BPF_ALU64 | BPF_MOV | BPF_K, 0, 0, 1));
+    }
+}
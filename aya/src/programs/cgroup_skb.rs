@@ -2,10 +2,14 @@ use std::os::unix::prelude::{AsRawFd, RawFd};
 
 use crate::{
     generated::{
-        bpf_attach_type::{BPF_CGROUP_INET_EGRESS, BPF_CGROUP_INET_INGRESS},
+        bpf_attach_type::{self, BPF_CGROUP_INET_EGRESS, BPF_CGROUP_INET_INGRESS},
         bpf_prog_type::BPF_PROG_TYPE_CGROUP_SKB,
     },
-    programs::{load_program, LinkRef, ProgAttachLink, ProgramData, ProgramError},
+    obj::ProgramSection,
+    programs::{
+        cgroup, load_program, query, query_attached_programs, CgroupAttachFlags, LinkRef,
+        ProgAttachLink, ProgramData, ProgramError, ProgramInfo,
+    },
     sys::{bpf_link_create, bpf_prog_attach, kernel_version},
 };
 
@@ -60,9 +64,25 @@ impl CgroupSkb {
     ///
     /// See also [`Program::load`](crate::programs::Program::load).
     pub fn load(&mut self) -> Result<(), ProgramError> {
+        self.data.expected_attach_type = self.expected_attach_type.map(|t| t.bpf_attach_type());
         load_program(BPF_PROG_TYPE_CGROUP_SKB, &mut self.data)
     }
 
+    /// Creates a program from the fd of a program that is already loaded.
+    ///
+    /// Returns [`ProgramError::UnexpectedProgramType`] if `fd` isn't a
+    /// cgroup skb program. See also
+    /// [`Program::from_fd`](crate::programs::Program::from_fd).
+    pub fn from_fd(fd: RawFd) -> Result<CgroupSkb, ProgramError> {
+        let data = ProgramData::from_fd(fd, BPF_PROG_TYPE_CGROUP_SKB, |name| {
+            ProgramSection::CgroupSkb { name }
+        })?;
+        Ok(CgroupSkb {
+            data,
+            expected_attach_type: None,
+        })
+    }
+
     /// Returns the expected attach type of the program.
     ///
     /// [`CgroupSkb`] programs can specify the expected attach type in their ELF
@@ -73,6 +93,26 @@ impl CgroupSkb {
         &self.expected_attach_type
     }
 
+    /// Returns the ids of the programs attached to `cgroup` for the given
+    /// direction, including the programs attached by other processes.
+    ///
+    /// This is the same as [`query_cgroup_skb_programs`].
+    pub fn query_prog_ids<T: AsRawFd>(
+        cgroup: T,
+        attach_type: CgroupSkbAttachType,
+    ) -> Result<Vec<u32>, ProgramError> {
+        query_cgroup_skb_programs(cgroup, attach_type)
+    }
+
+    /// Returns information about the programs attached to `cgroup` for the
+    /// given direction, including the programs attached by other processes.
+    pub fn query_programs<T: AsRawFd>(
+        cgroup: T,
+        attach_type: CgroupSkbAttachType,
+    ) -> Result<Vec<ProgramInfo>, ProgramError> {
+        query_attached_programs(cgroup, attach_type.bpf_attach_type())
+    }
+
     /// Attaches the program to the given cgroup.
     pub fn attach<T: AsRawFd>(
         &mut self,
@@ -82,10 +122,7 @@ impl CgroupSkb {
         let prog_fd = self.data.fd_or_err()?;
         let cgroup_fd = cgroup.as_raw_fd();
 
-        let attach_type = match attach_type {
-            CgroupSkbAttachType::Ingress => BPF_CGROUP_INET_INGRESS,
-            CgroupSkbAttachType::Egress => BPF_CGROUP_INET_EGRESS,
-        };
+        let attach_type = attach_type.bpf_attach_type();
         let k_ver = kernel_version().unwrap();
         if k_ver >= (5, 7, 0) {
             let link_fd = bpf_link_create(prog_fd, cgroup_fd, attach_type, None, 0).map_err(
@@ -96,7 +133,7 @@ impl CgroupSkb {
             )? as RawFd;
             Ok(self.data.link(FdLink { fd: Some(link_fd) }))
         } else {
-            bpf_prog_attach(prog_fd, cgroup_fd, attach_type).map_err(|(_, io_error)| {
+            bpf_prog_attach(prog_fd, cgroup_fd, attach_type, 0).map_err(|(_, io_error)| {
                 ProgramError::SyscallError {
                     call: "bpf_prog_attach".to_owned(),
                     io_error,
@@ -108,6 +145,27 @@ impl CgroupSkb {
                 .link(ProgAttachLink::new(prog_fd, cgroup_fd, attach_type)))
         }
     }
+
+    /// Attaches the program to the given cgroup with the given attach
+    /// `flags`.
+    ///
+    /// See [`CgroupAttachFlags`] for how the program is attached, the meaning
+    /// of `replace_fd` and the errors returned.
+    pub fn attach_with_flags<T: AsRawFd>(
+        &mut self,
+        cgroup: T,
+        attach_type: CgroupSkbAttachType,
+        flags: CgroupAttachFlags,
+        replace_fd: Option<RawFd>,
+    ) -> Result<LinkRef, ProgramError> {
+        cgroup::attach_with_flags(
+            &mut self.data,
+            cgroup.as_raw_fd(),
+            attach_type.bpf_attach_type(),
+            flags,
+            replace_fd,
+        )
+    }
 }
 
 /// Defines where to attach a [`CgroupSkb`] program.
@@ -118,3 +176,36 @@ pub enum CgroupSkbAttachType {
     /// Attach to egress.
     Egress,
 }
+
+impl CgroupSkbAttachType {
+    pub(crate) fn bpf_attach_type(self) -> bpf_attach_type {
+        match self {
+            CgroupSkbAttachType::Ingress => BPF_CGROUP_INET_INGRESS,
+            CgroupSkbAttachType::Egress => BPF_CGROUP_INET_EGRESS,
+        }
+    }
+}
+
+/// Returns the ids of the [`CgroupSkb`] programs attached to `cgroup` for the
+/// given direction.
+///
+/// The ids can be passed to `bpf_prog_get_fd_by_id` or `bpftool` to inspect
+/// the programs.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use aya::programs::{query_cgroup_skb_programs, CgroupSkbAttachType};
+///
+/// let file = File::open("/sys/fs/cgroup/unified")?;
+/// let ids = query_cgroup_skb_programs(file, CgroupSkbAttachType::Ingress)?;
+/// println!("{} ingress programs attached", ids.len());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn query_cgroup_skb_programs<T: AsRawFd>(
+    cgroup: T,
+    attach_type: CgroupSkbAttachType,
+) -> Result<Vec<u32>, ProgramError> {
+    query(cgroup, attach_type.bpf_attach_type(), 0, &mut None)
+}
@@ -0,0 +1,191 @@
+use std::os::unix::prelude::{AsRawFd, RawFd};
+
+use crate::{
+    generated::{
+        bpf_attach_type::{
+            self, BPF_CGROUP_INET4_POST_BIND, BPF_CGROUP_INET6_POST_BIND,
+            BPF_CGROUP_INET_SOCK_CREATE, BPF_CGROUP_INET_SOCK_RELEASE,
+        },
+        bpf_prog_type::BPF_PROG_TYPE_CGROUP_SOCK,
+        BPF_F_ALLOW_MULTI,
+    },
+    obj::ProgramSection,
+    programs::{
+        cgroup, load_program, query, query_attached_programs, CgroupAttachFlags, FdLink, LinkRef,
+        ProgAttachLink, ProgramData, ProgramError, ProgramInfo,
+    },
+    sys::{bpf_link_create, bpf_prog_attach, kernel_version},
+};
+
+/// A program that is called when sockets are created, bound or released in a
+/// cgroup.
+///
+/// [`CgroupSock`] programs are attached to a [cgroup] and can be used to allow
+/// or deny the creation of sockets, inspect the address sockets are bound to
+/// or track socket release. The hook point is selected with the ELF section
+/// name: `cgroup/sock_create`, `cgroup/post_bind4`, `cgroup/post_bind6` or
+/// `cgroup/sock_release`.
+///
+/// [cgroup]: https://man7.org/linux/man-pages/man7/cgroups.7.html
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 4.10.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[derive(thiserror::Error, Debug)]
+/// # enum Error {
+/// #     #[error(transparent)]
+/// #     IO(#[from] std::io::Error),
+/// #     #[error(transparent)]
+/// #     Map(#[from] aya::maps::MapError),
+/// #     #[error(transparent)]
+/// #     Program(#[from] aya::programs::ProgramError),
+/// #     #[error(transparent)]
+/// #     Bpf(#[from] aya::BpfError)
+/// # }
+/// # let mut bpf = aya::Bpf::load(&[])?;
+/// use std::fs::File;
+/// use std::convert::TryInto;
+/// use aya::programs::CgroupSock;
+///
+/// let file = File::open("/sys/fs/cgroup/unified")?;
+/// let sock_create: &mut CgroupSock = bpf.program_mut("sock_create").unwrap().try_into()?;
+/// sock_create.load()?;
+/// sock_create.attach(file)?;
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Debug)]
+#[doc(alias = "BPF_PROG_TYPE_CGROUP_SOCK")]
+pub struct CgroupSock {
+    pub(crate) data: ProgramData,
+    pub(crate) attach_type: CgroupSockAttachType,
+}
+
+impl CgroupSock {
+    /// Loads the program inside the kernel.
+    ///
+    /// See also [`Program::load`](crate::programs::Program::load).
+    pub fn load(&mut self) -> Result<(), ProgramError> {
+        self.data.expected_attach_type = Some(self.attach_type.bpf_attach_type());
+        load_program(BPF_PROG_TYPE_CGROUP_SOCK, &mut self.data)
+    }
+
+    /// Creates a program from the fd of a program that is already loaded.
+    ///
+    /// The hook point of a loaded program can't be queried from the kernel,
+    /// so `attach_type` must match the one the program was loaded with.
+    ///
+    /// Returns [`ProgramError::UnexpectedProgramType`] if `fd` isn't a
+    /// cgroup sock program.
+    pub fn from_fd(
+        fd: RawFd,
+        attach_type: CgroupSockAttachType,
+    ) -> Result<CgroupSock, ProgramError> {
+        let data = ProgramData::from_fd(fd, BPF_PROG_TYPE_CGROUP_SOCK, |name| {
+            ProgramSection::CgroupSockCreate { name }
+        })?;
+        Ok(CgroupSock { data, attach_type })
+    }
+
+    /// Returns the hook point of the program.
+    pub fn attach_type(&self) -> CgroupSockAttachType {
+        self.attach_type
+    }
+
+    /// Returns the ids of the programs attached to `cgroup` at the given hook
+    /// point, including the programs attached by other processes.
+    pub fn query_prog_ids<T: AsRawFd>(
+        cgroup: T,
+        attach_type: CgroupSockAttachType,
+    ) -> Result<Vec<u32>, ProgramError> {
+        query(cgroup, attach_type.bpf_attach_type(), 0, &mut None)
+    }
+
+    /// Returns information about the programs attached to `cgroup` at the
+    /// given hook point, including the programs attached by other processes.
+    pub fn query_programs<T: AsRawFd>(
+        cgroup: T,
+        attach_type: CgroupSockAttachType,
+    ) -> Result<Vec<ProgramInfo>, ProgramError> {
+        query_attached_programs(cgroup, attach_type.bpf_attach_type())
+    }
+
+    /// Attaches the program to the given cgroup.
+    ///
+    /// The program is attached with `BPF_F_ALLOW_MULTI`, so it runs alongside
+    /// the other programs attached to the cgroup and its descendants.
+    pub fn attach<T: AsRawFd>(&mut self, cgroup: T) -> Result<LinkRef, ProgramError> {
+        let prog_fd = self.data.fd_or_err()?;
+        let cgroup_fd = cgroup.as_raw_fd();
+        let attach_type = self.attach_type.bpf_attach_type();
+
+        let k_ver = kernel_version().unwrap();
+        if k_ver >= (5, 7, 0) {
+            let link_fd = bpf_link_create(prog_fd, cgroup_fd, attach_type, None, 0).map_err(
+                |(_, io_error)| ProgramError::SyscallError {
+                    call: "bpf_link_create".to_owned(),
+                    io_error,
+                },
+            )? as RawFd;
+            Ok(self.data.link(FdLink { fd: Some(link_fd) }))
+        } else {
+            bpf_prog_attach(prog_fd, cgroup_fd, attach_type, BPF_F_ALLOW_MULTI).map_err(
+                |(_, io_error)| ProgramError::SyscallError {
+                    call: "bpf_prog_attach".to_owned(),
+                    io_error,
+                },
+            )?;
+
+            Ok(self
+                .data
+                .link(ProgAttachLink::new(prog_fd, cgroup_fd, attach_type)))
+        }
+    }
+
+    /// Attaches the program to the given cgroup with the given attach
+    /// `flags`.
+    ///
+    /// See [`CgroupAttachFlags`] for how the program is attached, the meaning
+    /// of `replace_fd` and the errors returned.
+    pub fn attach_with_flags<T: AsRawFd>(
+        &mut self,
+        cgroup: T,
+        flags: CgroupAttachFlags,
+        replace_fd: Option<RawFd>,
+    ) -> Result<LinkRef, ProgramError> {
+        cgroup::attach_with_flags(
+            &mut self.data,
+            cgroup.as_raw_fd(),
+            self.attach_type.bpf_attach_type(),
+            flags,
+            replace_fd,
+        )
+    }
+}
+
+/// Defines where a [`CgroupSock`] program is attached.
+#[derive(Copy, Clone, Debug)]
+pub enum CgroupSockAttachType {
+    /// Called when a socket is created.
+    SockCreate,
+    /// Called after an IPv4 socket is bound.
+    PostBind4,
+    /// Called after an IPv6 socket is bound.
+    PostBind6,
+    /// Called when a socket is released.
+    SockRelease,
+}
+
+impl CgroupSockAttachType {
+    pub(crate) fn bpf_attach_type(self) -> bpf_attach_type {
+        match self {
+            CgroupSockAttachType::SockCreate => BPF_CGROUP_INET_SOCK_CREATE,
+            CgroupSockAttachType::PostBind4 => BPF_CGROUP_INET4_POST_BIND,
+            CgroupSockAttachType::PostBind6 => BPF_CGROUP_INET6_POST_BIND,
+            CgroupSockAttachType::SockRelease => BPF_CGROUP_INET_SOCK_RELEASE,
+        }
+    }
+}
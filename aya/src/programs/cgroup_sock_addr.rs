@@ -0,0 +1,294 @@
+use std::os::unix::prelude::{AsRawFd, RawFd};
+
+use crate::{
+    generated::{
+        bpf_attach_type::{
+            self, BPF_CGROUP_INET4_BIND, BPF_CGROUP_INET4_CONNECT, BPF_CGROUP_INET4_GETPEERNAME,
+            BPF_CGROUP_INET6_BIND, BPF_CGROUP_INET6_CONNECT, BPF_CGROUP_INET6_GETPEERNAME,
+            BPF_CGROUP_UDP4_RECVMSG, BPF_CGROUP_UDP4_SENDMSG, BPF_CGROUP_UDP6_RECVMSG,
+            BPF_CGROUP_UDP6_SENDMSG,
+        },
+        bpf_prog_type::BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+        BPF_F_ALLOW_MULTI,
+    },
+    obj::ProgramSection,
+    programs::{
+        cgroup, load_program, query, query_attached_programs, CgroupAttachFlags, FdLink, LinkRef,
+        ProgAttachLink, ProgramData, ProgramError, ProgramInfo,
+    },
+    sys::{bpf_link_create, bpf_prog_attach, kernel_version},
+};
+
+/// A program that is called when sockets connect, bind, send or receive
+/// messages or have their peer address queried in a cgroup.
+///
+/// [`CgroupSockAddr`] programs are attached to a [cgroup] and can inspect and
+/// rewrite the addresses passed to `connect()`, `bind()`, `sendmsg()`,
+/// `recvmsg()` and `getpeername()`, eg to transparently redirect connections.
+/// The hooks are separate for IPv4 and IPv6, and are selected with the ELF
+/// section name: `cgroup/connect4`, `cgroup/connect6`, `cgroup/bind4`,
+/// `cgroup/bind6`, `cgroup/sendmsg4`, `cgroup/sendmsg6`, `cgroup/recvmsg4`,
+/// `cgroup/recvmsg6`, `cgroup/getpeername4` or `cgroup/getpeername6`.
+///
+/// [cgroup]: https://man7.org/linux/man-pages/man7/cgroups.7.html
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 4.17.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[derive(thiserror::Error, Debug)]
+/// # enum Error {
+/// #     #[error(transparent)]
+/// #     IO(#[from] std::io::Error),
+/// #     #[error(transparent)]
+/// #     Map(#[from] aya::maps::MapError),
+/// #     #[error(transparent)]
+/// #     Program(#[from] aya::programs::ProgramError),
+/// #     #[error(transparent)]
+/// #     Bpf(#[from] aya::BpfError)
+/// # }
+/// # let mut bpf = aya::Bpf::load(&[])?;
+/// use std::fs::File;
+/// use std::convert::TryInto;
+/// use aya::programs::CgroupSockAddr;
+///
+/// let file = File::open("/sys/fs/cgroup/unified")?;
+/// let connect4: &mut CgroupSockAddr = bpf.program_mut("connect4").unwrap().try_into()?;
+/// connect4.load()?;
+/// connect4.attach(file)?;
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Debug)]
+#[doc(alias = "BPF_PROG_TYPE_CGROUP_SOCK_ADDR")]
+pub struct CgroupSockAddr {
+    pub(crate) data: ProgramData,
+    pub(crate) attach_type: CgroupSockAddrAttachType,
+}
+
+impl CgroupSockAddr {
+    /// Loads the program inside the kernel.
+    ///
+    /// The program is loaded with the attach type of its hook, which the
+    /// kernel requires to attach it to that hook.
+    ///
+    /// See also [`Program::load`](crate::programs::Program::load).
+    pub fn load(&mut self) -> Result<(), ProgramError> {
+        self.data.expected_attach_type = Some(self.attach_type.bpf_attach_type());
+        load_program(BPF_PROG_TYPE_CGROUP_SOCK_ADDR, &mut self.data)
+    }
+
+    /// Creates a program from the fd of a program that is already loaded.
+    ///
+    /// The hook point of a loaded program can't be queried from the kernel,
+    /// so `attach_type` must match the one the program was loaded with.
+    ///
+    /// Returns [`ProgramError::UnexpectedProgramType`] if `fd` isn't a
+    /// cgroup sock addr program.
+    pub fn from_fd(
+        fd: RawFd,
+        attach_type: CgroupSockAddrAttachType,
+    ) -> Result<CgroupSockAddr, ProgramError> {
+        let data = ProgramData::from_fd(fd, BPF_PROG_TYPE_CGROUP_SOCK_ADDR, attach_type.section())?;
+        Ok(CgroupSockAddr { data, attach_type })
+    }
+
+    /// Returns the hook point of the program.
+    pub fn attach_type(&self) -> CgroupSockAddrAttachType {
+        self.attach_type
+    }
+
+    /// Returns the ids of the programs attached to `cgroup` at the given hook
+    /// point, including the programs attached by other processes.
+    pub fn query_prog_ids<T: AsRawFd>(
+        cgroup: T,
+        attach_type: CgroupSockAddrAttachType,
+    ) -> Result<Vec<u32>, ProgramError> {
+        query(cgroup, attach_type.bpf_attach_type(), 0, &mut None)
+    }
+
+    /// Returns information about the programs attached to `cgroup` at the
+    /// given hook point, including the programs attached by other processes.
+    pub fn query_programs<T: AsRawFd>(
+        cgroup: T,
+        attach_type: CgroupSockAddrAttachType,
+    ) -> Result<Vec<ProgramInfo>, ProgramError> {
+        query_attached_programs(cgroup, attach_type.bpf_attach_type())
+    }
+
+    /// Attaches the program to the given cgroup.
+    ///
+    /// The program is attached with `BPF_F_ALLOW_MULTI`, so it runs alongside
+    /// the other programs attached to the cgroup and its descendants.
+    pub fn attach<T: AsRawFd>(&mut self, cgroup: T) -> Result<LinkRef, ProgramError> {
+        let prog_fd = self.data.fd_or_err()?;
+        let cgroup_fd = cgroup.as_raw_fd();
+        let attach_type = self.attach_type.bpf_attach_type();
+
+        let k_ver = kernel_version().unwrap();
+        if k_ver >= (5, 7, 0) {
+            let link_fd = bpf_link_create(prog_fd, cgroup_fd, attach_type, None, 0).map_err(
+                |(_, io_error)| ProgramError::SyscallError {
+                    call: "bpf_link_create".to_owned(),
+                    io_error,
+                },
+            )? as RawFd;
+            Ok(self.data.link(FdLink { fd: Some(link_fd) }))
+        } else {
+            bpf_prog_attach(prog_fd, cgroup_fd, attach_type, BPF_F_ALLOW_MULTI).map_err(
+                |(_, io_error)| ProgramError::SyscallError {
+                    call: "bpf_prog_attach".to_owned(),
+                    io_error,
+                },
+            )?;
+
+            Ok(self
+                .data
+                .link(ProgAttachLink::new(prog_fd, cgroup_fd, attach_type)))
+        }
+    }
+
+    /// Attaches the program to the given cgroup with the given attach
+    /// `flags`.
+    ///
+    /// See [`CgroupAttachFlags`] for how the program is attached, the meaning
+    /// of `replace_fd` and the errors returned.
+    pub fn attach_with_flags<T: AsRawFd>(
+        &mut self,
+        cgroup: T,
+        flags: CgroupAttachFlags,
+        replace_fd: Option<RawFd>,
+    ) -> Result<LinkRef, ProgramError> {
+        cgroup::attach_with_flags(
+            &mut self.data,
+            cgroup.as_raw_fd(),
+            self.attach_type.bpf_attach_type(),
+            flags,
+            replace_fd,
+        )
+    }
+}
+
+/// Defines where a [`CgroupSockAddr`] program is attached.
+///
+/// Each hook exists for IPv4 and for IPv6 sockets. IPv4 addresses passed to
+/// IPv6 sockets, eg `::ffff:127.0.0.1`, go through the IPv6 hooks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CgroupSockAddrAttachType {
+    /// Called when an IPv4 socket connects.
+    Connect4,
+    /// Called when an IPv6 socket connects.
+    Connect6,
+    /// Called when an IPv4 socket is bound.
+    Bind4,
+    /// Called when an IPv6 socket is bound.
+    Bind6,
+    /// Called when an IPv4 UDP socket sends a message.
+    Sendmsg4,
+    /// Called when an IPv6 UDP socket sends a message.
+    Sendmsg6,
+    /// Called when an IPv4 UDP socket receives a message.
+    Recvmsg4,
+    /// Called when an IPv6 UDP socket receives a message.
+    Recvmsg6,
+    /// Called when the peer address of an IPv4 socket is queried.
+    Getpeername4,
+    /// Called when the peer address of an IPv6 socket is queried.
+    Getpeername6,
+}
+
+impl CgroupSockAddrAttachType {
+    pub(crate) fn bpf_attach_type(self) -> bpf_attach_type {
+        use CgroupSockAddrAttachType::*;
+        match self {
+            Connect4 => BPF_CGROUP_INET4_CONNECT,
+            Connect6 => BPF_CGROUP_INET6_CONNECT,
+            Bind4 => BPF_CGROUP_INET4_BIND,
+            Bind6 => BPF_CGROUP_INET6_BIND,
+            Sendmsg4 => BPF_CGROUP_UDP4_SENDMSG,
+            Sendmsg6 => BPF_CGROUP_UDP6_SENDMSG,
+            Recvmsg4 => BPF_CGROUP_UDP4_RECVMSG,
+            Recvmsg6 => BPF_CGROUP_UDP6_RECVMSG,
+            Getpeername4 => BPF_CGROUP_INET4_GETPEERNAME,
+            Getpeername6 => BPF_CGROUP_INET6_GETPEERNAME,
+        }
+    }
+
+    pub(crate) fn from_section(section: &ProgramSection) -> Option<CgroupSockAddrAttachType> {
+        use CgroupSockAddrAttachType::*;
+        match section {
+            ProgramSection::CgroupSockAddrConnect4 { .. } => Some(Connect4),
+            ProgramSection::CgroupSockAddrConnect6 { .. } => Some(Connect6),
+            ProgramSection::CgroupSockAddrBind4 { .. } => Some(Bind4),
+            ProgramSection::CgroupSockAddrBind6 { .. } => Some(Bind6),
+            ProgramSection::CgroupSockAddrSendmsg4 { .. } => Some(Sendmsg4),
+            ProgramSection::CgroupSockAddrSendmsg6 { .. } => Some(Sendmsg6),
+            ProgramSection::CgroupSockAddrRecvmsg4 { .. } => Some(Recvmsg4),
+            ProgramSection::CgroupSockAddrRecvmsg6 { .. } => Some(Recvmsg6),
+            ProgramSection::CgroupSockAddrGetpeername4 { .. } => Some(Getpeername4),
+            ProgramSection::CgroupSockAddrGetpeername6 { .. } => Some(Getpeername6),
+            _ => None,
+        }
+    }
+
+    fn section(self) -> fn(String) -> ProgramSection {
+        use CgroupSockAddrAttachType::*;
+        match self {
+            Connect4 => |name| ProgramSection::CgroupSockAddrConnect4 { name },
+            Connect6 => |name| ProgramSection::CgroupSockAddrConnect6 { name },
+            Bind4 => |name| ProgramSection::CgroupSockAddrBind4 { name },
+            Bind6 => |name| ProgramSection::CgroupSockAddrBind6 { name },
+            Sendmsg4 => |name| ProgramSection::CgroupSockAddrSendmsg4 { name },
+            Sendmsg6 => |name| ProgramSection::CgroupSockAddrSendmsg6 { name },
+            Recvmsg4 => |name| ProgramSection::CgroupSockAddrRecvmsg4 { name },
+            Recvmsg6 => |name| ProgramSection::CgroupSockAddrRecvmsg6 { name },
+            Getpeername4 => |name| ProgramSection::CgroupSockAddrGetpeername4 { name },
+            Getpeername6 => |name| ProgramSection::CgroupSockAddrGetpeername6 { name },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attach_type_section() {
+        use CgroupSockAddrAttachType::*;
+        for attach_type in &[
+            Connect4,
+            Connect6,
+            Bind4,
+            Bind6,
+            Sendmsg4,
+            Sendmsg6,
+            Recvmsg4,
+            Recvmsg6,
+            Getpeername4,
+            Getpeername6,
+        ] {
+            let section = attach_type.section()("foo".to_owned());
+            assert_eq!(
+                CgroupSockAddrAttachType::from_section(&section),
+                Some(*attach_type)
+            );
+        }
+
+        let section = ProgramSection::CgroupSockAddrConnect4 {
+            name: "foo".to_owned(),
+        };
+        assert_ne!(
+            CgroupSockAddrAttachType::from_section(&section),
+            Some(Connect6)
+        );
+        assert_eq!(Connect6.bpf_attach_type(), BPF_CGROUP_INET6_CONNECT);
+        assert_eq!(
+            CgroupSockAddrAttachType::from_section(&ProgramSection::CgroupSockCreate {
+                name: "foo".to_owned()
+            }),
+            None
+        );
+    }
+}
@@ -1,14 +1,16 @@
+use libc::{close, ENOENT};
 use std::os::unix::prelude::{AsRawFd, RawFd};
 use thiserror::Error;
 
-use object::Endianness;
-
 use crate::{
     generated::{bpf_attach_type::BPF_CGROUP_INET_INGRESS, bpf_prog_type::BPF_PROG_TYPE_EXT},
-    obj::btf::BtfKind,
-    programs::{load_program, FdLink, LinkRef, ProgramData, ProgramError},
+    obj::btf::{BtfKind, BtfType},
+    programs::{
+        load_program, loaded_links, utils::get_btf_info, BpfLinkInfo, BpfLinkKind, FdLink, Link,
+        LinkRef, ProgramData, ProgramError, ProgramInfo,
+    },
     sys::{self, bpf_link_create},
-    Btf,
+    Btf, BtfError,
 };
 
 /// The type returned when loading or attaching an [`Extension`] fails
@@ -17,6 +19,17 @@ pub enum ExtensionError {
     /// target BPF program does not have BTF loaded to the kernel
     #[error("target BPF program does not have BTF loaded to the kernel")]
     NoBTF,
+
+    /// target BPF program is not loaded anymore
+    #[error("target BPF program is not loaded anymore")]
+    TargetUnloaded,
+
+    /// the new target function doesn't have the signature of the replaced function
+    #[error("function `{func_name}` doesn't have the signature of the replaced function")]
+    IncompatibleSignature {
+        /// the name of the new target function
+        func_name: String,
+    },
 }
 
 /// A program used to extend existing BPF programs
@@ -68,49 +81,8 @@ impl Extension {
     pub fn load<T: AsRawFd>(&mut self, program: T, func_name: &str) -> Result<(), ProgramError> {
         let target_prog_fd = program.as_raw_fd();
 
-        let info = sys::bpf_obj_get_info_by_fd(target_prog_fd).map_err(|io_error| {
-            ProgramError::SyscallError {
-                call: "bpf_obj_get_info_by_fd".to_owned(),
-                io_error,
-            }
-        })?;
-
-        if info.btf_id == 0 {
-            return Err(ProgramError::ExtensionError(ExtensionError::NoBTF));
-        }
-
-        let btf_fd = sys::bpf_btf_get_fd_by_id(info.btf_id).map_err(|io_error| {
-            ProgramError::SyscallError {
-                call: "bpf_btf_get_fd_by_id".to_owned(),
-                io_error,
-            }
-        })?;
-
-        let mut buf = vec![0u8; 4096];
-        let btf_info = match sys::btf_obj_get_info_by_fd(btf_fd, &mut buf) {
-            Ok(info) => {
-                if info.btf_size > buf.len() as u32 {
-                    buf.resize(info.btf_size as usize, 0u8);
-                    let btf_info =
-                        sys::btf_obj_get_info_by_fd(btf_fd, &mut buf).map_err(|io_error| {
-                            ProgramError::SyscallError {
-                                call: "bpf_obj_get_info_by_fd".to_owned(),
-                                io_error,
-                            }
-                        })?;
-                    Ok(btf_info)
-                } else {
-                    Ok(info)
-                }
-            }
-            Err(io_error) => Err(ProgramError::SyscallError {
-                call: "bpf_obj_get_info_by_fd".to_owned(),
-                io_error,
-            }),
-        }?;
-
-        let btf = Btf::parse(&buf[0..btf_info.btf_size as usize], Endianness::default())
-            .map_err(ProgramError::Btf)?;
+        let (btf_fd, btf) = get_btf_info(target_prog_fd)?;
+        let target_prog_id = get_prog_id(target_prog_fd)?;
 
         let btf_id = btf
             .id_by_type_name_kind(func_name, BtfKind::Func)
@@ -119,17 +91,103 @@ impl Extension {
         self.data.attach_btf_obj_fd = Some(btf_fd as u32);
         self.data.attach_prog_fd = Some(target_prog_fd);
         self.data.attach_btf_id = Some(btf_id);
+        self.data.attach_prog_id = Some(target_prog_id);
+        self.data.attach_func_name = Some(func_name.to_owned());
         load_program(BPF_PROG_TYPE_EXT, &mut self.data)
     }
 
+    /// Returns the name of the function replaced by the extension.
+    ///
+    /// This is the `func_name` passed to [`load`](Self::load), or `None` if
+    /// the extension hasn't been loaded.
+    pub fn loaded_func_name(&self) -> Option<&str> {
+        self.data.attach_func_name.as_deref()
+    }
+
+    /// Returns the id of the program the extension was loaded for.
+    ///
+    /// The id is queried with `bpf_obj_get_info_by_fd` when the extension is
+    /// loaded, so it keeps identifying the target even if its file
+    /// descriptor is closed. Returns `None` if the extension hasn't been
+    /// loaded.
+    pub fn target_prog_id(&self) -> Option<u32> {
+        self.data.attach_prog_id
+    }
+
     /// Attaches the extension
     ///
     /// Attaches the extension effectively replacing the original target function.
     /// Detaching the returned link restores the original function.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ExtensionError::TargetUnloaded`] if the target program passed
+    /// to [`load`](Self::load) isn't loaded anymore, or if its file descriptor
+    /// has since been reused for a different program.
     pub fn attach(&mut self) -> Result<LinkRef, ProgramError> {
-        let prog_fd = self.data.fd_or_err()?;
+        let btf_id = self.data.attach_btf_id.ok_or(ProgramError::NotLoaded)?;
+        self.attach_to(btf_id)
+    }
+
+    /// Makes the extension replace `new_func_name` instead of the function
+    /// it currently replaces, without reloading it.
+    ///
+    /// `new_func_name` must be a function of the target program passed to
+    /// [`load`](Self::load) with the same signature as the replaced function.
+    /// The extension is attached to `new_func_name` before `link`, the link
+    /// returned by [`attach`](Self::attach) or by a previous call to
+    /// `replace`, is detached, so `new_func_name` is replaced as soon as this
+    /// returns and the previously replaced function is restored. The kernel
+    /// can't change the target of an existing link, so a new link is
+    /// returned.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.10.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ExtensionError::IncompatibleSignature`] if the return or
+    /// parameter types of `new_func_name` differ from the ones of the
+    /// replaced function, and [`ExtensionError::TargetUnloaded`] if the
+    /// target program isn't loaded anymore.
+    pub fn replace(
+        &mut self,
+        mut link: LinkRef,
+        new_func_name: &str,
+    ) -> Result<LinkRef, ProgramError> {
         let target_fd = self.data.attach_prog_fd.ok_or(ProgramError::NotLoaded)?;
         let btf_id = self.data.attach_btf_id.ok_or(ProgramError::NotLoaded)?;
+
+        let (btf_fd, btf) = get_btf_info(target_fd)?;
+        unsafe { close(btf_fd) };
+        let new_btf_id = btf
+            .id_by_type_name_kind(new_func_name, BtfKind::Func)
+            .map_err(ProgramError::Btf)?;
+        if func_signature(&btf, btf_id)? != func_signature(&btf, new_btf_id)? {
+            return Err(ExtensionError::IncompatibleSignature {
+                func_name: new_func_name.to_owned(),
+            }
+            .into());
+        }
+
+        let new_link = self.attach_to(new_btf_id)?;
+        link.detach()?;
+        self.data.attach_btf_id = Some(new_btf_id);
+        self.data.attach_func_name = Some(new_func_name.to_owned());
+        Ok(new_link)
+    }
+
+    fn attach_to(&mut self, btf_id: u32) -> Result<LinkRef, ProgramError> {
+        let prog_fd = self.data.fd_or_err()?;
+        let target_fd = self.data.attach_prog_fd.ok_or(ProgramError::NotLoaded)?;
+        let target_prog_id = self.data.attach_prog_id.ok_or(ProgramError::NotLoaded)?;
+        // the fd could have been closed and recycled since load()
+        match get_prog_id(target_fd).map_err(target_unloaded) {
+            Ok(id) if id == target_prog_id => {}
+            Ok(_) => return Err(ProgramError::ExtensionError(ExtensionError::TargetUnloaded)),
+            Err(e) => return Err(e),
+        }
         // the attach type must be set as 0, which is bpf_attach_type::BPF_CGROUP_INET_INGRESS
         let link_fd = bpf_link_create(prog_fd, target_fd, BPF_CGROUP_INET_INGRESS, Some(btf_id), 0)
             .map_err(|(_, io_error)| ProgramError::SyscallError {
@@ -138,4 +196,231 @@ impl Extension {
             })? as RawFd;
         Ok(self.data.link(FdLink { fd: Some(link_fd) }))
     }
+
+    /// Detaches the extension and restores the original target function.
+    ///
+    /// Dropping or detaching the link returned by [`attach`](Self::attach)
+    /// already restores the original function, since the kernel reverts the
+    /// replacement when the link is released. This method consumes the link
+    /// and then checks that the target program is still loaded and that its
+    /// BTF still contains the replaced function.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ExtensionError::TargetUnloaded`] if the target program isn't
+    /// loaded anymore.
+    pub fn detach_and_restore(&mut self, mut link: LinkRef) -> Result<(), ProgramError> {
+        let target_fd = self.data.attach_prog_fd.ok_or(ProgramError::NotLoaded)?;
+        let btf_id = self.data.attach_btf_id.ok_or(ProgramError::NotLoaded)?;
+        link.detach()?;
+
+        let (btf_fd, btf) = get_btf_info(target_fd).map_err(target_unloaded)?;
+        unsafe { close(btf_fd) };
+
+        match btf.type_by_id(btf_id)?.kind()? {
+            Some(BtfKind::Func) => Ok(()),
+            _ => Err(ProgramError::ExtensionError(ExtensionError::TargetUnloaded)),
+        }
+    }
+
+    /// Returns the extensions attached to the program `program`, as the names
+    /// of the replaced functions and the ids of the extension programs.
+    ///
+    /// The kernel doesn't support `BPF_PROG_QUERY` on programs, so the links
+    /// of all the loaded programs are enumerated to find the `freplace`
+    /// links targeting `program`, including the ones created by other
+    /// processes. The names of the functions are resolved with the BTF of
+    /// `program`.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.9.
+    pub fn list_attached_extensions<T: AsRawFd>(
+        program: T,
+    ) -> Result<Vec<(String, u32)>, ProgramError> {
+        let target_prog_fd = program.as_raw_fd();
+        let target_prog_id = get_prog_id(target_prog_fd)?;
+        let (btf_fd, btf) = get_btf_info(target_prog_fd)?;
+        unsafe { close(btf_fd) };
+
+        let mut extensions = Vec::new();
+        for link in loaded_links() {
+            let link = link?;
+            let btf_id = match extension_target(&link, target_prog_id) {
+                Some(btf_id) => btf_id,
+                None => continue,
+            };
+            // fentry and fexit programs attached to BPF programs have the same
+            // link info as extensions
+            match ProgramInfo::from_id(link.prog_id()) {
+                Ok(info) if info.0.type_ == BPF_PROG_TYPE_EXT as u32 => {}
+                Ok(_) => continue,
+                // the extension was detached after its link id was returned
+                Err(ProgramError::SyscallError { io_error, .. })
+                    if io_error.raw_os_error() == Some(ENOENT) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            }
+            let func_name = btf
+                .type_name(btf.type_by_id(btf_id)?)?
+                .map(|name| name.into_owned())
+                .unwrap_or_default();
+            extensions.push((func_name, link.prog_id()));
+        }
+
+        Ok(extensions)
+    }
+}
+
+/// Returns the BTF id of the function replaced through `link` if it's a
+/// tracing link targeting the program `target_prog_id`.
+fn extension_target(link: &BpfLinkInfo, target_prog_id: u32) -> Option<u32> {
+    match link.kind() {
+        BpfLinkKind::Tracing {
+            target_obj_id,
+            target_btf_id,
+            ..
+        } if *target_obj_id == target_prog_id => Some(*target_btf_id),
+        _ => None,
+    }
+}
+
+/// Returns the return type and the parameter types of the function `func_id`.
+fn func_signature(btf: &Btf, func_id: u32) -> Result<(u32, Vec<u32>), ProgramError> {
+    let proto_id = match btf.type_by_id(func_id)? {
+        // Safety: union
+        BtfType::Func(ty) => unsafe { ty.__bindgen_anon_1.type_ },
+        _ => {
+            return Err(ProgramError::Btf(BtfError::UnexpectedBtfType {
+                type_id: func_id,
+            }))
+        }
+    };
+    match btf.type_by_id(proto_id)? {
+        BtfType::FuncProto(ty, params) => Ok((
+            // Safety: union
+            unsafe { ty.__bindgen_anon_1.type_ },
+            params.iter().map(|param| param.type_).collect(),
+        )),
+        _ => Err(ProgramError::Btf(BtfError::UnexpectedBtfType {
+            type_id: proto_id,
+        })),
+    }
+}
+
+/// Turns the `ENOENT` returned when the target program or its BTF is gone into
+/// [`ExtensionError::TargetUnloaded`], and passes the other errors through.
+fn target_unloaded(error: ProgramError) -> ProgramError {
+    match error {
+        ProgramError::SyscallError { ref io_error, .. }
+            if io_error.raw_os_error() == Some(ENOENT) =>
+        {
+            ProgramError::ExtensionError(ExtensionError::TargetUnloaded)
+        }
+        e => e,
+    }
+}
+
+/// Returns the id of the loaded program `prog_fd`.
+fn get_prog_id(prog_fd: RawFd) -> Result<u32, ProgramError> {
+    sys::bpf_obj_get_info_by_fd(prog_fd)
+        .map(|info| info.id)
+        .map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            io_error,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use libc::{EFAULT, EPERM};
+
+    use crate::{
+        generated::{bpf_cmd, btf_func_linkage, btf_param, BTF_INT_SIGNED},
+        sys::{override_syscall, Syscall},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_func_signature() {
+        let mut btf = Btf::new();
+        let name_off = btf.add_string("int".to_string());
+        let int_id = btf.add_type(BtfType::new_int(name_off, 4, BTF_INT_SIGNED, 0));
+        let name_off = btf.add_string("long".to_string());
+        let long_id = btf.add_type(BtfType::new_int(name_off, 8, BTF_INT_SIGNED, 0));
+        let mut add_func = |name: &str, param_type: u32| {
+            let params = vec![btf_param {
+                name_off: btf.add_string("a".to_string()),
+                type_: param_type,
+            }];
+            let proto_id = btf.add_type(BtfType::new_func_proto(params, int_id));
+            let name_off = btf.add_string(name.to_string());
+            btf.add_type(BtfType::new_func(
+                name_off,
+                proto_id,
+                btf_func_linkage::BTF_FUNC_GLOBAL,
+            ))
+        };
+        let foo = add_func("foo", int_id);
+        let bar = add_func("bar", int_id);
+        let baz = add_func("baz", long_id);
+
+        let foo = func_signature(&btf, foo).unwrap();
+        assert_eq!(foo, (int_id, vec![int_id]));
+        assert_eq!(foo, func_signature(&btf, bar).unwrap());
+        assert_ne!(foo, func_signature(&btf, baz).unwrap());
+        assert!(func_signature(&btf, int_id).is_err());
+    }
+
+    #[test]
+    fn test_extension_target() {
+        let link = |kind| BpfLinkInfo {
+            id: 1,
+            prog_id: 2,
+            kind,
+        };
+        let tracing = link(BpfLinkKind::Tracing {
+            attach_type: 0,
+            target_obj_id: 42,
+            target_btf_id: 7,
+        });
+        assert_eq!(extension_target(&tracing, 42), Some(7));
+        assert_eq!(extension_target(&tracing, 43), None);
+        assert_eq!(
+            extension_target(&link(BpfLinkKind::Xdp { ifindex: 42 }), 42),
+            None
+        );
+    }
+
+    #[test]
+    fn test_target_unloaded() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let errno = if unsafe { attr.info.bpf_fd } == 1 {
+                    ENOENT
+                } else {
+                    EPERM
+                };
+                Err((-1, io::Error::from_raw_os_error(errno)))
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        assert!(matches!(
+            get_btf_info(1).map_err(target_unloaded),
+            Err(ProgramError::ExtensionError(ExtensionError::TargetUnloaded))
+        ));
+        assert!(matches!(
+            get_prog_id(2).map_err(target_unloaded),
+            Err(ProgramError::SyscallError { io_error, .. }) if io_error.raw_os_error() == Some(EPERM)
+        ));
+    }
 }
@@ -1,8 +1,15 @@
 //! fentry programs.
+use libc::close;
+use std::os::unix::io::AsRawFd;
+
 use crate::{
     generated::{bpf_attach_type::BPF_TRACE_FENTRY, bpf_prog_type::BPF_PROG_TYPE_TRACING},
     obj::btf::{Btf, BtfKind},
-    programs::{load_program, utils::attach_raw_tracepoint, LinkRef, ProgramData, ProgramError},
+    programs::{
+        load_program,
+        utils::{attach_raw_tracepoint, find_func_in_btf_chain, get_btf_info},
+        LinkRef, ProgramData, ProgramError,
+    },
 };
 
 /// A program that can be attached to the entry point of (almost) any kernel
@@ -60,8 +67,67 @@ impl FEntry {
         load_program(BPF_PROG_TYPE_TRACING, &mut self.data)
     }
 
+    /// Loads the program so it's executed when the function `func_name` of
+    /// another eBPF program is entered.
+    ///
+    /// `program` is the already loaded target program. Its BTF, rather than
+    /// the BTF of the kernel, is used to find `func_name`, so the target must
+    /// have been loaded with BTF. The target is fixed at load time, the
+    /// program is then attached with [`attach`](Self::attach).
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.5.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ExtensionError::NoBTF`](crate::programs::ExtensionError::NoBTF)
+    /// if the target program doesn't have BTF.
+    pub fn load_for_bpf_prog<T: AsRawFd>(
+        &mut self,
+        program: T,
+        func_name: &str,
+    ) -> Result<(), ProgramError> {
+        let target_prog_fd = program.as_raw_fd();
+        let (btf_fd, btf) = get_btf_info(target_prog_fd)?;
+        unsafe { close(btf_fd) };
+
+        self.data.expected_attach_type = Some(BPF_TRACE_FENTRY);
+        self.data.attach_prog_fd = Some(target_prog_fd);
+        self.data.attach_btf_id = Some(btf.id_by_type_name_kind(func_name, BtfKind::Func)?);
+        load_program(BPF_PROG_TYPE_TRACING, &mut self.data)
+    }
+
     /// Attaches the program
     pub fn attach(&mut self) -> Result<LinkRef, ProgramError> {
         attach_raw_tracepoint(&mut self.data, None)
     }
+
+    /// Loads and attaches the program so it's executed when the function
+    /// `func_name` of the kernel module `module_name` is entered.
+    ///
+    /// The BTF of the module is read from `/sys/kernel/btf/<module_name>`
+    /// and the program is loaded with the BTF object of the module as its
+    /// target, or with the one of the kernel if `func_name` isn't defined by
+    /// the module. Since the target is fixed at load time, this both loads and
+    /// attaches the program, which must not have been loaded yet.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.11.
+    pub fn attach_to_kernel_module(
+        &mut self,
+        module_name: &str,
+        func_name: &str,
+    ) -> Result<LinkRef, ProgramError> {
+        let (btf_fd, btf_id, _) = find_func_in_btf_chain(module_name, func_name)?;
+        self.data.expected_attach_type = Some(BPF_TRACE_FENTRY);
+        self.data.attach_btf_obj_fd = Some(btf_fd as u32);
+        self.data.attach_btf_id = Some(btf_id);
+        let ret = load_program(BPF_PROG_TYPE_TRACING, &mut self.data);
+        unsafe { close(btf_fd) };
+        self.data.attach_btf_obj_fd = None;
+        ret?;
+        self.attach()
+    }
 }
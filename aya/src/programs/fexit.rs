@@ -1,10 +1,33 @@
 //! fexit programs.
+use libc::close;
+use std::os::unix::io::{AsRawFd, RawFd};
+use thiserror::Error;
+
 use crate::{
     generated::{bpf_attach_type::BPF_TRACE_FEXIT, bpf_prog_type::BPF_PROG_TYPE_TRACING},
-    obj::btf::{Btf, BtfKind},
-    programs::{load_program, utils::attach_raw_tracepoint, LinkRef, ProgramData, ProgramError},
+    obj::{
+        btf::{Btf, BtfKind, BtfType},
+        ProgramSection,
+    },
+    programs::{
+        load_program,
+        utils::{attach_raw_tracepoint, find_func_in_btf_chain, get_btf_info},
+        LinkRef, ProgramData, ProgramError,
+    },
 };
 
+/// The type returned when loading an [`FExit`] program fails.
+#[derive(Debug, Error)]
+pub enum FExitError {
+    /// Return value access was requested but the target function returns
+    /// `void`.
+    #[error("function `{func_name}` doesn't return a value")]
+    VoidReturnType {
+        /// The name of the target function
+        func_name: String,
+    },
+}
+
 /// A program that can be attached to the exit point of (almost) anny kernel
 /// function.
 ///
@@ -44,9 +67,39 @@ use crate::{
 #[doc(alias = "BPF_PROG_TYPE_TRACING")]
 pub struct FExit {
     pub(crate) data: ProgramData,
+    pub(crate) return_access: bool,
 }
 
 impl FExit {
+    /// Creates a program from the fd of a program that is already loaded.
+    ///
+    /// Returns [`ProgramError::UnexpectedProgramType`] if `fd` isn't a
+    /// tracing program. See also
+    /// [`Program::from_fd`](crate::programs::Program::from_fd).
+    pub fn from_fd(fd: RawFd) -> Result<FExit, ProgramError> {
+        let data = ProgramData::from_fd(fd, BPF_PROG_TYPE_TRACING, |name| ProgramSection::FExit {
+            name,
+        })?;
+        Ok(FExit {
+            data,
+            return_access: false,
+        })
+    }
+
+    /// Declares whether the program reads the return value of the target
+    /// function.
+    ///
+    /// The return value is always passed to fexit programs, after the
+    /// arguments of the function, so the program must declare it as its last
+    /// parameter with the type found in BTF. When `return_access` is `true`,
+    /// loading the program fails with [`FExitError::VoidReturnType`] if the
+    /// target function returns `void`, instead of with a verifier error about
+    /// the out of bounds parameter.
+    pub fn with_return_access(&mut self, return_access: bool) -> &mut FExit {
+        self.return_access = return_access;
+        self
+    }
+
     /// Loads the program inside the kernel.
     ///
     /// See also [`Program::load`](crate::programs::Program::load).
@@ -55,8 +108,47 @@ impl FExit {
     /// is exited. The `btf` argument must contain the BTF info for the running
     /// kernel.
     pub fn load(&mut self, fn_name: &str, btf: &Btf) -> Result<(), ProgramError> {
+        let btf_id = btf.id_by_type_name_kind(fn_name, BtfKind::Func)?;
+        if self.return_access {
+            check_return_type(btf, btf_id, fn_name)?;
+        }
+        self.data.expected_attach_type = Some(BPF_TRACE_FEXIT);
+        self.data.attach_btf_id = Some(btf_id);
+        load_program(BPF_PROG_TYPE_TRACING, &mut self.data)
+    }
+
+    /// Loads the program so it's executed when the function `func_name` of
+    /// another eBPF program is exited.
+    ///
+    /// `program` is the already loaded target program. Its BTF, rather than
+    /// the BTF of the kernel, is used to find `func_name`, so the target must
+    /// have been loaded with BTF. The target is fixed at load time, the
+    /// program is then attached with [`attach`](Self::attach).
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.5.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ExtensionError::NoBTF`](crate::programs::ExtensionError::NoBTF)
+    /// if the target program doesn't have BTF.
+    pub fn load_for_bpf_prog<T: AsRawFd>(
+        &mut self,
+        program: T,
+        func_name: &str,
+    ) -> Result<(), ProgramError> {
+        let target_prog_fd = program.as_raw_fd();
+        let (btf_fd, btf) = get_btf_info(target_prog_fd)?;
+        unsafe { close(btf_fd) };
+
+        let btf_id = btf.id_by_type_name_kind(func_name, BtfKind::Func)?;
+        if self.return_access {
+            check_return_type(&btf, btf_id, func_name)?;
+        }
         self.data.expected_attach_type = Some(BPF_TRACE_FEXIT);
-        self.data.attach_btf_id = Some(btf.id_by_type_name_kind(fn_name, BtfKind::Func)?);
+        self.data.attach_prog_fd = Some(target_prog_fd);
+        self.data.attach_btf_id = Some(btf_id);
         load_program(BPF_PROG_TYPE_TRACING, &mut self.data)
     }
 
@@ -64,4 +156,96 @@ impl FExit {
     pub fn attach(&mut self) -> Result<LinkRef, ProgramError> {
         attach_raw_tracepoint(&mut self.data, None)
     }
+
+    /// Loads and attaches the program so it's executed when the function
+    /// `func_name` of the kernel module `module_name` is exited.
+    ///
+    /// The BTF of the module is read from `/sys/kernel/btf/<module_name>`
+    /// and the program is loaded with the BTF object of the module as its
+    /// target, or with the one of the kernel if `func_name` isn't defined by
+    /// the module. Since the target is fixed at load time, this both loads and
+    /// attaches the program, which must not have been loaded yet.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.11.
+    pub fn attach_to_kernel_module(
+        &mut self,
+        module_name: &str,
+        func_name: &str,
+    ) -> Result<LinkRef, ProgramError> {
+        let (btf_fd, btf_id, btf) = find_func_in_btf_chain(module_name, func_name)?;
+        if self.return_access {
+            if let Err(e) = check_return_type(&btf, btf_id, func_name) {
+                unsafe { close(btf_fd) };
+                return Err(e);
+            }
+        }
+        self.data.expected_attach_type = Some(BPF_TRACE_FEXIT);
+        self.data.attach_btf_obj_fd = Some(btf_fd as u32);
+        self.data.attach_btf_id = Some(btf_id);
+        let ret = load_program(BPF_PROG_TYPE_TRACING, &mut self.data);
+        unsafe { close(btf_fd) };
+        self.data.attach_btf_obj_fd = None;
+        ret?;
+        self.attach()
+    }
+}
+
+// checks that the function `func_id` returns a value
+fn check_return_type(btf: &Btf, func_id: u32, func_name: &str) -> Result<(), ProgramError> {
+    let proto_id = match btf.type_by_id(func_id)? {
+        // Safety: union
+        BtfType::Func(ty) => unsafe { ty.__bindgen_anon_1.type_ },
+        _ => return Ok(()),
+    };
+    let return_type = match btf.type_by_id(proto_id)? {
+        // Safety: union
+        BtfType::FuncProto(ty, _) => unsafe { ty.__bindgen_anon_1.type_ },
+        _ => return Ok(()),
+    };
+    // type id 0 is void
+    if return_type == 0 {
+        return Err(FExitError::VoidReturnType {
+            func_name: func_name.to_owned(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generated::btf_func_linkage;
+
+    use super::*;
+
+    #[test]
+    fn test_check_return_type() {
+        let mut btf = Btf::new();
+        let name_offset = btf.add_string("int".to_string());
+        let int_type_id = btf.add_type(BtfType::new_int(name_offset, 4, 0, 0));
+
+        let proto = btf.add_type(BtfType::new_func_proto(Vec::new(), int_type_id));
+        let name_offset = btf.add_string("returns_int".to_string());
+        let returns_int = btf.add_type(BtfType::new_func(
+            name_offset,
+            proto,
+            btf_func_linkage::BTF_FUNC_GLOBAL,
+        ));
+
+        let proto = btf.add_type(BtfType::new_func_proto(Vec::new(), 0));
+        let name_offset = btf.add_string("returns_void".to_string());
+        let returns_void = btf.add_type(BtfType::new_func(
+            name_offset,
+            proto,
+            btf_func_linkage::BTF_FUNC_GLOBAL,
+        ));
+
+        assert!(check_return_type(&btf, returns_int, "returns_int").is_ok());
+        assert!(matches!(
+            check_return_type(&btf, returns_void, "returns_void"),
+            Err(ProgramError::FExitError(FExitError::VoidReturnType { .. }))
+        ));
+    }
 }
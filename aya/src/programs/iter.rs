@@ -0,0 +1,133 @@
+//! Iterator programs.
+use std::{
+    fs::File,
+    os::unix::io::{FromRawFd, RawFd},
+    path::Path,
+};
+
+use crate::{
+    generated::{bpf_attach_type::BPF_TRACE_ITER, bpf_prog_type::BPF_PROG_TYPE_TRACING},
+    maps::Map,
+    obj::btf::{Btf, BtfKind},
+    programs::{load_program, FdLink, Link, ProgramData, ProgramError},
+    sys::{bpf_iter_create, bpf_link_create_iter},
+};
+
+/// A program that iterates over kernel objects.
+///
+/// [`Iter`] programs are run by reading the files created with
+/// [`IterLink::open`]: the kernel calls the program once for each object of
+/// the iterator target, and the output the program writes with
+/// `bpf_seq_printf()` or `bpf_seq_write()` is read from the file. The target
+/// is selected when the program is loaded, for example `task` to iterate over
+/// all the tasks, `bpf_map_elem` to iterate over the elements of a map or
+/// `bpf_sk_storage_map` to iterate over the values of a socket storage map.
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 5.8, and 5.9
+/// for the map iterators attached with [`attach_to_map`](Iter::attach_to_map).
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut bpf = aya::Bpf::load(&[])?;
+/// use aya::{programs::Iter, Btf};
+/// use std::{convert::TryInto, io::Read};
+///
+/// let btf = Btf::from_sys_fs()?;
+/// let program: &mut Iter = bpf.program_mut("dump_tasks").unwrap().try_into()?;
+/// program.load("task", &btf)?;
+/// let link = program.attach()?;
+///
+/// let mut output = String::new();
+/// link.open()?.read_to_string(&mut output)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+#[doc(alias = "BPF_TRACE_ITER")]
+pub struct Iter {
+    pub(crate) data: ProgramData,
+}
+
+impl Iter {
+    /// Loads the program inside the kernel.
+    ///
+    /// See also [`Program::load`](crate::programs::Program::load).
+    ///
+    /// Loads the program so it iterates over the objects of `target`. The
+    /// `btf` argument must contain the BTF info for the running kernel, which
+    /// defines the `bpf_iter_<target>` function of each target it supports.
+    pub fn load(&mut self, target: &str, btf: &Btf) -> Result<(), ProgramError> {
+        self.data.expected_attach_type = Some(BPF_TRACE_ITER);
+        let type_name = format!("bpf_iter_{}", target);
+        self.data.attach_btf_id = Some(btf.id_by_type_name_kind(&type_name, BtfKind::Func)?);
+        load_program(BPF_PROG_TYPE_TRACING, &mut self.data)
+    }
+
+    /// Attaches the program to its target.
+    ///
+    /// Unlike the links of the other program types, the returned link is
+    /// owned by the caller and the iterator is detached when it's dropped.
+    pub fn attach(&mut self) -> Result<IterLink, ProgramError> {
+        self.attach_inner(None)
+    }
+
+    /// Attaches the program to iterate over the elements of `map`.
+    ///
+    /// This is required by the `bpf_map_elem` and `bpf_sk_storage_map`
+    /// targets. See [`attach`](Self::attach) for the ownership of the
+    /// returned link.
+    pub fn attach_to_map(&mut self, map: &Map) -> Result<IterLink, ProgramError> {
+        let map_fd = map.fd_or_err()?;
+        self.attach_inner(Some(map_fd))
+    }
+
+    fn attach_inner(&mut self, map_fd: Option<RawFd>) -> Result<IterLink, ProgramError> {
+        let prog_fd = self.data.fd_or_err()?;
+        let link_fd = bpf_link_create_iter(prog_fd, map_fd).map_err(|(_, io_error)| {
+            ProgramError::SyscallError {
+                call: "bpf_link_create".to_owned(),
+                io_error,
+            }
+        })? as RawFd;
+
+        Ok(IterLink {
+            inner: FdLink { fd: Some(link_fd) },
+        })
+    }
+}
+
+/// The link of an attached [`Iter`] program.
+///
+/// The iterator is detached when the link is dropped.
+#[derive(Debug)]
+pub struct IterLink {
+    pub(crate) inner: FdLink,
+}
+
+impl IterLink {
+    /// Creates a new instance of the iterator with `BPF_ITER_CREATE`.
+    ///
+    /// Reading the returned file runs the program over the objects of the
+    /// target, from the first one.
+    pub fn open(&self) -> Result<File, ProgramError> {
+        let link_fd = self.inner.fd.ok_or(ProgramError::AlreadyDetached)?;
+        let fd = bpf_iter_create(link_fd).map_err(|(_, io_error)| ProgramError::SyscallError {
+            call: "bpf_iter_create".to_owned(),
+            io_error,
+        })? as RawFd;
+
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+impl Link for IterLink {
+    fn detach(&mut self) -> Result<(), ProgramError> {
+        self.inner.detach()
+    }
+
+    fn pin(&mut self, path: &Path) -> Result<(), ProgramError> {
+        self.inner.pin(path)
+    }
+}
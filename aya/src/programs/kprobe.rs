@@ -1,13 +1,19 @@
 //! Kernel space probes.
-use std::io;
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    os::unix::io::RawFd,
+};
 use thiserror::Error;
 
 use crate::{
     generated::bpf_prog_type::BPF_PROG_TYPE_KPROBE,
+    obj::ProgramSection,
     programs::{
         load_program,
-        probe::{attach, ProbeKind},
-        LinkRef, ProgramData, ProgramError,
+        probe::{attach, attach_fd_link, attach_kretprobe_with_maxactive, ProbeKind},
+        FdLink, LinkRef, ProgramData, ProgramError,
     },
 };
 
@@ -19,6 +25,9 @@ use crate::{
 /// - `kprobe`: get attached to the *start* of the target functions
 /// - `kretprobe`: get attached to the *return address* of the target functions
 ///
+/// The kind is selected with the ELF section name of the program, `kprobe/...`
+/// or `kretprobe/...`, and is returned by [`KProbe::kind`].
+///
 /// # Minimum kernel version
 ///
 /// The minimum kernel version required to use this feature is 4.1.
@@ -50,6 +59,23 @@ impl KProbe {
         load_program(BPF_PROG_TYPE_KPROBE, &mut self.data)
     }
 
+    /// Creates a program from the fd of a program that is already loaded.
+    ///
+    /// The kernel can't tell the kinds of probes apart, so `kind` must match
+    /// the one the program was written for.
+    ///
+    /// Returns [`ProgramError::UnexpectedProgramType`] if `fd` isn't a probe
+    /// program or if `kind` isn't `KProbe` or `KRetProbe`.
+    pub fn from_fd(fd: RawFd, kind: ProbeKind) -> Result<KProbe, ProgramError> {
+        if !matches!(kind, ProbeKind::KProbe | ProbeKind::KRetProbe) {
+            return Err(ProgramError::UnexpectedProgramType);
+        }
+        let data = ProgramData::from_fd(fd, BPF_PROG_TYPE_KPROBE, |name| ProgramSection::KProbe {
+            name,
+        })?;
+        Ok(KProbe { data, kind })
+    }
+
     /// Returns `KProbe` if the program is a `kprobe`, or `KRetProbe` if the
     /// program is a `kretprobe`.
     pub fn kind(&self) -> ProbeKind {
@@ -60,16 +86,348 @@ impl KProbe {
     ///
     /// Attaches the probe to the given function name inside the kernel. If
     /// `offset` is non-zero, it is added to the address of the target
-    /// function.
+    /// function. The offset is in bytes from the start of the function and
+    /// must point at the start of an instruction, eg `offset` can be taken
+    /// from the disassembly of the function whose address is listed in
+    /// `/proc/kallsyms`.
     ///
     /// If the program is a `kprobe`, it is attached to the *start* address of the target function.
     /// Conversely if the program is a `kretprobe`, it is attached to the return address of the
-    /// target function.
+    /// target function. The kernel only accepts return probes at the start of
+    /// a function, so `offset` must be zero for `kretprobe` programs.
+    ///
+    /// Probes only fire when the out-of-line copy of the target function runs.
+    /// Calls to the function that the compiler has inlined into its callers
+    /// aren't traced, and attaching fails if the function has been inlined
+    /// everywhere and isn't listed in `/proc/kallsyms`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`KProbeError::Blacklisted`] if `fn_name` is listed in
+    /// `/sys/kernel/debug/kprobes/blacklist` and
+    /// [`KProbeError::InvalidOffset`] if `offset` isn't a multiple of the
+    /// instruction size on architectures with fixed size instructions, like
+    /// aarch64.
     pub fn attach(&mut self, fn_name: &str, offset: u64) -> Result<LinkRef, ProgramError> {
-        attach(&mut self.data, self.kind, fn_name, offset, None)
+        // the blacklist is only available when debugfs is mounted, if it can't
+        // be read let the kernel decide
+        let blacklist = kprobe_blacklist().ok();
+        self.attach_checked(blacklist.as_ref(), fn_name, offset, None)
+    }
+
+    /// Attaches the program to the given function, setting the cookie
+    /// returned by `bpf_get_attach_cookie()` in the program.
+    ///
+    /// The cookie lets a program attached to several functions tell which
+    /// attachment is running. See [`attach`](Self::attach) for the meaning of
+    /// `fn_name` and `offset`.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.15.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut bpf = aya::Bpf::load(&[])?;
+    /// use aya::programs::KProbe;
+    /// use std::convert::TryInto;
+    ///
+    /// let program: &mut KProbe = bpf.program_mut("trace_io").unwrap().try_into()?;
+    /// program.load()?;
+    /// program.attach_with_cookie("vfs_read", 0, 1)?;
+    /// program.attach_with_cookie("vfs_write", 0, 2)?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn attach_with_cookie(
+        &mut self,
+        fn_name: &str,
+        offset: u64,
+        cookie: u64,
+    ) -> Result<LinkRef, ProgramError> {
+        let blacklist = kprobe_blacklist().ok();
+        self.attach_checked(blacklist.as_ref(), fn_name, offset, Some(cookie))
+    }
+
+    /// Attaches the program through a bpf link and returns the link.
+    ///
+    /// [`attach`](Self::attach) already uses a bpf link on kernels that
+    /// support them for perf events, and falls back to
+    /// `PERF_EVENT_IOC_SET_BPF` on older ones. This method always creates a
+    /// bpf link, and the program stays attached for as long as the returned
+    /// [`FdLink`] exists, eg so that the link can be pinned or handed to
+    /// another process. See [`attach`](Self::attach) for the meaning of
+    /// `fn_name` and `offset`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`attach`](Self::attach), and
+    /// [`ProgramError::SyscallError`] if the kernel can't create bpf links for
+    /// perf events.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.15.
+    pub fn attach_bpf_link(&mut self, fn_name: &str, offset: u64) -> Result<FdLink, ProgramError> {
+        let blacklist = kprobe_blacklist().ok();
+        check_attachable(blacklist.as_ref(), fn_name, offset)?;
+        attach_fd_link(&mut self.data, self.kind, fn_name, offset)
+    }
+
+    /// Attaches a `kretprobe` program, allowing at most `maxactive`
+    /// instances of the probe to run at the same time.
+    ///
+    /// The kernel tracks each call of the target function that hasn't
+    /// returned yet to run the probe on return, and only allocates
+    /// `maxactive` slots for that. Calls made while all the slots are in use
+    /// aren't traced, so a `maxactive` that is too low causes missed return
+    /// probes when the function is called concurrently or sleeps. `2 *` the
+    /// number of CPUs is a reasonable value for most functions. When
+    /// `maxactive` is 0 the kernel picks the default, and the program is
+    /// attached as with [`attach`](Self::attach). `maxactive` only applies to
+    /// `kretprobe` programs and is ignored for `kprobe` ones.
+    ///
+    /// The limit can only be set on probes created through
+    /// `/sys/kernel/tracing/kprobe_events`, so the probe is always created
+    /// there rather than with `perf_event_open`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`KProbeError::MaxactiveTooLarge`] if `maxactive` is larger
+    /// than 4096, the maximum accepted by the kernel, and the same errors as
+    /// [`attach`](Self::attach) otherwise.
+    pub fn attach_with_maxactive(
+        &mut self,
+        fn_name: &str,
+        offset: u64,
+        maxactive: u16,
+    ) -> Result<LinkRef, ProgramError> {
+        check_maxactive(maxactive)?;
+        if !matches!(self.kind, ProbeKind::KRetProbe) || maxactive == 0 {
+            return self.attach(fn_name, offset);
+        }
+        let blacklist = kprobe_blacklist().ok();
+        check_attachable(blacklist.as_ref(), fn_name, offset)?;
+        attach_kretprobe_with_maxactive(&mut self.data, fn_name, offset, maxactive as u32)
+    }
+
+    /// Attaches the program to several functions.
+    ///
+    /// Each `(fn_name, offset)` pair is attached as with
+    /// [`attach`](Self::attach), so attaching still takes one
+    /// `perf_event_open` call per function. Instead of stopping at the first
+    /// error, every function is tried and the failures are returned alongside
+    /// the links of the successful attachments.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut bpf = aya::Bpf::load(&[])?;
+    /// use aya::programs::KProbe;
+    /// use std::convert::TryInto;
+    ///
+    /// let program: &mut KProbe = bpf.program_mut("trace_io").unwrap().try_into()?;
+    /// program.load()?;
+    /// let result = program.attach_many(&[("vfs_read", 0), ("vfs_write", 0)]);
+    /// for (function, error) in &result.failed {
+    ///     eprintln!("failed to attach to {}: {}", function, error);
+    /// }
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn attach_many(&mut self, funcs: &[(&str, u64)]) -> BatchAttachResult {
+        let blacklist = kprobe_blacklist().ok();
+        let mut result = BatchAttachResult {
+            succeeded: Vec::new(),
+            failed: Vec::new(),
+        };
+        for (fn_name, offset) in funcs {
+            match self.attach_checked(blacklist.as_ref(), fn_name, *offset, None) {
+                Ok(link) => result.succeeded.push(link),
+                Err(e) => result.failed.push((fn_name.to_string(), e)),
+            }
+        }
+
+        result
+    }
+
+    /// Returns whether a probe can be attached to the kernel function
+    /// `fn_name`.
+    ///
+    /// Returns `Ok(false)` if the function is blacklisted or isn't listed in
+    /// `/proc/kallsyms`, see [`traceability`](Self::traceability) for the
+    /// reason.
+    pub fn is_traceable(fn_name: &str) -> Result<bool, io::Error> {
+        Ok(KProbe::traceability(fn_name)? == TraceabilityResult::Traceable)
+    }
+
+    /// Checks whether a probe can be attached to the kernel function
+    /// `fn_name`.
+    ///
+    /// The function is looked up in `/sys/kernel/debug/kprobes/blacklist`
+    /// and in the function symbols of `/proc/kallsyms`. The blacklist is
+    /// skipped if debugfs isn't mounted.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use aya::programs::{KProbe, TraceabilityResult};
+    ///
+    /// match KProbe::traceability("try_to_wake_up")? {
+    ///     TraceabilityResult::Traceable => {}
+    ///     result => eprintln!("can't probe try_to_wake_up: {:?}", result),
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn traceability(fn_name: &str) -> Result<TraceabilityResult, io::Error> {
+        if let Ok(blacklist) = kprobe_blacklist() {
+            if blacklist.contains(fn_name) {
+                return Ok(TraceabilityResult::Blacklisted);
+            }
+        }
+        let reader = BufReader::new(File::open(KALLSYMS)?);
+        find_function_symbol(reader, fn_name)
+    }
+
+    fn attach_checked(
+        &mut self,
+        blacklist: Option<&HashSet<String>>,
+        fn_name: &str,
+        offset: u64,
+        cookie: Option<u64>,
+    ) -> Result<LinkRef, ProgramError> {
+        check_attachable(blacklist, fn_name, offset)?;
+        attach(&mut self.data, self.kind, fn_name, offset, 0, None, cookie)
     }
 }
 
+/// The result of [`KProbe::attach_many`].
+#[derive(Debug)]
+pub struct BatchAttachResult {
+    /// The links of the functions the program was attached to.
+    pub succeeded: Vec<LinkRef>,
+    /// The functions the program couldn't be attached to, with the error
+    /// returned for each of them.
+    pub failed: Vec<(String, ProgramError)>,
+}
+
+// the alignment of the instructions, always a power of two. Probes can only be
+// placed at the start of an instruction.
+#[cfg(target_arch = "aarch64")]
+const INSN_ALIGNMENT: u64 = 4;
+// thumb-2 kernels mix 2 and 4 byte instructions
+#[cfg(target_arch = "arm")]
+const INSN_ALIGNMENT: u64 = 2;
+#[cfg(not(any(target_arch = "aarch64", target_arch = "arm")))]
+const INSN_ALIGNMENT: u64 = 1;
+
+// the checks done before any kind of attachment. `blacklist` is `None` if it
+// couldn't be read, in which case the kernel decides.
+fn check_attachable(
+    blacklist: Option<&HashSet<String>>,
+    fn_name: &str,
+    offset: u64,
+) -> Result<(), KProbeError> {
+    if blacklist.map(|b| b.contains(fn_name)).unwrap_or(false) {
+        return Err(KProbeError::Blacklisted {
+            function: fn_name.to_owned(),
+        });
+    }
+    check_offset(offset, INSN_ALIGNMENT)
+}
+
+fn check_offset(offset: u64, alignment: u64) -> Result<(), KProbeError> {
+    if offset & (alignment - 1) != 0 {
+        return Err(KProbeError::InvalidOffset { offset, alignment });
+    }
+    Ok(())
+}
+
+// see KRETPROBE_MAXACTIVE_MAX in kernel/trace/trace_kprobe.c
+const MAXACTIVE_MAX: u16 = 4096;
+
+fn check_maxactive(maxactive: u16) -> Result<(), KProbeError> {
+    if maxactive > MAXACTIVE_MAX {
+        return Err(KProbeError::MaxactiveTooLarge { maxactive });
+    }
+    Ok(())
+}
+
+/// The result of [`KProbe::traceability`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceabilityResult {
+    /// The function can be probed.
+    Traceable,
+    /// The function is in the kprobe blacklist.
+    Blacklisted,
+    /// The function isn't a kernel function symbol.
+    NotFound,
+    /// The function has been inlined into its callers. Only the copies
+    /// specialized by the compiler, eg `<name>.isra.0` or `<name>.part.0`,
+    /// are listed in `/proc/kallsyms` and can be probed by their own name.
+    Inlined,
+}
+
+const KALLSYMS: &str = "/proc/kallsyms";
+
+fn find_function_symbol(
+    reader: impl BufRead,
+    fn_name: &str,
+) -> Result<TraceabilityResult, io::Error> {
+    let mut result = TraceabilityResult::NotFound;
+
+    // each line has the form `ffffffff81000000 T function_name [module]`. The
+    // addresses are zeroed for unprivileged readers, so only names are used.
+    for line in reader.lines() {
+        let line = line?;
+        let mut parts = line.split_whitespace();
+        let (kind, name) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(_), Some(kind), Some(name)) => (kind, name),
+            _ => continue,
+        };
+        if kind != "t" && kind != "T" {
+            continue;
+        }
+        if name == fn_name {
+            return Ok(TraceabilityResult::Traceable);
+        }
+        // compiler generated copies like `<name>.isra.0` or `<name>.cold`
+        if name
+            .strip_prefix(fn_name)
+            .map(|suffix| suffix.starts_with('.'))
+            .unwrap_or(false)
+        {
+            result = TraceabilityResult::Inlined;
+        }
+    }
+
+    Ok(result)
+}
+
+const KPROBE_BLACKLIST: &str = "/sys/kernel/debug/kprobes/blacklist";
+
+/// Returns the names of the kernel functions that can't be probed.
+///
+/// The list is read from `/sys/kernel/debug/kprobes/blacklist`, which requires
+/// debugfs to be mounted.
+pub fn kprobe_blacklist() -> Result<HashSet<String>, io::Error> {
+    let reader = BufReader::new(File::open(KPROBE_BLACKLIST)?);
+    parse_kprobe_blacklist(reader)
+}
+
+fn parse_kprobe_blacklist(reader: impl BufRead) -> Result<HashSet<String>, io::Error> {
+    let mut functions = HashSet::new();
+
+    // each line has the form `0xffffffff81000000-0xffffffff81000010\tfunction_name`
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(name) = line.split_whitespace().nth(1) {
+            functions.insert(name.to_owned());
+        }
+    }
+
+    Ok(functions)
+}
+
 /// The type returned when attaching a [`KProbe`] fails.
 #[derive(Debug, Error)]
 pub enum KProbeError {
@@ -82,4 +440,83 @@ pub enum KProbeError {
         #[source]
         io_error: io::Error,
     },
+
+    /// The target function is in the kprobe blacklist
+    #[error("`{function}` is in the kprobe blacklist and can't be probed")]
+    Blacklisted {
+        /// The function name
+        function: String,
+    },
+
+    /// The offset doesn't point at the start of an instruction
+    #[error("offset {offset:#x} isn't aligned to the {alignment} bytes instruction size")]
+    InvalidOffset {
+        /// The offset
+        offset: u64,
+        /// The required alignment
+        alignment: u64,
+    },
+
+    /// The maximum number of simultaneous kretprobe instances is too large
+    #[error("maxactive {maxactive} is larger than the maximum of 4096")]
+    MaxactiveTooLarge {
+        /// The requested maxactive
+        maxactive: u16,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kprobe_blacklist() {
+        let data = "0xffffffff81000000-0xffffffff81000010\tfoo\n\
+                    0xffffffff81000020-0xffffffff81000030\tbar [baz]\n\
+                    \n"
+        .as_bytes();
+        let blacklist = parse_kprobe_blacklist(BufReader::new(data)).unwrap();
+        assert_eq!(blacklist.len(), 2);
+        assert!(blacklist.contains("foo"));
+        assert!(blacklist.contains("bar"));
+    }
+
+    #[test]
+    fn test_find_function_symbol() {
+        let data = "ffffffff81000000 T foo\n\
+                    ffffffff81000010 t bar.isra.0\n\
+                    ffffffff81000020 D baz\n\
+                    0000000000000000 t qux\t[module]\n"
+            .as_bytes();
+        let find = |name| find_function_symbol(BufReader::new(data), name).unwrap();
+        assert_eq!(find("foo"), TraceabilityResult::Traceable);
+        assert_eq!(find("qux"), TraceabilityResult::Traceable);
+        assert_eq!(find("bar"), TraceabilityResult::Inlined);
+        assert_eq!(find("baz"), TraceabilityResult::NotFound);
+        assert_eq!(find("fo"), TraceabilityResult::NotFound);
+    }
+
+    #[test]
+    fn test_check_offset() {
+        assert!(check_offset(0, 4).is_ok());
+        assert!(check_offset(8, 4).is_ok());
+        assert!(matches!(
+            check_offset(6, 4),
+            Err(KProbeError::InvalidOffset {
+                offset: 6,
+                alignment: 4
+            })
+        ));
+        assert!(check_offset(7, 1).is_ok());
+    }
+
+    #[test]
+    fn test_check_maxactive() {
+        assert!(check_maxactive(0).is_ok());
+        assert!(check_maxactive(4096).is_ok());
+        assert!(matches!(
+            check_maxactive(4097),
+            Err(KProbeError::MaxactiveTooLarge { maxactive: 4097 })
+        ));
+    }
 }
@@ -64,7 +64,7 @@ impl LircMode2 {
         let prog_fd = self.data.fd_or_err()?;
         let lircdev_fd = lircdev.as_raw_fd();
 
-        bpf_prog_attach(prog_fd, lircdev_fd, BPF_LIRC_MODE2).map_err(|(_, io_error)| {
+        bpf_prog_attach(prog_fd, lircdev_fd, BPF_LIRC_MODE2, 0).map_err(|(_, io_error)| {
             ProgramError::SyscallError {
                 call: "bpf_prog_attach".to_owned(),
                 io_error,
@@ -75,6 +75,8 @@ impl LircMode2 {
     }
 
     /// Queries the lirc device for attached programs.
+    ///
+    /// `target_fd` is an open lirc device, eg `/dev/lirc0`.
     pub fn query<T: AsRawFd>(target_fd: T) -> Result<Vec<LircLink>, ProgramError> {
         let prog_ids = query(target_fd.as_raw_fd(), BPF_LIRC_MODE2, 0, &mut None)?;
 
@@ -99,6 +101,11 @@ impl LircMode2 {
     }
 }
 
+/// A [`LircMode2`] program attached to a lirc device.
+///
+/// Returned by [`LircMode2::query`] for the programs attached to a device,
+/// whether or not they were attached by this process. Calling
+/// [`detach`](Link::detach) detaches the program from the device.
 #[derive(Debug)]
 pub struct LircLink {
     prog_fd: Option<RawFd>,
@@ -113,6 +120,7 @@ impl LircLink {
         }
     }
 
+    /// Returns information about the attached program.
     pub fn info(&self) -> Result<ProgramInfo, ProgramError> {
         if let Some(fd) = self.prog_fd {
             match bpf_obj_get_info_by_fd(fd) {
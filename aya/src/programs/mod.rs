@@ -36,10 +36,15 @@
 //! [`Bpf::program`]: crate::Bpf::program
 //! [`Bpf::program_mut`]: crate::Bpf::program_mut
 //! [`maps`]: crate::maps
+mod cgroup;
+mod cgroup_device;
 mod cgroup_skb;
+mod cgroup_sock;
+mod cgroup_sock_addr;
 mod extension;
 mod fentry;
 mod fexit;
+mod iter;
 mod kprobe;
 mod lirc_mode2;
 mod lsm;
@@ -55,30 +60,43 @@ pub mod tc;
 mod tp_btf;
 mod trace_point;
 mod uprobe;
+mod usdt;
 mod utils;
 mod xdp;
 
-use libc::{close, dup, ENOSPC};
+use libc::{close, dup, EINVAL, ENOENT, ENOSPC, EPERM};
+use parking_lot::Mutex;
 use std::{
-    cell::RefCell,
     convert::TryFrom,
     ffi::CString,
     io,
+    ops::{Deref, DerefMut},
     os::unix::io::{AsRawFd, RawFd},
     path::Path,
-    rc::Rc,
+    sync::Arc,
 };
 use thiserror::Error;
 
-pub use cgroup_skb::{CgroupSkb, CgroupSkbAttachType};
+pub use crate::generated::{bpf_attach_type, bpf_prog_type};
+pub use cgroup::{
+    cgroup_attached_programs, CgroupAttachFlags, CgroupError, CgroupHierarchyIter, CgroupPrograms,
+};
+pub use cgroup_device::{CgroupDevice, CgroupDevicePolicy, DeviceAccess, DeviceType};
+pub use cgroup_skb::{query_cgroup_skb_programs, CgroupSkb, CgroupSkbAttachType};
+pub use cgroup_sock::{CgroupSock, CgroupSockAttachType};
+pub use cgroup_sock_addr::{CgroupSockAddr, CgroupSockAddrAttachType};
 pub use extension::{Extension, ExtensionError};
 pub use fentry::FEntry;
-pub use fexit::FExit;
-pub use kprobe::{KProbe, KProbeError};
-pub use lirc_mode2::LircMode2;
+pub use fexit::{FExit, FExitError};
+pub use iter::{Iter, IterLink};
+pub use kprobe::{kprobe_blacklist, BatchAttachResult, KProbe, KProbeError, TraceabilityResult};
+pub use lirc_mode2::{LircLink, LircMode2};
 pub use lsm::Lsm;
 use perf_attach::*;
-pub use perf_event::{PerfEvent, PerfEventScope, PerfTypeId, SamplePolicy};
+pub use perf_event::{
+    HwPerfEventBuilder, PerfEvent, PerfEventFd, PerfEventScope, PerfTypeId, SamplePolicy,
+    SoftwarePerfEvent, SoftwarePerfEventType,
+};
 pub use probe::ProbeKind;
 pub use raw_trace_point::RawTracePoint;
 pub use sk_msg::SkMsg;
@@ -87,19 +105,26 @@ pub use sock_ops::SockOps;
 pub use socket_filter::{SocketFilter, SocketFilterError};
 pub use tc::{SchedClassifier, TcAttachType, TcError};
 pub use tp_btf::BtfTracePoint;
-pub use trace_point::{TracePoint, TracePointError};
+pub use trace_point::{
+    available_tracepoints, tracepoint_format, TracePoint, TracePointError, TracePointField,
+    TracePointFormat,
+};
 pub use uprobe::{UProbe, UProbeError};
-pub use xdp::{Xdp, XdpError, XdpFlags};
+pub use usdt::{Usdt, UsdtError, UsdtProbe};
+pub use xdp::{Xdp, XdpAttachType, XdpError, XdpFlags, XdpLink, XdpMetadataFeatures};
 
 use crate::{
-    generated::{bpf_attach_type, bpf_prog_info, bpf_prog_type},
+    generated::{bpf_insn, bpf_link_info, bpf_link_type, bpf_prog_info},
     maps::MapError,
-    obj::{self, btf::BtfError, Function, KernelVersion},
+    obj::{self, btf::BtfError, copy_instructions, Function, KernelVersion, ProgramSection},
     sys::{
-        bpf_get_object, bpf_load_program, bpf_obj_get_info_by_fd, bpf_pin_object, bpf_prog_detach,
-        bpf_prog_get_fd_by_id, bpf_prog_query, retry_with_verifier_logs, BpfLoadProgramAttrs,
+        bpf_get_object, bpf_link_get_fd_by_id, bpf_link_get_info_by_fd, bpf_link_get_next_id,
+        bpf_load_program, bpf_obj_get_info_by_fd, bpf_pin_object, bpf_prog_detach,
+        bpf_prog_get_fd_by_id, bpf_prog_get_insns, bpf_prog_get_next_id, bpf_prog_query, ids,
+        retry_with_verifier_logs, BpfLoadProgramAttrs,
     },
-    util::VerifierLog,
+    util::{memlock_rlimit, VerifierLog},
+    VerifierLogLevel,
 };
 
 /// Error type returned when working with programs.
@@ -128,9 +153,35 @@ pub enum ProgramError {
         #[source]
         io_error: io::Error,
         /// The error log produced by the kernel verifier.
+        ///
+        /// When the object contains `.BTF.ext` data and the kernel supports
+        /// BTF, the log is annotated with the source lines of the program.
         verifier_log: String,
     },
 
+    /// Loading the program failed with `EPERM`, most likely because
+    /// `RLIMIT_MEMLOCK` is too low.
+    ///
+    /// Only reported on kernels older than 5.11, which charge the memory used
+    /// by programs against `RLIMIT_MEMLOCK`. Call
+    /// [`bump_memlock_rlimit`](crate::util::bump_memlock_rlimit) before
+    /// loading the program.
+    #[error("the BPF_PROG_LOAD syscall failed with EPERM, RLIMIT_MEMLOCK is {current} bytes but at least {needed} bytes are recommended. Call aya::util::bump_memlock_rlimit() before loading the program")]
+    InsufficientMemlock {
+        /// The current soft `RLIMIT_MEMLOCK` limit, in bytes.
+        current: u64,
+        /// The recommended minimum limit, in bytes.
+        needed: u64,
+    },
+
+    /// Hardware offload was requested for a program type that can't be
+    /// offloaded. Only XDP and TC classifier programs can be offloaded.
+    #[error("programs of type {prog_type:?} can't be offloaded")]
+    OffloadNotSupported {
+        /// The type of the program.
+        prog_type: bpf_prog_type,
+    },
+
     /// A syscall failed.
     #[error("`{call}` failed")]
     SyscallError {
@@ -152,6 +203,15 @@ pub enum ProgramError {
     #[error("unexpected program type")]
     UnexpectedProgramType,
 
+    /// The variant of a loaded program can't be determined from its fd.
+    ///
+    /// See [`Program::from_fd`].
+    #[error("the variant of a program of type {prog_type} can't be determined from its fd")]
+    AmbiguousProgramType {
+        /// The program type reported by the kernel
+        prog_type: u32,
+    },
+
     /// Invalid pin path
     #[error("invalid pin path `{error}`")]
     InvalidPinPath {
@@ -171,6 +231,10 @@ pub enum ProgramError {
     #[error(transparent)]
     UProbeError(#[from] UProbeError),
 
+    /// An error occurred while working with an [`Usdt`] program.
+    #[error(transparent)]
+    UsdtError(#[from] UsdtError),
+
     /// An error occurred while working with a [`TracePoint`].
     #[error(transparent)]
     TracePointError(#[from] TracePointError),
@@ -179,6 +243,10 @@ pub enum ProgramError {
     #[error(transparent)]
     SocketFilterError(#[from] SocketFilterError),
 
+    /// An error occurred while attaching a cgroup program.
+    #[error(transparent)]
+    CgroupError(#[from] CgroupError),
+
     /// An error occurred while working with an [`Xdp`] program.
     #[error(transparent)]
     XdpError(#[from] XdpError),
@@ -191,10 +259,18 @@ pub enum ProgramError {
     #[error(transparent)]
     ExtensionError(#[from] ExtensionError),
 
+    /// An error occurred while working with an [`FExit`] program.
+    #[error(transparent)]
+    FExitError(#[from] FExitError),
+
     /// An error occurred while working with BTF.
     #[error(transparent)]
     Btf(#[from] BtfError),
 
+    /// The link can't be pinned.
+    #[error("the link can't be pinned")]
+    LinkNotPinnable,
+
     /// The program is not attached.
     #[error("the program name `{name}` is invalid")]
     InvalidName {
@@ -216,6 +292,8 @@ pub enum Program {
     KProbe(KProbe),
     /// A [`UProbe`] program
     UProbe(UProbe),
+    /// A [`Usdt`] program
+    Usdt(Usdt),
     /// A [`TracePoint`] program
     TracePoint(TracePoint),
     /// A [`SocketFilter`] program
@@ -232,6 +310,12 @@ pub enum Program {
     SchedClassifier(SchedClassifier),
     /// A [`CgroupSkb`] program
     CgroupSkb(CgroupSkb),
+    /// A [`CgroupSock`] program
+    CgroupSock(CgroupSock),
+    /// A [`CgroupSockAddr`] program
+    CgroupSockAddr(CgroupSockAddr),
+    /// A [`CgroupDevice`] program
+    CgroupDevice(CgroupDevice),
     /// A [`LircMode2`] program
     LircMode2(LircMode2),
     /// A [`PerfEvent`] program
@@ -248,6 +332,8 @@ pub enum Program {
     FExit(FExit),
     /// A [`Extension`] program
     Extension(Extension),
+    /// An [`Iter`] program
+    Iter(Iter),
 }
 
 impl Program {
@@ -262,7 +348,15 @@ impl Program {
     /// If the program is already loaded, [`ProgramError::AlreadyLoaded`] is
     /// returned.
     pub fn load(&mut self) -> Result<(), ProgramError> {
-        load_program(self.prog_type(), self.data_mut())
+        // these program types must be loaded with the expected attach type
+        // of their section
+        match self {
+            Program::Xdp(p) => p.load(),
+            Program::CgroupSkb(p) => p.load(),
+            Program::CgroupSock(p) => p.load(),
+            Program::CgroupSockAddr(p) => p.load(),
+            _ => load_program(self.prog_type(), self.data_mut()),
+        }
     }
 
     /// Returns the low level program type.
@@ -271,6 +365,7 @@ impl Program {
         match self {
             Program::KProbe(_) => BPF_PROG_TYPE_KPROBE,
             Program::UProbe(_) => BPF_PROG_TYPE_KPROBE,
+            Program::Usdt(_) => BPF_PROG_TYPE_KPROBE,
             Program::TracePoint(_) => BPF_PROG_TYPE_TRACEPOINT,
             Program::SocketFilter(_) => BPF_PROG_TYPE_SOCKET_FILTER,
             Program::Xdp(_) => BPF_PROG_TYPE_XDP,
@@ -279,6 +374,9 @@ impl Program {
             Program::SockOps(_) => BPF_PROG_TYPE_SOCK_OPS,
             Program::SchedClassifier(_) => BPF_PROG_TYPE_SCHED_CLS,
             Program::CgroupSkb(_) => BPF_PROG_TYPE_CGROUP_SKB,
+            Program::CgroupSock(_) => BPF_PROG_TYPE_CGROUP_SOCK,
+            Program::CgroupSockAddr(_) => BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+            Program::CgroupDevice(_) => BPF_PROG_TYPE_CGROUP_DEVICE,
             Program::LircMode2(_) => BPF_PROG_TYPE_LIRC_MODE2,
             Program::PerfEvent(_) => BPF_PROG_TYPE_PERF_EVENT,
             Program::RawTracePoint(_) => BPF_PROG_TYPE_RAW_TRACEPOINT,
@@ -287,18 +385,236 @@ impl Program {
             Program::FEntry(_) => BPF_PROG_TYPE_TRACING,
             Program::FExit(_) => BPF_PROG_TYPE_TRACING,
             Program::Extension(_) => BPF_PROG_TYPE_EXT,
+            Program::Iter(_) => BPF_PROG_TYPE_TRACING,
+        }
+    }
+
+    /// Returns the expected attach type of the program.
+    ///
+    /// For loaded programs this is the value that was passed to the kernel at
+    /// load time. For programs that haven't been loaded yet it's the value the
+    /// program type sets when it's loaded, or `None` if it doesn't set any.
+    pub fn expected_attach_type(&self) -> Option<bpf_attach_type> {
+        let data = self.data();
+        if data.fd.is_some() {
+            return data.expected_attach_type;
+        }
+        match self {
+            Program::Xdp(p) => Some(p.attach_type.bpf_attach_type()),
+            Program::CgroupSkb(p) => p.expected_attach_type.map(|t| t.bpf_attach_type()),
+            Program::CgroupSock(p) => Some(p.attach_type.bpf_attach_type()),
+            Program::CgroupSockAddr(p) => Some(p.attach_type.bpf_attach_type()),
+            _ => data
+                .expected_attach_type
+                .or_else(|| data.obj.section.expected_attach_type()),
         }
     }
 
+    /// Returns the verifier log produced when the program was loaded.
+    ///
+    /// The log is only captured when the program was loaded by a
+    /// [`BpfLoader`](crate::BpfLoader) with a
+    /// [`log_level`](crate::BpfLoader::log_level) other than
+    /// [`VerifierLogLevel::Off`]. If loading fails, the log is returned in
+    /// [`ProgramError::LoadError`] instead.
+    pub fn verifier_log(&self) -> Option<&str> {
+        self.data().verifier_log.as_deref()
+    }
+
+    /// Returns the run time statistics of the program.
+    ///
+    /// The kernel only collects statistics while they're enabled, eg with
+    /// [`BpfLoader::enable_stats`](crate::BpfLoader::enable_stats), so all
+    /// the counters are zero otherwise.
+    pub fn stats(&self) -> Result<ProgramStats, ProgramError> {
+        let info = prog_info_by_fd(self.data().fd_or_err()?)?;
+        Ok(ProgramInfo(info).stats())
+    }
+
+    /// Returns the instructions of the loaded program, as translated by the
+    /// kernel.
+    ///
+    /// The instructions are the ones run by the interpreter or compiled by
+    /// the JIT: the verifier rewrites some of the instructions of the object,
+    /// eg to inline helpers or to replace map fds with map addresses.
+    /// Dumping the instructions requires `CAP_BPF` or `CAP_SYS_ADMIN`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ProgramError::SyscallError`] with an `EPERM` error if the
+    /// kernel doesn't allow the instructions to be dumped, eg because
+    /// `/proc/sys/kernel/kptr_restrict` hides kernel addresses from the
+    /// process.
+    pub fn dump_xlated_insns(&self) -> Result<Vec<bpf_insn>, ProgramError> {
+        let fd = self.data().fd_or_err()?;
+        let buf = bpf_prog_get_insns(fd, false).map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            io_error,
+        })?;
+        // loaded programs have at least one instruction, the code is empty
+        // when the kernel doesn't allow dumping it
+        if buf.is_empty() {
+            return Err(ProgramError::SyscallError {
+                call: "bpf_obj_get_info_by_fd".to_owned(),
+                io_error: io::Error::from_raw_os_error(EPERM),
+            });
+        }
+        // the length is always a multiple of the instruction size
+        Ok(copy_instructions(&buf).unwrap())
+    }
+
+    /// Returns the machine code the JIT compiled the loaded program to.
+    ///
+    /// The code is empty if the program isn't JIT compiled, when
+    /// `/proc/sys/net/core/bpf_jit_enable` is 0. Dumping the code requires
+    /// `CAP_BPF` or `CAP_SYS_ADMIN`, and the kernel also returns empty code
+    /// when `/proc/sys/kernel/kptr_restrict` hides kernel addresses from the
+    /// process.
+    pub fn dump_jited_insns(&self) -> Result<Vec<u8>, ProgramError> {
+        let fd = self.data().fd_or_err()?;
+        bpf_prog_get_insns(fd, true).map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            io_error,
+        })
+    }
+
+    /// Sets the kernel version passed to the kernel when the program is
+    /// loaded, overriding the version of the object.
+    ///
+    /// `version` is encoded like `LINUX_VERSION_CODE`, `major << 16 | minor
+    /// << 8 | patch`. Kernels older than 5.0 only load kprobe programs whose
+    /// version is the one of the running kernel. Newer kernels ignore the
+    /// version.
+    pub fn set_kern_version(&mut self, version: u32) {
+        self.data_mut().kern_version = Some(version);
+    }
+
     /// Pin the program to the provided path
     pub fn pin<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ProgramError> {
         self.data_mut().pin(path)
     }
 
+    /// Detaches the program from all the hooks it was attached to.
+    ///
+    /// The returned vector has one entry per link created by the program,
+    /// in the order the links were created. Links that were already detached
+    /// through their [`LinkRef`] return [`ProgramError::AlreadyDetached`].
+    pub fn detach_all(&mut self) -> Vec<Result<(), ProgramError>> {
+        self.data_mut()
+            .links
+            .drain(..)
+            .map(|link| link.lock().detach())
+            .collect()
+    }
+
+    /// Creates a program from the fd of a program that is already loaded.
+    ///
+    /// The type of the program is queried from the kernel, so this can be
+    /// used to attach a program received from another process, for example
+    /// over a unix socket. The program uses `fd` as is: `fd` must stay open
+    /// as long as the program is in use.
+    ///
+    /// Some program types are used by several variants that the kernel can't
+    /// tell apart: `BPF_PROG_TYPE_KPROBE` programs can be kprobes, kretprobes,
+    /// uprobes or USDT probes, `BPF_PROG_TYPE_TRACING` programs can be BTF
+    /// tracepoints, fentry or fexit programs, and the kind of
+    /// `BPF_PROG_TYPE_CGROUP_SOCK`, `BPF_PROG_TYPE_CGROUP_SOCK_ADDR`,
+    /// `BPF_PROG_TYPE_SK_SKB` and `BPF_PROG_TYPE_XDP` programs is set at load
+    /// time. For those, and for [`Extension`] programs, which need
+    /// their target to be attached, [`ProgramError::AmbiguousProgramType`]
+    /// is returned and the typed constructors like
+    /// [`KProbe::from_fd`] must be used instead.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let fd = 0;
+    /// use aya::programs::{Program, TcAttachType};
+    ///
+    /// // fd was received from another process
+    /// if let Program::SchedClassifier(mut prog) = Program::from_fd(fd)? {
+    ///     prog.attach("eth0", TcAttachType::Ingress)?;
+    /// }
+    /// # Ok::<(), aya::programs::ProgramError>(())
+    /// ```
+    pub fn from_fd(fd: RawFd) -> Result<Program, ProgramError> {
+        use crate::generated::bpf_prog_type::*;
+
+        let prog_type = prog_info_by_fd(fd)?.type_;
+        let program = match prog_type {
+            t if t == BPF_PROG_TYPE_TRACEPOINT as u32 => {
+                Program::TracePoint(TracePoint::from_fd(fd)?)
+            }
+            t if t == BPF_PROG_TYPE_SOCKET_FILTER as u32 => {
+                Program::SocketFilter(SocketFilter::from_fd(fd)?)
+            }
+            t if t == BPF_PROG_TYPE_SK_MSG as u32 => Program::SkMsg(SkMsg::from_fd(fd)?),
+            t if t == BPF_PROG_TYPE_SOCK_OPS as u32 => Program::SockOps(SockOps::from_fd(fd)?),
+            t if t == BPF_PROG_TYPE_SCHED_CLS as u32 => {
+                Program::SchedClassifier(SchedClassifier::from_fd(fd)?)
+            }
+            t if t == BPF_PROG_TYPE_CGROUP_SKB as u32 => {
+                Program::CgroupSkb(CgroupSkb::from_fd(fd)?)
+            }
+            t if t == BPF_PROG_TYPE_CGROUP_DEVICE as u32 => {
+                Program::CgroupDevice(CgroupDevice::from_fd(fd)?)
+            }
+            t if t == BPF_PROG_TYPE_LIRC_MODE2 as u32 => {
+                Program::LircMode2(LircMode2::from_fd(fd)?)
+            }
+            t if t == BPF_PROG_TYPE_PERF_EVENT as u32 => {
+                Program::PerfEvent(PerfEvent::from_fd(fd)?)
+            }
+            t if t == BPF_PROG_TYPE_RAW_TRACEPOINT as u32 => {
+                Program::RawTracePoint(RawTracePoint::from_fd(fd)?)
+            }
+            t if t == BPF_PROG_TYPE_LSM as u32 => Program::Lsm(Lsm::from_fd(fd)?),
+            prog_type => return Err(ProgramError::AmbiguousProgramType { prog_type }),
+        };
+
+        Ok(program)
+    }
+
+    /// Creates a program from the id of a program that is already loaded.
+    ///
+    /// This opens a new fd for the program with `BPF_PROG_GET_FD_BY_ID`,
+    /// which requires `CAP_SYS_ADMIN`, and then behaves like
+    /// [`Program::from_fd`]. The fd isn't closed when the program is dropped.
+    /// It can be retrieved with [`ProgramFd::fd`] and must be closed when no
+    /// longer needed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use aya::programs::{loaded_programs, Program};
+    ///
+    /// for info in loaded_programs() {
+    ///     let info = info?;
+    ///     if let Ok(Program::SchedClassifier(prog)) = Program::from_id(info.id()) {
+    ///         println!("tc program {:?}", info.name_as_str());
+    ///     }
+    /// }
+    /// # Ok::<(), aya::programs::ProgramError>(())
+    /// ```
+    pub fn from_id(id: u32) -> Result<Program, ProgramError> {
+        let fd = bpf_prog_get_fd_by_id(id).map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_prog_get_fd_by_id".to_owned(),
+            io_error,
+        })?;
+        match Program::from_fd(fd) {
+            Ok(program) => Ok(program),
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                Err(e)
+            }
+        }
+    }
+
     fn data(&self) -> &ProgramData {
         match self {
             Program::KProbe(p) => &p.data,
             Program::UProbe(p) => &p.data,
+            Program::Usdt(p) => &p.data,
             Program::TracePoint(p) => &p.data,
             Program::SocketFilter(p) => &p.data,
             Program::Xdp(p) => &p.data,
@@ -307,6 +623,9 @@ impl Program {
             Program::SockOps(p) => &p.data,
             Program::SchedClassifier(p) => &p.data,
             Program::CgroupSkb(p) => &p.data,
+            Program::CgroupSock(p) => &p.data,
+            Program::CgroupSockAddr(p) => &p.data,
+            Program::CgroupDevice(p) => &p.data,
             Program::LircMode2(p) => &p.data,
             Program::PerfEvent(p) => &p.data,
             Program::RawTracePoint(p) => &p.data,
@@ -315,6 +634,7 @@ impl Program {
             Program::FEntry(p) => &p.data,
             Program::FExit(p) => &p.data,
             Program::Extension(p) => &p.data,
+            Program::Iter(p) => &p.data,
         }
     }
 
@@ -322,6 +642,7 @@ impl Program {
         match self {
             Program::KProbe(p) => &mut p.data,
             Program::UProbe(p) => &mut p.data,
+            Program::Usdt(p) => &mut p.data,
             Program::TracePoint(p) => &mut p.data,
             Program::SocketFilter(p) => &mut p.data,
             Program::Xdp(p) => &mut p.data,
@@ -330,6 +651,9 @@ impl Program {
             Program::SockOps(p) => &mut p.data,
             Program::SchedClassifier(p) => &mut p.data,
             Program::CgroupSkb(p) => &mut p.data,
+            Program::CgroupSock(p) => &mut p.data,
+            Program::CgroupSockAddr(p) => &mut p.data,
+            Program::CgroupDevice(p) => &mut p.data,
             Program::LircMode2(p) => &mut p.data,
             Program::PerfEvent(p) => &mut p.data,
             Program::RawTracePoint(p) => &mut p.data,
@@ -338,6 +662,7 @@ impl Program {
             Program::FEntry(p) => &mut p.data,
             Program::FExit(p) => &mut p.data,
             Program::Extension(p) => &mut p.data,
+            Program::Iter(p) => &mut p.data,
         }
     }
 }
@@ -347,22 +672,89 @@ pub(crate) struct ProgramData {
     pub(crate) name: Option<String>,
     pub(crate) obj: obj::Program,
     pub(crate) fd: Option<RawFd>,
-    pub(crate) links: Vec<Rc<RefCell<dyn Link>>>,
+    pub(crate) links: Vec<Arc<Mutex<dyn Link + Send>>>,
     pub(crate) expected_attach_type: Option<bpf_attach_type>,
     pub(crate) attach_btf_obj_fd: Option<u32>,
     pub(crate) attach_btf_id: Option<u32>,
     pub(crate) attach_prog_fd: Option<RawFd>,
+    pub(crate) attach_prog_id: Option<u32>,
+    pub(crate) attach_func_name: Option<String>,
+    pub(crate) prog_ifindex: Option<u32>,
+    // extra BPF_F_* flags passed to BPF_PROG_LOAD
+    pub(crate) prog_flags: u32,
+    // the cookie of the last attachment made with a cookie
+    pub(crate) attach_cookie: Option<u64>,
     pub(crate) btf_fd: Option<RawFd>,
+    pub(crate) log_level: VerifierLogLevel,
+    pub(crate) log_buf_size: usize,
+    pub(crate) verifier_log: Option<String>,
+    pub(crate) token_fd: Option<RawFd>,
+    // overrides the kernel version of the object
+    pub(crate) kern_version: Option<u32>,
 }
 
 impl ProgramData {
+    /// Creates the data of the already loaded program `fd`, checking that
+    /// the program is of type `prog_type`.
+    pub(crate) fn from_fd(
+        fd: RawFd,
+        prog_type: bpf_prog_type,
+        section: fn(String) -> ProgramSection,
+    ) -> Result<ProgramData, ProgramError> {
+        let info = prog_info_by_fd(fd)?;
+        if info.type_ != prog_type as u32 {
+            return Err(ProgramError::UnexpectedProgramType);
+        }
+        let name = ProgramInfo(info)
+            .name_as_str()
+            .unwrap_or_default()
+            .to_owned();
+
+        Ok(ProgramData {
+            name: Some(name.clone()),
+            obj: obj::Program {
+                license: CString::default(),
+                kernel_version: KernelVersion::Any,
+                section: section(name.clone()),
+                function: Function {
+                    address: 0,
+                    name,
+                    section_index: object::SectionIndex(0),
+                    section_offset: 0,
+                    instructions: Vec::new(),
+                    func_info: Default::default(),
+                    line_info: Default::default(),
+                    func_info_rec_size: 0,
+                    line_info_rec_size: 0,
+                },
+            },
+            fd: Some(fd),
+            links: Vec::new(),
+            expected_attach_type: None,
+            attach_btf_obj_fd: None,
+            attach_btf_id: None,
+            attach_prog_fd: None,
+            attach_prog_id: None,
+            attach_func_name: None,
+            prog_ifindex: None,
+            prog_flags: 0,
+            attach_cookie: None,
+            btf_fd: None,
+            log_level: VerifierLogLevel::Off,
+            log_buf_size: 0,
+            verifier_log: None,
+            token_fd: None,
+            kern_version: None,
+        })
+    }
+
     fn fd_or_err(&self) -> Result<RawFd, ProgramError> {
         self.fd.ok_or(ProgramError::NotLoaded)
     }
 
-    pub fn link<T: Link + 'static>(&mut self, link: T) -> LinkRef {
-        let link: Rc<RefCell<dyn Link>> = Rc::new(RefCell::new(link));
-        self.links.push(Rc::clone(&link));
+    pub fn link<T: Link + Send + 'static>(&mut self, link: T) -> LinkRef {
+        let link: Arc<Mutex<dyn Link + Send>> = Arc::new(Mutex::new(link));
+        self.links.push(Arc::clone(&link));
         LinkRef::new(link)
     }
 
@@ -384,11 +776,26 @@ impl ProgramData {
     }
 }
 
+fn prog_info_by_fd(fd: RawFd) -> Result<bpf_prog_info, ProgramError> {
+    bpf_obj_get_info_by_fd(fd).map_err(|io_error| ProgramError::SyscallError {
+        call: "bpf_obj_get_info_by_fd".to_owned(),
+        io_error,
+    })
+}
+
 fn load_program(prog_type: bpf_prog_type, data: &mut ProgramData) -> Result<(), ProgramError> {
     let ProgramData { obj, fd, .. } = data;
     if fd.is_some() {
         return Err(ProgramError::AlreadyLoaded);
     }
+    if data.prog_ifindex.is_some()
+        && !matches!(
+            prog_type,
+            bpf_prog_type::BPF_PROG_TYPE_XDP | bpf_prog_type::BPF_PROG_TYPE_SCHED_CLS
+        )
+    {
+        return Err(ProgramError::OffloadNotSupported { prog_type });
+    }
     let crate::obj::Program {
         function:
             Function {
@@ -404,15 +811,9 @@ fn load_program(prog_type: bpf_prog_type, data: &mut ProgramData) -> Result<(),
         ..
     } = obj;
 
-    let target_kernel_version = match *kernel_version {
-        KernelVersion::Any => {
-            let (major, minor, patch) = crate::sys::kernel_version().unwrap();
-            (major << 16) + (minor << 8) + patch
-        }
-        _ => (*kernel_version).into(),
-    };
+    let target_kernel_version = data.kern_version.unwrap_or_else(|| kernel_version.as_u32());
 
-    let mut logger = VerifierLog::new();
+    let mut logger = VerifierLog::with_level(data.log_level.bits(), data.log_buf_size);
 
     let prog_name = if let Some(name) = &data.name {
         let mut name = name.clone();
@@ -426,7 +827,7 @@ fn load_program(prog_type: bpf_prog_type, data: &mut ProgramData) -> Result<(),
         None
     };
 
-    let attr = BpfLoadProgramAttrs {
+    let mut attr = BpfLoadProgramAttrs {
         name: prog_name,
         ty: prog_type,
         insns: instructions,
@@ -441,15 +842,44 @@ fn load_program(prog_type: bpf_prog_type, data: &mut ProgramData) -> Result<(),
         func_info: func_info.clone(),
         line_info_rec_size: *line_info_rec_size,
         line_info: line_info.clone(),
+        token_fd: data.token_fd,
+        prog_ifindex: data.prog_ifindex,
+        prog_flags: data.prog_flags,
     };
-    let ret = retry_with_verifier_logs(10, &mut logger, |logger| bpf_load_program(&attr, logger));
+    let mut ret =
+        retry_with_verifier_logs(10, &mut logger, |logger| bpf_load_program(&attr, logger));
+    // Before 5.0 kprobes only load when kern_version is the version of the
+    // running kernel. Objects built for another kernel are retried with the
+    // running version, unless the version was overridden.
+    if let Err((_, io_error)) = &ret {
+        let current = KernelVersion::Any.as_u32();
+        if io_error.raw_os_error() == Some(EINVAL)
+            && prog_type == bpf_prog_type::BPF_PROG_TYPE_KPROBE
+            && data.kern_version.is_none()
+            && attr.kernel_version != current
+            && crate::sys::kernel_version().unwrap() < (5, 0, 0)
+        {
+            attr.kernel_version = current;
+            ret =
+                retry_with_verifier_logs(10, &mut logger, |logger| bpf_load_program(&attr, logger));
+        }
+    }
 
     match ret {
         Ok(prog_fd) => {
             *fd = Some(prog_fd as RawFd);
+            if data.log_level != VerifierLogLevel::Off {
+                logger.truncate();
+                data.verifier_log = logger.as_c_str().map(|s| s.to_string_lossy().to_string());
+            }
             Ok(())
         }
         Err((_, io_error)) => {
+            if io_error.raw_os_error() == Some(EPERM) {
+                if let Some(err) = check_memlock_rlimit() {
+                    return Err(err);
+                }
+            }
             logger.truncate();
             return Err(ProgramError::LoadError {
                 io_error,
@@ -462,6 +892,27 @@ fn load_program(prog_type: bpf_prog_type, data: &mut ProgramData) -> Result<(),
     }
 }
 
+// the limit below which a BPF_PROG_LOAD failing with EPERM is reported as
+// ProgramError::InsufficientMemlock
+const MIN_MEMLOCK_RLIMIT: u64 = 1024 * 1024;
+
+// Before 5.11 the memory used by programs is charged against RLIMIT_MEMLOCK,
+// and BPF_PROG_LOAD fails with EPERM when the limit is exceeded. Newer
+// kernels use memcg accounting instead, so EPERM means missing capabilities.
+fn check_memlock_rlimit() -> Option<ProgramError> {
+    match crate::sys::kernel_version() {
+        Ok(k_ver) if k_ver < (5, 11, 0) => {}
+        _ => return None,
+    }
+    match memlock_rlimit() {
+        Ok(current) if current < MIN_MEMLOCK_RLIMIT => Some(ProgramError::InsufficientMemlock {
+            current,
+            needed: MIN_MEMLOCK_RLIMIT,
+        }),
+        _ => None,
+    }
+}
+
 pub(crate) fn query<T: AsRawFd>(
     target_fd: T,
     attach_type: bpf_attach_type,
@@ -500,38 +951,277 @@ pub(crate) fn query<T: AsRawFd>(
     }
 }
 
+/// Returns information about the programs attached to `target_fd` with the
+/// given attach type.
+pub(crate) fn query_attached_programs<T: AsRawFd>(
+    target_fd: T,
+    attach_type: bpf_attach_type,
+) -> Result<Vec<ProgramInfo>, ProgramError> {
+    query(target_fd, attach_type, 0, &mut None)?
+        .into_iter()
+        .map(ProgramInfo::from_id)
+        .collect()
+}
+
 /// Detach an attached program
 pub trait Link: std::fmt::Debug {
     /// detaches an attached program
     fn detach(&mut self) -> Result<(), ProgramError>;
+
+    /// Pins the link to the provided path.
+    ///
+    /// Only links backed by a kernel `bpf_link` can be pinned, other links
+    /// return [`ProgramError::LinkNotPinnable`].
+    fn pin(&mut self, _path: &Path) -> Result<(), ProgramError> {
+        Err(ProgramError::LinkNotPinnable)
+    }
 }
 
 /// The return type of `program.attach(...)`.
 ///
 /// [`LinkRef`] implements the [`Link`] trait and can be used to detach a
-/// program.
+/// program. It can be sent to and shared between threads, for example to
+/// detach the program from a task spawned on a multi-threaded runtime.
 #[derive(Debug)]
 pub struct LinkRef {
-    inner: Rc<RefCell<dyn Link>>,
+    inner: Arc<Mutex<dyn Link + Send>>,
 }
 
 impl LinkRef {
-    fn new(link: Rc<RefCell<dyn Link>>) -> LinkRef {
+    fn new(link: Arc<Mutex<dyn Link + Send>>) -> LinkRef {
         LinkRef { inner: link }
     }
 }
 
 impl Link for LinkRef {
     fn detach(&mut self) -> Result<(), ProgramError> {
-        self.inner.borrow_mut().detach()
+        self.inner.lock().detach()
+    }
+
+    fn pin(&mut self, path: &Path) -> Result<(), ProgramError> {
+        self.inner.lock().pin(path)
+    }
+}
+
+/// A [`LinkRef`] bundled with a value that must outlive it.
+///
+/// [`OwnedLink`] ties the lifetime of a link to the lifetime of `program`,
+/// for example a shared handle to the [`Bpf`](crate::Bpf) instance or the
+/// [`Program`] the link was created from. The link is always dropped before
+/// the value.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::{convert::TryInto, rc::Rc, cell::RefCell};
+/// use aya::{Bpf, programs::{OwnedLink, Xdp, XdpFlags}};
+///
+/// let bpf = Rc::new(RefCell::new(Bpf::load_file("file.o")?));
+/// let link = {
+///     let mut bpf_ref = bpf.borrow_mut();
+///     let prog: &mut Xdp = bpf_ref.program_mut("xdp").unwrap().try_into()?;
+///     prog.load()?;
+///     prog.attach("eth0", XdpFlags::default())?
+/// };
+/// let link = OwnedLink::new(Rc::clone(&bpf), link);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct OwnedLink<P> {
+    link: LinkRef,
+    program: P,
+}
+
+impl<P> OwnedLink<P> {
+    /// Creates a new link that keeps `program` alive.
+    pub fn new(program: P, link: LinkRef) -> OwnedLink<P> {
+        OwnedLink { link, program }
+    }
+
+    /// Returns the value kept alive by the link.
+    pub fn program(&self) -> &P {
+        &self.program
+    }
+}
+
+impl<P> Deref for OwnedLink<P> {
+    type Target = LinkRef;
+
+    fn deref(&self) -> &LinkRef {
+        &self.link
+    }
+}
+
+impl<P> DerefMut for OwnedLink<P> {
+    fn deref_mut(&mut self) -> &mut LinkRef {
+        &mut self.link
+    }
+}
+
+impl<P: std::fmt::Debug> Link for OwnedLink<P> {
+    fn detach(&mut self) -> Result<(), ProgramError> {
+        self.link.detach()
+    }
+
+    fn pin(&mut self, path: &Path) -> Result<(), ProgramError> {
+        self.link.pin(path)
+    }
+}
+
+/// A collection of the links returned by `program.attach(...)`.
+///
+/// [`LinkRef`] values don't detach their program when dropped, so a link
+/// that isn't stored stays attached until the program is unloaded.
+/// [`ProgramLinks`] keeps the links of a program attached to several hooks
+/// together and detaches all of them when it's dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut bpf = aya::Bpf::load(&[])?;
+/// use std::convert::TryInto;
+/// use aya::programs::{KProbe, ProgramLinks};
+///
+/// let program: &mut KProbe = bpf.program_mut("trace_io").unwrap().try_into()?;
+/// program.load()?;
+/// let mut links = ProgramLinks::new();
+/// for fn_name in &["vfs_read", "vfs_write"] {
+///     links.push(program.attach(fn_name, 0)?);
+/// }
+/// for result in links.detach_all() {
+///     result?;
+/// }
+/// # Ok::<(), aya::BpfError>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct ProgramLinks {
+    links: Vec<LinkRef>,
+}
+
+impl ProgramLinks {
+    /// Creates an empty collection.
+    pub fn new() -> ProgramLinks {
+        ProgramLinks { links: Vec::new() }
+    }
+
+    /// Adds a link to the collection.
+    pub fn push(&mut self, link: LinkRef) {
+        self.links.push(link);
+    }
+
+    /// Returns the number of links in the collection.
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    /// Returns `true` if the collection contains no links.
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// An iterator visiting the links in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &LinkRef> {
+        self.links.iter()
+    }
+
+    /// Detaches and removes all the links.
+    ///
+    /// The returned vector has one entry per link, in the order the links
+    /// were added, so that the links that failed to detach can be told
+    /// apart.
+    pub fn detach_all(&mut self) -> Vec<Result<(), ProgramError>> {
+        self.links.drain(..).map(|mut link| link.detach()).collect()
+    }
+}
+
+impl Extend<LinkRef> for ProgramLinks {
+    fn extend<I: IntoIterator<Item = LinkRef>>(&mut self, iter: I) {
+        self.links.extend(iter)
     }
 }
 
+impl Drop for ProgramLinks {
+    fn drop(&mut self) {
+        let _ = self.detach_all();
+    }
+}
+
+/// A link backed by a kernel `bpf_link`.
+///
+/// Links can be pinned to bpffs with [`Link::pin`]. A pinned link keeps its
+/// program attached after the process that created it exits, until the
+/// pinned file is removed. This can be used to reload a daemon without
+/// detaching its programs: the old binary pins its links before `exec`-ing
+/// the new binary, which reopens them with [`FdLink::from_pinned_path`] and
+/// then removes the pinned files.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::{fs, path::Path};
+/// use aya::programs::FdLink;
+///
+/// let path = Path::new("/sys/fs/bpf/my_link");
+/// let link = FdLink::from_pinned_path(path)?;
+/// fs::remove_file(path)?;
+/// // the program is detached once `link` is dropped
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
 #[derive(Debug)]
-pub(crate) struct FdLink {
+pub struct FdLink {
     fd: Option<RawFd>,
 }
 
+impl FdLink {
+    /// Opens a link pinned at the provided path.
+    pub fn from_pinned_path<P: AsRef<Path>>(path: P) -> Result<FdLink, ProgramError> {
+        let path_string =
+            CString::new(path.as_ref().to_string_lossy().into_owned()).map_err(|e| {
+                ProgramError::InvalidPinPath {
+                    error: e.to_string(),
+                }
+            })?;
+        let fd = bpf_get_object(&path_string).map_err(|(_code, io_error)| {
+            ProgramError::SyscallError {
+                call: "BPF_OBJ_GET".to_string(),
+                io_error,
+            }
+        })? as RawFd;
+
+        Ok(FdLink { fd: Some(fd) })
+    }
+
+    /// Returns information about the link, like its id, the id of the
+    /// attached program and where the program is attached.
+    pub fn info(&self) -> Result<BpfLinkInfo, ProgramError> {
+        let fd = self.fd.ok_or(ProgramError::AlreadyDetached)?;
+        link_info_by_fd(fd)
+    }
+}
+
+fn link_info_by_fd(fd: RawFd) -> Result<BpfLinkInfo, ProgramError> {
+    let get_info = |tp_name: &mut [u8]| {
+        bpf_link_get_info_by_fd(fd, tp_name).map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            io_error,
+        })
+    };
+
+    let info = get_info(&mut [])?;
+    let mut tp_name = Vec::new();
+    if info.type_ == bpf_link_type::BPF_LINK_TYPE_RAW_TRACEPOINT as u32 {
+        // the first call only tells us how long the tracepoint name is
+        let len = unsafe { info.__bindgen_anon_1.raw_tracepoint.tp_name_len };
+        if len > 0 {
+            tp_name.resize(len as usize, 0);
+            let info = get_info(&mut tp_name)?;
+            return Ok(BpfLinkInfo::new(&info, &tp_name));
+        }
+    }
+
+    Ok(BpfLinkInfo::new(&info, &tp_name))
+}
+
 impl Link for FdLink {
     fn detach(&mut self) -> Result<(), ProgramError> {
         if let Some(fd) = self.fd.take() {
@@ -541,6 +1231,22 @@ impl Link for FdLink {
             Err(ProgramError::AlreadyDetached)
         }
     }
+
+    fn pin(&mut self, path: &Path) -> Result<(), ProgramError> {
+        let fd = self.fd.ok_or(ProgramError::AlreadyDetached)?;
+        let path_string = CString::new(path.to_string_lossy().into_owned()).map_err(|e| {
+            ProgramError::InvalidPinPath {
+                error: e.to_string(),
+            }
+        })?;
+        bpf_pin_object(fd, &path_string).map_err(|(_code, io_error)| {
+            ProgramError::SyscallError {
+                call: "BPF_OBJ_PIN".to_string(),
+                io_error,
+            }
+        })?;
+        Ok(())
+    }
 }
 
 impl Drop for FdLink {
@@ -550,7 +1256,7 @@ impl Drop for FdLink {
 }
 
 #[derive(Debug)]
-struct ProgAttachLink {
+pub(crate) struct ProgAttachLink {
     prog_fd: Option<RawFd>,
     target_fd: Option<RawFd>,
     attach_type: bpf_attach_type,
@@ -622,6 +1328,7 @@ macro_rules! impl_program_fd {
 impl_program_fd!(
     KProbe,
     UProbe,
+    Usdt,
     TracePoint,
     SocketFilter,
     Xdp,
@@ -629,6 +1336,9 @@ impl_program_fd!(
     SkSkb,
     SchedClassifier,
     CgroupSkb,
+    CgroupSock,
+    CgroupSockAddr,
+    CgroupDevice,
     LircMode2,
     PerfEvent,
     Lsm,
@@ -637,6 +1347,7 @@ impl_program_fd!(
     FEntry,
     FExit,
     Extension,
+    Iter,
 );
 
 macro_rules! impl_try_from_program {
@@ -670,6 +1381,7 @@ macro_rules! impl_try_from_program {
 impl_try_from_program!(
     KProbe,
     UProbe,
+    Usdt,
     TracePoint,
     SocketFilter,
     Xdp,
@@ -678,6 +1390,9 @@ impl_try_from_program!(
     SockOps,
     SchedClassifier,
     CgroupSkb,
+    CgroupSock,
+    CgroupSockAddr,
+    CgroupDevice,
     LircMode2,
     PerfEvent,
     Lsm,
@@ -686,11 +1401,66 @@ impl_try_from_program!(
     FEntry,
     FExit,
     Extension,
+    Iter,
+);
+
+macro_rules! impl_from_fd {
+    ($($ty:ident => $prog_type:ident),+ $(,)?) => {
+        $(
+            impl $ty {
+                /// Creates a program from the fd of a program that is already
+                /// loaded.
+                ///
+                /// Returns [`ProgramError::UnexpectedProgramType`] if `fd`
+                /// isn't a program of the right type. See also
+                /// [`Program::from_fd`].
+                pub fn from_fd(fd: RawFd) -> Result<$ty, ProgramError> {
+                    let data = ProgramData::from_fd(
+                        fd,
+                        bpf_prog_type::$prog_type,
+                        |name| ProgramSection::$ty { name },
+                    )?;
+                    Ok($ty { data })
+                }
+            }
+        )+
+    }
+}
+
+impl_from_fd!(
+    Usdt => BPF_PROG_TYPE_KPROBE,
+    TracePoint => BPF_PROG_TYPE_TRACEPOINT,
+    SocketFilter => BPF_PROG_TYPE_SOCKET_FILTER,
+    SkMsg => BPF_PROG_TYPE_SK_MSG,
+    SockOps => BPF_PROG_TYPE_SOCK_OPS,
+    CgroupDevice => BPF_PROG_TYPE_CGROUP_DEVICE,
+    LircMode2 => BPF_PROG_TYPE_LIRC_MODE2,
+    PerfEvent => BPF_PROG_TYPE_PERF_EVENT,
+    RawTracePoint => BPF_PROG_TYPE_RAW_TRACEPOINT,
+    Lsm => BPF_PROG_TYPE_LSM,
+    BtfTracePoint => BPF_PROG_TYPE_TRACING,
+    FEntry => BPF_PROG_TYPE_TRACING,
+    Iter => BPF_PROG_TYPE_TRACING,
 );
 
 /// Provides information about a loaded program, like name, id and statistics
 pub struct ProgramInfo(bpf_prog_info);
 
+/// Run time statistics of a loaded program.
+///
+/// The counters only grow while the kernel collects statistics, see
+/// [`BpfLoader::enable_stats`](crate::BpfLoader::enable_stats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramStats {
+    /// The number of times the program ran.
+    pub run_count: u64,
+    /// The total time spent running the program, in nanoseconds.
+    pub run_time_ns: u64,
+    /// The number of times the program didn't run because it was already
+    /// running on the same CPU.
+    pub recursion_misses: u64,
+}
+
 impl ProgramInfo {
     /// The name of the program as was provided when it was load. This is limited to 16 bytes
     pub fn name(&self) -> &[u8] {
@@ -717,6 +1487,17 @@ impl ProgramInfo {
         self.0.id
     }
 
+    /// The run time statistics of the program.
+    ///
+    /// See [`Program::stats`].
+    pub fn stats(&self) -> ProgramStats {
+        ProgramStats {
+            run_count: self.0.run_cnt,
+            run_time_ns: self.0.run_time_ns,
+            recursion_misses: self.0.recursion_misses,
+        }
+    }
+
     /// Returns the fd associated with the program.
     ///
     /// The returned fd must be closed when no longer needed.
@@ -729,6 +1510,25 @@ impl ProgramInfo {
         Ok(fd as RawFd)
     }
 
+    /// Returns information about the loaded program with the given id.
+    pub fn from_id(id: u32) -> Result<ProgramInfo, ProgramError> {
+        let fd = bpf_prog_get_fd_by_id(id).map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_prog_get_fd_by_id".to_owned(),
+            io_error,
+        })?;
+
+        let info = bpf_obj_get_info_by_fd(fd);
+        unsafe {
+            libc::close(fd);
+        }
+        let info = info.map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            io_error,
+        })?;
+
+        Ok(ProgramInfo(info))
+    }
+
     /// Loads a program from a pinned path in bpffs.
     pub fn from_pinned<P: AsRef<Path>>(path: P) -> Result<ProgramInfo, ProgramError> {
         let path_string = match CString::new(path.as_ref().to_str().unwrap()) {
@@ -757,3 +1557,918 @@ impl ProgramInfo {
         Ok(ProgramInfo(info))
     }
 }
+
+/// Returns information about all the links currently loaded in the kernel.
+///
+/// The links are enumerated by id, including the links created by other
+/// processes. Links detached while the enumeration is in progress are
+/// skipped. Enumerating links requires the `CAP_SYS_ADMIN` capability.
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 5.8.
+///
+/// # Examples
+///
+/// ```no_run
+/// use aya::programs::{loaded_links, BpfLinkKind};
+///
+/// for info in loaded_links() {
+///     let info = info?;
+///     if let BpfLinkKind::Xdp { ifindex } = info.kind() {
+///         let prog = info.attached_program()?;
+///         println!("{:?} attached to interface {}", prog.name_as_str(), ifindex);
+///     }
+/// }
+/// # Ok::<(), aya::programs::ProgramError>(())
+/// ```
+pub fn loaded_links() -> impl Iterator<Item = Result<BpfLinkInfo, ProgramError>> {
+    ids(bpf_link_get_next_id).filter_map(|id| {
+        let id = match id {
+            Ok(id) => id,
+            Err(io_error) => {
+                return Some(Err(ProgramError::SyscallError {
+                    call: "bpf_link_get_next_id".to_owned(),
+                    io_error,
+                }))
+            }
+        };
+        let fd = match bpf_link_get_fd_by_id(id) {
+            Ok(fd) => fd,
+            // the link was released after its id was returned
+            Err(io_error) if io_error.raw_os_error() == Some(ENOENT) => return None,
+            Err(io_error) => {
+                return Some(Err(ProgramError::SyscallError {
+                    call: "bpf_link_get_fd_by_id".to_owned(),
+                    io_error,
+                }))
+            }
+        };
+        let info = link_info_by_fd(fd);
+        unsafe { close(fd) };
+        Some(info)
+    })
+}
+
+/// Classifies a program from the name of its ELF section.
+///
+/// Returns the program type, the attach type the program is expected to be
+/// loaded with if the section implies one, and the attach target encoded in
+/// the section. Returns `None` if the section isn't a program section known
+/// to aya.
+///
+/// # Examples
+///
+/// ```
+/// use aya::programs::{bpf_attach_type, bpf_prog_type, section_program_type};
+///
+/// let (prog_type, attach_type, target) = section_program_type("kprobe/try_to_wake_up").unwrap();
+/// assert_eq!(prog_type, bpf_prog_type::BPF_PROG_TYPE_KPROBE);
+/// assert_eq!(attach_type, None);
+/// assert_eq!(target, "try_to_wake_up");
+///
+/// let (prog_type, attach_type, _) = section_program_type("cgroup/connect4/restrict").unwrap();
+/// assert_eq!(prog_type, bpf_prog_type::BPF_PROG_TYPE_CGROUP_SOCK_ADDR);
+/// assert_eq!(attach_type, Some(bpf_attach_type::BPF_CGROUP_INET4_CONNECT));
+///
+/// assert!(section_program_type("license").is_none());
+/// ```
+pub fn section_program_type(
+    section_name: &str,
+) -> Option<(bpf_prog_type, Option<bpf_attach_type>, String)> {
+    obj::Object::section_program_type(section_name)
+}
+
+/// Returns information about all the programs currently loaded in the kernel.
+///
+/// The programs are enumerated by id, including the programs loaded by other
+/// processes. Programs that are unloaded while iterating are skipped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use aya::programs::loaded_programs;
+///
+/// for info in loaded_programs() {
+///     let info = info?;
+///     println!("{}: {:?}", info.id(), info.name_as_str());
+/// }
+/// # Ok::<(), aya::programs::ProgramError>(())
+/// ```
+pub fn loaded_programs() -> impl Iterator<Item = Result<ProgramInfo, ProgramError>> {
+    ids(bpf_prog_get_next_id).filter_map(|id| {
+        let info = id
+            .map_err(|io_error| ProgramError::SyscallError {
+                call: "bpf_prog_get_next_id".to_owned(),
+                io_error,
+            })
+            .and_then(ProgramInfo::from_id);
+        match info {
+            // the program was unloaded after its id was returned
+            Err(ProgramError::SyscallError { io_error, .. })
+                if io_error.raw_os_error() == Some(ENOENT) =>
+            {
+                None
+            }
+            info => Some(info),
+        }
+    })
+}
+
+/// Provides information about a link, like its id and the id of the program
+/// attached through it.
+///
+/// See [`FdLink::info`] and [`loaded_links`].
+#[derive(Debug, Clone)]
+pub struct BpfLinkInfo {
+    id: u32,
+    prog_id: u32,
+    kind: BpfLinkKind,
+}
+
+/// The type of a link, along with the information specific to that type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BpfLinkKind {
+    /// A link to a raw tracepoint.
+    RawTracePoint {
+        /// The name of the tracepoint.
+        tp_name: String,
+    },
+    /// A link created for `fentry`, `fexit`, `lsm` or `freplace` programs.
+    Tracing {
+        /// The attach type of the program.
+        attach_type: u32,
+        /// The id of the program for `freplace` links, the id of the BTF
+        /// object containing the target otherwise.
+        target_obj_id: u32,
+        /// The BTF type id of the target.
+        target_btf_id: u32,
+    },
+    /// A link to a cgroup.
+    Cgroup {
+        /// The id of the cgroup.
+        cgroup_id: u64,
+        /// The attach type of the program.
+        attach_type: u32,
+    },
+    /// A link to a BPF iterator.
+    Iter,
+    /// A link to a network namespace.
+    NetNs {
+        /// The inode number of the network namespace.
+        netns_ino: u32,
+        /// The attach type of the program.
+        attach_type: u32,
+    },
+    /// A link to a network interface.
+    Xdp {
+        /// The index of the interface.
+        ifindex: u32,
+    },
+    /// A link to a perf event.
+    PerfEvent,
+    /// A link type not known to this version of aya.
+    Unknown {
+        /// The link type as reported by the kernel.
+        link_type: u32,
+    },
+}
+
+impl BpfLinkInfo {
+    fn new(info: &bpf_link_info, tp_name: &[u8]) -> BpfLinkInfo {
+        use bpf_link_type::*;
+
+        let data = &info.__bindgen_anon_1;
+        let kind = unsafe {
+            match info.type_ {
+                t if t == BPF_LINK_TYPE_RAW_TRACEPOINT as u32 => {
+                    let len = tp_name
+                        .iter()
+                        .position(|b| *b == 0)
+                        .unwrap_or(tp_name.len());
+                    BpfLinkKind::RawTracePoint {
+                        tp_name: String::from_utf8_lossy(&tp_name[..len]).into_owned(),
+                    }
+                }
+                t if t == BPF_LINK_TYPE_TRACING as u32 => BpfLinkKind::Tracing {
+                    attach_type: data.tracing.attach_type,
+                    target_obj_id: data.tracing.target_obj_id,
+                    target_btf_id: data.tracing.target_btf_id,
+                },
+                t if t == BPF_LINK_TYPE_CGROUP as u32 => BpfLinkKind::Cgroup {
+                    cgroup_id: data.cgroup.cgroup_id,
+                    attach_type: data.cgroup.attach_type,
+                },
+                t if t == BPF_LINK_TYPE_ITER as u32 => BpfLinkKind::Iter,
+                t if t == BPF_LINK_TYPE_NETNS as u32 => BpfLinkKind::NetNs {
+                    netns_ino: data.netns.netns_ino,
+                    attach_type: data.netns.attach_type,
+                },
+                t if t == BPF_LINK_TYPE_XDP as u32 => BpfLinkKind::Xdp {
+                    ifindex: data.xdp.ifindex,
+                },
+                t if t == BPF_LINK_TYPE_PERF_EVENT as u32 => BpfLinkKind::PerfEvent,
+                link_type => BpfLinkKind::Unknown { link_type },
+            }
+        };
+
+        BpfLinkInfo {
+            id: info.id,
+            prog_id: info.prog_id,
+            kind,
+        }
+    }
+
+    /// The id of the link. Each link has a unique id.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The id of the program attached through the link.
+    pub fn prog_id(&self) -> u32 {
+        self.prog_id
+    }
+
+    /// The type of the link and the information specific to that type.
+    pub fn kind(&self) -> &BpfLinkKind {
+        &self.kind
+    }
+
+    /// Returns information about the program attached through the link.
+    pub fn attached_program(&self) -> Result<ProgramInfo, ProgramError> {
+        ProgramInfo::from_id(self.prog_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, mem, os::unix::io::AsRawFd};
+
+    use libc::{EFAULT, EINVAL};
+
+    use crate::{
+        generated::{bpf_attr, bpf_cmd, bpf_map_info},
+        sys::{bpf_link_create_iter, override_kernel_version, override_syscall, Syscall},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_link_info_xdp() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_link_info) };
+                info.type_ = bpf_link_type::BPF_LINK_TYPE_XDP as u32;
+                info.id = 3;
+                info.prog_id = 7;
+                info.__bindgen_anon_1.xdp.ifindex = 2;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let link = FdLink { fd: Some(42) };
+        let info = link.info().unwrap();
+        std::mem::forget(link);
+
+        assert_eq!(info.id(), 3);
+        assert_eq!(info.prog_id(), 7);
+        assert_eq!(info.kind(), &BpfLinkKind::Xdp { ifindex: 2 });
+    }
+
+    #[test]
+    fn test_loaded_objects() {
+        // 3 programs, maps and links, of which the second was released before
+        // its fd could be retrieved
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd:
+                    bpf_cmd::BPF_PROG_GET_NEXT_ID
+                    | bpf_cmd::BPF_MAP_GET_NEXT_ID
+                    | bpf_cmd::BPF_LINK_GET_NEXT_ID,
+                attr,
+            } => {
+                let start_id = unsafe { attr.__bindgen_anon_6.__bindgen_anon_1.start_id };
+                if start_id >= 3 {
+                    return Err((-1, io::Error::from_raw_os_error(ENOENT)));
+                }
+                // the kernel writes the next id into the attr
+                let attr = attr as *const bpf_attr as *mut bpf_attr;
+                unsafe { (*attr).__bindgen_anon_6.next_id = start_id + 1 };
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd:
+                    bpf_cmd::BPF_PROG_GET_FD_BY_ID
+                    | bpf_cmd::BPF_MAP_GET_FD_BY_ID
+                    | bpf_cmd::BPF_LINK_GET_FD_BY_ID,
+                attr,
+            } => match unsafe { attr.__bindgen_anon_6.__bindgen_anon_1.prog_id } {
+                2 => Err((-1, io::Error::from_raw_os_error(ENOENT))),
+                // fds that are surely not open, so closing them is harmless
+                id => Ok(10_000 + id as i64),
+            },
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let id = unsafe { attr.info.bpf_fd } - 10_000;
+                let info = unsafe { attr.info.info };
+                match unsafe { attr.info.info_len } as usize {
+                    len if len == mem::size_of::<bpf_link_info>() => {
+                        let info = unsafe { &mut *(info as *mut bpf_link_info) };
+                        info.type_ = bpf_link_type::BPF_LINK_TYPE_CGROUP as u32;
+                        info.id = id;
+                    }
+                    len if len == mem::size_of::<bpf_map_info>() => {
+                        unsafe { (*(info as *mut bpf_map_info)).id = id };
+                    }
+                    _ => unsafe { (*(info as *mut bpf_prog_info)).id = id },
+                }
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let ids = loaded_programs()
+            .map(|info| info.unwrap().id())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 3]);
+        let ids = crate::maps::loaded_maps()
+            .map(|info| info.unwrap().id())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 3]);
+        let ids = loaded_links()
+            .map(|info| info.unwrap().id())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_link_info_raw_tracepoint() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_link_info) };
+                info.type_ = bpf_link_type::BPF_LINK_TYPE_RAW_TRACEPOINT as u32;
+                let tp = unsafe { &mut info.__bindgen_anon_1.raw_tracepoint };
+                let name = b"sched_switch\0";
+                if tp.tp_name != 0 {
+                    assert_eq!(tp.tp_name_len as usize, name.len());
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(
+                            name.as_ptr(),
+                            tp.tp_name as *mut u8,
+                            name.len(),
+                        )
+                    };
+                }
+                tp.tp_name_len = name.len() as u32;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let link = FdLink { fd: Some(42) };
+        let info = link.info().unwrap();
+        std::mem::forget(link);
+
+        assert_eq!(
+            info.kind(),
+            &BpfLinkKind::RawTracePoint {
+                tp_name: "sched_switch".to_owned()
+            }
+        );
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_programs_are_send_sync() {
+        assert_send_sync::<crate::Bpf>();
+        assert_send_sync::<Program>();
+        assert_send_sync::<ProgramInfo>();
+        assert_send_sync::<LinkRef>();
+        assert_send_sync::<FdLink>();
+        assert_send_sync::<KProbe>();
+        assert_send_sync::<UProbe>();
+        assert_send_sync::<Usdt>();
+        assert_send_sync::<TracePoint>();
+        assert_send_sync::<SocketFilter>();
+        assert_send_sync::<Xdp>();
+        assert_send_sync::<SkMsg>();
+        assert_send_sync::<SkSkb>();
+        assert_send_sync::<SockOps>();
+        assert_send_sync::<SchedClassifier>();
+        assert_send_sync::<CgroupSkb>();
+        assert_send_sync::<CgroupSock>();
+        assert_send_sync::<CgroupSockAddr>();
+        assert_send_sync::<CgroupDevice>();
+        assert_send_sync::<LircMode2>();
+        assert_send_sync::<PerfEvent>();
+        assert_send_sync::<RawTracePoint>();
+        assert_send_sync::<Lsm>();
+        assert_send_sync::<BtfTracePoint>();
+        assert_send_sync::<FEntry>();
+        assert_send_sync::<FExit>();
+        assert_send_sync::<Extension>();
+        assert_send_sync::<Iter>();
+    }
+
+    #[derive(Debug)]
+    struct TestLink(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+    impl Link for TestLink {
+        fn detach(&mut self) -> Result<(), ProgramError> {
+            self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_link_ref_across_threads() {
+        let detached = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let mut link = LinkRef::new(Arc::new(Mutex::new(TestLink(Arc::clone(&detached)))));
+
+        std::thread::spawn(move || link.detach().unwrap())
+            .join()
+            .unwrap();
+        assert!(detached.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_from_fd() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = match unsafe { attr.info.bpf_fd } {
+                    1 => bpf_prog_type::BPF_PROG_TYPE_SCHED_CLS,
+                    _ => bpf_prog_type::BPF_PROG_TYPE_KPROBE,
+                } as u32;
+                for (dst, src) in info.name.iter_mut().zip(b"my_prog") {
+                    *dst = *src as _;
+                }
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        match Program::from_fd(1) {
+            Ok(Program::SchedClassifier(prog)) => {
+                assert_eq!(prog.fd(), Some(1));
+                assert_eq!(prog.data.name.as_deref(), Some("my_prog"));
+            }
+            _ => panic!("expected a SchedClassifier program"),
+        }
+        assert!(matches!(
+            Program::from_fd(2),
+            Err(ProgramError::AmbiguousProgramType { .. })
+        ));
+        assert!(matches!(
+            SchedClassifier::from_fd(2),
+            Err(ProgramError::UnexpectedProgramType)
+        ));
+        assert!(KProbe::from_fd(2, ProbeKind::KRetProbe).is_ok());
+        assert!(matches!(
+            KProbe::from_fd(2, ProbeKind::UProbe),
+            Err(ProgramError::UnexpectedProgramType)
+        ));
+    }
+
+    #[test]
+    fn test_load_prog_ifindex() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = bpf_prog_type::BPF_PROG_TYPE_XDP as u32;
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_LOAD,
+                attr,
+            } if unsafe { attr.__bindgen_anon_3.prog_ifindex } == 3 => Ok(10_000),
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut xdp = Xdp::from_fd(1, XdpAttachType::Interface).unwrap();
+        xdp.data.fd = None;
+        xdp.set_prog_ifindex(3);
+        assert!(xdp.load().is_ok());
+        assert_eq!(xdp.fd(), Some(10_000));
+
+        xdp.data.fd = None;
+        assert!(matches!(
+            load_program(bpf_prog_type::BPF_PROG_TYPE_KPROBE, &mut xdp.data),
+            Err(ProgramError::OffloadNotSupported { .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_expected_attach_type() {
+        use crate::generated::bpf_attach_type::*;
+        use bpf_prog_type::*;
+
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = match unsafe { attr.info.bpf_fd } {
+                    1 => BPF_PROG_TYPE_CGROUP_SKB,
+                    _ => BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+                } as u32;
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_LOAD,
+                attr,
+            } => {
+                let attr = unsafe { &attr.__bindgen_anon_3 };
+                match (attr.prog_type, attr.expected_attach_type) {
+                    (t, a)
+                        if t == BPF_PROG_TYPE_CGROUP_SKB as u32
+                            && a == BPF_CGROUP_INET_EGRESS as u32 =>
+                    {
+                        Ok(10_000)
+                    }
+                    (t, a)
+                        if t == BPF_PROG_TYPE_CGROUP_SOCK_ADDR as u32
+                            && a == BPF_CGROUP_INET4_CONNECT as u32 =>
+                    {
+                        Ok(10_001)
+                    }
+                    _ => Err((-1, io::Error::from_raw_os_error(EINVAL))),
+                }
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut skb = CgroupSkb::from_fd(1).unwrap();
+        skb.data.fd = None;
+        skb.expected_attach_type = Some(CgroupSkbAttachType::Egress);
+        let mut program = Program::CgroupSkb(skb);
+        assert_eq!(program.expected_attach_type(), Some(BPF_CGROUP_INET_EGRESS));
+        program.load().unwrap();
+        assert_eq!(program.expected_attach_type(), Some(BPF_CGROUP_INET_EGRESS));
+
+        // the generic load must set the attach type the kernel requires
+        let mut sock_addr = CgroupSockAddr::from_fd(2, CgroupSockAddrAttachType::Connect4).unwrap();
+        sock_addr.data.fd = None;
+        let mut program = Program::CgroupSockAddr(sock_addr);
+        program.load().unwrap();
+        assert_eq!(program.fd(), Some(10_001));
+        assert_eq!(
+            program.expected_attach_type(),
+            Some(BPF_CGROUP_INET4_CONNECT)
+        );
+    }
+
+    #[test]
+    fn test_dump_insns() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = bpf_prog_type::BPF_PROG_TYPE_KPROBE as u32;
+                if info.xlated_prog_insns != 0 {
+                    // mov r0, 0; exit
+                    let insns = unsafe {
+                        std::slice::from_raw_parts_mut(info.xlated_prog_insns as *mut u8, 16)
+                    };
+                    insns.copy_from_slice(&[0xb7, 0, 0, 0, 0, 0, 0, 0, 0x95, 0, 0, 0, 0, 0, 0, 0]);
+                } else if info.jited_prog_insns != 0 {
+                    let code = unsafe {
+                        std::slice::from_raw_parts_mut(info.jited_prog_insns as *mut u8, 2)
+                    };
+                    code.copy_from_slice(&[0x90, 0xc3]);
+                }
+                info.xlated_prog_len = 16;
+                info.jited_prog_len = 2;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let program = Program::KProbe(KProbe::from_fd(1, ProbeKind::KProbe).unwrap());
+        let insns = program.dump_xlated_insns().unwrap();
+        assert_eq!(insns.len(), 2);
+        assert_eq!(insns[0].code, 0xb7);
+        assert_eq!(insns[1].code, 0x95);
+        assert_eq!(program.dump_jited_insns().unwrap(), vec![0x90, 0xc3]);
+    }
+
+    #[test]
+    fn test_dump_insns_restricted() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                // like the kernel when bpf_dump_raw_ok() is false: the lengths
+                // are reported but the code isn't copied
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = bpf_prog_type::BPF_PROG_TYPE_KPROBE as u32;
+                info.xlated_prog_len = 16;
+                info.jited_prog_len = 2;
+                info.xlated_prog_insns = 0;
+                info.jited_prog_insns = 0;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let program = Program::KProbe(KProbe::from_fd(1, ProbeKind::KProbe).unwrap());
+        match program.dump_xlated_insns() {
+            Err(ProgramError::SyscallError { io_error, .. }) => {
+                assert_eq!(io_error.raw_os_error(), Some(EPERM))
+            }
+            res => panic!("unexpected result {:?}", res.map(|insns| insns.len())),
+        }
+        assert!(program.dump_jited_insns().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_set_kern_version() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = bpf_prog_type::BPF_PROG_TYPE_KPROBE as u32;
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_LOAD,
+                attr,
+            } if unsafe { attr.__bindgen_anon_3.kern_version } == 0x040900 => Ok(10_000),
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut program = Program::KProbe(KProbe::from_fd(1, ProbeKind::KProbe).unwrap());
+        program.data_mut().fd = None;
+        program.set_kern_version(0x040900);
+        assert!(program.load().is_ok());
+        assert_eq!(program.fd(), Some(10_000));
+    }
+
+    #[test]
+    fn test_kern_version_retry() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = bpf_prog_type::BPF_PROG_TYPE_KPROBE as u32;
+                Ok(0)
+            }
+            // only the version of the running kernel, with the patch level
+            // clamped to 255, is accepted
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_LOAD,
+                attr,
+            } if unsafe { attr.__bindgen_anon_3.kern_version } == 0x0409ff => Ok(10_000),
+            _ => Err((-1, io::Error::from_raw_os_error(EINVAL))),
+        });
+        override_kernel_version((4, 9, 337));
+
+        let mut program = Program::KProbe(KProbe::from_fd(1, ProbeKind::KProbe).unwrap());
+        program.data_mut().fd = None;
+        program.data_mut().obj.kernel_version = KernelVersion::Version(0x040e00);
+        program.load().unwrap();
+        assert_eq!(program.fd(), Some(10_000));
+
+        // the version isn't retried when it's overridden
+        program.data_mut().fd = None;
+        program.set_kern_version(0x040e00);
+        assert!(matches!(
+            program.load(),
+            Err(ProgramError::LoadError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_program_from_id() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_GET_FD_BY_ID,
+                attr,
+            } => Ok(10_000 + unsafe { attr.__bindgen_anon_6.__bindgen_anon_1.prog_id } as i64),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.id = unsafe { attr.info.bpf_fd } - 10_000;
+                info.type_ = bpf_prog_type::BPF_PROG_TYPE_SCHED_CLS as u32;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let program = Program::from_id(5).unwrap();
+        assert!(matches!(program, Program::SchedClassifier(_)));
+        assert_eq!(program.data().fd, Some(10_005));
+        assert_eq!(prog_info_by_fd(10_005).unwrap().id, 5);
+    }
+
+    #[test]
+    fn test_program_stats() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_GET_FD_BY_ID,
+                ..
+            } => Ok(10_000),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = bpf_prog_type::BPF_PROG_TYPE_SCHED_CLS as u32;
+                info.run_cnt = 3;
+                info.run_time_ns = 1500;
+                info.recursion_misses = 1;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let program = Program::from_id(1).unwrap();
+        assert_eq!(
+            program.stats().unwrap(),
+            ProgramStats {
+                run_count: 3,
+                run_time_ns: 1500,
+                recursion_misses: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_iter_attach() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = bpf_prog_type::BPF_PROG_TYPE_TRACING as u32;
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_LINK_CREATE,
+                attr,
+            } if unsafe {
+                attr.link_create.prog_fd == 10_000
+                    && attr.link_create.attach_type == bpf_attach_type::BPF_TRACE_ITER as u32
+            } =>
+            {
+                let u = unsafe { attr.link_create.__bindgen_anon_2.__bindgen_anon_1 };
+                match u.iter_info_len {
+                    0 => Ok(10_001),
+                    4 if unsafe { *(u.iter_info as *const u32) } == 10_002 => Ok(10_003),
+                    _ => Err((-1, io::Error::from_raw_os_error(EINVAL))),
+                }
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_ITER_CREATE,
+                attr,
+            } if unsafe { attr.iter_create.link_fd } == 10_001 => Ok(10_004),
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut iter = Iter::from_fd(10_000).unwrap();
+        let link = iter.attach().unwrap();
+        assert_eq!(link.inner.fd, Some(10_001));
+        let file = link.open().unwrap();
+        assert_eq!(file.as_raw_fd(), 10_004);
+        // don't close a real fd
+        std::mem::forget(file);
+
+        assert_eq!(bpf_link_create_iter(10_000, Some(10_002)).unwrap(), 10_003);
+    }
+
+    #[test]
+    fn test_perf_attach_with_cookie() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = bpf_prog_type::BPF_PROG_TYPE_KPROBE as u32;
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_LINK_CREATE,
+                attr,
+            } if unsafe {
+                attr.link_create.prog_fd == 10_000
+                    && attr.link_create.__bindgen_anon_1.target_fd == 10_001
+                    && attr.link_create.attach_type == bpf_attach_type::BPF_PERF_EVENT as u32
+                    && attr.link_create.__bindgen_anon_2.perf_event.bpf_cookie == 42
+            } =>
+            {
+                Ok(10_002)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut kprobe = KProbe::from_fd(10_000, ProbeKind::KProbe).unwrap();
+        assert!(perf_attach_with_cookie(&mut kprobe.data, 10_001, 42).is_ok());
+        assert_eq!(kprobe.data.attach_cookie, Some(42));
+    }
+
+    #[derive(Debug)]
+    struct CountingLink {
+        detached: Arc<Mutex<u32>>,
+    }
+
+    impl Link for CountingLink {
+        fn detach(&mut self) -> Result<(), ProgramError> {
+            *self.detached.lock() += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_program_links() {
+        let detached = Arc::new(Mutex::new(0));
+        let new_link = || {
+            LinkRef::new(Arc::new(Mutex::new(CountingLink {
+                detached: Arc::clone(&detached),
+            })))
+        };
+
+        let mut links = ProgramLinks::new();
+        assert!(links.is_empty());
+        links.push(new_link());
+        links.extend(vec![new_link(), new_link()]);
+        assert_eq!(links.len(), 3);
+        assert_eq!(links.iter().count(), 3);
+
+        let results = links.detach_all();
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(*detached.lock(), 3);
+        assert!(links.is_empty());
+
+        links.push(new_link());
+        drop(links);
+        assert_eq!(*detached.lock(), 4);
+    }
+
+    #[test]
+    fn test_perf_attach_fd_link() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = bpf_prog_type::BPF_PROG_TYPE_KPROBE as u32;
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_LINK_CREATE,
+                attr,
+            } if unsafe {
+                attr.link_create.prog_fd == 10_000
+                    && attr.link_create.__bindgen_anon_1.target_fd == 10_001
+                    && attr.link_create.attach_type == bpf_attach_type::BPF_PERF_EVENT as u32
+            } =>
+            {
+                Ok(10_002)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut kprobe = KProbe::from_fd(10_000, ProbeKind::KProbe).unwrap();
+        let mut link = perf_attach_fd_link(&mut kprobe.data, 10_001).unwrap();
+        assert_eq!(link.fd, Some(10_002));
+        // the link is owned by the caller
+        assert!(kprobe.data.links.is_empty());
+        // don't close a real fd
+        link.fd = None;
+
+        assert!(matches!(
+            perf_attach_fd_link(&mut kprobe.data, 10_003),
+            Err(ProgramError::SyscallError { call, .. }) if call == "bpf_link_create"
+        ));
+    }
+}
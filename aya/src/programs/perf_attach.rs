@@ -2,8 +2,8 @@ use libc::close;
 use std::os::unix::io::RawFd;
 
 use crate::{
-    programs::{probe::detach_debug_fs, ProbeKind},
-    sys::perf_event_ioctl,
+    programs::{probe::detach_debug_fs, FdLink, ProbeKind},
+    sys::{bpf_link_create_perf_event, kernel_version, perf_event_ioctl},
     PERF_EVENT_IOC_DISABLE, PERF_EVENT_IOC_ENABLE, PERF_EVENT_IOC_SET_BPF,
 };
 
@@ -41,10 +41,59 @@ impl Drop for PerfLink {
     }
 }
 
+// A perf event the program is attached to through a bpf link, created by
+// bpf_link_create instead of PERF_EVENT_IOC_SET_BPF
+#[derive(Debug)]
+struct PerfEventLink {
+    link_fd: Option<RawFd>,
+    perf_fd: RawFd,
+}
+
+impl Link for PerfEventLink {
+    fn detach(&mut self) -> Result<(), ProgramError> {
+        if let Some(fd) = self.link_fd.take() {
+            let _ = perf_event_ioctl(self.perf_fd, PERF_EVENT_IOC_DISABLE, 0);
+            unsafe {
+                close(fd);
+                close(self.perf_fd);
+            }
+            Ok(())
+        } else {
+            Err(ProgramError::AlreadyDetached)
+        }
+    }
+}
+
+impl Drop for PerfEventLink {
+    fn drop(&mut self) {
+        let _ = self.detach();
+    }
+}
+
+// bpf links can be created for perf events since kernel 5.15, older kernels
+// only support PERF_EVENT_IOC_SET_BPF
 pub(crate) fn perf_attach(data: &mut ProgramData, fd: RawFd) -> Result<LinkRef, ProgramError> {
+    if kernel_version().unwrap() >= (5, 15, 0) {
+        let link_fd = perf_link_create(data, fd, 0)?;
+        return Ok(data.link(PerfEventLink {
+            link_fd: Some(link_fd),
+            perf_fd: fd,
+        }));
+    }
     perf_attach_either(data, fd, None, None)
 }
 
+// The link keeps a reference to the perf event, so the perf event fd is
+// closed and the caller owns the returned link.
+pub(crate) fn perf_attach_fd_link(
+    data: &mut ProgramData,
+    fd: RawFd,
+) -> Result<FdLink, ProgramError> {
+    let link_fd = perf_link_create(data, fd, 0)?;
+    unsafe { close(fd) };
+    Ok(FdLink { fd: Some(link_fd) })
+}
+
 pub(crate) fn perf_attach_debugfs(
     data: &mut ProgramData,
     fd: RawFd,
@@ -54,6 +103,43 @@ pub(crate) fn perf_attach_debugfs(
     perf_attach_either(data, fd, Some(probe_kind), Some(event_alias))
 }
 
+// Attaching with a cookie requires bpf_link_create, which is available for
+// perf events since kernel 5.15.
+pub(crate) fn perf_attach_with_cookie(
+    data: &mut ProgramData,
+    fd: RawFd,
+    cookie: u64,
+) -> Result<LinkRef, ProgramError> {
+    let link_fd = perf_link_create(data, fd, cookie)?;
+    data.attach_cookie = Some(cookie);
+
+    Ok(data.link(PerfEventLink {
+        link_fd: Some(link_fd),
+        perf_fd: fd,
+    }))
+}
+
+// closes the perf event fd if the link can't be created
+fn perf_link_create(data: &ProgramData, fd: RawFd, cookie: u64) -> Result<RawFd, ProgramError> {
+    let prog_fd = match data.fd_or_err() {
+        Ok(prog_fd) => prog_fd,
+        Err(e) => {
+            unsafe { close(fd) };
+            return Err(e);
+        }
+    };
+    match bpf_link_create_perf_event(prog_fd, fd, cookie) {
+        Ok(link_fd) => Ok(link_fd as RawFd),
+        Err((_, io_error)) => {
+            unsafe { close(fd) };
+            Err(ProgramError::SyscallError {
+                call: "bpf_link_create".to_owned(),
+                io_error,
+            })
+        }
+    }
+}
+
 fn perf_attach_either(
     data: &mut ProgramData,
     fd: RawFd,
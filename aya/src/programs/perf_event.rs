@@ -1,5 +1,24 @@
 //! Perf event programs.
-use crate::{generated::bpf_prog_type::BPF_PROG_TYPE_PERF_EVENT, sys::perf_event_open};
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use libc::close;
+
+use crate::{
+    generated::perf_event_sample_format::PERF_SAMPLE_RAW,
+    generated::{
+        bpf_prog_type::BPF_PROG_TYPE_PERF_EVENT,
+        perf_hw_id::PERF_COUNT_HW_CPU_CYCLES,
+        perf_sw_ids::{
+            PERF_COUNT_SW_CONTEXT_SWITCHES, PERF_COUNT_SW_CPU_CLOCK, PERF_COUNT_SW_CPU_MIGRATIONS,
+            PERF_COUNT_SW_PAGE_FAULTS, PERF_COUNT_SW_PAGE_FAULTS_MAJ,
+            PERF_COUNT_SW_PAGE_FAULTS_MIN, PERF_COUNT_SW_TASK_CLOCK,
+        },
+    },
+    sys::{perf_event_open, PerfEventAttrBuilder},
+};
 
 use crate::generated::perf_type_id::{
     PERF_TYPE_BREAKPOINT, PERF_TYPE_HARDWARE, PERF_TYPE_HW_CACHE, PERF_TYPE_RAW,
@@ -135,32 +154,322 @@ impl PerfEvent {
         scope: PerfEventScope,
         sample_policy: SamplePolicy,
     ) -> Result<LinkRef, ProgramError> {
-        let (sample_period, sample_frequency) = match sample_policy {
-            SamplePolicy::Period(period) => (period, None),
-            SamplePolicy::Frequency(frequency) => (0, Some(frequency)),
-        };
-        let (pid, cpu) = match scope {
+        let perf_event = HwPerfEventBuilder::new(perf_type, config)
+            .scope(scope)
+            .sample_policy(sample_policy)
+            .build()
+            .map_err(|io_error| ProgramError::SyscallError {
+                call: "perf_event_open".to_owned(),
+                io_error,
+            })?;
+
+        self.attach_perf_event(perf_event)
+    }
+
+    /// Attaches to a perf event opened with [`HwPerfEventBuilder`].
+    ///
+    /// The program takes ownership of the event, which gets closed when the
+    /// program is detached.
+    pub fn attach_perf_event(&mut self, perf_event: PerfEventFd) -> Result<LinkRef, ProgramError> {
+        perf_attach(&mut self.data, perf_event.into_raw_fd())
+    }
+}
+
+/// An open perf event.
+///
+/// The event is closed when dropped, unless it's passed to
+/// [`PerfEvent::attach_perf_event`].
+#[derive(Debug)]
+pub struct PerfEventFd {
+    fd: Option<RawFd>,
+}
+
+impl PerfEventFd {
+    fn into_raw_fd(mut self) -> RawFd {
+        self.fd.take().unwrap()
+    }
+}
+
+impl AsRawFd for PerfEventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.unwrap()
+    }
+}
+
+impl Drop for PerfEventFd {
+    fn drop(&mut self) {
+        if let Some(fd) = self.fd.take() {
+            unsafe { close(fd) };
+        }
+    }
+}
+
+/// Builds the perf events [`PerfEvent`] programs attach to.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut bpf = aya::Bpf::load(&[])?;
+/// use std::convert::TryInto;
+/// use aya::util::online_cpus;
+/// use aya::programs::perf_event::{HwPerfEventBuilder, PerfEvent, PerfEventScope};
+///
+/// let prog: &mut PerfEvent = bpf.program_mut("profile_cycles").unwrap().try_into()?;
+/// prog.load()?;
+///
+/// for cpu in online_cpus()? {
+///     let event = HwPerfEventBuilder::cycle_sampling(99)
+///         .scope(PerfEventScope::AllProcessesOneCpu { cpu })
+///         .build()?;
+///     prog.attach_perf_event(event)?;
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct HwPerfEventBuilder {
+    perf_type: PerfTypeId,
+    config: u64,
+    scope: PerfEventScope,
+    sample_policy: SamplePolicy,
+    group_fd: Option<RawFd>,
+}
+
+impl HwPerfEventBuilder {
+    /// Creates a builder for the event `config` of type `perf_type`.
+    ///
+    /// See [`PerfEvent::attach`] for the encoding of `config`. The event
+    /// defaults to the calling process on any CPU, with a sample period of 1.
+    pub fn new(perf_type: PerfTypeId, config: u64) -> HwPerfEventBuilder {
+        HwPerfEventBuilder {
+            perf_type,
+            config,
+            scope: PerfEventScope::CallingProcessAnyCpu,
+            sample_policy: SamplePolicy::Period(1),
+            group_fd: None,
+        }
+    }
+
+    /// Creates a builder that samples CPU cycles `frequency` times per second.
+    ///
+    /// This is the usual setup of sampling CPU profilers.
+    pub fn cycle_sampling(frequency: u64) -> HwPerfEventBuilder {
+        HwPerfEventBuilder::new(PerfTypeId::Hardware, PERF_COUNT_HW_CPU_CYCLES as u64)
+            .sample_policy(SamplePolicy::Frequency(frequency))
+    }
+
+    /// Sets the processes and CPUs the event is measured on.
+    pub fn scope(mut self, scope: PerfEventScope) -> HwPerfEventBuilder {
+        self.scope = scope;
+        self
+    }
+
+    /// Sets how often the event is sampled.
+    pub fn sample_policy(mut self, sample_policy: SamplePolicy) -> HwPerfEventBuilder {
+        self.sample_policy = sample_policy;
+        self
+    }
+
+    /// Opens the event as part of the group led by `group_fd`.
+    pub fn group_fd(mut self, group_fd: RawFd) -> HwPerfEventBuilder {
+        self.group_fd = Some(group_fd);
+        self
+    }
+
+    /// Opens the perf event.
+    pub fn build(&self) -> Result<PerfEventFd, io::Error> {
+        let (pid, cpu) = match self.scope {
             PerfEventScope::CallingProcessAnyCpu => (0, -1),
             PerfEventScope::CallingProcessOneCpu { cpu } => (0, cpu as i32),
             PerfEventScope::OneProcessAnyCpu { pid } => (pid as i32, -1),
             PerfEventScope::OneProcessOneCpu { cpu, pid } => (pid as i32, cpu as i32),
             PerfEventScope::AllProcessesOneCpu { cpu } => (-1, cpu as i32),
         };
-        let fd = perf_event_open(
-            perf_type as u32,
-            config,
-            pid,
-            cpu,
+        let attr = PerfEventAttrBuilder::new(self.perf_type.clone() as u32, self.config)
+            .sample_type(PERF_SAMPLE_RAW as u64);
+        let attr = match self.sample_policy {
+            SamplePolicy::Period(period) => attr.sample_period(period),
+            SamplePolicy::Frequency(frequency) => attr.sample_frequency(frequency),
+        }
+        .build();
+        let fd = perf_event_open(&attr, pid, cpu, self.group_fd.unwrap_or(-1), 0)?;
+
+        Ok(PerfEventFd { fd: Some(fd) })
+    }
+}
+
+/// The software counters a [`SoftwarePerfEvent`] can measure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftwarePerfEventType {
+    /// The CPU clock, a high-resolution per-CPU timer (`PERF_COUNT_SW_CPU_CLOCK`).
+    CpuClock,
+    /// The clock of the measured task (`PERF_COUNT_SW_TASK_CLOCK`).
+    TaskClock,
+    /// Page faults (`PERF_COUNT_SW_PAGE_FAULTS`).
+    PageFaults,
+    /// Context switches (`PERF_COUNT_SW_CONTEXT_SWITCHES`).
+    ContextSwitches,
+    /// Migrations of the measured task to another CPU
+    /// (`PERF_COUNT_SW_CPU_MIGRATIONS`).
+    CpuMigrations,
+    /// Minor page faults, which didn't require disk I/O
+    /// (`PERF_COUNT_SW_PAGE_FAULTS_MIN`).
+    PageFaultsMin,
+    /// Major page faults, which required disk I/O
+    /// (`PERF_COUNT_SW_PAGE_FAULTS_MAJ`).
+    PageFaultsMaj,
+}
+
+impl SoftwarePerfEventType {
+    fn config(self) -> u64 {
+        let id = match self {
+            SoftwarePerfEventType::CpuClock => PERF_COUNT_SW_CPU_CLOCK,
+            SoftwarePerfEventType::TaskClock => PERF_COUNT_SW_TASK_CLOCK,
+            SoftwarePerfEventType::PageFaults => PERF_COUNT_SW_PAGE_FAULTS,
+            SoftwarePerfEventType::ContextSwitches => PERF_COUNT_SW_CONTEXT_SWITCHES,
+            SoftwarePerfEventType::CpuMigrations => PERF_COUNT_SW_CPU_MIGRATIONS,
+            SoftwarePerfEventType::PageFaultsMin => PERF_COUNT_SW_PAGE_FAULTS_MIN,
+            SoftwarePerfEventType::PageFaultsMaj => PERF_COUNT_SW_PAGE_FAULTS_MAJ,
+        };
+        id as u64
+    }
+}
+
+/// A software perf counter [`PerfEvent`] programs can be attached to.
+///
+/// Software counters are maintained by the kernel, so unlike the hardware
+/// events opened with [`HwPerfEventBuilder`] they're available in virtual
+/// machines and on CPUs without a PMU. The program runs every
+/// `sample_period` occurrences of the event, eg every 100 page faults.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut bpf = aya::Bpf::load(&[])?;
+/// use std::convert::TryInto;
+/// use aya::util::online_cpus;
+/// use aya::programs::perf_event::{
+///     PerfEvent, PerfEventScope, SoftwarePerfEvent, SoftwarePerfEventType,
+/// };
+///
+/// let prog: &mut PerfEvent = bpf.program_mut("page_faults").unwrap().try_into()?;
+/// prog.load()?;
+///
+/// let event = SoftwarePerfEvent::new(SoftwarePerfEventType::PageFaults, 100);
+/// for cpu in online_cpus()? {
+///     event.attach(prog, PerfEventScope::AllProcessesOneCpu { cpu })?;
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct SoftwarePerfEvent {
+    event: SoftwarePerfEventType,
+    sample_period: u64,
+}
+
+impl SoftwarePerfEvent {
+    /// Creates a software counter of type `event`, sampled every
+    /// `sample_period` occurrences.
+    pub fn new(event: SoftwarePerfEventType, sample_period: u64) -> SoftwarePerfEvent {
+        SoftwarePerfEvent {
+            event,
             sample_period,
-            sample_frequency,
-            false,
-            0,
-        )
-        .map_err(|(_code, io_error)| ProgramError::SyscallError {
-            call: "perf_event_open".to_owned(),
-            io_error,
-        })? as i32;
-
-        perf_attach(&mut self.data, fd)
+        }
+    }
+
+    /// Returns the type of the counter.
+    pub fn event(&self) -> SoftwarePerfEventType {
+        self.event
+    }
+
+    /// Opens the counter on the processes and CPUs of `scope`.
+    pub fn open(&self, scope: PerfEventScope) -> Result<PerfEventFd, io::Error> {
+        HwPerfEventBuilder::new(PerfTypeId::Software, self.event.config())
+            .scope(scope)
+            .sample_policy(SamplePolicy::Period(self.sample_period))
+            .build()
+    }
+
+    /// Opens the counter on the processes and CPUs of `scope` and attaches
+    /// `program` to it.
+    ///
+    /// The counter is disabled and closed when the returned link is
+    /// detached.
+    pub fn attach(
+        &self,
+        program: &mut PerfEvent,
+        scope: PerfEventScope,
+    ) -> Result<LinkRef, ProgramError> {
+        let perf_event = self
+            .open(scope)
+            .map_err(|io_error| ProgramError::SyscallError {
+                call: "perf_event_open".to_owned(),
+                io_error,
+            })?;
+        program.attach_perf_event(perf_event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libc::EFAULT;
+
+    use crate::sys::{override_syscall, Syscall};
+
+    use super::*;
+
+    #[test]
+    fn test_cycle_sampling() {
+        override_syscall(|call| match call {
+            Syscall::PerfEventOpen {
+                attr,
+                pid: -1,
+                cpu: 2,
+                group: 7,
+                ..
+            } if attr.type_ == PERF_TYPE_HARDWARE as u32
+                && attr.config == PERF_COUNT_HW_CPU_CYCLES as u64
+                && attr.freq() == 1
+                && unsafe { attr.__bindgen_anon_1.sample_freq } == 99 =>
+            {
+                Ok(42)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut event = HwPerfEventBuilder::cycle_sampling(99)
+            .scope(PerfEventScope::AllProcessesOneCpu { cpu: 2 })
+            .group_fd(7)
+            .build()
+            .unwrap();
+        assert_eq!(event.as_raw_fd(), 42);
+        // don't close a real fd
+        event.fd.take();
+    }
+
+    #[test]
+    fn test_software_perf_event() {
+        override_syscall(|call| match call {
+            Syscall::PerfEventOpen {
+                attr,
+                pid: 1234,
+                cpu: -1,
+                group: -1,
+                ..
+            } if attr.type_ == PERF_TYPE_SOFTWARE as u32
+                && attr.config == PERF_COUNT_SW_CONTEXT_SWITCHES as u64
+                && attr.freq() == 0
+                && unsafe { attr.__bindgen_anon_1.sample_period } == 10 =>
+            {
+                Ok(42)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let mut event = SoftwarePerfEvent::new(SoftwarePerfEventType::ContextSwitches, 10)
+            .open(PerfEventScope::OneProcessAnyCpu { pid: 1234 })
+            .unwrap();
+        assert_eq!(event.as_raw_fd(), 42);
+        // don't close a real fd
+        event.fd.take();
     }
 }
@@ -7,9 +7,11 @@ use std::{
 
 use crate::{
     programs::{
-        kprobe::KProbeError, perf_attach, perf_attach_debugfs,
-        trace_point::read_sys_fs_trace_point_id, uprobe::UProbeError, LinkRef, ProgramData,
-        ProgramError,
+        kprobe::KProbeError,
+        perf_attach, perf_attach_debugfs, perf_attach_fd_link, perf_attach_with_cookie,
+        trace_point::{find_tracefs, read_sys_fs_trace_point_id},
+        uprobe::UProbeError,
+        FdLink, LinkRef, ProgramData, ProgramError,
     },
     sys::{kernel_version, perf_event_open_probe, perf_event_open_trace_point},
 };
@@ -41,22 +43,58 @@ pub(crate) fn attach(
     kind: ProbeKind,
     fn_name: &str,
     offset: u64,
+    ref_ctr_offset: u64,
     pid: Option<pid_t>,
+    cookie: Option<u64>,
 ) -> Result<LinkRef, ProgramError> {
+    // cookies can only be set through bpf_link_create, which requires a perf
+    // event created by perf_event_open so the debugfs fallback isn't used
+    if let Some(cookie) = cookie {
+        let fd = create_as_probe(kind, fn_name, offset, ref_ctr_offset, pid)?;
+        return perf_attach_with_cookie(program_data, fd, cookie);
+    }
+
     // https://github.com/torvalds/linux/commit/e12f03d7031a977356e3d7b75a68c2185ff8d155
     // Use debugfs to create probe
     let k_ver = kernel_version().unwrap();
     if k_ver < (4, 17, 0) {
-        let (fd, event_alias) = create_as_trace_point(kind, fn_name, offset, pid)?;
+        let (fd, event_alias) = create_as_trace_point(kind, fn_name, offset, pid, 0)?;
 
         return perf_attach_debugfs(program_data, fd, kind, event_alias);
     };
 
-    let fd = create_as_probe(kind, fn_name, offset, pid)?;
+    let fd = create_as_probe(kind, fn_name, offset, ref_ctr_offset, pid)?;
 
     perf_attach(program_data, fd)
 }
 
+// bpf links require a perf event created by perf_event_open, so the debugfs
+// fallback isn't used
+pub(crate) fn attach_fd_link(
+    program_data: &mut ProgramData,
+    kind: ProbeKind,
+    fn_name: &str,
+    offset: u64,
+) -> Result<FdLink, ProgramError> {
+    let fd = create_as_probe(kind, fn_name, offset, 0, None)?;
+    perf_attach_fd_link(program_data, fd)
+}
+
+// The number of instances of a kretprobe that can run at the same time can
+// only be set in the definition of a probe created through tracefs, so the
+// debugfs path is always used.
+pub(crate) fn attach_kretprobe_with_maxactive(
+    program_data: &mut ProgramData,
+    fn_name: &str,
+    offset: u64,
+    maxactive: u32,
+) -> Result<LinkRef, ProgramError> {
+    let kind = ProbeKind::KRetProbe;
+    let (fd, event_alias) = create_as_trace_point(kind, fn_name, offset, None, maxactive)?;
+
+    perf_attach_debugfs(program_data, fd, kind, event_alias)
+}
+
 pub(crate) fn detach_debug_fs(kind: ProbeKind, event_alias: &str) -> Result<(), ProgramError> {
     use ProbeKind::*;
 
@@ -74,6 +112,7 @@ fn create_as_probe(
     kind: ProbeKind,
     fn_name: &str,
     offset: u64,
+    ref_ctr_offset: u64,
     pid: Option<pid_t>,
 ) -> Result<i32, ProgramError> {
     use ProbeKind::*;
@@ -97,7 +136,7 @@ fn create_as_probe(
         _ => None,
     };
 
-    let fd = perf_event_open_probe(perf_ty, ret_bit, fn_name, offset, pid).map_err(
+    let fd = perf_event_open_probe(perf_ty, ret_bit, fn_name, offset, ref_ctr_offset, pid).map_err(
         |(_code, io_error)| ProgramError::SyscallError {
             call: "perf_event_open".to_owned(),
             io_error,
@@ -112,13 +151,14 @@ fn create_as_trace_point(
     name: &str,
     offset: u64,
     pid: Option<pid_t>,
+    maxactive: u32,
 ) -> Result<(i32, String), ProgramError> {
     use ProbeKind::*;
 
     let event_alias = match kind {
-        KProbe | KRetProbe => create_probe_event(kind, name, offset)
+        KProbe | KRetProbe => create_probe_event(kind, name, offset, maxactive)
             .map_err(|(filename, io_error)| KProbeError::FileError { filename, io_error })?,
-        UProbe | URetProbe => create_probe_event(kind, name, offset)
+        UProbe | URetProbe => create_probe_event(kind, name, offset, maxactive)
             .map_err(|(filename, io_error)| UProbeError::FileError { filename, io_error })?,
     };
 
@@ -134,14 +174,23 @@ fn create_as_trace_point(
     Ok((fd, event_alias))
 }
 
+// the file where the probes created through tracefs are registered, eg
+// /sys/kernel/tracing/kprobe_events
+fn probe_events_file(kind: ProbeKind) -> Result<String, (String, io::Error)> {
+    let file_name = format!("{}_events", kind.pmu());
+    let tracefs = find_tracefs().map_err(|e| (file_name.clone(), e))?;
+    Ok(tracefs.join(file_name).to_string_lossy().into_owned())
+}
+
 fn create_probe_event(
     kind: ProbeKind,
     fn_name: &str,
     offset: u64,
+    maxactive: u32,
 ) -> Result<String, (String, io::Error)> {
     use ProbeKind::*;
 
-    let events_file_name = format!("/sys/kernel/debug/tracing/{}_events", kind.pmu());
+    let events_file_name = probe_events_file(kind)?;
     let probe_type_prefix = match kind {
         KProbe | UProbe => 'p',
         KRetProbe | URetProbe => 'r',
@@ -158,9 +207,15 @@ fn create_probe_event(
         UProbe => format!(":{:#x}", offset),
         _ => "".to_string(),
     };
+    // eg r16: for a kretprobe with at most 16 instances running at once
+    let maxactive = match kind {
+        KRetProbe if maxactive > 0 => maxactive.to_string(),
+        _ => "".to_string(),
+    };
     let probe = format!(
-        "{}:{}s/{} {}{}\n",
+        "{}{}:{}s/{} {}{}\n",
         probe_type_prefix,
+        maxactive,
         kind.pmu(),
         event_alias,
         fn_name,
@@ -180,7 +235,7 @@ fn create_probe_event(
 }
 
 fn delete_probe_event(kind: ProbeKind, event_alias: &str) -> Result<(), (String, io::Error)> {
-    let events_file_name = format!("/sys/kernel/debug/tracing/{}_events", kind.pmu());
+    let events_file_name = probe_events_file(kind)?;
 
     let events =
         fs::read_to_string(&events_file_name).map_err(|e| (events_file_name.clone(), e))?;
@@ -14,6 +14,10 @@ use crate::{
 /// be attached to. See`/sys/kernel/debug/tracing/events` for a list of which
 /// events can be traced.
 ///
+/// Raw tracepoint programs access their arguments as untyped `u64` values. To
+/// access them as typed kernel structures through BTF, use a
+/// [`BtfTracePoint`](crate::programs::BtfTracePoint) instead.
+///
 /// # Minimum kernel version
 ///
 /// The minimum kernel version required to use this feature is 4.17.
@@ -11,6 +11,12 @@ use crate::{
 /// filter and redirect messages sent on sockets. See also [`SockMap`] and
 /// [`SockHash`].
 ///
+/// The program is attached with the `BPF_SK_MSG_VERDICT` attach type and runs
+/// for every message sent on the sockets stored in the map. It can redirect a
+/// message to another socket of the map with `bpf_msg_redirect_map()` or
+/// `bpf_msg_redirect_hash()`, available in `aya-bpf` as `SockMap::redirect_msg`
+/// and `SockHash::redirect_msg`.
+///
 /// # Minimum kernel version
 ///
 /// The minimum kernel version required to use this feature is 4.17.
@@ -72,7 +78,7 @@ impl SkMsg {
         let prog_fd = self.data.fd_or_err()?;
         let map_fd = map.fd_or_err()?;
 
-        bpf_prog_attach(prog_fd, map_fd, BPF_SK_MSG_VERDICT).map_err(|(_, io_error)| {
+        bpf_prog_attach(prog_fd, map_fd, BPF_SK_MSG_VERDICT, 0).map_err(|(_, io_error)| {
             ProgramError::SyscallError {
                 call: "bpf_prog_attach".to_owned(),
                 io_error,
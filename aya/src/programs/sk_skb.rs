@@ -1,9 +1,12 @@
+use std::os::unix::io::RawFd;
+
 use crate::{
     generated::{
         bpf_attach_type::{BPF_SK_SKB_STREAM_PARSER, BPF_SK_SKB_STREAM_VERDICT},
         bpf_prog_type::BPF_PROG_TYPE_SK_SKB,
     },
     maps::sock::SocketMap,
+    obj::ProgramSection,
     programs::{load_program, LinkRef, ProgAttachLink, ProgramData, ProgramError},
     sys::bpf_prog_attach,
 };
@@ -17,12 +20,28 @@ pub enum SkSkbKind {
     StreamVerdict,
 }
 
+impl SkSkbKind {
+    fn section(self) -> fn(String) -> ProgramSection {
+        match self {
+            SkSkbKind::StreamParser => |name| ProgramSection::SkSkbStreamParser { name },
+            SkSkbKind::StreamVerdict => |name| ProgramSection::SkSkbStreamVerdict { name },
+        }
+    }
+}
+
 /// A program used to intercept ingress socket buffers.
 ///
 /// [`SkSkb`] programs are attached to [socket maps], and can be used to
 /// inspect, redirect or filter incoming packet. See also [`SockMap`] and
 /// [`SockHash`].
 ///
+/// The [kind](SkSkbKind) of the program is set by its ELF section,
+/// `sk_skb/stream_parser` or `sk_skb/stream_verdict`. A stream parser is
+/// used together with a stream verdict attached to the same map: the parser
+/// returns the length of the next message in the stream, and the verdict
+/// decides what to do with it, typically redirecting it to another socket in
+/// the map with `bpf_sk_redirect_map()`.
+///
 /// # Minimum kernel version
 ///
 /// The minimum kernel version required to use this feature is 4.14.
@@ -60,6 +79,23 @@ impl SkSkb {
         load_program(BPF_PROG_TYPE_SK_SKB, &mut self.data)
     }
 
+    /// Creates a program from the fd of a program that is already loaded.
+    ///
+    /// The kind of a loaded program can't be queried from the kernel, so
+    /// `kind` must match the one the program was written for.
+    ///
+    /// Returns [`ProgramError::UnexpectedProgramType`] if `fd` isn't a
+    /// sk_skb program.
+    pub fn from_fd(fd: RawFd, kind: SkSkbKind) -> Result<SkSkb, ProgramError> {
+        let data = ProgramData::from_fd(fd, BPF_PROG_TYPE_SK_SKB, kind.section())?;
+        Ok(SkSkb { data, kind })
+    }
+
+    /// Returns the kind of the program.
+    pub fn kind(&self) -> SkSkbKind {
+        self.kind
+    }
+
     /// Attaches the program to the given socket map.
     pub fn attach(&mut self, map: &dyn SocketMap) -> Result<LinkRef, ProgramError> {
         let prog_fd = self.data.fd_or_err()?;
@@ -69,7 +105,7 @@ impl SkSkb {
             SkSkbKind::StreamParser => BPF_SK_SKB_STREAM_PARSER,
             SkSkbKind::StreamVerdict => BPF_SK_SKB_STREAM_VERDICT,
         };
-        bpf_prog_attach(prog_fd, map_fd, attach_type).map_err(|(_, io_error)| {
+        bpf_prog_attach(prog_fd, map_fd, attach_type, 0).map_err(|(_, io_error)| {
             ProgramError::SyscallError {
                 call: "bpf_prog_attach".to_owned(),
                 io_error,
@@ -80,3 +116,44 @@ impl SkSkb {
             .link(ProgAttachLink::new(prog_fd, map_fd, attach_type)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use libc::EFAULT;
+
+    use super::*;
+    use crate::{
+        generated::{bpf_cmd, bpf_prog_info},
+        sys::{override_syscall, Syscall},
+    };
+
+    #[test]
+    fn test_from_fd() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = BPF_PROG_TYPE_SK_SKB as u32;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let prog = SkSkb::from_fd(1, SkSkbKind::StreamVerdict).unwrap();
+        assert!(matches!(prog.kind, SkSkbKind::StreamVerdict));
+        assert!(matches!(
+            prog.data.obj.section,
+            ProgramSection::SkSkbStreamVerdict { .. }
+        ));
+
+        let prog = SkSkb::from_fd(1, SkSkbKind::StreamParser).unwrap();
+        assert!(matches!(
+            prog.data.obj.section,
+            ProgramSection::SkSkbStreamParser { .. }
+        ));
+    }
+}
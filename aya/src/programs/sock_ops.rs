@@ -1,8 +1,11 @@
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 
 use crate::{
     generated::{bpf_attach_type::BPF_CGROUP_SOCK_OPS, bpf_prog_type::BPF_PROG_TYPE_SOCK_OPS},
-    programs::{load_program, LinkRef, ProgAttachLink, ProgramData, ProgramError},
+    programs::{
+        cgroup, load_program, query, query_attached_programs, CgroupAttachFlags, LinkRef,
+        ProgAttachLink, ProgramData, ProgramError, ProgramInfo,
+    },
     sys::bpf_prog_attach,
 };
 
@@ -10,7 +13,31 @@ use crate::{
 ///
 /// [`SockOps`] programs can access or set socket options, connection
 /// parameters, watch connection state changes and more. They are attached to
-/// cgroups.
+/// [cgroups][cgroup].
+///
+/// The program is called with a `struct bpf_sock_ops` context at several
+/// points of the lifetime of the TCP connections of the cgroup. The `op`
+/// field of the context identifies the event, for example
+/// `BPF_SOCK_OPS_TCP_CONNECT_CB` when a connection is initiated,
+/// `BPF_SOCK_OPS_ACTIVE_ESTABLISHED_CB` and
+/// `BPF_SOCK_OPS_PASSIVE_ESTABLISHED_CB` when an active or passive connection
+/// is established, or `BPF_SOCK_OPS_TIMEOUT_INIT` and
+/// `BPF_SOCK_OPS_RWND_INIT` when the kernel asks the program for connection
+/// parameters, in which case the program returns the value in the `reply`
+/// field.
+///
+/// Some events, like `BPF_SOCK_OPS_RETRANS_CB`, `BPF_SOCK_OPS_STATE_CB` and
+/// `BPF_SOCK_OPS_RTT_CB`, are only reported for the sockets that enabled them
+/// with the `bpf_sock_ops_cb_flags_set()` helper. The callback flags of a
+/// socket can't be set from user space: the program sets them, typically
+/// when the connection is established, with
+/// `SockOpsContext::set_cb_flags()` from `aya-bpf`.
+///
+/// The program is detached from the cgroup by calling
+/// [`detach`](crate::programs::Link::detach) on the link returned by
+/// [`SockOps::attach`].
+///
+/// [cgroup]: https://man7.org/linux/man-pages/man7/cgroups.7.html
 ///
 /// # Minimum kernel version
 ///
@@ -40,6 +67,7 @@ use crate::{
 /// prog.load()?;
 /// prog.attach(file)?;
 /// # Ok::<(), Error>(())
+/// ```
 #[derive(Debug)]
 #[doc(alias = "BPF_PROG_TYPE_SOCK_OPS")]
 pub struct SockOps {
@@ -54,12 +82,24 @@ impl SockOps {
         load_program(BPF_PROG_TYPE_SOCK_OPS, &mut self.data)
     }
 
+    /// Returns the ids of the programs attached to `cgroup`, including the
+    /// programs attached by other processes.
+    pub fn query_prog_ids<T: AsRawFd>(cgroup: T) -> Result<Vec<u32>, ProgramError> {
+        query(cgroup, BPF_CGROUP_SOCK_OPS, 0, &mut None)
+    }
+
+    /// Returns information about the programs attached to `cgroup`,
+    /// including the programs attached by other processes.
+    pub fn query_programs<T: AsRawFd>(cgroup: T) -> Result<Vec<ProgramInfo>, ProgramError> {
+        query_attached_programs(cgroup, BPF_CGROUP_SOCK_OPS)
+    }
+
     /// Attaches the program to the given cgroup.
     pub fn attach<T: AsRawFd>(&mut self, cgroup: T) -> Result<LinkRef, ProgramError> {
         let prog_fd = self.data.fd_or_err()?;
         let cgroup_fd = cgroup.as_raw_fd();
 
-        bpf_prog_attach(prog_fd, cgroup_fd, BPF_CGROUP_SOCK_OPS).map_err(|(_, io_error)| {
+        bpf_prog_attach(prog_fd, cgroup_fd, BPF_CGROUP_SOCK_OPS, 0).map_err(|(_, io_error)| {
             ProgramError::SyscallError {
                 call: "bpf_prog_attach".to_owned(),
                 io_error,
@@ -69,4 +109,24 @@ impl SockOps {
             .data
             .link(ProgAttachLink::new(prog_fd, cgroup_fd, BPF_CGROUP_SOCK_OPS)))
     }
+
+    /// Attaches the program to the given cgroup with the given attach
+    /// `flags`.
+    ///
+    /// See [`CgroupAttachFlags`] for how the program is attached, the meaning
+    /// of `replace_fd` and the errors returned.
+    pub fn attach_with_flags<T: AsRawFd>(
+        &mut self,
+        cgroup: T,
+        flags: CgroupAttachFlags,
+        replace_fd: Option<RawFd>,
+    ) -> Result<LinkRef, ProgramError> {
+        cgroup::attach_with_flags(
+            &mut self.data,
+            cgroup.as_raw_fd(),
+            BPF_CGROUP_SOCK_OPS,
+            flags,
+            replace_fd,
+        )
+    }
 }
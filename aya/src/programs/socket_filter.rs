@@ -72,6 +72,10 @@ impl SocketFilter {
     }
 
     /// Attaches the filter on the given socket.
+    ///
+    /// `socket` can be any type implementing [`AsRawFd`], so sockets from
+    /// [`std::net`] like [`TcpStream`](std::net::TcpStream) and
+    /// [`UdpSocket`](std::net::UdpSocket) can be used directly.
     pub fn attach<T: AsRawFd>(&mut self, socket: T) -> Result<LinkRef, ProgramError> {
         let prog_fd = self.data.fd_or_err()?;
         let socket = socket.as_raw_fd();
@@ -11,6 +11,7 @@ use crate::{
     generated::{
         bpf_prog_type::BPF_PROG_TYPE_SCHED_CLS, TC_H_CLSACT, TC_H_MIN_EGRESS, TC_H_MIN_INGRESS,
     },
+    obj::ProgramSection,
     programs::{load_program, Link, LinkRef, ProgramData, ProgramError},
     sys::{
         netlink_find_filter_with_name, netlink_qdisc_add_clsact, netlink_qdisc_attach,
@@ -110,6 +111,19 @@ impl TcAttachType {
 }
 
 impl SchedClassifier {
+    /// Requests that the program is offloaded to the network device with the
+    /// given interface index when it's loaded.
+    ///
+    /// Offloaded programs are verified and compiled for the device by its
+    /// driver, so loading fails if the driver doesn't support offload. This
+    /// must be called before [`load`](Self::load).
+    /// The program must then be attached to the same device with the
+    /// `skip_sw` flag.
+    pub fn set_prog_ifindex(&mut self, ifindex: u32) -> &mut SchedClassifier {
+        self.data.prog_ifindex = Some(ifindex);
+        self
+    }
+
     /// Loads the program inside the kernel.
     ///
     /// See also [`Program::load`](crate::programs::Program::load).
@@ -117,6 +131,24 @@ impl SchedClassifier {
         load_program(BPF_PROG_TYPE_SCHED_CLS, &mut self.data)
     }
 
+    /// Creates a program from the fd of a program that is already loaded.
+    ///
+    /// The name of the program reported by the kernel, truncated to 15
+    /// characters, is used as the name of the tc filter.
+    ///
+    /// Returns [`ProgramError::UnexpectedProgramType`] if `fd` isn't a
+    /// classifier. See also [`Program::from_fd`](crate::programs::Program::from_fd).
+    pub fn from_fd(fd: RawFd) -> Result<SchedClassifier, ProgramError> {
+        let data = ProgramData::from_fd(fd, BPF_PROG_TYPE_SCHED_CLS, |name| {
+            ProgramSection::SchedClassifier { name }
+        })?;
+        let name = data.name.clone().unwrap_or_default();
+        Ok(SchedClassifier {
+            data,
+            name: CString::new(name).unwrap_or_default().into_boxed_c_str(),
+        })
+    }
+
     /// Attaches the program to the given `interface`.
     ///
     /// # Errors
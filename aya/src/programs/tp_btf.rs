@@ -9,9 +9,15 @@ use crate::{
 /// a pre-defined kernel trace point.
 ///
 /// The kernel provides a set of pre-defined trace points that eBPF programs can
-/// be attached to. See `/sys/kernel/debug/tracing/events` for a list of which
+/// be attached to. See `/sys/kernel/tracing/events` for a list of which
 /// events can be traced.
 ///
+/// Unlike [`RawTracePoint`](crate::programs::RawTracePoint) programs, which
+/// are loaded as `BPF_PROG_TYPE_RAW_TRACEPOINT`, BTF-enabled tracepoints are
+/// loaded as `BPF_PROG_TYPE_TRACING` with the `BPF_TRACE_RAW_TP` expected
+/// attach type, and can access the tracepoint arguments as typed pointers to
+/// kernel structures.
+///
 /// # Minimum kernel version
 ///
 /// The minimum kernel version required to use this feature is 5.5.
@@ -43,6 +49,7 @@ use crate::{
 #[derive(Debug)]
 #[doc(alias = "BPF_TRACE_RAW_TP")]
 #[doc(alias = "BPF_PROG_TYPE_TRACING")]
+#[doc(alias = "TypedRawTracepoint")]
 pub struct BtfTracePoint {
     pub(crate) data: ProgramData,
 }
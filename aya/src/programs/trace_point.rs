@@ -1,4 +1,11 @@
-use std::{fs, io};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 
 use crate::{generated::bpf_prog_type::BPF_PROG_TYPE_TRACEPOINT, sys::perf_event_open_trace_point};
@@ -17,13 +24,20 @@ pub enum TracePointError {
         #[source]
         io_error: io::Error,
     },
+
+    /// The kernel module is not loaded
+    #[error("kernel module `{module_name}` is not loaded")]
+    ModuleNotLoaded {
+        /// The name of the module
+        module_name: String,
+    },
 }
 
 /// A program that can be attached at a pre-defined kernel trace point.
 ///
 /// The kernel provides a set of pre-defined trace points that eBPF programs can
-/// be attached to. See `/sys/kernel/debug/tracing/events` for a list of which
-/// events can be traced.
+/// be attached to. See `/sys/kernel/tracing/events` for a list of which
+/// events can be traced, or call [`available_tracepoints`].
 ///
 /// # Minimum kernel version
 ///
@@ -69,7 +83,30 @@ impl TracePoint {
     /// Attaches to a given trace point.
     ///
     /// For a list of the available event categories and names, see
-    /// `/sys/kernel/debug/tracing/events`.
+    /// `/sys/kernel/tracing/events`.
+    ///
+    /// The trace point id is read from `events/<category>/<name>/id` in
+    /// tracefs, and the program is attached with `perf_event_open`. tracefs
+    /// is looked up at:
+    ///
+    /// 1. `/sys/kernel/tracing`, where tracefs is mounted on its own. Since
+    ///    kernel 4.1 this doesn't require debugfs.
+    /// 2. `/sys/kernel/debug/tracing`, where the kernel automounts tracefs
+    ///    when debugfs is mounted.
+    ///
+    /// The kernel only exposes trace point ids through tracefs, so attaching
+    /// fails with [`TracePointError::FileError`] if it's mounted at neither
+    /// location. In containers, tracefs can be mounted with
+    /// `mount -t tracefs nodev /sys/kernel/tracing`. Alternatively
+    /// [`RawTracePoint`](crate::programs::RawTracePoint) and
+    /// [`BtfTracePoint`](crate::programs::BtfTracePoint) programs are
+    /// attached by name and don't need tracefs.
+    ///
+    /// The program runs every time the trace point is hit, on every CPU and
+    /// in every process. The kernel attaches eBPF programs to the trace point
+    /// itself rather than to the perf event, so the event can't be used to
+    /// restrict the program to a process or a CPU. To only handle some
+    /// processes, filter on `bpf_get_current_pid_tgid()` inside the program.
     pub fn attach(&mut self, category: &str, name: &str) -> Result<LinkRef, ProgramError> {
         let id = read_sys_fs_trace_point_id(category, name)?;
         let fd = perf_event_open_trace_point(id, None).map_err(|(_code, io_error)| {
@@ -81,13 +118,320 @@ impl TracePoint {
 
         perf_attach(&mut self.data, fd)
     }
+
+    /// Attaches to a trace point defined by a kernel module.
+    ///
+    /// Module trace points appear in `/sys/kernel/tracing/events` like the
+    /// other trace points, under the category chosen by the module, once the
+    /// module is loaded. This method checks that `module_name` is loaded
+    /// before attaching, so that a missing module is reported as
+    /// [`TracePointError::ModuleNotLoaded`] rather than as a missing file.
+    /// Use [`TracePoint::wait_for_module`] to wait for a module that is
+    /// being loaded.
+    ///
+    /// The program stays attached until the module is unloaded, after which
+    /// the trace point doesn't exist anymore and the program stops running.
+    pub fn attach_to_module(
+        &mut self,
+        module_name: &str,
+        category: &str,
+        name: &str,
+    ) -> Result<LinkRef, ProgramError> {
+        if !is_module_loaded(Path::new(SYS_MODULE_PATH), module_name) {
+            return Err(TracePointError::ModuleNotLoaded {
+                module_name: module_name.to_owned(),
+            }
+            .into());
+        }
+        self.attach(category, name)
+    }
+
+    /// Returns the parsed `format` file of the given trace point.
+    ///
+    /// The format describes the fields of the raw data of the trace point,
+    /// which is what eBPF programs attached to it receive and what perf
+    /// event samples of the trace point contain. See also
+    /// [`tracepoint_format`] for the unparsed file.
+    pub fn format(category: &str, name: &str) -> Result<TracePointFormat, io::Error> {
+        TracePointFormat::parse(&tracepoint_format(category, name)?)
+    }
+
+    /// Waits up to `timeout` for the kernel module `module_name` to be loaded.
+    ///
+    /// Returns [`TracePointError::ModuleNotLoaded`] if the module is still not
+    /// loaded when the timeout expires.
+    pub fn wait_for_module(module_name: &str, timeout: Duration) -> Result<(), TracePointError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if is_module_loaded(Path::new(SYS_MODULE_PATH), module_name) {
+                return Ok(());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(TracePointError::ModuleNotLoaded {
+                    module_name: module_name.to_owned(),
+                });
+            }
+            thread::sleep(MODULE_POLL_INTERVAL.min(deadline - now));
+        }
+    }
+}
+
+/// The layout of the raw data of a trace point, parsed from its `format` file.
+///
+/// See [`TracePoint::format`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracePointFormat {
+    /// The name of the trace point.
+    pub name: String,
+    /// The id of the trace point.
+    pub id: u32,
+    /// The fields shared by all the trace points, like `common_pid`.
+    pub common_fields: Vec<TracePointField>,
+    /// The fields specific to the trace point.
+    pub event_fields: Vec<TracePointField>,
+}
+
+/// A field of a trace point, see [`TracePointFormat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracePointField {
+    /// The name of the field.
+    pub name: String,
+    /// The C type of the field, eg `unsigned short` or `char[16]`.
+    pub type_name: String,
+    /// The offset of the field from the start of the raw data.
+    pub offset: usize,
+    /// The size of the field in bytes.
+    pub size: usize,
+    /// Whether the field is a signed integer.
+    pub is_signed: bool,
+}
+
+impl TracePointFormat {
+    /// Parses the contents of the `format` file of a trace point.
+    pub fn parse(format: &str) -> Result<TracePointFormat, io::Error> {
+        let mut name = None;
+        let mut id = None;
+        let mut common_fields = Vec::new();
+        let mut event_fields = Vec::new();
+        // the common fields are separated from the event fields by an
+        // empty line
+        let mut in_common = true;
+        let mut in_fields = false;
+        for line in format.lines() {
+            let line = line.trim();
+            if let Some(value) = line.strip_prefix("name:") {
+                name = Some(value.trim().to_owned());
+            } else if let Some(value) = line.strip_prefix("ID:") {
+                id = Some(value.trim().parse::<u32>().map_err(invalid_format)?);
+            } else if line == "format:" {
+                in_fields = true;
+            } else if line.starts_with("print fmt:") {
+                break;
+            } else if in_fields && line.is_empty() {
+                if !common_fields.is_empty() {
+                    in_common = false;
+                }
+            } else if in_fields {
+                let field = parse_field(line)?;
+                if in_common {
+                    common_fields.push(field);
+                } else {
+                    event_fields.push(field);
+                }
+            }
+        }
+
+        Ok(TracePointFormat {
+            name: name.ok_or_else(|| invalid_format("missing name"))?,
+            id: id.ok_or_else(|| invalid_format("missing ID"))?,
+            common_fields,
+            event_fields,
+        })
+    }
+
+    /// Decodes the integer fields of the raw data of the trace point, eg the
+    /// data of a perf event sample.
+    ///
+    /// Returns the value of each common and event field that is an integer
+    /// of 1, 2, 4 or 8 bytes, sign extended according to the format. Other
+    /// fields, like arrays and strings, and fields that don't fit in
+    /// `raw_bytes` are skipped.
+    pub fn read_event(&self, raw_bytes: &[u8]) -> HashMap<String, i64> {
+        self.common_fields
+            .iter()
+            .chain(self.event_fields.iter())
+            .filter_map(|field| Some((field.name.clone(), read_field(field, raw_bytes)?)))
+            .collect()
+    }
+}
+
+fn invalid_format<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+// parses a line like
+// `field:unsigned short common_type;	offset:0;	size:2;	signed:0;`
+fn parse_field(line: &str) -> Result<TracePointField, io::Error> {
+    let mut decl = None;
+    let mut offset = None;
+    let mut size = None;
+    let mut is_signed = false;
+    for attr in line.split(';').map(str::trim).filter(|a| !a.is_empty()) {
+        let (key, value) = match attr.find(':') {
+            Some(i) => (&attr[..i], attr[i + 1..].trim()),
+            None => return Err(invalid_format(format!("invalid field `{}`", line))),
+        };
+        match key {
+            "field" => decl = Some(value),
+            "offset" => offset = Some(value.parse::<usize>().map_err(invalid_format)?),
+            "size" => size = Some(value.parse::<usize>().map_err(invalid_format)?),
+            "signed" => is_signed = value == "1",
+            _ => {}
+        }
+    }
+    let decl = decl.ok_or_else(|| invalid_format(format!("invalid field `{}`", line)))?;
+
+    // the name is the last word of the declaration, arrays keep the size
+    // with the type, eg `char prev_comm[16]` is a `char[16]` called prev_comm
+    let (type_name, name) = match decl.rfind(' ') {
+        Some(i) => (decl[..i].trim(), &decl[i + 1..]),
+        None => return Err(invalid_format(format!("invalid field `{}`", line))),
+    };
+    let (name, type_name) = match name.find('[') {
+        Some(i) => (&name[..i], format!("{}{}", type_name, &name[i..])),
+        None => (name, type_name.to_owned()),
+    };
+
+    Ok(TracePointField {
+        name: name.to_owned(),
+        type_name,
+        offset: offset.ok_or_else(|| invalid_format(format!("invalid field `{}`", line)))?,
+        size: size.ok_or_else(|| invalid_format(format!("invalid field `{}`", line)))?,
+        is_signed,
+    })
+}
+
+fn read_field(field: &TracePointField, data: &[u8]) -> Option<i64> {
+    if field.type_name.contains('[') {
+        return None;
+    }
+    let bytes = data.get(field.offset..field.offset.checked_add(field.size)?)?;
+    let value = match (field.size, field.is_signed) {
+        (1, true) => bytes[0] as i8 as i64,
+        (1, false) => bytes[0] as i64,
+        (2, true) => i16::from_ne_bytes(bytes.try_into().ok()?) as i64,
+        (2, false) => u16::from_ne_bytes(bytes.try_into().ok()?) as i64,
+        (4, true) => i32::from_ne_bytes(bytes.try_into().ok()?) as i64,
+        (4, false) => u32::from_ne_bytes(bytes.try_into().ok()?) as i64,
+        (8, _) => i64::from_ne_bytes(bytes.try_into().ok()?),
+        _ => return None,
+    };
+    Some(value)
+}
+
+const SYS_MODULE_PATH: &str = "/sys/module";
+const MODULE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Loadable modules have an `initstate` which is `live` once their init
+// function has run, and their trace points are registered. Built-in modules
+// don't have one, but their trace points are always available.
+fn is_module_loaded(sys_module: &Path, module_name: &str) -> bool {
+    // the kernel treats dashes and underscores in module names the same, and
+    // uses underscores in sysfs
+    let module = sys_module.join(module_name.replace('-', "_"));
+    match fs::read_to_string(module.join("initstate")) {
+        Ok(state) => state.trim() == "live",
+        Err(_) => module.is_dir(),
+    }
+}
+
+// tracefs is preferred, and the tracing directory inside debugfs is used as a
+// fallback for systems where tracefs isn't mounted on its own
+const TRACEFS_PATHS: &[&str] = &["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+// returns the first of `paths` where tracefs is mounted
+fn find_tracefs_in(paths: &[&str]) -> Result<PathBuf, io::Error> {
+    paths
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.join("events").is_dir())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("tracefs isn't mounted at {}", paths.join(" or ")),
+            )
+        })
+}
+
+/// Returns the directory where tracefs is mounted.
+pub(crate) fn find_tracefs() -> Result<PathBuf, io::Error> {
+    find_tracefs_in(TRACEFS_PATHS)
+}
+
+fn find_tracefs_events() -> Result<PathBuf, io::Error> {
+    Ok(find_tracefs()?.join("events"))
+}
+
+/// Returns the `(category, name)` pairs of all the trace points available on the system.
+///
+/// The trace points are enumerated from the `events` directory of either
+/// `/sys/kernel/tracing` or `/sys/kernel/debug/tracing`. Any pair returned can
+/// be passed to [`TracePoint::attach`].
+pub fn available_tracepoints() -> Result<Vec<(String, String)>, io::Error> {
+    list_tracepoints(&find_tracefs_events()?)
+}
+
+/// Returns the contents of the `format` file of the given trace point.
+///
+/// The format describes the layout of the data passed to programs attached to
+/// the trace point.
+pub fn tracepoint_format(category: &str, name: &str) -> Result<String, io::Error> {
+    fs::read_to_string(
+        find_tracefs_events()?
+            .join(category)
+            .join(name)
+            .join("format"),
+    )
+}
+
+fn list_tracepoints(events: &Path) -> Result<Vec<(String, String)>, io::Error> {
+    let mut tracepoints = Vec::new();
+    for category in fs::read_dir(events)? {
+        let category = category?;
+        if !category.file_type()?.is_dir() {
+            continue;
+        }
+        for event in fs::read_dir(category.path())? {
+            let event = event?;
+            if !event.file_type()?.is_dir() {
+                continue;
+            }
+            tracepoints.push((
+                category.file_name().to_string_lossy().into_owned(),
+                event.file_name().to_string_lossy().into_owned(),
+            ));
+        }
+    }
+    tracepoints.sort();
+
+    Ok(tracepoints)
 }
 
 pub(crate) fn read_sys_fs_trace_point_id(
     category: &str,
     name: &str,
 ) -> Result<u32, TracePointError> {
-    let file = format!("/sys/kernel/debug/tracing/events/{}/{}/id", category, name);
+    let events = find_tracefs_events().map_err(|io_error| TracePointError::FileError {
+        filename: TRACEFS_PATHS.join(", "),
+        io_error,
+    })?;
+    let file = events
+        .join(category)
+        .join(name)
+        .join("id")
+        .to_string_lossy()
+        .into_owned();
 
     let id = fs::read_to_string(&file).map_err(|io_error| TracePointError::FileError {
         filename: file.clone(),
@@ -103,3 +447,153 @@ pub(crate) fn read_sys_fs_trace_point_id(
 
     Ok(id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_tracepoints() {
+        let events = std::env::temp_dir().join(format!("aya-test-events-{}", std::process::id()));
+        fs::create_dir_all(events.join("sched/sched_switch")).unwrap();
+        fs::create_dir_all(events.join("sched/sched_wakeup")).unwrap();
+        fs::create_dir_all(events.join("syscalls/sys_enter_open")).unwrap();
+        fs::write(events.join("enable"), "0").unwrap();
+        fs::write(events.join("sched/enable"), "0").unwrap();
+
+        let tracepoints = list_tracepoints(&events);
+        fs::remove_dir_all(&events).unwrap();
+
+        assert_eq!(
+            tracepoints.unwrap(),
+            vec![
+                ("sched".to_owned(), "sched_switch".to_owned()),
+                ("sched".to_owned(), "sched_wakeup".to_owned()),
+                ("syscalls".to_owned(), "sys_enter_open".to_owned()),
+            ]
+        );
+    }
+
+    const SCHED_SWITCH_FORMAT: &str = "name: sched_switch
+ID: 316
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:char prev_comm[16];\toffset:8;\tsize:16;\tsigned:0;
+\tfield:pid_t prev_pid;\toffset:24;\tsize:4;\tsigned:1;
+\tfield:long prev_state;\toffset:32;\tsize:8;\tsigned:1;
+
+print fmt: \"prev_comm=%s prev_pid=%d\", REC->prev_comm, REC->prev_pid
+";
+
+    #[test]
+    fn test_parse_format() {
+        let format = TracePointFormat::parse(SCHED_SWITCH_FORMAT).unwrap();
+        assert_eq!(format.name, "sched_switch");
+        assert_eq!(format.id, 316);
+        assert_eq!(format.common_fields.len(), 4);
+        assert_eq!(
+            format.common_fields[0],
+            TracePointField {
+                name: "common_type".to_owned(),
+                type_name: "unsigned short".to_owned(),
+                offset: 0,
+                size: 2,
+                is_signed: false,
+            }
+        );
+        assert_eq!(format.event_fields.len(), 3);
+        assert_eq!(format.event_fields[0].name, "prev_comm");
+        assert_eq!(format.event_fields[0].type_name, "char[16]");
+        assert_eq!(format.event_fields[1].name, "prev_pid");
+        assert!(format.event_fields[1].is_signed);
+
+        assert_eq!(
+            TracePointFormat::parse("ID: 1\nformat:\n")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_read_event() {
+        let format = TracePointFormat::parse(SCHED_SWITCH_FORMAT).unwrap();
+        let mut data = vec![0u8; 40];
+        data[0..2].copy_from_slice(&316u16.to_ne_bytes());
+        data[4..8].copy_from_slice(&(-1i32).to_ne_bytes());
+        data[8..12].copy_from_slice(b"bash");
+        data[24..28].copy_from_slice(&1234i32.to_ne_bytes());
+        data[32..40].copy_from_slice(&2i64.to_ne_bytes());
+
+        let event = format.read_event(&data);
+        assert_eq!(event["common_type"], 316);
+        assert_eq!(event["common_pid"], -1);
+        assert_eq!(event["prev_pid"], 1234);
+        assert_eq!(event["prev_state"], 2);
+        assert!(!event.contains_key("prev_comm"));
+
+        // fields past the end of the data are skipped
+        let event = format.read_event(&data[..30]);
+        assert_eq!(event["prev_pid"], 1234);
+        assert!(!event.contains_key("prev_state"));
+    }
+
+    #[test]
+    fn test_is_module_loaded() {
+        let sys_module =
+            std::env::temp_dir().join(format!("aya-test-modules-{}", std::process::id()));
+        fs::create_dir_all(sys_module.join("nf_conntrack")).unwrap();
+        fs::write(sys_module.join("nf_conntrack/initstate"), "live\n").unwrap();
+        fs::create_dir_all(sys_module.join("coming")).unwrap();
+        fs::write(sys_module.join("coming/initstate"), "coming\n").unwrap();
+        fs::create_dir_all(sys_module.join("builtin")).unwrap();
+
+        let loaded = [
+            is_module_loaded(&sys_module, "nf_conntrack"),
+            is_module_loaded(&sys_module, "nf-conntrack"),
+            is_module_loaded(&sys_module, "coming"),
+            is_module_loaded(&sys_module, "builtin"),
+            is_module_loaded(&sys_module, "missing"),
+        ];
+        fs::remove_dir_all(&sys_module).unwrap();
+
+        assert_eq!(loaded, [true, true, false, true, false]);
+    }
+
+    #[test]
+    fn test_find_tracefs() {
+        let root = std::env::temp_dir().join(format!("aya-test-tracefs-{}", std::process::id()));
+        let tracefs = root.join("tracing");
+        let debugfs = root.join("debug/tracing");
+        let missing = root.join("missing");
+        fs::create_dir_all(tracefs.join("events")).unwrap();
+        fs::create_dir_all(debugfs.join("events")).unwrap();
+        let (tracefs, debugfs, missing) = (
+            tracefs.to_str().unwrap(),
+            debugfs.to_str().unwrap(),
+            missing.to_str().unwrap(),
+        );
+
+        let found = [
+            find_tracefs_in(&[tracefs, debugfs]).ok(),
+            find_tracefs_in(&[missing, debugfs]).ok(),
+            find_tracefs_in(&[missing]).ok(),
+        ];
+        let err = find_tracefs_in(&[missing]).unwrap_err();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            found,
+            [
+                Some(PathBuf::from(tracefs)),
+                Some(PathBuf::from(debugfs)),
+                None
+            ]
+        );
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
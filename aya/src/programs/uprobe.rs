@@ -1,13 +1,13 @@
 //! User space probes.
 use libc::pid_t;
-use object::{Object, ObjectSymbol};
+use object::{Object, ObjectSegment, ObjectSymbol};
 use std::{
     error::Error,
     ffi::CStr,
     fs,
     io::{self, BufRead, Cursor, Read},
     mem,
-    os::raw::c_char,
+    os::{raw::c_char, unix::io::RawFd},
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -15,6 +15,7 @@ use thiserror::Error;
 
 use crate::{
     generated::bpf_prog_type::BPF_PROG_TYPE_KPROBE,
+    obj::ProgramSection,
     programs::{
         load_program,
         probe::{attach, ProbeKind},
@@ -52,6 +53,23 @@ impl UProbe {
         load_program(BPF_PROG_TYPE_KPROBE, &mut self.data)
     }
 
+    /// Creates a program from the fd of a program that is already loaded.
+    ///
+    /// The kernel can't tell the kinds of probes apart, so `kind` must match
+    /// the one the program was written for.
+    ///
+    /// Returns [`ProgramError::UnexpectedProgramType`] if `fd` isn't a probe
+    /// program or if `kind` isn't `UProbe` or `URetProbe`.
+    pub fn from_fd(fd: RawFd, kind: ProbeKind) -> Result<UProbe, ProgramError> {
+        if !matches!(kind, ProbeKind::UProbe | ProbeKind::URetProbe) {
+            return Err(ProgramError::UnexpectedProgramType);
+        }
+        let data = ProgramData::from_fd(fd, BPF_PROG_TYPE_KPROBE, |name| ProgramSection::UProbe {
+            name,
+        })?;
+        Ok(UProbe { data, kind })
+    }
+
     /// Returns `UProbe` if the program is a `uprobe`, or `URetProbe` if the
     /// program is a `uretprobe`.
     pub fn kind(&self) -> ProbeKind {
@@ -79,7 +97,64 @@ impl UProbe {
         target: T,
         pid: Option<pid_t>,
     ) -> Result<LinkRef, ProgramError> {
-        let target = target.as_ref();
+        self.attach_inner(fn_name, offset, target.as_ref(), pid, None)
+    }
+
+    /// Attaches the program, setting the cookie returned by
+    /// `bpf_get_attach_cookie()` in the program.
+    ///
+    /// The cookie lets a program attached to several functions tell which
+    /// attachment is running. See [`attach`](Self::attach) for the meaning of
+    /// the other arguments.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.15.
+    pub fn attach_with_cookie<T: AsRef<Path>>(
+        &mut self,
+        fn_name: Option<&str>,
+        offset: u64,
+        target: T,
+        pid: Option<pid_t>,
+        cookie: u64,
+    ) -> Result<LinkRef, ProgramError> {
+        self.attach_inner(fn_name, offset, target.as_ref(), pid, Some(cookie))
+    }
+
+    /// Returns the file offset of the symbol `symbol_name` in the ELF binary
+    /// `binary_path`.
+    ///
+    /// The dynamic and then the static symbol table are searched, and the
+    /// virtual address of the symbol is translated to an offset in the file
+    /// using the segment that contains it, which is what uprobes are attached
+    /// to. This works for both executables (`ET_EXEC`) and shared objects or
+    /// position independent executables (`ET_DYN`).
+    ///
+    /// [`attach`](Self::attach) resolves the symbol passed as `fn_name` the
+    /// same way.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UProbeError::SymbolNotFound`] if the binary doesn't define
+    /// the symbol.
+    pub fn resolve_offset(binary_path: &Path, symbol_name: &str) -> Result<u64, UProbeError> {
+        resolve_symbol(binary_path, symbol_name).map_err(|error| match error {
+            ResolveSymbolError::Unknown(symbol) => UProbeError::SymbolNotFound { symbol },
+            error => UProbeError::SymbolError {
+                symbol: symbol_name.to_string(),
+                error: Box::new(error),
+            },
+        })
+    }
+
+    fn attach_inner(
+        &mut self,
+        fn_name: Option<&str>,
+        offset: u64,
+        target: &Path,
+        pid: Option<pid_t>,
+        cookie: Option<u64>,
+    ) -> Result<LinkRef, ProgramError> {
         let target_str = &*target.as_os_str().to_string_lossy();
 
         let mut path = if let Some(pid) = pid {
@@ -111,15 +186,20 @@ impl UProbe {
         })?;
 
         let sym_offset = if let Some(fn_name) = fn_name {
-            resolve_symbol(&path, fn_name).map_err(|error| UProbeError::SymbolError {
-                symbol: fn_name.to_string(),
-                error: Box::new(error),
-            })?
+            UProbe::resolve_offset(Path::new(&path), fn_name)?
         } else {
             0
         };
 
-        attach(&mut self.data, self.kind, &path, sym_offset + offset, pid)
+        attach(
+            &mut self.data,
+            self.kind,
+            &path,
+            sym_offset + offset,
+            0,
+            pid,
+            cookie,
+        )
     }
 }
 
@@ -151,6 +231,13 @@ pub enum UProbeError {
         error: Box<dyn Error + Send + Sync>,
     },
 
+    /// The target symbol is not defined in the binary.
+    #[error("unknown symbol `{symbol}`")]
+    SymbolNotFound {
+        /// symbol name
+        symbol: String,
+    },
+
     /// There was an error accessing `filename`.
     #[error("`{filename}`")]
     FileError {
@@ -297,13 +384,65 @@ enum ResolveSymbolError {
     Unknown(String),
 }
 
-fn resolve_symbol(path: &str, symbol: &str) -> Result<u64, ResolveSymbolError> {
+fn resolve_symbol(path: &Path, symbol: &str) -> Result<u64, ResolveSymbolError> {
     let data = fs::read(path)?;
     let obj = object::read::File::parse(&*data)?;
 
-    obj.dynamic_symbols()
+    let address = obj
+        .dynamic_symbols()
         .chain(obj.symbols())
+        // undefined symbols, eg functions imported from shared libraries,
+        // have address 0
+        .filter(|sym| sym.is_definition())
         .find(|sym| sym.name().map(|name| name == symbol).unwrap_or(false))
         .map(|s| s.address())
-        .ok_or_else(|| ResolveSymbolError::Unknown(symbol.to_string()))
+        .ok_or_else(|| ResolveSymbolError::Unknown(symbol.to_string()))?;
+
+    Ok(address_to_offset(&obj, address))
+}
+
+// Translates a virtual address to a file offset using the loadable segment
+// containing it. The two are usually equal for ET_DYN binaries, but
+// executables are linked at a base address, eg 0x400000 on x86_64.
+fn address_to_offset(obj: &object::read::File<'_>, address: u64) -> u64 {
+    obj.segments()
+        .find_map(|segment| {
+            let start = segment.address();
+            let (offset, size) = segment.file_range();
+            if address >= start && address < start + size {
+                Some(address - start + offset)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_offset() {
+        // the test binary defines main
+        let exe = Path::new("/proc/self/exe");
+        let offset = UProbe::resolve_offset(exe, "main").unwrap();
+        assert!(offset < fs::metadata(exe).unwrap().len());
+
+        assert!(matches!(
+            UProbe::resolve_offset(exe, "aya_no_such_symbol"),
+            Err(UProbeError::SymbolNotFound { symbol }) if symbol == "aya_no_such_symbol"
+        ));
+    }
+
+    #[test]
+    fn test_resolve_offset_undefined() {
+        // getrlimit is imported from the C library, the test binary only has
+        // an undefined dynamic symbol for it
+        let exe = Path::new("/proc/self/exe");
+        assert!(matches!(
+            UProbe::resolve_offset(exe, "getrlimit"),
+            Err(UProbeError::SymbolNotFound { symbol }) if symbol == "getrlimit"
+        ));
+    }
 }
@@ -0,0 +1,363 @@
+//! User statically-defined tracepoints.
+use libc::pid_t;
+use object::{Object, ObjectSection, ObjectSegment};
+use std::{
+    convert::TryInto,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+use crate::{
+    generated::bpf_prog_type::BPF_PROG_TYPE_KPROBE,
+    programs::{
+        load_program,
+        probe::{attach, ProbeKind},
+        LinkRef, ProgramData, ProgramError,
+    },
+    sys::kernel_version,
+};
+
+const STAPSDT_NOTE_SECTION: &str = ".note.stapsdt";
+const STAPSDT_BASE_SECTION: &str = ".stapsdt.base";
+const STAPSDT_NOTE_NAME: &[u8] = b"stapsdt";
+const STAPSDT_NOTE_TYPE: u32 = 3;
+
+/// A user statically-defined tracepoint (USDT) program.
+///
+/// USDT probes are markers compiled into user space binaries, for example
+/// with the `DTRACE_PROBE` macros of `<sys/sdt.h>`. Many runtimes and
+/// databases ship with USDT probes. The probes are described in the
+/// `.note.stapsdt` section of the binary, and [`Usdt::list_probes`] can be
+/// used to enumerate them.
+///
+/// Some probes are guarded by a semaphore, a counter in the binary that the
+/// traced code checks to skip preparing the probe arguments when nothing is
+/// attached. The kernel increments the semaphore when the probe is attached
+/// and decrements it when the probe is detached.
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 4.17, or 4.20
+/// for probes guarded by a semaphore.
+///
+/// # Examples
+///
+/// ```no_run
+/// # let mut bpf = aya::Bpf::load(&[])?;
+/// use aya::programs::Usdt;
+/// use std::convert::TryInto;
+///
+/// let program: &mut Usdt = bpf.program_mut("query_start").unwrap().try_into()?;
+/// program.load()?;
+///
+/// let probes = Usdt::list_probes("/usr/bin/postgres", Some("postgresql"))?;
+/// let probe = probes.iter().find(|p| p.name == "query__start").unwrap();
+/// program.attach(probe, None)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+#[doc(alias = "BPF_PROG_TYPE_KPROBE")]
+pub struct Usdt {
+    pub(crate) data: ProgramData,
+}
+
+impl Usdt {
+    /// Loads the program inside the kernel.
+    ///
+    /// See also [`Program::load`](crate::programs::Program::load).
+    pub fn load(&mut self) -> Result<(), ProgramError> {
+        load_program(BPF_PROG_TYPE_KPROBE, &mut self.data)
+    }
+
+    /// Returns the USDT probes defined in the binary or library at `path`.
+    ///
+    /// If `provider` is not `None`, only the probes of the given provider are
+    /// returned.
+    pub fn list_probes<P: AsRef<Path>>(
+        path: P,
+        provider: Option<&str>,
+    ) -> Result<Vec<UsdtProbe>, UsdtError> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|io_error| UsdtError::FileError {
+            filename: path.to_owned(),
+            io_error,
+        })?;
+        let probes = parse_probes(path, &data).map_err(|error| UsdtError::InvalidNotes {
+            filename: path.to_owned(),
+            error,
+        })?;
+
+        Ok(probes
+            .into_iter()
+            .filter(|probe| provider.map(|p| probe.provider == p).unwrap_or(true))
+            .collect())
+    }
+
+    /// Attaches the program to the given probe.
+    ///
+    /// If `pid` is not `None`, the program executes only when the probe is hit
+    /// by the given `pid`. If the probe is guarded by a semaphore, the kernel
+    /// keeps the semaphore incremented while the program is attached.
+    pub fn attach(
+        &mut self,
+        probe: &UsdtProbe,
+        pid: Option<pid_t>,
+    ) -> Result<LinkRef, ProgramError> {
+        let k_ver = kernel_version().unwrap();
+        if probe.semaphore_offset != 0 && k_ver < (4, 20, 0) {
+            return Err(UsdtError::SemaphoreNotSupported.into());
+        }
+        let path = probe.path.to_string_lossy();
+
+        attach(
+            &mut self.data,
+            ProbeKind::UProbe,
+            &path,
+            probe.offset,
+            probe.semaphore_offset,
+            pid,
+            None,
+        )
+    }
+}
+
+/// A USDT probe defined in a binary.
+///
+/// See [`Usdt::list_probes`].
+#[derive(Debug, Clone)]
+pub struct UsdtProbe {
+    /// The path of the binary defining the probe.
+    pub path: PathBuf,
+    /// The provider of the probe.
+    pub provider: String,
+    /// The name of the probe.
+    pub name: String,
+    /// The address of the probe.
+    pub address: u64,
+    /// The address of the semaphore guarding the probe, or zero if the probe
+    /// doesn't have a semaphore.
+    pub semaphore_addr: u64,
+    /// The description of the probe arguments, for example `-4@%edi 8@%rsi`.
+    pub args: String,
+    offset: u64,
+    semaphore_offset: u64,
+}
+
+/// The type returned when working with [`Usdt`] programs fails.
+#[derive(Debug, Error)]
+pub enum UsdtError {
+    /// There was an error reading `filename`.
+    #[error("`{filename}`")]
+    FileError {
+        /// The file name
+        filename: PathBuf,
+        /// The [`io::Error`] returned from the file operation
+        #[source]
+        io_error: io::Error,
+    },
+
+    /// The USDT notes of `filename` could not be parsed.
+    #[error("invalid USDT notes in `{filename}`: {error}")]
+    InvalidNotes {
+        /// The file name
+        filename: PathBuf,
+        /// The error message
+        error: String,
+    },
+
+    /// The probe is guarded by a semaphore, which requires kernel 4.20 or
+    /// later.
+    #[error("USDT semaphores are not supported by the running kernel")]
+    SemaphoreNotSupported,
+}
+
+fn parse_probes(path: &Path, data: &[u8]) -> Result<Vec<UsdtProbe>, String> {
+    let obj = object::read::File::parse(data).map_err(|e| e.to_string())?;
+    let notes = match obj.section_by_name(STAPSDT_NOTE_SECTION) {
+        Some(section) => section.data().map_err(|e| e.to_string())?,
+        None => return Ok(Vec::new()),
+    };
+    let base_addr = obj
+        .section_by_name(STAPSDT_BASE_SECTION)
+        .map(|section| section.address());
+
+    // the file offset of an address, found through the segment containing it
+    let file_offset = |address: u64| {
+        obj.segments()
+            .find(|segment| {
+                address >= segment.address() && address < segment.address() + segment.size()
+            })
+            .map(|segment| address - segment.address() + segment.file_range().0)
+            .ok_or_else(|| format!("address {:#x} is not mapped by any segment", address))
+    };
+
+    parse_notes(notes, obj.is_64(), obj.is_little_endian())?
+        .into_iter()
+        .map(|note| {
+            // the note records the address of .stapsdt.base at link time, if
+            // the binary was prelinked the probe addresses must be adjusted
+            let address = match base_addr {
+                Some(base_addr) => note.pc.wrapping_add(base_addr.wrapping_sub(note.base)),
+                None => note.pc,
+            };
+            let semaphore_offset = if note.semaphore != 0 {
+                file_offset(note.semaphore)?
+            } else {
+                0
+            };
+            Ok(UsdtProbe {
+                path: path.to_owned(),
+                provider: note.provider,
+                name: note.name,
+                address,
+                semaphore_addr: note.semaphore,
+                args: note.args,
+                offset: file_offset(address)?,
+                semaphore_offset,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct StapsdtNote {
+    pc: u64,
+    base: u64,
+    semaphore: u64,
+    provider: String,
+    name: String,
+    args: String,
+}
+
+fn parse_notes(data: &[u8], is_64: bool, little_endian: bool) -> Result<Vec<StapsdtNote>, String> {
+    let read_u32 = |data: &[u8], offset: usize| -> Result<u32, String> {
+        let bytes: [u8; 4] = data
+            .get(offset..offset + 4)
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| "truncated note".to_owned())?;
+        Ok(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let read_addr = |data: &[u8], offset: usize| -> Result<u64, String> {
+        if is_64 {
+            let bytes: [u8; 8] = data
+                .get(offset..offset + 8)
+                .and_then(|b| b.try_into().ok())
+                .ok_or_else(|| "truncated note".to_owned())?;
+            Ok(if little_endian {
+                u64::from_le_bytes(bytes)
+            } else {
+                u64::from_be_bytes(bytes)
+            })
+        } else {
+            read_u32(data, offset).map(u64::from)
+        }
+    };
+    let align4 = |n: usize| (n + 3) & !3;
+
+    let mut notes = Vec::new();
+    let mut offset = 0;
+    // each note has the form `namesz descsz type name desc`, with name and
+    // desc padded to 4 bytes
+    while offset + 12 <= data.len() {
+        let name_size = read_u32(data, offset)? as usize;
+        let desc_size = read_u32(data, offset + 4)? as usize;
+        let note_type = read_u32(data, offset + 8)?;
+        let name_start = offset + 12;
+        let desc_start = name_start + align4(name_size);
+        let desc_end = desc_start + desc_size;
+        let name = data
+            .get(name_start..name_start + name_size)
+            .ok_or_else(|| "truncated note".to_owned())?;
+        let desc = data
+            .get(desc_start..desc_end)
+            .ok_or_else(|| "truncated note".to_owned())?;
+        offset = desc_start + align4(desc_size);
+
+        if note_type != STAPSDT_NOTE_TYPE || name.strip_suffix(&[0]) != Some(STAPSDT_NOTE_NAME) {
+            continue;
+        }
+
+        // desc is `pc base semaphore provider\0 name\0 args\0`
+        let addr_size = if is_64 { 8 } else { 4 };
+        let pc = read_addr(desc, 0)?;
+        let base = read_addr(desc, addr_size)?;
+        let semaphore = read_addr(desc, 2 * addr_size)?;
+        let mut strings = desc
+            .get(3 * addr_size..)
+            .ok_or_else(|| "truncated note".to_owned())?
+            .split(|b| *b == 0)
+            .map(|s| String::from_utf8_lossy(s).into_owned());
+        let provider = strings
+            .next()
+            .ok_or_else(|| "missing provider".to_owned())?;
+        let name = strings.next().ok_or_else(|| "missing name".to_owned())?;
+        let args = strings.next().unwrap_or_default();
+
+        notes.push(StapsdtNote {
+            pc,
+            base,
+            semaphore,
+            provider,
+            name,
+            args,
+        });
+    }
+
+    Ok(notes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(name: &[u8], note_type: u32, desc: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        data.extend_from_slice(&note_type.to_le_bytes());
+        data.extend_from_slice(name);
+        data.resize((data.len() + 3) & !3, 0);
+        data.extend_from_slice(desc);
+        data.resize((data.len() + 3) & !3, 0);
+        data
+    }
+
+    #[test]
+    fn test_parse_notes() {
+        let mut desc = Vec::new();
+        desc.extend_from_slice(&0x1130u64.to_le_bytes());
+        desc.extend_from_slice(&0x2004u64.to_le_bytes());
+        desc.extend_from_slice(&0x4010u64.to_le_bytes());
+        desc.extend_from_slice(b"myapp\0query__start\0-4@%edi 8@%rsi\0");
+
+        let mut data = note(b"GNU\0", 1, &[0; 16]);
+        data.extend(note(b"stapsdt\0", STAPSDT_NOTE_TYPE, &desc));
+
+        let notes = parse_notes(&data, true, true).unwrap();
+        assert_eq!(
+            notes,
+            vec![StapsdtNote {
+                pc: 0x1130,
+                base: 0x2004,
+                semaphore: 0x4010,
+                provider: "myapp".to_owned(),
+                name: "query__start".to_owned(),
+                args: "-4@%edi 8@%rsi".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_truncated_note() {
+        let mut data = note(b"stapsdt\0", STAPSDT_NOTE_TYPE, &[0; 8]);
+        assert!(parse_notes(&data, true, true).is_err());
+
+        data.truncate(14);
+        assert!(parse_notes(&data, true, true).is_err());
+    }
+}
@@ -1,9 +1,14 @@
 //! Common functions shared between multiple eBPF program types.
-use std::{ffi::CStr, os::unix::io::RawFd};
+use std::{ffi::CStr, io, os::unix::io::RawFd};
+
+use libc::{close, ENOENT};
+use object::Endianness;
 
 use crate::{
-    programs::{FdLink, LinkRef, ProgramData, ProgramError},
-    sys::bpf_raw_tracepoint_open,
+    obj::btf::BtfKind,
+    programs::{ExtensionError, FdLink, LinkRef, ProgramData, ProgramError},
+    sys::{self, bpf_raw_tracepoint_open},
+    Btf,
 };
 
 /// Attaches the program to a raw tracepoint.
@@ -22,3 +27,212 @@ pub(crate) fn attach_raw_tracepoint(
 
     Ok(program_data.link(FdLink { fd: Some(pfd) }))
 }
+
+/// Returns the BTF of the loaded program `prog_fd` along with its fd.
+pub(crate) fn get_btf_info(prog_fd: RawFd) -> Result<(RawFd, Btf), ProgramError> {
+    let info =
+        sys::bpf_obj_get_info_by_fd(prog_fd).map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            io_error,
+        })?;
+
+    if info.btf_id == 0 {
+        return Err(ProgramError::ExtensionError(ExtensionError::NoBTF));
+    }
+
+    let btf_fd =
+        sys::bpf_btf_get_fd_by_id(info.btf_id).map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_btf_get_fd_by_id".to_owned(),
+            io_error,
+        })?;
+
+    let data = sys::btf_obj_get_data(btf_fd).map_err(|io_error| ProgramError::SyscallError {
+        call: "bpf_obj_get_info_by_fd".to_owned(),
+        io_error,
+    })?;
+
+    let btf = Btf::parse(&data, Endianness::default()).map_err(ProgramError::Btf)?;
+
+    Ok((btf_fd as RawFd, btf))
+}
+
+/// Finds the function `func_name` in the BTF of the kernel module `module`.
+///
+/// The BTF of modules is split BTF: its type ids continue the ones of the
+/// kernel BTF, so the function is looked up in the chain formed by the
+/// kernel BTF followed by the module BTF. Returns the fd of the BTF object
+/// defining the function, which must be passed as `attach_btf_obj_fd` when
+/// loading the program, the id of the function and the merged BTF. Functions
+/// that the module doesn't define are found in the kernel BTF, and the fd of
+/// the `vmlinux` BTF object is returned for them.
+pub(crate) fn find_func_in_btf_chain(
+    module: &str,
+    func_name: &str,
+) -> Result<(RawFd, u32, Btf), ProgramError> {
+    let base = Btf::from_sys_fs()?;
+    let btf = Btf::from_sys_fs_module(module, &base)?;
+    let (object, btf_id) = find_func_btf_object(&base, &btf, module, func_name)?;
+    let btf_fd = kernel_module_btf_fd(object)?;
+    Ok((btf_fd, btf_id, btf))
+}
+
+// returns the name of the BTF object defining `func_name`, `module` or
+// `vmlinux`, and the id of the function in `btf`, the split BTF of `module`
+// on top of `base`
+fn find_func_btf_object<'a>(
+    base: &Btf,
+    btf: &Btf,
+    module: &'a str,
+    func_name: &str,
+) -> Result<(&'a str, u32), ProgramError> {
+    let btf_id = btf.id_by_type_name_kind(func_name, BtfKind::Func)?;
+    // the types of the kernel come first in the chain
+    if (btf_id as usize) < base.types().count() {
+        Ok(("vmlinux", btf_id))
+    } else {
+        Ok((module, btf_id))
+    }
+}
+
+// returns the fd of the BTF object the kernel created for `module`, or for
+// the kernel itself if `module` is `vmlinux`
+fn kernel_module_btf_fd(module: &str) -> Result<RawFd, ProgramError> {
+    let mut id = 0;
+    loop {
+        id = match sys::bpf_btf_get_next_id(id).map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_btf_get_next_id".to_owned(),
+            io_error,
+        })? {
+            Some(id) => id,
+            None => break,
+        };
+        let fd = match sys::bpf_btf_get_fd_by_id(id) {
+            Ok(fd) => fd,
+            // the object was released after its id was returned
+            Err(e) if e.raw_os_error() == Some(ENOENT) => continue,
+            Err(io_error) => {
+                return Err(ProgramError::SyscallError {
+                    call: "bpf_btf_get_fd_by_id".to_owned(),
+                    io_error,
+                })
+            }
+        };
+        match sys::btf_obj_get_kernel_name(fd) {
+            Ok(Some(name)) if name == module => return Ok(fd),
+            Ok(_) => unsafe {
+                close(fd);
+            },
+            Err(io_error) => {
+                unsafe { close(fd) };
+                return Err(ProgramError::SyscallError {
+                    call: "bpf_obj_get_info_by_fd".to_owned(),
+                    io_error,
+                });
+            }
+        }
+    }
+
+    Err(ProgramError::SyscallError {
+        call: "bpf_btf_get_next_id".to_owned(),
+        io_error: io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no BTF object for kernel module `{}`", module),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        generated::{bpf_attr, bpf_btf_info, bpf_cmd, btf_func_linkage, BTF_INT_SIGNED},
+        obj::btf::BtfType,
+        sys::{override_syscall, Syscall},
+    };
+
+    use super::*;
+
+    fn add_func(btf: &mut Btf, name: &str, proto_id: u32) -> u32 {
+        let name_off = btf.add_string(name.to_string());
+        btf.add_type(BtfType::new_func(
+            name_off,
+            proto_id,
+            btf_func_linkage::BTF_FUNC_GLOBAL,
+        ))
+    }
+
+    #[test]
+    fn test_find_func_btf_object() {
+        let mut base = Btf::new();
+        let name_off = base.add_string("int".to_string());
+        let int_id = base.add_type(BtfType::new_int(name_off, 4, BTF_INT_SIGNED, 0));
+        let proto_id = base.add_type(BtfType::new_func_proto(Vec::new(), int_id));
+        let vfs_read = add_func(&mut base, "vfs_read", proto_id);
+        // the split BTF of the module continues the ids of the kernel
+        let mut btf = base.clone();
+        let nvme_probe = add_func(&mut btf, "nvme_probe", proto_id);
+
+        assert_eq!(
+            find_func_btf_object(&base, &btf, "nvme", "nvme_probe").unwrap(),
+            ("nvme", nvme_probe)
+        );
+        assert_eq!(
+            find_func_btf_object(&base, &btf, "nvme", "vfs_read").unwrap(),
+            ("vmlinux", vfs_read)
+        );
+        assert!(find_func_btf_object(&base, &btf, "nvme", "xfs_read").is_err());
+    }
+
+    #[test]
+    fn test_kernel_module_btf_fd() {
+        // the BTF objects 1 to 4 are vmlinux, an object released before its
+        // fd could be retrieved, nvme and the BTF of a program
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_BTF_GET_NEXT_ID,
+                attr,
+            } => {
+                let start_id = unsafe { attr.__bindgen_anon_6.__bindgen_anon_1.start_id };
+                if start_id >= 4 {
+                    return Err((-1, io::Error::from_raw_os_error(ENOENT)));
+                }
+                // the kernel writes the next id into the attr
+                let attr = attr as *const bpf_attr as *mut bpf_attr;
+                unsafe { (*attr).__bindgen_anon_6.next_id = start_id + 1 };
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_BTF_GET_FD_BY_ID,
+                attr,
+            } => match unsafe { attr.__bindgen_anon_6.__bindgen_anon_1.btf_id } {
+                2 => Err((-1, io::Error::from_raw_os_error(ENOENT))),
+                // fds that are surely not open, so closing them is harmless
+                id => Ok(10_000 + id as i64),
+            },
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_btf_info) };
+                let name: &[u8] = match unsafe { attr.info.bpf_fd } {
+                    10_001 => b"vmlinux\0",
+                    10_003 => b"nvme\0",
+                    _ => return Ok(0),
+                };
+                info.kernel_btf = 1;
+                unsafe {
+                    std::ptr::copy_nonoverlapping(name.as_ptr(), info.name as *mut u8, name.len())
+                };
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(libc::EFAULT))),
+        });
+
+        assert_eq!(kernel_module_btf_fd("nvme").unwrap(), 10_003);
+        assert_eq!(kernel_module_btf_fd("vmlinux").unwrap(), 10_001);
+        assert!(matches!(
+            kernel_module_btf_fd("xfs"),
+            Err(ProgramError::SyscallError { io_error, .. })
+                if io_error.kind() == io::ErrorKind::NotFound
+        ));
+    }
+}
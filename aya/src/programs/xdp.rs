@@ -1,20 +1,26 @@
 use bitflags;
-use libc::if_nametoindex;
-use std::{ffi::CString, io, os::unix::io::RawFd};
+use libc::{if_nametoindex, EACCES, EBUSY, EEXIST, EOPNOTSUPP, EPERM};
+use std::{ffi::CString, io, ops::DerefMut, os::unix::io::RawFd, path::Path};
 use thiserror::Error;
 
 use crate::{
     generated::{
-        bpf_attach_type::{self, BPF_XDP},
+        bpf_attach_type::{self, BPF_XDP, BPF_XDP_CPUMAP, BPF_XDP_DEVMAP},
         bpf_prog_type::BPF_PROG_TYPE_XDP,
         XDP_FLAGS_DRV_MODE, XDP_FLAGS_HW_MODE, XDP_FLAGS_REPLACE, XDP_FLAGS_SKB_MODE,
         XDP_FLAGS_UPDATE_IF_NOEXIST,
     },
-    programs::{load_program, FdLink, Link, LinkRef, ProgramData, ProgramError},
-    sys::{bpf_link_create, kernel_version, netlink_set_xdp_fd},
+    maps::{CpuMap, DevMap, Map},
+    obj::ProgramSection,
+    programs::{load_program, prog_info_by_fd, FdLink, Link, LinkRef, ProgramData, ProgramError},
+    sys::{
+        bpf_link_create, kernel_version, netlink_list_interfaces, netlink_set_xdp_fd,
+        netlink_xdp_rx_metadata_features, BPF_F_XDP_DEV_BOUND_ONLY,
+    },
+    util::{netns::NetNs, IfaceInfo},
 };
 
-/// The type returned when attaching an [`Xdp`] program fails on kernels `< 5.9`.
+/// The type returned when attaching an [`Xdp`] program fails.
 #[derive(Debug, Error)]
 pub enum XdpError {
     /// netlink error while attaching XDP program
@@ -24,8 +30,56 @@ pub enum XdpError {
         #[source]
         io_error: io::Error,
     },
+
+    /// another XDP program is already attached to the interface
+    #[error("an XDP program is already attached to interface {ifindex}")]
+    AlreadyAttached {
+        /// the index of the interface
+        ifindex: u32,
+    },
+
+    /// the network driver doesn't support the requested XDP mode
+    #[error("the network driver doesn't support the requested XDP mode")]
+    DriverNotSupported,
+
+    /// the caller doesn't have the permissions needed to attach the program
+    #[error("permission denied while attaching XDP program")]
+    PermissionDenied,
+
+    /// the program was loaded for a different attach type
+    #[error("the program was loaded for {attach_type:?} attachment, expected {expected:?}")]
+    InvalidAttachType {
+        /// the attach type the program was loaded with
+        attach_type: XdpAttachType,
+        /// the attach type required by the operation
+        expected: XdpAttachType,
+    },
+
+    /// the network driver doesn't implement some of the requested metadata
+    #[error("the network driver doesn't support the XDP metadata {features:?}")]
+    MetadataNotSupported {
+        /// the requested features the driver doesn't implement
+        features: XdpMetadataFeatures,
+    },
 }
 
+impl XdpError {
+    /// Maps the errno from a failed attach to the matching error, if any.
+    fn from_attach_error(io_error: &io::Error, if_index: RawFd) -> Option<XdpError> {
+        match io_error.raw_os_error()? {
+            EBUSY | EEXIST => Some(XdpError::AlreadyAttached {
+                ifindex: if_index as u32,
+            }),
+            EOPNOTSUPP | ENOTSUPP => Some(XdpError::DriverNotSupported),
+            EPERM | EACCES => Some(XdpError::PermissionDenied),
+            _ => None,
+        }
+    }
+}
+
+// ENOTSUPP is kernel internal but can leak to userspace from drivers.
+const ENOTSUPP: i32 = 524;
+
 bitflags! {
     /// Flags passed to [`Xdp::attach()`].
     #[derive(Default)]
@@ -43,6 +97,53 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// The packet metadata [`Xdp`] programs read with the XDP metadata
+    /// kfuncs, passed to [`Xdp::attach_with_metadata()`].
+    pub struct XdpMetadataFeatures: u64 {
+        /// The hardware receive timestamp, read with
+        /// `bpf_xdp_metadata_rx_timestamp()`.
+        const HW_TIMESTAMP = 1 << 0;
+        /// The receive hash, read with `bpf_xdp_metadata_rx_hash()`.
+        const RX_HASH = 1 << 1;
+        /// The stripped VLAN tag, read with `bpf_xdp_metadata_rx_vlan_tag()`.
+        const RX_VLAN_TAG = 1 << 2;
+    }
+}
+
+/// Defines where an [`Xdp`] program is attached.
+///
+/// The attach type is selected with the ELF section name: `xdp` for
+/// interfaces, `xdp/cpumap` for cpumap entries and `xdp/devmap` for devmap
+/// entries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum XdpAttachType {
+    /// The program runs on the packets received by a network interface.
+    Interface,
+    /// The program runs on the packets redirected to a [`CpuMap`] entry.
+    CpuMap,
+    /// The program runs on the packets redirected to a [`DevMap`] entry.
+    DevMap,
+}
+
+impl XdpAttachType {
+    pub(crate) fn bpf_attach_type(self) -> bpf_attach_type {
+        match self {
+            XdpAttachType::Interface => BPF_XDP,
+            XdpAttachType::CpuMap => BPF_XDP_CPUMAP,
+            XdpAttachType::DevMap => BPF_XDP_DEVMAP,
+        }
+    }
+
+    fn section(self) -> fn(String) -> ProgramSection {
+        match self {
+            XdpAttachType::Interface => |name| ProgramSection::Xdp { name },
+            XdpAttachType::CpuMap => |name| ProgramSection::XdpCpuMap { name },
+            XdpAttachType::DevMap => |name| ProgramSection::XdpDevMap { name },
+        }
+    }
+}
+
 /// An XDP program.
 ///
 /// eXpress Data Path (XDP) programs can be attached to the very early stages of network
@@ -54,6 +155,26 @@ bitflags! {
 ///
 /// The minimum kernel version required to use this feature is 4.8.
 ///
+/// # Redirect chains
+///
+/// Programs attached to an interface with [`Xdp::attach`] run first, on
+/// every packet received by the interface. When such a program redirects a
+/// packet to a [`CpuMap`] or [`DevMap`] entry with `bpf_redirect_map()`,
+/// the program of the entry, if any, runs next:
+///
+/// * a cpumap program runs on the target CPU, after the packet is dequeued
+///   and before an skb is built for the network stack. It can drop the
+///   packet, pass it to the stack of that CPU or redirect it again.
+/// * a devmap program runs just before the packet is transmitted by the
+///   target interface, with the egress interface available in the context.
+///   It can drop the packet or let it be transmitted.
+///
+/// Entry programs must be loaded from `xdp/cpumap` or `xdp/devmap` sections
+/// and are attached with [`Xdp::attach_to_cpumap`] and
+/// [`Xdp::attach_to_devmap`], which store the program in the map entry
+/// rather than creating a link. Replacing or clearing the entry detaches the
+/// program.
+///
 /// # Examples
 ///
 /// ```no_run
@@ -69,17 +190,116 @@ bitflags! {
 #[doc(alias = "BPF_PROG_TYPE_XDP")]
 pub struct Xdp {
     pub(crate) data: ProgramData,
+    pub(crate) attach_type: XdpAttachType,
 }
 
 impl Xdp {
+    /// Requests that the program is offloaded to the network device with the
+    /// given interface index when it's loaded.
+    ///
+    /// Offloaded programs are verified and compiled for the device by its
+    /// driver, so loading fails if the driver doesn't support offload. This
+    /// must be called before [`load`](Self::load).
+    /// The program must then be attached with [`XdpFlags::HW_MODE`].
+    pub fn set_prog_ifindex(&mut self, ifindex: u32) -> &mut Xdp {
+        self.data.prog_ifindex = Some(ifindex);
+        self
+    }
+
+    /// Requests that the program is bound to the network device with the
+    /// given interface index when it's loaded.
+    ///
+    /// Unlike [`set_prog_ifindex`](Self::set_prog_ifindex), the program still
+    /// runs on the host, but it can call the XDP metadata kfuncs implemented
+    /// by the driver of the device, and can only be attached to that device.
+    /// This must be called before [`load`](Self::load).
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 6.3.
+    pub fn set_dev_bound_ifindex(&mut self, ifindex: u32) -> &mut Xdp {
+        self.data.prog_ifindex = Some(ifindex);
+        self.data.prog_flags |= BPF_F_XDP_DEV_BOUND_ONLY;
+        self
+    }
+
     /// Loads the program inside the kernel.
     ///
     /// See also [`Program::load`](crate::programs::Program::load).
     pub fn load(&mut self) -> Result<(), ProgramError> {
-        self.data.expected_attach_type = Some(bpf_attach_type::BPF_XDP);
+        self.data.expected_attach_type = Some(self.attach_type.bpf_attach_type());
         load_program(BPF_PROG_TYPE_XDP, &mut self.data)
     }
 
+    /// Creates a program from the fd of a program that is already loaded.
+    ///
+    /// The kernel doesn't report the expected attach type of loaded
+    /// programs, so `attach_type` must match the one the program was loaded
+    /// with.
+    ///
+    /// Returns [`ProgramError::UnexpectedProgramType`] if `fd` isn't an XDP
+    /// program.
+    pub fn from_fd(fd: RawFd, attach_type: XdpAttachType) -> Result<Xdp, ProgramError> {
+        let data = ProgramData::from_fd(fd, BPF_PROG_TYPE_XDP, attach_type.section())?;
+        Ok(Xdp { data, attach_type })
+    }
+
+    /// Returns where the program is attached.
+    pub fn attach_type(&self) -> XdpAttachType {
+        self.attach_type
+    }
+
+    /// Runs the program on the packets redirected to the CPU at `cpu_index`
+    /// of `cpu_map`.
+    ///
+    /// The entry is set with a queue of `queue_size` packets. The program must
+    /// have been loaded from an `xdp/cpumap` section.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.9.
+    pub fn attach_to_cpumap<T: DerefMut<Target = Map>>(
+        &mut self,
+        cpu_map: &mut CpuMap<T>,
+        cpu_index: u32,
+        queue_size: u32,
+    ) -> Result<(), ProgramError> {
+        self.check_attach_type(XdpAttachType::CpuMap)?;
+        let prog_fd = self.data.fd_or_err()?;
+        cpu_map.set(cpu_index, queue_size, Some(prog_fd), 0)?;
+        Ok(())
+    }
+
+    /// Runs the program on the packets redirected to the entry at `index` of
+    /// `dev_map`, before they are transmitted by the interface `ifindex`.
+    ///
+    /// The program must have been loaded from an `xdp/devmap` section.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to use this feature is 5.8.
+    pub fn attach_to_devmap<T: DerefMut<Target = Map>>(
+        &mut self,
+        dev_map: &mut DevMap<T>,
+        index: u32,
+        ifindex: u32,
+    ) -> Result<(), ProgramError> {
+        self.check_attach_type(XdpAttachType::DevMap)?;
+        let prog_fd = self.data.fd_or_err()?;
+        dev_map.set(index, ifindex, Some(prog_fd), 0)?;
+        Ok(())
+    }
+
+    fn check_attach_type(&self, expected: XdpAttachType) -> Result<(), XdpError> {
+        if self.attach_type != expected {
+            return Err(XdpError::InvalidAttachType {
+                attach_type: self.attach_type,
+                expected,
+            });
+        }
+        Ok(())
+    }
+
     /// Attaches the program to the given `interface`.
     ///
     /// # Errors
@@ -87,53 +307,211 @@ impl Xdp {
     /// If the given `interface` does not exist
     /// [`ProgramError::UnknownInterface`] is returned.
     ///
-    /// When attaching fails, [`ProgramError::SyscallError`] is returned for
-    /// kernels `>= 5.9.0`, and instead
+    /// If another program is already attached to the interface
+    /// [`XdpError::AlreadyAttached`] is returned. If the driver doesn't support
+    /// the requested mode [`XdpError::DriverNotSupported`] is returned, and if
+    /// the caller lacks the needed privileges [`XdpError::PermissionDenied`]
+    /// is returned. Programs loaded from `xdp/cpumap` or `xdp/devmap` sections
+    /// can't be attached to interfaces and return
+    /// [`XdpError::InvalidAttachType`].
+    ///
+    /// When attaching fails for any other reason, [`ProgramError::SyscallError`]
+    /// is returned for kernels `>= 5.9.0`, and instead
     /// [`XdpError::NetlinkError`] is returned for older
     /// kernels.
     pub fn attach(&mut self, interface: &str, flags: XdpFlags) -> Result<LinkRef, ProgramError> {
-        let prog_fd = self.data.fd_or_err()?;
-        let c_interface = CString::new(interface).unwrap();
-        let if_index = unsafe { if_nametoindex(c_interface.as_ptr()) } as RawFd;
-        if if_index == 0 {
-            return Err(ProgramError::UnknownInterface {
-                name: interface.to_string(),
-            });
+        let link = self.attach_inner(interface, flags, None)?;
+        Ok(self.data.link(link))
+    }
+
+    /// Attaches the program to the given `interface` of the network
+    /// namespace `netns`.
+    ///
+    /// The calling thread is moved to `netns` while attaching, so `interface`
+    /// is looked up in `netns` rather than in the namespace of the caller.
+    /// On kernels older than 5.9, where the program is attached through
+    /// netlink, the link keeps a reference to `netns` and is detached from
+    /// there as well. Moving to another namespace requires `CAP_SYS_ADMIN`.
+    ///
+    /// See [`attach`](Self::attach) for the errors returned when attaching
+    /// fails. If the thread can't be moved to `netns`,
+    /// [`ProgramError::SyscallError`] is returned. It's also returned if the
+    /// thread can't be moved back to its original namespace after attaching,
+    /// in which case the program stays attached until it's detached with
+    /// [`Program::detach_all`](crate::programs::Program::detach_all) or
+    /// dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut bpf = aya::Bpf::load(&[])?;
+    /// use aya::{programs::{Xdp, XdpFlags}, util::netns::NetNs};
+    /// use std::convert::TryInto;
+    ///
+    /// let netns = NetNs::from_path("/run/netns/blue")?;
+    /// let program: &mut Xdp = bpf.program_mut("intercept_packets").unwrap().try_into()?;
+    /// program.load()?;
+    /// program.attach_in_namespace("veth0", XdpFlags::default(), &netns)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn attach_in_namespace(
+        &mut self,
+        interface: &str,
+        flags: XdpFlags,
+        netns: &NetNs,
+    ) -> Result<LinkRef, ProgramError> {
+        let setns_error = |io_error| ProgramError::SyscallError {
+            call: "setns".to_owned(),
+            io_error,
+        };
+        let link_netns = netns.try_clone().map_err(setns_error)?;
+        let (link, restore_error) = netns
+            .run_in(|| self.attach_inner(interface, flags, Some(link_netns)))
+            .map_err(setns_error)?;
+        let link = self.data.link(link?);
+        match restore_error {
+            Some(io_error) => Err(setns_error(io_error)),
+            None => Ok(link),
         }
+    }
+
+    /// Attaches the program to the given `interface` after checking that its
+    /// driver implements the XDP metadata `metadata_features`.
+    ///
+    /// The kernel doesn't negotiate metadata with the driver: the metadata
+    /// kfuncs of a driver that doesn't implement them just return
+    /// `-EOPNOTSUPP`. This method queries the features the driver implements
+    /// with the `netdev` generic netlink family first, so that a missing
+    /// feature is reported when attaching instead. The program must have
+    /// been bound to the interface with
+    /// [`set_dev_bound_ifindex`](Self::set_dev_bound_ifindex) before being
+    /// loaded to call the kfuncs.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`XdpError::MetadataNotSupported`] with the missing features
+    /// if the driver doesn't implement all of `metadata_features`, and
+    /// [`XdpError::NetlinkError`] if the features can't be queried. See
+    /// [`attach`](Self::attach) for the other errors.
+    ///
+    /// # Minimum kernel version
+    ///
+    /// The minimum kernel version required to query the metadata features
+    /// is 6.8. Older kernels don't report them and any requested feature is
+    /// reported as missing.
+    pub fn attach_with_metadata(
+        &mut self,
+        interface: &str,
+        flags: XdpFlags,
+        metadata_features: XdpMetadataFeatures,
+    ) -> Result<LinkRef, ProgramError> {
+        self.check_attach_type(XdpAttachType::Interface)?;
+        let if_index = if_index(interface)?;
+        let supported = netlink_xdp_rx_metadata_features(if_index as u32)
+            .map_err(|io_error| XdpError::NetlinkError { io_error })?;
+        let missing = metadata_features - XdpMetadataFeatures::from_bits_truncate(supported);
+        if !missing.is_empty() {
+            return Err(XdpError::MetadataNotSupported { features: missing }.into());
+        }
+        let link = self.attach_inner(interface, flags, None)?;
+        Ok(self.data.link(link))
+    }
+
+    /// Attaches the program to the given `interface` and returns the link
+    /// to the caller.
+    ///
+    /// Unlike [`attach`](Self::attach), the link isn't owned by the program:
+    /// it is detached when the returned [`XdpLink`] is dropped, and it can
+    /// be queried for the interface and mode the program is attached in.
+    /// See [`attach`](Self::attach) for the errors returned when attaching
+    /// fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let mut bpf = aya::Bpf::load(&[])?;
+    /// use aya::programs::{Xdp, XdpFlags};
+    /// use std::convert::TryInto;
+    ///
+    /// let program: &mut Xdp = bpf.program_mut("intercept_packets").unwrap().try_into()?;
+    /// program.load()?;
+    /// let link = program.attach_link("eth0", XdpFlags::default())?;
+    /// if !link.is_native()? {
+    ///     eprintln!("interface {} fell back to skb mode", link.ifindex());
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn attach_link(
+        &mut self,
+        interface: &str,
+        flags: XdpFlags,
+    ) -> Result<XdpLink, ProgramError> {
+        self.attach_inner(interface, flags, None)
+    }
+
+    fn attach_inner(
+        &mut self,
+        interface: &str,
+        flags: XdpFlags,
+        netns: Option<NetNs>,
+    ) -> Result<XdpLink, ProgramError> {
+        self.check_attach_type(XdpAttachType::Interface)?;
+        let prog_fd = self.data.fd_or_err()?;
+        let if_index = if_index(interface)?;
 
         let k_ver = kernel_version().unwrap();
-        if k_ver >= (5, 9, 0) {
+        let inner = if k_ver >= (5, 9, 0) {
             let link_fd = bpf_link_create(prog_fd, if_index, BPF_XDP, None, flags.bits).map_err(
-                |(_, io_error)| ProgramError::SyscallError {
-                    call: "bpf_link_create".to_owned(),
-                    io_error,
+                |(_, io_error)| match XdpError::from_attach_error(&io_error, if_index) {
+                    Some(error) => ProgramError::XdpError(error),
+                    None => ProgramError::SyscallError {
+                        call: "bpf_link_create".to_owned(),
+                        io_error,
+                    },
                 },
             )? as RawFd;
-            Ok(self
-                .data
-                .link(XdpLink::FdLink(FdLink { fd: Some(link_fd) })))
+            XdpLinkInner::FdLink(FdLink { fd: Some(link_fd) })
         } else {
-            unsafe { netlink_set_xdp_fd(if_index, prog_fd, None, flags.bits) }
-                .map_err(|io_error| XdpError::NetlinkError { io_error })?;
-
-            Ok(self.data.link(XdpLink::NlLink(NlLink {
-                if_index,
+            unsafe { netlink_set_xdp_fd(if_index, prog_fd, None, flags.bits) }.map_err(
+                |io_error| {
+                    XdpError::from_attach_error(&io_error, if_index)
+                        .unwrap_or(XdpError::NetlinkError { io_error })
+                },
+            )?;
+            XdpLinkInner::NlLink(NlLink {
                 prog_fd: Some(prog_fd),
                 flags,
-            })))
-        }
+            })
+        };
+
+        Ok(XdpLink {
+            if_index,
+            netns,
+            inner,
+        })
     }
 }
 
+fn if_index(interface: &str) -> Result<RawFd, ProgramError> {
+    let c_interface = CString::new(interface).unwrap();
+    let if_index = unsafe { if_nametoindex(c_interface.as_ptr()) } as RawFd;
+    if if_index == 0 {
+        return Err(ProgramError::UnknownInterface {
+            name: interface.to_string(),
+        });
+    }
+    Ok(if_index)
+}
+
+// a program attached with netlink, before bpf_link support for XDP in 5.9
 #[derive(Debug)]
 struct NlLink {
-    if_index: i32,
     prog_fd: Option<RawFd>,
     flags: XdpFlags,
 }
 
-impl Link for NlLink {
-    fn detach(&mut self) -> Result<(), ProgramError> {
+impl NlLink {
+    fn detach(&mut self, if_index: RawFd, netns: Option<&NetNs>) -> Result<(), ProgramError> {
         if let Some(fd) = self.prog_fd.take() {
             let k_ver = kernel_version().unwrap();
             let flags = if k_ver >= (5, 7, 0) {
@@ -141,7 +519,11 @@ impl Link for NlLink {
             } else {
                 self.flags.bits
             };
-            let _ = unsafe { netlink_set_xdp_fd(self.if_index, -1, Some(fd), flags) };
+            let detach = || unsafe { netlink_set_xdp_fd(if_index, -1, Some(fd), flags) };
+            let _ = match netns {
+                Some(netns) => netns.run_in(detach).map(|(ret, _)| ret),
+                None => Ok(detach()),
+            };
             Ok(())
         } else {
             Err(ProgramError::AlreadyDetached)
@@ -149,23 +531,188 @@ impl Link for NlLink {
     }
 }
 
-impl Drop for NlLink {
-    fn drop(&mut self) {
-        let _ = self.detach();
-    }
-}
-
 #[derive(Debug)]
-enum XdpLink {
+enum XdpLinkInner {
     FdLink(FdLink),
     NlLink(NlLink),
 }
 
+/// An [`Xdp`] program attached to a network interface.
+///
+/// Returned by [`Xdp::attach_link`]. The program is detached when the link
+/// is dropped.
+#[derive(Debug)]
+pub struct XdpLink {
+    if_index: RawFd,
+    // the namespace of the interface, if it isn't the one of the caller
+    netns: Option<NetNs>,
+    inner: XdpLinkInner,
+}
+
+impl XdpLink {
+    /// Returns the index of the interface the program is attached to.
+    pub fn ifindex(&self) -> u32 {
+        self.if_index as u32
+    }
+
+    /// Returns the mode the program currently runs in on the interface, as
+    /// [`XdpFlags::SKB_MODE`], [`XdpFlags::DRV_MODE`] or
+    /// [`XdpFlags::HW_MODE`].
+    ///
+    /// The mode is queried from the interface with netlink, since the
+    /// kernel doesn't report the flags of XDP links. When the program was
+    /// attached without a mode flag, this returns the mode the kernel
+    /// picked. Only the modes the program of this link is attached in are
+    /// returned, so an empty value means it was detached or replaced.
+    pub fn current_flags(&self) -> Result<XdpFlags, ProgramError> {
+        let prog_id = match &self.inner {
+            XdpLinkInner::FdLink(link) => match link.fd {
+                Some(_) => link.info()?.prog_id(),
+                None => return Ok(XdpFlags::empty()),
+            },
+            XdpLinkInner::NlLink(link) => match link.prog_fd {
+                Some(fd) => prog_info_by_fd(fd)?.id,
+                None => return Ok(XdpFlags::empty()),
+            },
+        };
+        let interfaces = match &self.netns {
+            Some(netns) => {
+                netns
+                    .run_in(netlink_list_interfaces)
+                    .and_then(|(interfaces, restore_error)| match restore_error {
+                        Some(io_error) => Err(io_error),
+                        None => interfaces,
+                    })
+            }
+            None => netlink_list_interfaces(),
+        }
+        .map_err(|io_error| XdpError::NetlinkError { io_error })?;
+
+        Ok(interfaces
+            .iter()
+            .find(|iface| iface.ifindex == self.ifindex())
+            .map(|iface| prog_flags(iface, prog_id))
+            .unwrap_or_else(XdpFlags::empty))
+    }
+
+    /// Returns whether the program runs in the network driver.
+    ///
+    /// See [`current_flags`](Self::current_flags).
+    pub fn is_native(&self) -> Result<bool, ProgramError> {
+        Ok(self.current_flags()?.contains(XdpFlags::DRV_MODE))
+    }
+
+    /// Returns whether the program is offloaded to the network card.
+    ///
+    /// See [`current_flags`](Self::current_flags).
+    pub fn is_offloaded(&self) -> Result<bool, ProgramError> {
+        Ok(self.current_flags()?.contains(XdpFlags::HW_MODE))
+    }
+}
+
+// the modes in which the program `prog_id` is attached to `iface`
+fn prog_flags(iface: &IfaceInfo, prog_id: u32) -> XdpFlags {
+    let mut flags = XdpFlags::empty();
+    for (id, mode) in [
+        (iface.xdp_drv_prog_id, XdpFlags::DRV_MODE),
+        (iface.xdp_skb_prog_id, XdpFlags::SKB_MODE),
+        (iface.xdp_hw_prog_id, XdpFlags::HW_MODE),
+    ] {
+        if id == Some(prog_id) {
+            flags |= mode;
+        }
+    }
+    flags
+}
+
 impl Link for XdpLink {
     fn detach(&mut self) -> Result<(), ProgramError> {
-        match self {
-            XdpLink::FdLink(link) => link.detach(),
-            XdpLink::NlLink(link) => link.detach(),
+        match &mut self.inner {
+            XdpLinkInner::FdLink(link) => link.detach(),
+            XdpLinkInner::NlLink(link) => link.detach(self.if_index, self.netns.as_ref()),
+        }
+    }
+
+    fn pin(&mut self, path: &Path) -> Result<(), ProgramError> {
+        match &mut self.inner {
+            XdpLinkInner::FdLink(link) => link.pin(path),
+            XdpLinkInner::NlLink(_) => Err(ProgramError::LinkNotPinnable),
+        }
+    }
+}
+
+impl Drop for XdpLink {
+    fn drop(&mut self) {
+        if let XdpLinkInner::NlLink(link) = &mut self.inner {
+            let _ = link.detach(self.if_index, self.netns.as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libc::EFAULT;
+
+    use super::*;
+    use crate::{
+        generated::{bpf_cmd, bpf_prog_info},
+        sys::{override_syscall, Syscall},
+    };
+
+    fn iface(drv: Option<u32>, skb: Option<u32>, hw: Option<u32>) -> IfaceInfo {
+        IfaceInfo {
+            ifindex: 1,
+            name: "eth0".to_owned(),
+            xdp_prog_id: drv.or(skb).or(hw),
+            xdp_flags: 0,
+            xdp_drv_prog_id: drv,
+            xdp_skb_prog_id: skb,
+            xdp_hw_prog_id: hw,
         }
     }
+
+    #[test]
+    fn test_prog_flags() {
+        assert_eq!(
+            prog_flags(&iface(Some(42), None, None), 42),
+            XdpFlags::DRV_MODE
+        );
+        // another program replaced the one of the link
+        assert_eq!(
+            prog_flags(&iface(Some(43), None, None), 42),
+            XdpFlags::empty()
+        );
+        assert_eq!(prog_flags(&iface(None, None, None), 42), XdpFlags::empty());
+        // XDP_ATTACHED_MULTI, with the program of the link in skb mode only
+        assert_eq!(
+            prog_flags(&iface(Some(43), Some(42), None), 42),
+            XdpFlags::SKB_MODE
+        );
+        assert_eq!(
+            prog_flags(&iface(Some(42), None, Some(42)), 42),
+            XdpFlags::DRV_MODE | XdpFlags::HW_MODE
+        );
+    }
+
+    #[test]
+    fn test_from_fd() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = BPF_PROG_TYPE_XDP as u32;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        });
+
+        let xdp = Xdp::from_fd(1, XdpAttachType::CpuMap).unwrap();
+        assert_eq!(xdp.attach_type(), XdpAttachType::CpuMap);
+        assert!(matches!(
+            xdp.data.obj.section,
+            ProgramSection::XdpCpuMap { .. }
+        ));
+    }
 }
@@ -4,6 +4,7 @@ use crate::{
     Btf,
 };
 use libc::{c_char, c_long, close, ENOENT, ENOSPC};
+use thiserror::Error;
 
 use std::{
     cmp::{self, min},
@@ -17,9 +18,10 @@ use std::{
 use crate::{
     bpf_map_def,
     generated::{
-        bpf_attach_type, bpf_attr, bpf_btf_info, bpf_cmd, bpf_insn, bpf_prog_info, bpf_prog_type,
+        bpf_attach_type, bpf_attr, bpf_btf_info, bpf_cmd, bpf_insn, bpf_link_info, bpf_map_info,
+        bpf_prog_info, bpf_prog_type, BPF_F_NUMA_NODE, BPF_F_TOKEN_FD,
     },
-    maps::PerCpuValues,
+    maps::{MapCreateOptions, PerCpuValues},
     obj::btf::{FuncSecInfo, LineSecInfo},
     sys::{kernel_version, SysResult},
     util::VerifierLog,
@@ -28,7 +30,12 @@ use crate::{
 
 use super::{syscall, Syscall};
 
-pub(crate) fn bpf_create_map(name: &CStr, def: &bpf_map_def) -> SysResult {
+pub(crate) fn bpf_create_map(
+    name: &CStr,
+    def: &bpf_map_def,
+    options: &MapCreateOptions,
+    btf_fd: Option<RawFd>,
+) -> SysResult {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
 
     let u = unsafe { &mut attr.__bindgen_anon_1 };
@@ -37,6 +44,22 @@ pub(crate) fn bpf_create_map(name: &CStr, def: &bpf_map_def) -> SysResult {
     u.value_size = def.value_size;
     u.max_entries = def.max_entries;
     u.map_flags = def.map_flags;
+    if let Some(fd) = options.inner_map_fd {
+        u.inner_map_fd = fd as u32;
+    }
+    if let Some(node) = options.numa_node {
+        u.map_flags |= BPF_F_NUMA_NODE;
+        u.numa_node = node;
+    }
+    if let Some(fd) = options.token_fd {
+        u.map_flags |= BPF_F_TOKEN_FD;
+        u.map_token_fd = fd;
+    }
+    if let Some(fd) = btf_fd {
+        u.btf_fd = fd as u32;
+        u.btf_key_type_id = options.btf_key_type_id;
+        u.btf_value_type_id = options.btf_value_type_id;
+    }
 
     // https://github.com/torvalds/linux/commit/ad5b177bd73f5107d97c36f56395c4281fb6f089
     // The map name was added as a parameter in kernel 4.15+ so we skip adding it on
@@ -82,6 +105,9 @@ pub(crate) struct BpfLoadProgramAttrs<'a> {
     pub(crate) func_info: FuncSecInfo,
     pub(crate) line_info_rec_size: usize,
     pub(crate) line_info: LineSecInfo,
+    pub(crate) token_fd: Option<RawFd>,
+    pub(crate) prog_ifindex: Option<u32>,
+    pub(crate) prog_flags: u32,
 }
 
 pub(crate) fn bpf_load_program(
@@ -129,9 +155,10 @@ pub(crate) fn bpf_load_program(
             u.func_info_rec_size = aya_attr.func_info_rec_size as u32;
         }
     }
+    let log_level = logger.level();
     let log_buf = logger.buf();
     if log_buf.capacity() > 0 {
-        u.log_level = 7;
+        u.log_level = log_level;
         u.log_buf = log_buf.as_mut_ptr() as u64;
         u.log_size = log_buf.capacity() as u32;
     }
@@ -145,6 +172,14 @@ pub(crate) fn bpf_load_program(
     if let Some(v) = aya_attr.attach_btf_id {
         u.attach_btf_id = v;
     }
+    if let Some(fd) = aya_attr.token_fd {
+        u.prog_flags |= BPF_F_TOKEN_FD;
+        u.prog_token_fd = fd;
+    }
+    if let Some(ifindex) = aya_attr.prog_ifindex {
+        u.prog_ifindex = ifindex;
+    }
+    u.prog_flags |= aya_attr.prog_flags;
 
     sys_bpf(bpf_cmd::BPF_PROG_LOAD, &attr)
 }
@@ -196,8 +231,8 @@ pub(crate) fn bpf_map_lookup_elem_per_cpu<K: Pod, V: Pod>(
 ) -> Result<Option<PerCpuValues<V>>, (c_long, io::Error)> {
     let mut mem = PerCpuValues::<V>::alloc_kernel_mem().map_err(|io_error| (-1, io_error))?;
     match bpf_map_lookup_elem_ptr(fd, key, mem.as_mut_ptr(), flags) {
-        Ok(_) => Ok(Some(unsafe { PerCpuValues::from_kernel_mem(mem) })),
-        Err((_, io_error)) if io_error.raw_os_error() == Some(ENOENT) => Ok(None),
+        Ok(Some(())) => Ok(Some(unsafe { PerCpuValues::from_kernel_mem(mem) })),
+        Ok(None) => Ok(None),
         Err(e) => Err(e),
     }
 }
@@ -304,6 +339,62 @@ pub(crate) fn bpf_map_get_next_key<K>(
     }
 }
 
+// since kernel 5.6
+//
+// in_batch is the cursor returned by the previous call, or None to start from
+// the beginning of the map
+pub(crate) fn bpf_map_lookup_batch<K, V>(
+    fd: RawFd,
+    in_batch: Option<&u64>,
+    out_batch: &mut u64,
+    keys: &mut [K],
+    values: &mut [V],
+    count: &mut u32,
+) -> SysResult {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    let u = unsafe { &mut attr.batch };
+    u.map_fd = fd as u32;
+    u.in_batch = in_batch.map(|b| b as *const u64 as u64).unwrap_or(0);
+    u.out_batch = out_batch as *mut u64 as u64;
+    u.keys = keys.as_mut_ptr() as u64;
+    u.values = values.as_mut_ptr() as u64;
+    u.count = *count;
+
+    let ret = sys_bpf(bpf_cmd::BPF_MAP_LOOKUP_BATCH, &attr);
+
+    // the kernel sets count to the number of elements copied, even when it
+    // returns ENOENT at the end of the map
+    *count = unsafe { attr.batch.count };
+
+    ret
+}
+
+// since kernel 5.6
+pub(crate) fn bpf_map_update_batch<K, V>(
+    fd: RawFd,
+    keys: &[K],
+    values: &[V],
+    count: &mut u32,
+    elem_flags: u64,
+) -> SysResult {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    let u = unsafe { &mut attr.batch };
+    u.map_fd = fd as u32;
+    u.keys = keys.as_ptr() as u64;
+    u.values = values.as_ptr() as u64;
+    u.count = *count;
+    u.elem_flags = elem_flags;
+
+    let ret = sys_bpf(bpf_cmd::BPF_MAP_UPDATE_BATCH, &attr);
+
+    // the kernel sets count to the number of elements updated, even on error
+    *count = unsafe { attr.batch.count };
+
+    ret
+}
+
 // since kernel 5.2
 pub(crate) fn bpf_map_freeze(fd: RawFd) -> SysResult {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
@@ -333,16 +424,74 @@ pub(crate) fn bpf_link_create(
     sys_bpf(bpf_cmd::BPF_LINK_CREATE, &attr)
 }
 
+// attaches the iterator prog_fd, to the elements of map_fd for map iterators
+pub(crate) fn bpf_link_create_iter(prog_fd: RawFd, map_fd: Option<RawFd>) -> SysResult {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    // union bpf_iter_link_info starts with the map fd for map iterators, and
+    // the kernel zero-fills the fields past iter_info_len
+    let iter_info = map_fd.map(|fd| fd as u32);
+
+    attr.link_create.prog_fd = prog_fd as u32;
+    attr.link_create.attach_type = bpf_attach_type::BPF_TRACE_ITER as u32;
+    if let Some(iter_info) = &iter_info {
+        let u = unsafe { &mut attr.link_create.__bindgen_anon_2.__bindgen_anon_1 };
+        u.iter_info = iter_info as *const _ as u64;
+        u.iter_info_len = mem::size_of::<u32>() as u32;
+    }
+
+    sys_bpf(bpf_cmd::BPF_LINK_CREATE, &attr)
+}
+
+pub(crate) fn bpf_iter_create(link_fd: RawFd) -> SysResult {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    attr.iter_create.link_fd = link_fd as u32;
+
+    sys_bpf(bpf_cmd::BPF_ITER_CREATE, &attr)
+}
+
+// attaches prog_fd to the perf event perf_fd, setting the cookie returned by
+// bpf_get_attach_cookie()
+pub(crate) fn bpf_link_create_perf_event(
+    prog_fd: RawFd,
+    perf_fd: RawFd,
+    bpf_cookie: u64,
+) -> SysResult {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    attr.link_create.prog_fd = prog_fd as u32;
+    attr.link_create.__bindgen_anon_1.target_fd = perf_fd as u32;
+    attr.link_create.attach_type = bpf_attach_type::BPF_PERF_EVENT as u32;
+    attr.link_create.__bindgen_anon_2.perf_event.bpf_cookie = bpf_cookie;
+
+    sys_bpf(bpf_cmd::BPF_LINK_CREATE, &attr)
+}
+
 pub(crate) fn bpf_prog_attach(
     prog_fd: RawFd,
     target_fd: RawFd,
     attach_type: bpf_attach_type,
+    flags: u32,
+) -> SysResult {
+    bpf_prog_attach_replace(prog_fd, target_fd, attach_type, flags, None)
+}
+
+// replace_fd is the program to replace when flags contains BPF_F_REPLACE
+pub(crate) fn bpf_prog_attach_replace(
+    prog_fd: RawFd,
+    target_fd: RawFd,
+    attach_type: bpf_attach_type,
+    flags: u32,
+    replace_fd: Option<RawFd>,
 ) -> SysResult {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
 
     attr.__bindgen_anon_5.attach_bpf_fd = prog_fd as u32;
     attr.__bindgen_anon_5.target_fd = target_fd as u32;
     attr.__bindgen_anon_5.attach_type = attach_type as u32;
+    attr.__bindgen_anon_5.attach_flags = flags;
+    if let Some(fd) = replace_fd {
+        attr.__bindgen_anon_5.replace_bpf_fd = fd as u32;
+    }
 
     sys_bpf(bpf_cmd::BPF_PROG_ATTACH, &attr)
 }
@@ -399,6 +548,71 @@ pub(crate) fn bpf_prog_get_fd_by_id(prog_id: u32) -> Result<RawFd, io::Error> {
     }
 }
 
+pub(crate) fn bpf_map_get_fd_by_id(map_id: u32) -> Result<RawFd, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    attr.__bindgen_anon_6.__bindgen_anon_1.map_id = map_id;
+
+    match sys_bpf(bpf_cmd::BPF_MAP_GET_FD_BY_ID, &attr) {
+        Ok(v) => Ok(v as RawFd),
+        Err((_, err)) => Err(err),
+    }
+}
+
+pub(crate) fn bpf_prog_get_next_id(start_id: u32) -> Result<Option<u32>, io::Error> {
+    bpf_obj_get_next_id(bpf_cmd::BPF_PROG_GET_NEXT_ID, start_id)
+}
+
+pub(crate) fn bpf_map_get_next_id(start_id: u32) -> Result<Option<u32>, io::Error> {
+    bpf_obj_get_next_id(bpf_cmd::BPF_MAP_GET_NEXT_ID, start_id)
+}
+
+pub(crate) fn bpf_btf_get_next_id(start_id: u32) -> Result<Option<u32>, io::Error> {
+    bpf_obj_get_next_id(bpf_cmd::BPF_BTF_GET_NEXT_ID, start_id)
+}
+
+pub(crate) fn bpf_link_get_next_id(start_id: u32) -> Result<Option<u32>, io::Error> {
+    bpf_obj_get_next_id(bpf_cmd::BPF_LINK_GET_NEXT_ID, start_id)
+}
+
+/// Iterates over the ids returned by `next_id`, eg [`bpf_prog_get_next_id`],
+/// in increasing order. The iteration stops after the first error.
+pub(crate) fn ids(
+    next_id: fn(u32) -> Result<Option<u32>, io::Error>,
+) -> impl Iterator<Item = Result<u32, io::Error>> {
+    let mut start_id = Some(0);
+    std::iter::from_fn(move || {
+        let id = next_id(start_id?);
+        start_id = match id {
+            Ok(Some(id)) => Some(id),
+            _ => None,
+        };
+        id.transpose()
+    })
+}
+
+// returns the first id greater than start_id, or None if there's none
+fn bpf_obj_get_next_id(cmd: bpf_cmd, start_id: u32) -> Result<Option<u32>, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    attr.__bindgen_anon_6.__bindgen_anon_1.start_id = start_id;
+
+    match sys_bpf(cmd, &attr) {
+        Ok(_) => Ok(Some(unsafe { attr.__bindgen_anon_6.next_id })),
+        Err((_, err)) if err.raw_os_error() == Some(ENOENT) => Ok(None),
+        Err((_, err)) => Err(err),
+    }
+}
+
+pub(crate) fn bpf_link_get_fd_by_id(link_id: u32) -> Result<RawFd, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    attr.__bindgen_anon_6.__bindgen_anon_1.link_id = link_id;
+
+    match sys_bpf(bpf_cmd::BPF_LINK_GET_FD_BY_ID, &attr) {
+        Ok(v) => Ok(v as RawFd),
+        Err((_, err)) => Err(err),
+    }
+}
+
 pub(crate) fn bpf_obj_get_info_by_fd(prog_fd: RawFd) -> Result<bpf_prog_info, io::Error> {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
     // info gets entirely populated by the kernel
@@ -414,6 +628,99 @@ pub(crate) fn bpf_obj_get_info_by_fd(prog_fd: RawFd) -> Result<bpf_prog_info, io
     }
 }
 
+// Returns the JITed code of the program when `jited` is true, and its
+// translated instructions otherwise. The kernel only copies the code if a big
+// enough buffer is passed, so the length is queried first.
+pub(crate) fn bpf_prog_get_insns(prog_fd: RawFd, jited: bool) -> Result<Vec<u8>, io::Error> {
+    let info = bpf_obj_get_info_by_fd(prog_fd)?;
+    let len = if jited {
+        info.jited_prog_len
+    } else {
+        info.xlated_prog_len
+    };
+    let mut buf = vec![0u8; len as usize];
+    if len == 0 {
+        return Ok(buf);
+    }
+
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let mut info = unsafe { mem::zeroed::<bpf_prog_info>() };
+    if jited {
+        info.jited_prog_len = len;
+        info.jited_prog_insns = buf.as_mut_ptr() as u64;
+    } else {
+        info.xlated_prog_len = len;
+        info.xlated_prog_insns = buf.as_mut_ptr() as u64;
+    }
+    attr.info.bpf_fd = prog_fd as u32;
+    attr.info.info = &info as *const _ as u64;
+    attr.info.info_len = mem::size_of::<bpf_prog_info>() as u32;
+    sys_bpf(bpf_cmd::BPF_OBJ_GET_INFO_BY_FD, &attr).map_err(|(_, io_error)| io_error)?;
+
+    // the kernel sets the length of the code even if it didn't copy all of it
+    let (copied, insns) = if jited {
+        (info.jited_prog_len, info.jited_prog_insns)
+    } else {
+        (info.xlated_prog_len, info.xlated_prog_insns)
+    };
+    // when kptr_restrict doesn't allow dumping the code, the kernel still
+    // reports its length but zeroes the pointer instead of copying it
+    if insns == 0 {
+        return Ok(Vec::new());
+    }
+    buf.truncate(min(copied, len) as usize);
+    Ok(buf)
+}
+
+// not part of the generated bindings yet, see include/uapi/linux/bpf.h
+pub(crate) const BPF_F_XDP_DEV_BOUND_ONLY: u32 = 1 << 6;
+
+// `enum bpf_stats_type` isn't part of the generated bindings
+pub(crate) const BPF_STATS_RUN_TIME: u32 = 0;
+
+// the stats stay enabled until the returned fd is closed
+pub(crate) fn bpf_enable_stats(stats_type: u32) -> SysResult {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    attr.enable_stats.type_ = stats_type;
+
+    sys_bpf(bpf_cmd::BPF_ENABLE_STATS, &attr)
+}
+
+pub(crate) fn bpf_map_get_info_by_fd(map_fd: RawFd) -> Result<bpf_map_info, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    // info gets entirely populated by the kernel
+    let info = unsafe { mem::zeroed::<bpf_map_info>() };
+
+    attr.info.bpf_fd = map_fd as u32;
+    attr.info.info = &info as *const _ as u64;
+    attr.info.info_len = mem::size_of::<bpf_map_info>() as u32;
+
+    match sys_bpf(bpf_cmd::BPF_OBJ_GET_INFO_BY_FD, &attr) {
+        Ok(_) => Ok(info),
+        Err((_, err)) => Err(err),
+    }
+}
+
+pub(crate) fn bpf_link_get_info_by_fd(
+    link_fd: RawFd,
+    tp_name: &mut [u8],
+) -> Result<bpf_link_info, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let mut info = unsafe { mem::zeroed::<bpf_link_info>() };
+    if !tp_name.is_empty() {
+        info.__bindgen_anon_1.raw_tracepoint.tp_name = tp_name.as_mut_ptr() as u64;
+        info.__bindgen_anon_1.raw_tracepoint.tp_name_len = tp_name.len() as u32;
+    }
+    attr.info.bpf_fd = link_fd as u32;
+    attr.info.info = &info as *const bpf_link_info as u64;
+    attr.info.info_len = mem::size_of::<bpf_link_info>() as u32;
+
+    match sys_bpf(bpf_cmd::BPF_OBJ_GET_INFO_BY_FD, &attr) {
+        Ok(_) => Ok(info),
+        Err((_, err)) => Err(err),
+    }
+}
+
 pub(crate) fn btf_obj_get_info_by_fd(
     prog_fd: RawFd,
     buf: &mut [u8],
@@ -433,6 +740,41 @@ pub(crate) fn btf_obj_get_info_by_fd(
     }
 }
 
+// returns the raw data of the BTF object `btf_fd`
+pub(crate) fn btf_obj_get_data(btf_fd: RawFd) -> Result<Vec<u8>, io::Error> {
+    let mut buf = vec![0u8; 4096];
+    let mut info = btf_obj_get_info_by_fd(btf_fd, &mut buf)?;
+    if info.btf_size > buf.len() as u32 {
+        buf.resize(info.btf_size as usize, 0u8);
+        info = btf_obj_get_info_by_fd(btf_fd, &mut buf)?;
+    }
+    buf.truncate(info.btf_size as usize);
+
+    Ok(buf)
+}
+
+// returns the name of the BTF object `btf_fd` if it's the BTF of the kernel
+// or of a kernel module
+pub(crate) fn btf_obj_get_kernel_name(btf_fd: RawFd) -> Result<Option<String>, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let mut info = unsafe { mem::zeroed::<bpf_btf_info>() };
+    let mut name = [0u8; 64];
+    info.name = name.as_mut_ptr() as u64;
+    info.name_len = name.len() as u32;
+    attr.info.bpf_fd = btf_fd as u32;
+    attr.info.info = &info as *const bpf_btf_info as u64;
+    attr.info.info_len = mem::size_of::<bpf_btf_info>() as u32;
+
+    if let Err((_, err)) = sys_bpf(bpf_cmd::BPF_OBJ_GET_INFO_BY_FD, &attr) {
+        return Err(err);
+    }
+    if info.kernel_btf == 0 {
+        return Ok(None);
+    }
+    let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+    Ok(Some(String::from_utf8_lossy(&name[..len]).into_owned()))
+}
+
 pub(crate) fn bpf_raw_tracepoint_open(name: Option<&CStr>, prog_fd: RawFd) -> SysResult {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
 
@@ -445,20 +787,101 @@ pub(crate) fn bpf_raw_tracepoint_open(name: Option<&CStr>, prog_fd: RawFd) -> Sy
     sys_bpf(bpf_cmd::BPF_RAW_TRACEPOINT_OPEN, &attr)
 }
 
-pub(crate) fn bpf_load_btf(raw_btf: &[u8], log: &mut VerifierLog) -> SysResult {
+pub(crate) fn bpf_load_btf(
+    raw_btf: &[u8],
+    token_fd: Option<RawFd>,
+    log_level: u32,
+    log: &mut VerifierLog,
+) -> SysResult {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
     let u = unsafe { &mut attr.__bindgen_anon_7 };
     u.btf = raw_btf.as_ptr() as *const _ as u64;
     u.btf_size = mem::size_of_val(raw_btf) as u32;
+    if let Some(fd) = token_fd {
+        u.btf_flags |= BPF_F_TOKEN_FD;
+        u.btf_token_fd = fd;
+    }
     let log_buf = log.buf();
     if log_buf.capacity() > 0 {
-        u.btf_log_level = 1;
+        u.btf_log_level = log_level;
         u.btf_log_buf = log_buf.as_mut_ptr() as u64;
         u.btf_log_size = log_buf.capacity() as u32;
     }
     sys_bpf(bpf_cmd::BPF_BTF_LOAD, &attr)
 }
 
+/// The error returned by [`bpf_btf_load`].
+#[derive(Debug, Error)]
+#[error("the BPF_BTF_LOAD syscall failed. Verifier output: {log}")]
+pub struct BtfLoadError {
+    /// The [`io::Error`] returned by the `BPF_BTF_LOAD` syscall.
+    #[source]
+    pub io_error: io::Error,
+    /// The log of the kernel BTF checker.
+    pub log: String,
+}
+
+/// Loads the raw BTF `data` in the kernel and returns the BTF fd.
+///
+/// `log_level` and `log_buf_size` set the log requested from the kernel on
+/// the first attempt. With a `log_level` of 0, no log is requested unless
+/// loading fails, in which case loading is retried with a level 1 log so
+/// that the error always comes with the log of the kernel.
+pub fn bpf_btf_load(
+    data: &[u8],
+    log_level: u32,
+    log_buf_size: usize,
+) -> Result<RawFd, BtfLoadError> {
+    btf_load_with_log(data, None, log_level, log_buf_size)
+}
+
+pub(crate) fn btf_load_with_log(
+    data: &[u8],
+    token_fd: Option<RawFd>,
+    log_level: u32,
+    log_buf_size: usize,
+) -> Result<RawFd, BtfLoadError> {
+    let mut log = VerifierLog::with_level(log_level, log_buf_size);
+    let log_level = cmp::max(log_level, 1);
+    let ret = retry_with_verifier_logs(10, &mut log, |log| {
+        bpf_load_btf(data, token_fd, log_level, log)
+    });
+    match ret {
+        Ok(fd) => Ok(fd as RawFd),
+        Err((_, io_error)) => {
+            log.truncate();
+            Err(BtfLoadError {
+                io_error,
+                log: log
+                    .as_c_str()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "[none]".to_owned()),
+            })
+        }
+    }
+}
+
+/// Creates a BPF token from a BPF filesystem instance.
+///
+/// `bpffs_fd` is a file descriptor of the root of a BPF filesystem mounted
+/// with delegation options. The returned token fd allows loading the programs
+/// and creating the maps the filesystem delegates, see
+/// [`BpfLoader::with_token`](crate::BpfLoader::with_token).
+///
+/// # Minimum kernel version
+///
+/// The minimum kernel version required to use this feature is 6.9.
+pub fn bpf_token_create(bpffs_fd: RawFd) -> Result<RawFd, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let u = unsafe { &mut attr.token_create };
+    u.bpffs_fd = bpffs_fd as u32;
+
+    match sys_bpf(bpf_cmd::BPF_TOKEN_CREATE, &attr) {
+        Ok(v) => Ok(v as RawFd),
+        Err((_, err)) => Err(err),
+    }
+}
+
 pub(crate) fn bpf_btf_get_fd_by_id(id: u32) -> Result<RawFd, io::Error> {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
     attr.__bindgen_anon_6.__bindgen_anon_1.btf_id = id;
@@ -766,3 +1189,54 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::override_syscall;
+    use libc::EINVAL;
+
+    #[test]
+    fn test_ids_stop_after_error() {
+        fn next_id(start_id: u32) -> Result<Option<u32>, io::Error> {
+            match start_id {
+                0 | 1 => Ok(Some(start_id + 1)),
+                _ => Err(io::Error::from_raw_os_error(EINVAL)),
+            }
+        }
+        let ids = ids(next_id).collect::<Vec<_>>();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[0].as_ref().unwrap(), &1);
+        assert_eq!(ids[1].as_ref().unwrap(), &2);
+        assert_eq!(ids[2].as_ref().unwrap_err().raw_os_error(), Some(EINVAL));
+    }
+
+    #[test]
+    fn test_btf_load_error_log() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_BTF_LOAD,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_7 };
+                if u.btf_log_size > 0 {
+                    assert_eq!(u.btf_log_level, 1);
+                    let log = b"Invalid magic\0";
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(
+                            log.as_ptr(),
+                            u.btf_log_buf as *mut u8,
+                            log.len(),
+                        )
+                    };
+                }
+                Err((-1, io::Error::from_raw_os_error(EINVAL)))
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EINVAL))),
+        });
+
+        let err = bpf_btf_load(&[0; 24], 0, 0).unwrap_err();
+        assert_eq!(err.io_error.raw_os_error(), Some(EINVAL));
+        assert_eq!(err.log, "Invalid magic");
+    }
+}
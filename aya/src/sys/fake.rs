@@ -1,15 +1,24 @@
-use std::{cell::RefCell, io, ptr};
+use std::cell::RefCell;
+#[cfg(test)]
+use std::{cell::Cell, io, ptr};
 
+#[cfg(test)]
 use libc::c_void;
 
 use super::{SysResult, Syscall};
+use crate::generated::{bpf_cmd, bpf_map_type};
 
 type SyscallFn = unsafe fn(Syscall) -> SysResult;
 
-#[cfg(test)]
 thread_local! {
     pub(crate) static TEST_SYSCALL: RefCell<SyscallFn> = RefCell::new(test_syscall);
+    static MOCK_KERNEL_STATE: RefCell<MockKernelState> = RefCell::new(MockKernelState::default());
+}
+
+#[cfg(test)]
+thread_local! {
     pub(crate) static TEST_MMAP_RET: RefCell<*mut c_void> = RefCell::new(ptr::null_mut());
+    pub(crate) static TEST_KERNEL_VERSION: Cell<(u32, u32, u32)> = const { Cell::new((0xff, 0xff, 0xff)) };
 }
 
 #[cfg(test)]
@@ -17,7 +26,331 @@ unsafe fn test_syscall(_call: Syscall) -> SysResult {
     Err((-1, io::Error::from_raw_os_error(libc::EINVAL)))
 }
 
-#[cfg(test)]
+// outside of aya's own tests the syscalls reach the kernel unless a
+// MockKernel is alive
+#[cfg(not(test))]
+use super::syscall_impl as test_syscall;
+
 pub(crate) fn override_syscall(call: unsafe fn(Syscall) -> SysResult) {
     TEST_SYSCALL.with(|test_impl| *test_impl.borrow_mut() = call);
 }
+
+/// Sets the version returned by `kernel_version()` in the current thread.
+#[cfg(test)]
+pub(crate) fn override_kernel_version(version: (u32, u32, u32)) {
+    TEST_KERNEL_VERSION.with(|v| v.set(version));
+}
+
+/// A syscall recorded by [`MockKernel`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall {
+    /// A `BPF_MAP_CREATE` command.
+    MapCreate {
+        /// The name of the map, truncated to 15 bytes by the loader.
+        name: String,
+        /// The type of the map, a `bpf_map_type` value.
+        map_type: u32,
+        /// The size of the keys, in bytes.
+        key_size: u32,
+        /// The size of the values, in bytes.
+        value_size: u32,
+        /// The maximum number of entries.
+        max_entries: u32,
+    },
+    /// A `BPF_PROG_LOAD` command.
+    ProgLoad {
+        /// The name of the program, truncated to 15 bytes by the loader.
+        name: String,
+        /// The type of the program, a `bpf_prog_type` value.
+        prog_type: u32,
+    },
+    /// Any other `bpf(2)` command.
+    Bpf {
+        /// The command.
+        cmd: bpf_cmd,
+    },
+    /// A `perf_event_open(2)` call.
+    PerfEventOpen,
+    /// An `ioctl(2)` on a perf event.
+    PerfEventIoctl,
+}
+
+const MOCK_FIRST_FD: i64 = 100;
+
+#[derive(Debug, Default)]
+struct MockKernelState {
+    calls: Vec<MockCall>,
+    next_fd: i64,
+}
+
+/// A fake kernel that records all the syscalls issued on the current thread.
+///
+/// While a `MockKernel` is alive, the `bpf(2)`, `perf_event_open(2)` and
+/// perf event `ioctl(2)` calls made by aya on the current thread are
+/// recorded instead of reaching the kernel. Every syscall succeeds, and
+/// calls that create objects — maps, programs, links or perf events —
+/// return synthetic, increasing file descriptors starting at 100. The
+/// syscalls reach the kernel again once the mock is dropped. Expectations
+/// registered with `expect_*` are checked when the mock is dropped.
+///
+/// This is meant to unit test code that loads eBPF objects without root
+/// privileges or a recent kernel. The file descriptors returned by the mock
+/// don't refer to anything, so the maps and programs can't be used: for
+/// example, [`Bpf`](crate::Bpf) closes them when it's dropped. Other
+/// operations, like attaching XDP programs with netlink, still need a
+/// kernel.
+///
+/// Available with the `mock_kernel` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use aya::{
+///     programs::Xdp,
+///     sys::{MockCall, MockKernel},
+///     Bpf,
+/// };
+/// use std::convert::TryInto;
+///
+/// let mut kernel = MockKernel::new();
+/// kernel.expect_prog_load(1);
+///
+/// let mut bpf = Bpf::load_file("xdp.o")?;
+/// let program: &mut Xdp = bpf.program_mut("pass").unwrap().try_into()?;
+/// program.load()?;
+/// assert!(kernel
+///     .calls()
+///     .iter()
+///     .any(|call| matches!(call, MockCall::ProgLoad { name, .. } if name == "pass")));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct MockKernel {
+    expected_prog_loads: Option<usize>,
+}
+
+impl MockKernel {
+    /// Starts recording the syscalls of the current thread, forgetting the
+    /// ones recorded by previous mocks.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> MockKernel {
+        MOCK_KERNEL_STATE.with(|state| {
+            *state.borrow_mut() = MockKernelState {
+                calls: Vec::new(),
+                next_fd: MOCK_FIRST_FD,
+            }
+        });
+        override_syscall(mock_syscall);
+        MockKernel {
+            expected_prog_loads: None,
+        }
+    }
+
+    /// Expects exactly `n` `BPF_PROG_LOAD` calls to be made before the mock is dropped.
+    pub fn expect_prog_load(&mut self, n: usize) -> &mut MockKernel {
+        self.expected_prog_loads = Some(n);
+        self
+    }
+
+    /// Returns all the syscalls recorded so far.
+    pub fn calls(&self) -> Vec<MockCall> {
+        MOCK_KERNEL_STATE.with(|state| state.borrow().calls.clone())
+    }
+
+    /// Asserts that a map called `name` of type `map_type` has been created.
+    pub fn assert_map_created(&self, name: &str, map_type: bpf_map_type) {
+        assert!(
+            self.calls().iter().any(|call| matches!(
+                call,
+                MockCall::MapCreate { name: n, map_type: t, .. } if n == name && *t == map_type as u32
+            )),
+            "map `{}` of type {:?} was not created",
+            name,
+            map_type
+        );
+    }
+
+    fn prog_loads(&self) -> usize {
+        self.calls()
+            .iter()
+            .filter(|call| matches!(call, MockCall::ProgLoad { .. }))
+            .count()
+    }
+}
+
+impl Drop for MockKernel {
+    fn drop(&mut self) {
+        override_syscall(test_syscall);
+        if std::thread::panicking() {
+            return;
+        }
+        if let Some(expected) = self.expected_prog_loads {
+            assert_eq!(
+                self.prog_loads(),
+                expected,
+                "unexpected number of program loads"
+            );
+        }
+    }
+}
+
+fn c_chars_to_string(chars: &[libc::c_char]) -> String {
+    let bytes = chars
+        .iter()
+        .map(|c| *c as u8)
+        .take_while(|c| *c != 0)
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+unsafe fn mock_syscall(call: Syscall) -> SysResult {
+    use bpf_cmd::*;
+
+    let (call, creates_fd) = match call {
+        Syscall::Bpf {
+            cmd: BPF_MAP_CREATE,
+            attr,
+        } => {
+            let u = &attr.__bindgen_anon_1;
+            (
+                MockCall::MapCreate {
+                    name: c_chars_to_string(&u.map_name),
+                    map_type: u.map_type,
+                    key_size: u.key_size,
+                    value_size: u.value_size,
+                    max_entries: u.max_entries,
+                },
+                true,
+            )
+        }
+        Syscall::Bpf {
+            cmd: BPF_PROG_LOAD,
+            attr,
+        } => {
+            let u = &attr.__bindgen_anon_3;
+            (
+                MockCall::ProgLoad {
+                    name: c_chars_to_string(&u.prog_name),
+                    prog_type: u.prog_type,
+                },
+                true,
+            )
+        }
+        Syscall::Bpf { cmd, .. } => (
+            MockCall::Bpf { cmd },
+            matches!(
+                cmd,
+                BPF_OBJ_GET
+                    | BPF_BTF_LOAD
+                    | BPF_LINK_CREATE
+                    | BPF_RAW_TRACEPOINT_OPEN
+                    | BPF_PROG_GET_FD_BY_ID
+                    | BPF_MAP_GET_FD_BY_ID
+                    | BPF_BTF_GET_FD_BY_ID
+            ),
+        ),
+        Syscall::PerfEventOpen { .. } => (MockCall::PerfEventOpen, true),
+        Syscall::PerfEventIoctl { .. } => (MockCall::PerfEventIoctl, false),
+    };
+
+    MOCK_KERNEL_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.calls.push(call);
+        if creates_fd {
+            let fd = state.next_fd;
+            state.next_fd += 1;
+            Ok(fd)
+        } else {
+            Ok(0)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bpf_map_def,
+        generated::{bpf_map_type::BPF_MAP_TYPE_HASH, bpf_prog_type::BPF_PROG_TYPE_XDP},
+        maps::Map,
+        obj::{
+            self,
+            btf::{FuncSecInfo, LineSecInfo},
+            MapKind,
+        },
+        sys::{bpf_load_program, BpfLoadProgramAttrs},
+        util::VerifierLog,
+    };
+    use std::ffi::CString;
+
+    #[test]
+    fn test_mock_kernel_records_map_create() {
+        let kernel = MockKernel::new();
+        let mut map = Map {
+            obj: obj::Map {
+                def: bpf_map_def {
+                    map_type: BPF_MAP_TYPE_HASH as u32,
+                    key_size: 4,
+                    value_size: 8,
+                    max_entries: 1024,
+                    ..Default::default()
+                },
+                section_index: 0,
+                data: Vec::new(),
+                kind: MapKind::Other,
+            },
+            fd: None,
+            pinned: false,
+            frozen: false,
+            name: None,
+        };
+        let fd = map.create("my_map").unwrap();
+        assert_eq!(fd as i64, MOCK_FIRST_FD);
+        kernel.assert_map_created("my_map", BPF_MAP_TYPE_HASH);
+        assert_eq!(
+            kernel.calls(),
+            vec![MockCall::MapCreate {
+                name: "my_map".to_owned(),
+                map_type: BPF_MAP_TYPE_HASH as u32,
+                key_size: 4,
+                value_size: 8,
+                max_entries: 1024,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_mock_kernel_expect_prog_load() {
+        let mut kernel = MockKernel::new();
+        kernel.expect_prog_load(1);
+        let license = CString::new("GPL").unwrap();
+        let attr = BpfLoadProgramAttrs {
+            name: Some(CString::new("my_prog").unwrap()),
+            ty: BPF_PROG_TYPE_XDP,
+            insns: &[],
+            license: &license,
+            kernel_version: 0,
+            expected_attach_type: None,
+            prog_btf_fd: None,
+            attach_btf_obj_fd: None,
+            attach_btf_id: None,
+            attach_prog_fd: None,
+            func_info_rec_size: 0,
+            func_info: FuncSecInfo::default(),
+            line_info_rec_size: 0,
+            line_info: LineSecInfo::default(),
+            token_fd: None,
+            prog_ifindex: None,
+            prog_flags: 0,
+        };
+        let mut logger = VerifierLog::new();
+        assert_eq!(bpf_load_program(&attr, &mut logger).unwrap(), MOCK_FIRST_FD);
+        assert_eq!(
+            kernel.calls(),
+            vec![MockCall::ProgLoad {
+                name: "my_prog".to_owned(),
+                prog_type: BPF_PROG_TYPE_XDP as u32,
+            }]
+        );
+    }
+}
@@ -1,8 +1,12 @@
+//! Low level access to the `bpf(2)` and `perf_event_open(2)` syscalls.
+//!
+//! Aya wraps the syscalls for all the features it supports. The items in this
+//! module are an escape hatch for prototyping features it doesn't support yet.
 mod bpf;
 mod netlink;
 mod perf_event;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "mock_kernel"))]
 mod fake;
 
 use std::io;
@@ -14,12 +18,16 @@ use libc::utsname;
 use libc::{c_int, c_long, pid_t};
 
 pub(crate) use bpf::*;
-#[cfg(test)]
+pub use bpf::{bpf_btf_load, bpf_token_create, BtfLoadError};
+#[cfg(any(test, feature = "mock_kernel"))]
 pub(crate) use fake::*;
+#[cfg(feature = "mock_kernel")]
+pub use fake::{MockCall, MockKernel};
 pub(crate) use netlink::*;
 pub(crate) use perf_event::*;
+pub use perf_event::{perf_event_open, PerfEventAttrBuilder};
 
-use crate::generated::{bpf_attr, bpf_cmd, perf_event_attr};
+pub use crate::generated::{bpf_attr, bpf_cmd, perf_event_attr};
 
 pub(crate) type SysResult = Result<c_long, (c_long, io::Error)>;
 
@@ -43,11 +51,32 @@ pub(crate) enum Syscall<'a> {
     },
 }
 
+/// Calls the `bpf(2)` syscall with the given command and attributes.
+///
+/// This is a thin wrapper around the syscall and doesn't check its arguments.
+/// On success it returns the value returned by the syscall, which depending on
+/// `cmd` can be a new file descriptor owned by the caller.
+///
+/// # Safety
+///
+/// The caller must make sure that `attr` points to a valid `bpf_attr` of at
+/// least `size` bytes, that the fields used by `cmd` are initialized as the
+/// kernel expects, and that any pointers stored in `attr` are valid for the
+/// kernel to read or write.
+pub unsafe fn bpf_raw_cmd(cmd: bpf_cmd, attr: *mut bpf_attr, size: u32) -> Result<i64, io::Error> {
+    let ret = libc::syscall(libc::SYS_bpf, cmd, attr, size);
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ret as i64)
+}
+
 fn syscall(call: Syscall) -> SysResult {
-    #[cfg(not(test))]
+    #[cfg(not(any(test, feature = "mock_kernel")))]
     return unsafe { syscall_impl(call) };
 
-    #[cfg(test)]
+    #[cfg(any(test, feature = "mock_kernel"))]
     return TEST_SYSCALL.with(|test_impl| unsafe { test_impl.borrow()(call) });
 }
 
@@ -79,7 +108,7 @@ unsafe fn syscall_impl(call: Syscall) -> SysResult {
 
 #[cfg(test)]
 pub(crate) fn kernel_version() -> Result<(u32, u32, u32), ()> {
-    Ok((0xff, 0xff, 0xff))
+    Ok(TEST_KERNEL_VERSION.with(|v| v.get()))
 }
 
 #[cfg(not(test))]
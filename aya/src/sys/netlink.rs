@@ -1,26 +1,44 @@
-use std::{collections::HashMap, ffi::CStr, io, mem, os::unix::io::RawFd, ptr, slice};
+use std::{
+    collections::HashMap, convert::TryInto, ffi::CStr, io, mem, os::unix::io::RawFd, ptr, slice,
+};
 use thiserror::Error;
 
 use libc::{
-    close, getsockname, nlattr, nlmsgerr, nlmsghdr, recv, send, setsockopt, sockaddr_nl, socket,
-    AF_NETLINK, AF_UNSPEC, ETH_P_ALL, IFLA_XDP, NETLINK_EXT_ACK, NETLINK_ROUTE, NLA_ALIGNTO,
-    NLA_F_NESTED, NLA_TYPE_MASK, NLMSG_DONE, NLMSG_ERROR, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP,
-    NLM_F_ECHO, NLM_F_EXCL, NLM_F_MULTI, NLM_F_REQUEST, RTM_DELTFILTER, RTM_GETTFILTER,
-    RTM_NEWQDISC, RTM_NEWTFILTER, RTM_SETLINK, SOCK_RAW, SOL_NETLINK,
+    close, genlmsghdr, getsockname, nlattr, nlmsgerr, nlmsghdr, recv, send, setsockopt,
+    sockaddr_nl, socket, AF_NETLINK, AF_UNSPEC, CTRL_ATTR_FAMILY_ID, CTRL_ATTR_FAMILY_NAME,
+    CTRL_CMD_GETFAMILY, ETH_P_ALL, GENL_ID_CTRL, IFLA_IFNAME, IFLA_XDP, NETLINK_EXT_ACK,
+    NETLINK_GENERIC, NETLINK_ROUTE, NLA_ALIGNTO, NLA_F_NESTED, NLA_TYPE_MASK, NLMSG_DONE,
+    NLMSG_ERROR, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_ECHO, NLM_F_EXCL, NLM_F_MULTI,
+    NLM_F_REQUEST, RTM_DELTFILTER, RTM_GETLINK, RTM_GETTFILTER, RTM_NEWLINK, RTM_NEWQDISC,
+    RTM_NEWTFILTER, RTM_SETLINK, SOCK_RAW, SOL_NETLINK,
 };
 
 use crate::{
     generated::{
-        ifinfomsg, tcmsg, IFLA_XDP_EXPECTED_FD, IFLA_XDP_FD, IFLA_XDP_FLAGS, NLMSG_ALIGNTO,
-        TCA_BPF_FD, TCA_BPF_FLAGS, TCA_BPF_FLAG_ACT_DIRECT, TCA_BPF_NAME, TCA_KIND, TCA_OPTIONS,
-        TC_H_CLSACT, TC_H_INGRESS, TC_H_MAJ_MASK, TC_H_UNSPEC, XDP_FLAGS_REPLACE,
+        ifinfomsg, tcmsg, IFLA_XDP_ATTACHED, IFLA_XDP_DRV_PROG_ID, IFLA_XDP_EXPECTED_FD,
+        IFLA_XDP_FD, IFLA_XDP_FLAGS, IFLA_XDP_HW_PROG_ID, IFLA_XDP_PROG_ID, IFLA_XDP_SKB_PROG_ID,
+        NLMSG_ALIGNTO, TCA_BPF_FD, TCA_BPF_FLAGS, TCA_BPF_FLAG_ACT_DIRECT, TCA_BPF_NAME, TCA_KIND,
+        TCA_OPTIONS, TC_H_CLSACT, TC_H_INGRESS, TC_H_MAJ_MASK, TC_H_UNSPEC, XDP_FLAGS_DRV_MODE,
+        XDP_FLAGS_HW_MODE, XDP_FLAGS_REPLACE, XDP_FLAGS_SKB_MODE,
     },
     programs::TcAttachType,
-    util::tc_handler_make,
+    util::{tc_handler_make, IfaceInfo},
 };
 
 const NLA_HDR_LEN: usize = align_to(mem::size_of::<nlattr>(), NLA_ALIGNTO as usize);
 
+// see `enum { XDP_ATTACHED_NONE, ... }` in include/uapi/linux/if_link.h
+const XDP_ATTACHED_DRV: u8 = 1;
+const XDP_ATTACHED_SKB: u8 = 2;
+const XDP_ATTACHED_HW: u8 = 3;
+const XDP_ATTACHED_MULTI: u8 = 4;
+
+// see include/uapi/linux/netdev.h
+const NETDEV_FAMILY_NAME: &[u8] = b"netdev\0";
+const NETDEV_CMD_DEV_GET: u8 = 1;
+const NETDEV_A_DEV_IFINDEX: u16 = 1;
+const NETDEV_A_DEV_XDP_RX_METADATA_FEATURES: u16 = 5;
+
 // Safety: marking this as unsafe overall because of all the pointer math required to comply with
 // netlink alignments
 pub(crate) unsafe fn netlink_set_xdp_fd(
@@ -240,6 +258,198 @@ pub(crate) unsafe fn netlink_find_filter_with_name(
     Ok(prios)
 }
 
+pub(crate) fn netlink_list_interfaces() -> Result<Vec<IfaceInfo>, io::Error> {
+    // Safety: Request is POD so this is safe
+    let mut req = unsafe { mem::zeroed::<Request>() };
+
+    let nlmsg_len = mem::size_of::<nlmsghdr>() + mem::size_of::<ifinfomsg>();
+    req.header = nlmsghdr {
+        nlmsg_len: nlmsg_len as u32,
+        nlmsg_flags: (NLM_F_REQUEST | NLM_F_DUMP) as u16,
+        nlmsg_type: RTM_GETLINK,
+        nlmsg_pid: 0,
+        nlmsg_seq: 1,
+    };
+    req.if_info.ifi_family = AF_UNSPEC as u8;
+
+    let sock = NetlinkSocket::open()?;
+    sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
+
+    let mut interfaces = Vec::new();
+    for msg in sock.recv()? {
+        if msg.header.nlmsg_type != RTM_NEWLINK {
+            continue;
+        }
+        interfaces.push(parse_link(&msg.data)?);
+    }
+
+    Ok(interfaces)
+}
+
+fn parse_link(data: &[u8]) -> Result<IfaceInfo, io::Error> {
+    if data.len() < mem::size_of::<ifinfomsg>() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "buffer smaller than ifinfomsg",
+        ));
+    }
+    // Safety: ifinfomsg is POD so read is safe
+    let if_info = unsafe { ptr::read_unaligned(data.as_ptr() as *const ifinfomsg) };
+    let attrs_offset = align_to(mem::size_of::<ifinfomsg>(), NLMSG_ALIGNTO as usize);
+    let attrs = parse_attrs(&data[attrs_offset.min(data.len())..])?;
+
+    let name = attrs
+        .get(&IFLA_IFNAME)
+        .map(|attr| {
+            let len = attr
+                .data
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or(attr.data.len());
+            String::from_utf8_lossy(&attr.data[..len]).into_owned()
+        })
+        .unwrap_or_default();
+
+    let mut xdp_prog_id = None;
+    let mut xdp_flags = 0;
+    let mut mode_prog_ids = (None, None, None);
+    if let Some(xdp) = attrs.get(&IFLA_XDP) {
+        let xdp = parse_attrs(xdp.data)?;
+        let read_u32 = |attr_type: u16| {
+            xdp.get(&attr_type)
+                .and_then(|attr| attr.data.get(..4))
+                .map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+                .filter(|id| *id != 0)
+        };
+        let attached = xdp
+            .get(&(IFLA_XDP_ATTACHED as u16))
+            .and_then(|attr| attr.data.first().copied())
+            .unwrap_or(0);
+
+        let prog_id = read_u32(IFLA_XDP_PROG_ID as u16);
+        let drv_prog_id = read_u32(IFLA_XDP_DRV_PROG_ID as u16);
+        let skb_prog_id = read_u32(IFLA_XDP_SKB_PROG_ID as u16);
+        let hw_prog_id = read_u32(IFLA_XDP_HW_PROG_ID as u16);
+        mode_prog_ids = match attached {
+            XDP_ATTACHED_DRV => (drv_prog_id.or(prog_id), None, None),
+            XDP_ATTACHED_SKB => (None, skb_prog_id.or(prog_id), None),
+            XDP_ATTACHED_HW => (None, None, hw_prog_id.or(prog_id)),
+            // programs are attached in more than one mode, and the kernel
+            // only reports the id of each one of them
+            XDP_ATTACHED_MULTI => (drv_prog_id, skb_prog_id, hw_prog_id),
+            _ => (None, None, None),
+        };
+        let (drv_prog_id, skb_prog_id, hw_prog_id) = mode_prog_ids;
+        if drv_prog_id.is_some() {
+            xdp_flags |= XDP_FLAGS_DRV_MODE;
+        }
+        if skb_prog_id.is_some() {
+            xdp_flags |= XDP_FLAGS_SKB_MODE;
+        }
+        if hw_prog_id.is_some() {
+            xdp_flags |= XDP_FLAGS_HW_MODE;
+        }
+        xdp_prog_id = prog_id.or(drv_prog_id).or(skb_prog_id).or(hw_prog_id);
+    }
+    let (xdp_drv_prog_id, xdp_skb_prog_id, xdp_hw_prog_id) = mode_prog_ids;
+
+    Ok(IfaceInfo {
+        ifindex: if_info.ifi_index as u32,
+        name,
+        xdp_prog_id,
+        xdp_flags,
+        xdp_drv_prog_id,
+        xdp_skb_prog_id,
+        xdp_hw_prog_id,
+    })
+}
+
+/// Returns the `NETDEV_XDP_RX_METADATA_*` features the driver of the
+/// interface `if_index` implements, using the `netdev` generic netlink
+/// family.
+pub(crate) fn netlink_xdp_rx_metadata_features(if_index: u32) -> Result<u64, io::Error> {
+    let sock = NetlinkSocket::open_protocol(NETLINK_GENERIC)?;
+    let family_id = genl_family_id(&sock, NETDEV_FAMILY_NAME)?;
+
+    // Safety: GenlRequest is POD so this is safe
+    let mut req = unsafe { mem::zeroed::<GenlRequest>() };
+    let nlmsg_len = mem::size_of::<nlmsghdr>() + mem::size_of::<genlmsghdr>();
+    req.header = nlmsghdr {
+        nlmsg_len: nlmsg_len as u32,
+        nlmsg_flags: NLM_F_REQUEST as u16,
+        nlmsg_type: family_id,
+        nlmsg_pid: 0,
+        nlmsg_seq: 1,
+    };
+    req.genl_header.cmd = NETDEV_CMD_DEV_GET;
+    req.genl_header.version = 1;
+
+    let attrs_buf = unsafe { request_attributes(&mut req, nlmsg_len) };
+    let len = write_attr(attrs_buf, 0, NETDEV_A_DEV_IFINDEX, if_index)?;
+    req.header.nlmsg_len += align_to(len, NLA_ALIGNTO as usize) as u32;
+
+    sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
+    match sock.recv()?.first() {
+        Some(msg) if msg.header.nlmsg_type == family_id => {
+            parse_xdp_rx_metadata_features(&msg.data)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no reply to NETDEV_CMD_DEV_GET",
+        )),
+    }
+}
+
+fn genl_family_id(sock: &NetlinkSocket, name: &[u8]) -> Result<u16, io::Error> {
+    // Safety: GenlRequest is POD so this is safe
+    let mut req = unsafe { mem::zeroed::<GenlRequest>() };
+    let nlmsg_len = mem::size_of::<nlmsghdr>() + mem::size_of::<genlmsghdr>();
+    req.header = nlmsghdr {
+        nlmsg_len: nlmsg_len as u32,
+        nlmsg_flags: NLM_F_REQUEST as u16,
+        nlmsg_type: GENL_ID_CTRL as u16,
+        nlmsg_pid: 0,
+        nlmsg_seq: 1,
+    };
+    req.genl_header.cmd = CTRL_CMD_GETFAMILY as u8;
+    req.genl_header.version = 1;
+
+    let attrs_buf = unsafe { request_attributes(&mut req, nlmsg_len) };
+    let len = write_attr_bytes(attrs_buf, 0, CTRL_ATTR_FAMILY_NAME as u16, name)?;
+    req.header.nlmsg_len += align_to(len, NLA_ALIGNTO as usize) as u32;
+
+    sock.send(&bytes_of(&req)[..req.header.nlmsg_len as usize])?;
+    for msg in sock.recv()? {
+        let attrs = parse_attrs(genl_attrs(&msg.data))?;
+        if let Some(id) = attrs
+            .get(&(CTRL_ATTR_FAMILY_ID as u16))
+            .and_then(|attr| attr.data.get(..2))
+        {
+            return Ok(u16::from_ne_bytes(id.try_into().unwrap()));
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "generic netlink family not found",
+    ))
+}
+
+fn genl_attrs(data: &[u8]) -> &[u8] {
+    let attrs_offset = align_to(mem::size_of::<genlmsghdr>(), NLMSG_ALIGNTO as usize);
+    &data[attrs_offset.min(data.len())..]
+}
+
+fn parse_xdp_rx_metadata_features(data: &[u8]) -> Result<u64, io::Error> {
+    let attrs = parse_attrs(genl_attrs(data))?;
+    // kernels that don't report the features don't implement any of them
+    Ok(attrs
+        .get(&NETDEV_A_DEV_XDP_RX_METADATA_FEATURES)
+        .and_then(|attr| attr.data.get(..8))
+        .map(|b| u64::from_ne_bytes(b.try_into().unwrap()))
+        .unwrap_or(0))
+}
+
 #[repr(C)]
 struct Request {
     header: nlmsghdr,
@@ -254,6 +464,13 @@ struct TcRequest {
     attrs: [u8; 64],
 }
 
+#[repr(C)]
+struct GenlRequest {
+    header: nlmsghdr,
+    genl_header: genlmsghdr,
+    attrs: [u8; 64],
+}
+
 struct NetlinkSocket {
     sock: RawFd,
     _nl_pid: u32,
@@ -261,8 +478,12 @@ struct NetlinkSocket {
 
 impl NetlinkSocket {
     fn open() -> Result<NetlinkSocket, io::Error> {
+        NetlinkSocket::open_protocol(NETLINK_ROUTE)
+    }
+
+    fn open_protocol(protocol: i32) -> Result<NetlinkSocket, io::Error> {
         // Safety: libc wrapper
-        let sock = unsafe { socket(AF_NETLINK, SOCK_RAW, NETLINK_ROUTE) };
+        let sock = unsafe { socket(AF_NETLINK, SOCK_RAW, protocol) };
         if sock < 0 {
             return Err(io::Error::last_os_error());
         }
@@ -303,7 +524,9 @@ impl NetlinkSocket {
     }
 
     fn recv(&self) -> Result<Vec<NetlinkMessage>, io::Error> {
-        let mut buf = [0u8; 4096];
+        // link dumps can contain messages much larger than a page, for
+        // example for interfaces with many VFs
+        let mut buf = [0u8; 32768];
         let mut messages = Vec::new();
         let mut multipart = true;
         'out: while multipart {
@@ -590,10 +813,79 @@ fn bytes_of<T>(val: &T) -> &[u8] {
 
 #[cfg(test)]
 mod tests {
-    use std::{convert::TryInto, ffi::CString};
+    use std::ffi::CString;
 
     use super::*;
 
+    #[test]
+    fn test_parse_link() {
+        let mut data = vec![0u8; 64];
+        let if_info = ifinfomsg {
+            ifi_family: AF_UNSPEC as u8,
+            __ifi_pad: 0,
+            ifi_type: 0,
+            ifi_index: 2,
+            ifi_flags: 0,
+            ifi_change: 0,
+        };
+        data[..mem::size_of::<ifinfomsg>()].copy_from_slice(bytes_of(&if_info));
+        let mut offset = mem::size_of::<ifinfomsg>();
+        offset += write_attr_bytes(&mut data, offset, IFLA_IFNAME, b"eth0\0").unwrap();
+        let mut attrs = NestedAttrs::new(&mut data[offset..], IFLA_XDP);
+        attrs
+            .write_attr(IFLA_XDP_ATTACHED as u16, XDP_ATTACHED_SKB)
+            .unwrap();
+        attrs.write_attr(IFLA_XDP_PROG_ID as u16, 42u32).unwrap();
+        attrs
+            .write_attr(IFLA_XDP_SKB_PROG_ID as u16, 42u32)
+            .unwrap();
+        offset += attrs.finish().unwrap();
+        data.truncate(offset);
+
+        let iface = parse_link(&data).unwrap();
+        assert_eq!(iface.ifindex, 2);
+        assert_eq!(iface.name, "eth0");
+        assert_eq!(iface.xdp_prog_id, Some(42));
+        assert_eq!(iface.xdp_flags, XDP_FLAGS_SKB_MODE);
+        assert_eq!(iface.xdp_skb_prog_id, Some(42));
+        assert_eq!(iface.xdp_drv_prog_id, None);
+    }
+
+    #[test]
+    fn test_parse_link_multi() {
+        let mut data = vec![0u8; 64];
+        let mut offset = mem::size_of::<ifinfomsg>();
+        let mut attrs = NestedAttrs::new(&mut data[offset..], IFLA_XDP);
+        attrs
+            .write_attr(IFLA_XDP_ATTACHED as u16, XDP_ATTACHED_MULTI)
+            .unwrap();
+        attrs
+            .write_attr(IFLA_XDP_DRV_PROG_ID as u16, 42u32)
+            .unwrap();
+        attrs
+            .write_attr(IFLA_XDP_SKB_PROG_ID as u16, 43u32)
+            .unwrap();
+        offset += attrs.finish().unwrap();
+        data.truncate(offset);
+
+        let iface = parse_link(&data).unwrap();
+        assert_eq!(iface.xdp_flags, XDP_FLAGS_DRV_MODE | XDP_FLAGS_SKB_MODE);
+        assert_eq!(iface.xdp_drv_prog_id, Some(42));
+        assert_eq!(iface.xdp_skb_prog_id, Some(43));
+        assert_eq!(iface.xdp_hw_prog_id, None);
+    }
+
+    #[test]
+    fn test_parse_link_without_xdp() {
+        let mut data = vec![0u8; mem::size_of::<ifinfomsg>()];
+        data[4..8].copy_from_slice(&1i32.to_ne_bytes());
+
+        let iface = parse_link(&data).unwrap();
+        assert_eq!(iface.ifindex, 1);
+        assert_eq!(iface.xdp_prog_id, None);
+        assert_eq!(iface.xdp_flags, 0);
+    }
+
     #[test]
     fn test_nested_attrs() {
         let mut buf = [0; 64];
@@ -721,4 +1013,26 @@ mod tests {
         let name = CStr::from_bytes_with_nul(inner.data).unwrap();
         assert_eq!(name.to_string_lossy(), "foo");
     }
+
+    #[test]
+    fn test_parse_xdp_rx_metadata_features() {
+        let mut data = vec![0u8; 32];
+        // genlmsghdr, then the attributes
+        let offset = mem::size_of::<genlmsghdr>();
+        let len = write_attr(&mut data, offset, NETDEV_A_DEV_IFINDEX, 2u32).unwrap();
+        let features_len = write_attr(
+            &mut data,
+            offset + len,
+            NETDEV_A_DEV_XDP_RX_METADATA_FEATURES,
+            3u64,
+        )
+        .unwrap();
+        data.truncate(offset + len + features_len);
+
+        assert_eq!(parse_xdp_rx_metadata_features(&data).unwrap(), 3);
+        assert_eq!(
+            parse_xdp_rx_metadata_features(&data[..offset + len]).unwrap(),
+            0
+        );
+    }
 }
@@ -1,10 +1,10 @@
-use std::{ffi::CString, mem};
+use std::{ffi::CString, io, mem, os::unix::io::RawFd};
 
 use libc::{c_int, pid_t};
 
 use crate::generated::{
     perf_event_attr,
-    perf_event_sample_format::PERF_SAMPLE_RAW,
+    perf_event_sample_format::{PERF_SAMPLE_CALLCHAIN, PERF_SAMPLE_RAW},
     perf_sw_ids::PERF_COUNT_SW_BPF_OUTPUT,
     perf_type_id::{PERF_TYPE_SOFTWARE, PERF_TYPE_TRACEPOINT},
     PERF_FLAG_FD_CLOEXEC,
@@ -12,53 +12,145 @@ use crate::generated::{
 
 use super::{syscall, SysResult, Syscall};
 
-#[allow(clippy::too_many_arguments)]
-pub(crate) fn perf_event_open(
-    perf_type: u32,
-    config: u64,
+/// Builds the `perf_event_attr` passed to [`perf_event_open`].
+///
+/// The attributes start zeroed, with `size` set to the size of
+/// `perf_event_attr`, so the fields that aren't set keep the default the
+/// kernel gives to zero.
+///
+/// # Examples
+///
+/// ```no_run
+/// use aya::sys::{perf_event_open, PerfEventAttrBuilder};
+///
+/// // PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CPU_CLOCK
+/// let attr = PerfEventAttrBuilder::new(1, 0).sample_frequency(99).build();
+/// let fd = perf_event_open(&attr, -1, 0, -1, 0)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Copy)]
+pub struct PerfEventAttrBuilder {
+    attr: perf_event_attr,
+}
+
+impl PerfEventAttrBuilder {
+    /// Creates a builder for the event `config` of type `perf_type`.
+    pub fn new(perf_type: u32, config: u64) -> PerfEventAttrBuilder {
+        let mut attr = unsafe { mem::zeroed::<perf_event_attr>() };
+        attr.size = mem::size_of::<perf_event_attr>() as u32;
+        attr.type_ = perf_type;
+        attr.config = config;
+        PerfEventAttrBuilder { attr }
+    }
+
+    /// Samples the event every `period` occurrences.
+    pub fn sample_period(mut self, period: u64) -> PerfEventAttrBuilder {
+        self.attr.set_freq(0);
+        self.attr.__bindgen_anon_1.sample_period = period;
+        self
+    }
+
+    /// Samples the event `frequency` times per second.
+    pub fn sample_frequency(mut self, frequency: u64) -> PerfEventAttrBuilder {
+        self.attr.set_freq(1);
+        self.attr.__bindgen_anon_1.sample_freq = frequency;
+        self
+    }
+
+    /// Sets the `PERF_SAMPLE_*` values included in the samples.
+    pub fn sample_type(mut self, sample_type: u64) -> PerfEventAttrBuilder {
+        self.attr.sample_type = sample_type;
+        self
+    }
+
+    /// Wakes up readers of the event every `events` samples.
+    pub fn wakeup_events(mut self, events: u32) -> PerfEventAttrBuilder {
+        self.attr.__bindgen_anon_2.wakeup_events = events;
+        self
+    }
+
+    /// Sets the `config1` field, used by some event types to extend `config`.
+    pub fn config1(mut self, config1: u64) -> PerfEventAttrBuilder {
+        self.attr.__bindgen_anon_3.config1 = config1;
+        self
+    }
+
+    /// Sets the `config2` field, used by some event types to extend `config1`.
+    pub fn config2(mut self, config2: u64) -> PerfEventAttrBuilder {
+        self.attr.__bindgen_anon_4.config2 = config2;
+        self
+    }
+
+    /// Opens the event disabled, it must then be enabled with
+    /// `PERF_EVENT_IOC_ENABLE`.
+    pub fn disabled(mut self, disabled: bool) -> PerfEventAttrBuilder {
+        self.attr.set_disabled(disabled as u64);
+        self
+    }
+
+    /// Also counts the events of the children of the measured process.
+    pub fn inherit(mut self, inherit: bool) -> PerfEventAttrBuilder {
+        self.attr.set_inherit(inherit as u64);
+        self
+    }
+
+    /// Returns the attributes.
+    pub fn build(self) -> perf_event_attr {
+        self.attr
+    }
+}
+
+/// Calls the `perf_event_open(2)` syscall.
+///
+/// `pid`, `cpu`, `group_fd` and `flags` are passed to the kernel unchanged.
+/// `attr` is copied before the call, and if its `size` field is zero it's set
+/// to the size of `perf_event_attr`. On success the new perf event fd is
+/// returned and owned by the caller.
+pub fn perf_event_open(
+    attr: &perf_event_attr,
     pid: pid_t,
     cpu: c_int,
-    sample_period: u64,
-    sample_frequency: Option<u64>,
-    wakeup: bool,
+    group_fd: c_int,
     flags: u32,
-) -> SysResult {
-    let mut attr = unsafe { mem::zeroed::<perf_event_attr>() };
-
-    attr.config = config;
-    attr.size = mem::size_of::<perf_event_attr>() as u32;
-    attr.type_ = perf_type;
-    attr.sample_type = PERF_SAMPLE_RAW as u64;
-    // attr.inherits = if pid > 0 { 1 } else { 0 };
-    attr.__bindgen_anon_2.wakeup_events = if wakeup { 1 } else { 0 };
-
-    if let Some(frequency) = sample_frequency {
-        attr.set_freq(1);
-        attr.__bindgen_anon_1.sample_freq = frequency;
-    } else {
-        attr.__bindgen_anon_1.sample_period = sample_period;
+) -> Result<RawFd, io::Error> {
+    let mut attr = *attr;
+    if attr.size == 0 {
+        attr.size = mem::size_of::<perf_event_attr>() as u32;
     }
+    open(attr, pid, cpu, group_fd, flags)
+        .map(|fd| fd as RawFd)
+        .map_err(|(_, io_error)| io_error)
+}
 
+fn open(attr: perf_event_attr, pid: pid_t, cpu: c_int, group: c_int, flags: u32) -> SysResult {
     syscall(Syscall::PerfEventOpen {
         attr,
         pid,
         cpu,
-        group: -1,
+        group,
         flags,
     })
 }
 
-pub(crate) fn perf_event_open_bpf(cpu: c_int) -> SysResult {
-    perf_event_open(
-        PERF_TYPE_SOFTWARE as u32,
-        PERF_COUNT_SW_BPF_OUTPUT as u64,
-        -1,
-        cpu,
-        1,
-        None,
-        true,
-        PERF_FLAG_FD_CLOEXEC,
-    )
+// opens the event backing a perf buffer. If `max_stack` is set, the samples
+// are prefixed with the callchain of the program, at most `max_stack` frames
+// deep.
+pub(crate) fn perf_event_open_bpf(cpu: c_int, max_stack: Option<u16>) -> SysResult {
+    let mut sample_type = PERF_SAMPLE_RAW as u64;
+    if max_stack.is_some() {
+        sample_type |= PERF_SAMPLE_CALLCHAIN as u64;
+    }
+    let mut attr =
+        PerfEventAttrBuilder::new(PERF_TYPE_SOFTWARE as u32, PERF_COUNT_SW_BPF_OUTPUT as u64)
+            .sample_type(sample_type)
+            .sample_period(1)
+            .wakeup_events(1)
+            .build();
+    if let Some(max_stack) = max_stack {
+        attr.sample_max_stack = max_stack;
+    }
+
+    open(attr, -1, cpu, -1, PERF_FLAG_FD_CLOEXEC)
 }
 
 pub(crate) fn perf_event_open_probe(
@@ -66,50 +158,37 @@ pub(crate) fn perf_event_open_probe(
     ret_bit: Option<u32>,
     name: &str,
     offset: u64,
+    ref_ctr_offset: u64,
     pid: Option<pid_t>,
 ) -> SysResult {
-    let mut attr = unsafe { mem::zeroed::<perf_event_attr>() };
-
+    let mut config = 0;
     if let Some(ret_bit) = ret_bit {
-        attr.config = 1 << ret_bit;
+        config = 1 << ret_bit;
     }
+    // the offset of the reference counter (USDT semaphore) is stored in the
+    // upper 32 bits of config, see PERF_UPROBE_REF_CTR_OFFSET_SHIFT
+    config |= ref_ctr_offset << 32;
 
     let c_name = CString::new(name).unwrap();
 
-    attr.size = mem::size_of::<perf_event_attr>() as u32;
-    attr.type_ = ty;
-    attr.__bindgen_anon_3.config1 = c_name.as_ptr() as u64;
-    attr.__bindgen_anon_4.config2 = offset;
+    let attr = PerfEventAttrBuilder::new(ty, config)
+        .config1(c_name.as_ptr() as u64)
+        .config2(offset)
+        .build();
 
     let cpu = if pid.is_some() { -1 } else { 0 };
     let pid = pid.unwrap_or(-1);
 
-    syscall(Syscall::PerfEventOpen {
-        attr,
-        pid,
-        cpu,
-        group: -1,
-        flags: PERF_FLAG_FD_CLOEXEC,
-    })
+    open(attr, pid, cpu, -1, PERF_FLAG_FD_CLOEXEC)
 }
 
 pub(crate) fn perf_event_open_trace_point(id: u32, pid: Option<pid_t>) -> SysResult {
-    let mut attr = unsafe { mem::zeroed::<perf_event_attr>() };
-
-    attr.size = mem::size_of::<perf_event_attr>() as u32;
-    attr.type_ = PERF_TYPE_TRACEPOINT as u32;
-    attr.config = id as u64;
+    let attr = PerfEventAttrBuilder::new(PERF_TYPE_TRACEPOINT as u32, id as u64).build();
 
     let cpu = if pid.is_some() { -1 } else { 0 };
     let pid = pid.unwrap_or(-1);
 
-    syscall(Syscall::PerfEventOpen {
-        attr,
-        pid,
-        cpu,
-        group: -1,
-        flags: PERF_FLAG_FD_CLOEXEC,
-    })
+    open(attr, pid, cpu, -1, PERF_FLAG_FD_CLOEXEC)
 }
 
 pub(crate) fn perf_event_ioctl(fd: c_int, request: c_int, arg: c_int) -> SysResult {
@@ -2,6 +2,7 @@
 use std::{
     cmp,
     collections::BTreeMap,
+    convert::TryFrom,
     ffi::{CStr, CString},
     fs::{self, File},
     io::{self, BufReader},
@@ -9,12 +10,20 @@ use std::{
     str::FromStr,
 };
 
-use crate::generated::{TC_H_MAJ_MASK, TC_H_MIN_MASK};
+use crate::{
+    generated::{TC_H_MAJ_MASK, TC_H_MIN_MASK},
+    sys::netlink_list_interfaces,
+};
 
-use libc::{if_nametoindex, sysconf, _SC_PAGESIZE};
+use libc::{
+    getrlimit, if_nametoindex, rlim_t, rlimit, setrlimit, sysconf, _SC_PAGESIZE, RLIMIT_MEMLOCK,
+    RLIM_INFINITY,
+};
 
 use io::BufRead;
 
+pub mod netns;
+
 const ONLINE_CPUS: &str = "/sys/devices/system/cpu/online";
 pub(crate) const POSSIBLE_CPUS: &str = "/sys/devices/system/cpu/possible";
 
@@ -93,6 +102,253 @@ fn parse_kernel_symbols(reader: impl BufRead) -> Result<BTreeMap<u64, String>, i
     Ok(syms)
 }
 
+/// Loads the file backed executable mappings of a process from
+/// `/proc/<pid>/maps`.
+///
+/// The returned map associates the start address of each mapping with the
+/// path of the mapped file. It can be passed to
+/// [`StackTrace::resolve`](crate::maps::stack_trace::StackTrace::resolve) to
+/// find the binary or shared library each frame of a user space stack trace
+/// belongs to.
+pub fn process_mappings(pid: u32) -> Result<BTreeMap<u64, String>, io::Error> {
+    let mut reader = BufReader::new(File::open(format!("/proc/{}/maps", pid))?);
+    parse_process_mappings(&mut reader)
+}
+
+fn parse_process_mappings(reader: impl BufRead) -> Result<BTreeMap<u64, String>, io::Error> {
+    Ok(parse_process_mapping_ranges(reader)?
+        .into_iter()
+        .map(|mapping| (mapping.start, mapping.path))
+        .collect())
+}
+
+// A file backed executable mapping of a process.
+pub(crate) struct ProcessMapping {
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    // the offset of `start` in the mapped file
+    pub(crate) offset: u64,
+    pub(crate) path: String,
+}
+
+// Like process_mappings, but keeps the end address and file offset of the
+// mappings, which are needed to find the symbols of the addresses in them.
+pub(crate) fn process_mapping_ranges(pid: u32) -> Result<Vec<ProcessMapping>, io::Error> {
+    let mut reader = BufReader::new(File::open(format!("/proc/{}/maps", pid))?);
+    parse_process_mapping_ranges(&mut reader)
+}
+
+fn parse_process_mapping_ranges(reader: impl BufRead) -> Result<Vec<ProcessMapping>, io::Error> {
+    let mut mappings = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        // address perms offset dev inode pathname, the pathname is padded
+        // with spaces and can contain spaces itself
+        let parts = line.splitn(6, ' ').collect::<Vec<_>>();
+        if parts.len() < 6 || !parts[1].contains('x') {
+            continue;
+        }
+        let path = parts[5].trim_start();
+        if !path.starts_with('/') {
+            continue;
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, line.clone());
+        let hex = |s: &str| u64::from_str_radix(s, 16).map_err(|_| invalid());
+        let (start, end) = match parts[0].split_once('-') {
+            Some((start, end)) => (hex(start)?, hex(end)?),
+            None => return Err(invalid()),
+        };
+        mappings.push(ProcessMapping {
+            start,
+            end,
+            offset: hex(parts[2])?,
+            path: path.to_owned(),
+        });
+    }
+
+    Ok(mappings)
+}
+
+/// Information about a network interface, returned by [`list_interfaces`].
+#[derive(Debug, Clone)]
+pub struct IfaceInfo {
+    /// The index of the interface.
+    pub ifindex: u32,
+    /// The name of the interface.
+    pub name: String,
+    /// The id of the XDP program attached to the interface, if any.
+    pub xdp_prog_id: Option<u32>,
+    /// The XDP attach mode, as `XDP_FLAGS_SKB_MODE`, `XDP_FLAGS_DRV_MODE`
+    /// and `XDP_FLAGS_HW_MODE` bits, or zero if no program is attached. See
+    /// [`XdpFlags`](crate::programs::XdpFlags).
+    ///
+    /// Programs can be attached in more than one mode at the same time, in
+    /// which case more than one bit is set.
+    pub xdp_flags: u32,
+    /// The id of the XDP program attached in native mode, if any.
+    pub xdp_drv_prog_id: Option<u32>,
+    /// The id of the XDP program attached in generic mode, if any.
+    pub xdp_skb_prog_id: Option<u32>,
+    /// The id of the XDP program offloaded to the network card, if any.
+    pub xdp_hw_prog_id: Option<u32>,
+}
+
+/// Returns the network interfaces of the current network namespace, along
+/// with the XDP program attached to each of them.
+///
+/// The interfaces are enumerated with a `RTM_GETLINK` netlink request.
+pub fn list_interfaces() -> Result<Vec<IfaceInfo>, io::Error> {
+    netlink_list_interfaces()
+}
+
+/// Removes the `RLIMIT_MEMLOCK` limit of the current process.
+///
+/// Before 5.11, the kernel charges the memory used by eBPF programs and maps
+/// against `RLIMIT_MEMLOCK`, whose default is often too low to load anything
+/// but the smallest programs. Loading then fails with `EPERM`. Call this
+/// before loading any program on those kernels. Raising the hard limit
+/// requires `CAP_SYS_RESOURCE`.
+///
+/// # Examples
+///
+/// ```no_run
+/// aya::util::bump_memlock_rlimit()?;
+/// let bpf = aya::Bpf::load_file("file.o")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn bump_memlock_rlimit() -> Result<(), io::Error> {
+    set_memlock_rlimits(RLIM_INFINITY)
+}
+
+/// Sets the soft `RLIMIT_MEMLOCK` limit of the current process to `bytes`.
+///
+/// The hard limit is only raised if it's lower than `bytes`, which requires
+/// `CAP_SYS_RESOURCE`. It's never lowered, since that can't be undone
+/// without the capability. See [`bump_memlock_rlimit`].
+///
+/// # Errors
+///
+/// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `bytes`
+/// doesn't fit in `rlim_t`, which is 32 bits wide on some 32-bit targets.
+pub fn set_memlock_rlimit(bytes: u64) -> Result<(), io::Error> {
+    let bytes = rlim_t::try_from(bytes).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} bytes is out of the range of rlim_t", bytes),
+        )
+    })?;
+    set_memlock_rlimits(bytes)
+}
+
+fn set_memlock_rlimits(bytes: rlim_t) -> Result<(), io::Error> {
+    let current = memlock_rlimits()?;
+    let rlim_max = if current.rlim_max == RLIM_INFINITY || current.rlim_max >= bytes {
+        current.rlim_max
+    } else {
+        bytes
+    };
+    let limit = rlimit {
+        rlim_cur: bytes,
+        rlim_max,
+    };
+    // Safety: libc wrapper
+    if unsafe { setrlimit(RLIMIT_MEMLOCK, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Raises the `RLIMIT_MEMLOCK` limit of the current process to at least
+/// `required_bytes`, if it's lower.
+///
+/// Unlike [`bump_memlock_rlimit`], the limit is left alone if it's already
+/// high enough, and only the soft limit is raised if the hard limit allows
+/// it, which doesn't require any capability. Raising the hard limit requires
+/// `CAP_SYS_RESOURCE`.
+///
+/// # Errors
+///
+/// Returns an error of kind [`io::ErrorKind::PermissionDenied`] describing
+/// how to get a sufficient limit if it can't be raised.
+///
+/// # Examples
+///
+/// ```no_run
+/// // 16 MiB is enough for the maps and programs of file.o
+/// aya::util::ensure_memlock_sufficient(16 * 1024 * 1024)?;
+/// let bpf = aya::Bpf::load_file("file.o")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn ensure_memlock_sufficient(required_bytes: usize) -> Result<(), io::Error> {
+    let current = memlock_rlimits()?;
+    // usize is wider than rlim_t on some 32-bit targets
+    let required = rlim_t::try_from(required_bytes).unwrap_or(RLIM_INFINITY);
+    let limit = match raised_memlock_rlimit(&current, required) {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    // Safety: libc wrapper
+    if unsafe { setrlimit(RLIMIT_MEMLOCK, &limit) } != 0 {
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::PermissionDenied {
+            return Err(error);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "RLIMIT_MEMLOCK is {} bytes but {} bytes are required, and raising it requires \
+                 CAP_SYS_RESOURCE. Run as root, raise the limit of the process (eg with \
+                 LimitMEMLOCK= in a systemd unit) or use a kernel >= 5.11, which charges eBPF \
+                 memory to the memory cgroup instead",
+                current.rlim_cur, required_bytes
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Returns the limit to set to allow locking `required` bytes, or None if the
+// current limit is enough. Only the soft limit is raised when the hard limit
+// allows it.
+fn raised_memlock_rlimit(current: &rlimit, required: rlim_t) -> Option<rlimit> {
+    if current.rlim_cur == RLIM_INFINITY || current.rlim_cur >= required {
+        return None;
+    }
+    let rlim_max = if current.rlim_max == RLIM_INFINITY || current.rlim_max >= required {
+        current.rlim_max
+    } else {
+        required
+    };
+    Some(rlimit {
+        rlim_cur: required,
+        rlim_max,
+    })
+}
+
+// returns the soft RLIMIT_MEMLOCK limit, u64::MAX if there's no limit
+pub(crate) fn memlock_rlimit() -> Result<u64, io::Error> {
+    let limit = memlock_rlimits()?.rlim_cur;
+    if limit == RLIM_INFINITY {
+        return Ok(u64::MAX);
+    }
+    // rlim_t is u32 on 32-bit glibc
+    #[allow(clippy::unnecessary_cast)]
+    Ok(limit as u64)
+}
+
+fn memlock_rlimits() -> Result<rlimit, io::Error> {
+    let mut limit = rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // Safety: libc wrapper
+    if unsafe { getrlimit(RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(limit)
+}
+
 pub(crate) fn ifindex_from_ifname(if_name: &str) -> Result<u32, io::Error> {
     let c_str_if_name = CString::new(if_name)?;
     let c_if_name = c_str_if_name.as_ptr();
@@ -159,13 +415,38 @@ pub(crate) unsafe fn bytes_of<T>(val: &T) -> &[u8] {
 const MIN_LOG_BUF_SIZE: usize = 1024 * 10;
 const MAX_LOG_BUF_SIZE: usize = (std::u32::MAX >> 8) as usize;
 
+// when retrying after a failure without an explicit level, log everything
+const DEFAULT_LOG_LEVEL: u32 = 7;
+
 pub(crate) struct VerifierLog {
     buf: Vec<u8>,
+    level: u32,
 }
 
 impl VerifierLog {
     pub(crate) fn new() -> VerifierLog {
-        VerifierLog { buf: Vec::new() }
+        VerifierLog {
+            buf: Vec::new(),
+            level: DEFAULT_LOG_LEVEL,
+        }
+    }
+
+    // Creates a log that is requested from the first attempt, with a buffer of
+    // at least `size` bytes. A level of 0 behaves like `new()`.
+    pub(crate) fn with_level(level: u32, size: usize) -> VerifierLog {
+        if level == 0 {
+            return VerifierLog::new();
+        }
+        let mut log = VerifierLog {
+            buf: vec![0; size.clamp(MIN_LOG_BUF_SIZE, MAX_LOG_BUF_SIZE)],
+            level,
+        };
+        log.reset();
+        log
+    }
+
+    pub(crate) fn level(&self) -> u32 {
+        self.level
     }
 
     pub(crate) fn buf(&mut self) -> &mut Vec<u8> {
@@ -236,6 +517,20 @@ mod tests {
         assert!(parse_cpu_ranges("foo").is_err());
     }
 
+    #[test]
+    fn test_verifier_log_with_level() {
+        let mut log = VerifierLog::with_level(0, 4096);
+        assert_eq!(log.level(), DEFAULT_LOG_LEVEL);
+        assert_eq!(log.buf().capacity(), 0);
+
+        let mut log = VerifierLog::with_level(4, 16);
+        assert_eq!(log.level(), 4);
+        assert_eq!(log.buf().len(), MIN_LOG_BUF_SIZE);
+
+        let mut log = VerifierLog::with_level(1, 1024 * 1024);
+        assert_eq!(log.buf().len(), 1024 * 1024);
+    }
+
     #[test]
     fn test_parse_kernel_symbols() {
         let data = "0000000000002000 A irq_stack_backing_store\n\
@@ -249,4 +544,60 @@ mod tests {
         );
         assert_eq!(syms.get(&0x6000u64).unwrap().as_str(), "cpu_tss_rw");
     }
+
+    #[test]
+    fn test_parse_process_mappings() {
+        let data = "55d4a8c00000-55d4a8c28000 r--p 00000000 fd:01 1234 /usr/bin/cat\n\
+                    55d4a8c28000-55d4a8c3d000 r-xp 00028000 fd:01 1234 /usr/bin/cat\n\
+                    7f1c2e000000-7f1c2e1b0000 r-xp 00000000 fd:01 5678 /usr/lib/libc.so.6\n\
+                    7ffd2a5f2000-7ffd2a5f4000 r-xp 00000000 00:00 0 [vdso]\n\
+                    7ffd2a600000-7ffd2a621000 rw-p 00000000 00:00 0\n"
+            .as_bytes();
+        let mappings = parse_process_mappings(&mut BufReader::new(data)).unwrap();
+        assert_eq!(
+            mappings.keys().collect::<Vec<_>>(),
+            vec![&0x55d4a8c28000, &0x7f1c2e000000]
+        );
+        assert_eq!(
+            mappings.get(&0x7f1c2e000000u64).unwrap().as_str(),
+            "/usr/lib/libc.so.6"
+        );
+    }
+
+    #[test]
+    fn test_raised_memlock_rlimit() {
+        let limit = |rlim_cur, rlim_max| rlimit { rlim_cur, rlim_max };
+        let raised = |current, required| {
+            raised_memlock_rlimit(&current, required).map(|l| (l.rlim_cur, l.rlim_max))
+        };
+
+        assert_eq!(raised(limit(RLIM_INFINITY, RLIM_INFINITY), 1024), None);
+        assert_eq!(raised(limit(4096, 4096), 1024), None);
+        // only the soft limit needs to be raised
+        assert_eq!(raised(limit(64, 4096), 1024), Some((1024, 4096)));
+        assert_eq!(
+            raised(limit(64, RLIM_INFINITY), 1024),
+            Some((1024, RLIM_INFINITY))
+        );
+        assert_eq!(raised(limit(64, 512), 1024), Some((1024, 1024)));
+    }
+
+    #[test]
+    fn test_parse_process_mapping_ranges() {
+        let data = "55d4a8c28000-55d4a8c3d000 r-xp 00028000 fd:01 1234 /usr/bin/cat\n\
+                    7ffd2a5f2000-7ffd2a5f4000 r-xp 00000000 00:00 0 [vdso]\n\
+                    7f3c1a000000-7f3c1a100000 r-xp 00000000 fd:01 5678                       /opt/my app/lib.so\n\
+                    7f3c1b000000-7f3c1b001000 r-xp 00000000 00:00 0 \n"
+            .as_bytes();
+        let mappings = parse_process_mapping_ranges(&mut BufReader::new(data)).unwrap();
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].start, 0x55d4a8c28000);
+        assert_eq!(mappings[0].end, 0x55d4a8c3d000);
+        assert_eq!(mappings[0].offset, 0x28000);
+        assert_eq!(mappings[0].path, "/usr/bin/cat");
+        assert_eq!(mappings[1].path, "/opt/my app/lib.so");
+
+        let data = "55d4a8c28000 r-xp 00028000 fd:01 1234 /usr/bin/cat\n".as_bytes();
+        assert!(parse_process_mapping_ranges(&mut BufReader::new(data)).is_err());
+    }
 }
@@ -0,0 +1,144 @@
+//! Network namespaces.
+use std::{
+    ffi::CString,
+    io,
+    os::unix::{ffi::OsStrExt, io::AsRawFd, io::RawFd},
+    path::Path,
+};
+
+use libc::{close, dup, open, setns, CLONE_NEWNET, O_CLOEXEC, O_RDONLY};
+
+// the namespace of the calling thread, which is what setns() changes
+const THREAD_NETNS: &str = "/proc/thread-self/ns/net";
+
+/// A network namespace.
+///
+/// The namespace is kept alive by an open fd for as long as the value
+/// exists, even if all of its processes exit. It can be passed to
+/// [`Xdp::attach_in_namespace`](crate::programs::Xdp::attach_in_namespace)
+/// to attach programs to the interfaces of a container.
+///
+/// # Examples
+///
+/// ```no_run
+/// use aya::util::netns::NetNs;
+///
+/// // the namespace of a container whose init process is 1234
+/// let netns = NetNs::from_pid(1234)?;
+/// // a namespace created with `ip netns add blue`
+/// let blue = NetNs::from_path("/run/netns/blue")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct NetNs {
+    fd: RawFd,
+}
+
+impl NetNs {
+    /// Opens the network namespace of the current process.
+    pub fn current() -> Result<NetNs, io::Error> {
+        NetNs::from_path("/proc/self/ns/net")
+    }
+
+    /// Opens the network namespace of the process `pid`.
+    pub fn from_pid(pid: u32) -> Result<NetNs, io::Error> {
+        NetNs::from_path(format!("/proc/{}/ns/net", pid))
+    }
+
+    /// Opens the network namespace at `path`, eg a namespace bind-mounted in
+    /// `/run/netns` by `ip netns add`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<NetNs, io::Error> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+        // Safety: libc wrapper
+        let fd = unsafe { open(path.as_ptr(), O_RDONLY | O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(NetNs { fd })
+    }
+
+    pub(crate) fn try_clone(&self) -> Result<NetNs, io::Error> {
+        // Safety: libc wrapper
+        let fd = unsafe { dup(self.fd) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(NetNs { fd })
+    }
+
+    /// Runs `f` with the calling thread moved to the namespace, then moves
+    /// the thread back to its original namespace, even if `f` panics.
+    ///
+    /// Sockets created by `f`, like netlink sockets, belong to the namespace
+    /// and interface names are resolved in it. Moving a thread to another
+    /// network namespace requires `CAP_SYS_ADMIN`.
+    ///
+    /// Returns an error if the thread can't be moved to the namespace, in
+    /// which case `f` isn't called. Otherwise returns the result of `f`
+    /// along with the error moving the thread back, if any, since `f` may
+    /// have had side effects the caller must handle either way.
+    pub(crate) fn run_in<F: FnOnce() -> R, R>(
+        &self,
+        f: F,
+    ) -> Result<(R, Option<io::Error>), io::Error> {
+        let guard = RestoreNetNs {
+            original: Some(NetNs::from_path(THREAD_NETNS)?),
+        };
+        set_thread_netns(self.fd)?;
+        let ret = f();
+        Ok((ret, guard.restore().err()))
+    }
+}
+
+// Moves the calling thread back to `original` when dropped, so that a
+// panicking closure doesn't leave it in another namespace.
+struct RestoreNetNs {
+    original: Option<NetNs>,
+}
+
+impl RestoreNetNs {
+    fn restore(mut self) -> Result<(), io::Error> {
+        let original = self.original.take().unwrap();
+        set_thread_netns(original.fd)
+    }
+}
+
+impl Drop for RestoreNetNs {
+    fn drop(&mut self) {
+        if let Some(original) = self.original.take() {
+            let _ = set_thread_netns(original.fd);
+        }
+    }
+}
+
+fn set_thread_netns(fd: RawFd) -> Result<(), io::Error> {
+    // Safety: libc wrapper
+    if unsafe { setns(fd, CLONE_NEWNET) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl AsRawFd for NetNs {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for NetNs {
+    fn drop(&mut self) {
+        // Safety: libc wrapper
+        unsafe { close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_path_not_found() {
+        let err = NetNs::from_path("/nonexistent/ns/net").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
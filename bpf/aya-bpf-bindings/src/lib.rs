@@ -22,6 +22,11 @@ mod gen {
 }
 pub use gen::{getters, helpers};
 
+pub mod btf_types;
+
+/// Marker trait for types that can safely be converted to and from byte slices.
+pub unsafe trait Pod: Copy + 'static {}
+
 pub mod bindings {
     pub use crate::gen::bindings::*;
 
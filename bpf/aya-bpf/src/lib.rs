@@ -8,6 +8,7 @@ mod args;
 pub mod helpers;
 pub mod maps;
 pub mod programs;
+pub mod timer;
 
 pub use aya_bpf_cty as cty;
 
@@ -12,6 +12,8 @@ pub mod per_cpu_array;
 pub mod perf;
 pub mod program_array;
 pub mod queue;
+pub mod ring_buf;
+pub mod sk_storage;
 pub mod sock_hash;
 pub mod sock_map;
 pub mod stack_trace;
@@ -23,6 +25,8 @@ pub use per_cpu_array::PerCpuArray;
 pub use perf::{PerfEventArray, PerfEventByteArray};
 pub use program_array::ProgramArray;
 pub use queue::Queue;
+pub use ring_buf::{RingBuf, RingBufEntry};
+pub use sk_storage::SkStorage;
 pub use sock_hash::SockHash;
 pub use sock_map::SockMap;
 pub use stack_trace::StackTrace;
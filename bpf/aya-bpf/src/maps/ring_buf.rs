@@ -0,0 +1,130 @@
+use core::{
+    mem::{self, MaybeUninit},
+    ops::{Deref, DerefMut},
+};
+
+use aya_bpf_cty::c_long;
+
+use crate::{
+    bindings::{bpf_map_def, bpf_map_type::BPF_MAP_TYPE_RINGBUF},
+    helpers::{bpf_ringbuf_discard, bpf_ringbuf_output, bpf_ringbuf_reserve, bpf_ringbuf_submit},
+    maps::PinningType,
+};
+
+/// A ring buffer used to send data to user space.
+///
+/// By default the kernel wakes up the user space consumer when a record is
+/// committed and the consumer has caught up with the producer. Passing
+/// [`BPF_RB_NO_WAKEUP`](crate::bindings::BPF_RB_NO_WAKEUP) as flags skips
+/// the wakeup, and [`BPF_RB_FORCE_WAKEUP`](crate::bindings::BPF_RB_FORCE_WAKEUP)
+/// forces it. Skipping the wakeups of most records and forcing one every N
+/// records, or once enough bytes are pending, trades latency for throughput:
+/// the consumer is woken up less often and reads more records each time.
+#[repr(transparent)]
+pub struct RingBuf {
+    def: bpf_map_def,
+}
+
+impl RingBuf {
+    /// Creates a ring buffer of `byte_size` bytes, which must be a power of
+    /// two multiple of the page size.
+    pub const fn with_byte_size(byte_size: u32, flags: u32) -> RingBuf {
+        RingBuf {
+            def: build_def(byte_size, flags, PinningType::None),
+        }
+    }
+
+    /// Creates a ring buffer of `byte_size` bytes like
+    /// [`with_byte_size`](RingBuf::with_byte_size), pinned by name.
+    ///
+    /// User space pins the ring buffer at `MAP_NAME` in the directory set
+    /// with `BpfLoader::map_pin_path`. If a map is already pinned there, it's
+    /// reused instead of creating a new one, so the ring buffer can be shared
+    /// between programs and outlive the process that loaded them.
+    pub const fn pinned(byte_size: u32, flags: u32) -> RingBuf {
+        RingBuf {
+            def: build_def(byte_size, flags, PinningType::ByName),
+        }
+    }
+
+    /// Reserves space for a `T` in the ring buffer.
+    ///
+    /// Returns `None` if the ring buffer is full. The verifier requires the
+    /// returned entry to be either submitted or discarded.
+    #[inline]
+    pub fn reserve<T>(&mut self, flags: u64) -> Option<RingBufEntry<T>> {
+        let ptr = unsafe {
+            bpf_ringbuf_reserve(
+                &mut self.def as *mut _ as *mut _,
+                mem::size_of::<T>() as u64,
+                flags,
+            )
+        } as *mut MaybeUninit<T>;
+        unsafe { ptr.as_mut() }.map(|data| RingBufEntry { data })
+    }
+
+    /// Copies `data` to the ring buffer.
+    ///
+    /// `flags` controls the wakeup of the consumer, see [`RingBuf`].
+    #[inline]
+    pub fn output<T>(&mut self, data: &T, flags: u64) -> Result<(), c_long> {
+        let ret = unsafe {
+            bpf_ringbuf_output(
+                &mut self.def as *mut _ as *mut _,
+                data as *const _ as *mut _,
+                mem::size_of::<T>() as u64,
+                flags,
+            )
+        };
+        (ret >= 0).then(|| ()).ok_or(ret)
+    }
+}
+
+/// Space reserved in a [`RingBuf`] with [`RingBuf::reserve`].
+///
+/// The entry dereferences to the uninitialized reserved space.
+pub struct RingBufEntry<T: 'static> {
+    data: &'static mut MaybeUninit<T>,
+}
+
+impl<T> RingBufEntry<T> {
+    /// Commits the entry, making it visible to user space.
+    ///
+    /// `flags` controls the wakeup of the consumer, see [`RingBuf`].
+    #[inline]
+    pub fn submit(self, flags: u64) {
+        unsafe { bpf_ringbuf_submit(self.data as *mut _ as *mut _, flags) };
+    }
+
+    /// Releases the entry without sending it to user space.
+    #[inline]
+    pub fn discard(self, flags: u64) {
+        unsafe { bpf_ringbuf_discard(self.data as *mut _ as *mut _, flags) };
+    }
+}
+
+impl<T> Deref for RingBufEntry<T> {
+    type Target = MaybeUninit<T>;
+
+    fn deref(&self) -> &MaybeUninit<T> {
+        self.data
+    }
+}
+
+impl<T> DerefMut for RingBufEntry<T> {
+    fn deref_mut(&mut self) -> &mut MaybeUninit<T> {
+        self.data
+    }
+}
+
+const fn build_def(byte_size: u32, flags: u32, pin: PinningType) -> bpf_map_def {
+    bpf_map_def {
+        type_: BPF_MAP_TYPE_RINGBUF,
+        key_size: 0,
+        value_size: 0,
+        max_entries: byte_size,
+        map_flags: flags,
+        id: 0,
+        pinning: pin as u32,
+    }
+}
@@ -0,0 +1,104 @@
+use core::{marker::PhantomData, mem, ptr};
+
+use aya_bpf_cty::{c_long, c_void};
+
+use crate::{
+    bindings::{
+        bpf_map_def, bpf_map_type::BPF_MAP_TYPE_SK_STORAGE, bpf_sock, BPF_F_NO_PREALLOC,
+        BPF_SK_STORAGE_GET_F_CREATE,
+    },
+    helpers::{bpf_sk_storage_delete, bpf_sk_storage_get},
+    maps::PinningType,
+    BpfContext,
+};
+
+/// Per-socket storage.
+///
+/// The kernel frees the value stored for a socket when the socket is closed.
+///
+/// The kernel only creates socket storage maps that have BTF key and value
+/// types. Since the map definition doesn't carry BTF, user space must provide
+/// it when loading the program, using `MapCreateOptions::with_btf` and
+/// `BpfLoader::map_create_options` in `aya`.
+#[repr(transparent)]
+pub struct SkStorage<V> {
+    def: bpf_map_def,
+    _v: PhantomData<V>,
+}
+
+impl<V> SkStorage<V> {
+    pub const fn new(flags: u32) -> SkStorage<V> {
+        SkStorage {
+            def: build_def::<V>(flags, PinningType::None),
+            _v: PhantomData,
+        }
+    }
+
+    pub const fn pinned(flags: u32) -> SkStorage<V> {
+        SkStorage {
+            def: build_def::<V>(flags, PinningType::ByName),
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns the value stored for `socket`, or `None` if no value is stored.
+    #[inline]
+    pub unsafe fn get<C: BpfContext>(&mut self, _ctx: &C, socket: *mut bpf_sock) -> Option<*mut V> {
+        let value = bpf_sk_storage_get(
+            &mut self.def as *mut _ as *mut _,
+            socket as *mut c_void,
+            ptr::null_mut(),
+            0,
+        );
+        if value.is_null() {
+            None
+        } else {
+            Some(value as *mut V)
+        }
+    }
+
+    /// Stores `value` for `socket`, replacing any existing value.
+    #[inline]
+    pub unsafe fn insert<C: BpfContext>(
+        &mut self,
+        _ctx: &C,
+        socket: *mut bpf_sock,
+        value: &V,
+    ) -> Result<(), c_long> {
+        let storage = bpf_sk_storage_get(
+            &mut self.def as *mut _ as *mut _,
+            socket as *mut c_void,
+            ptr::null_mut(),
+            BPF_SK_STORAGE_GET_F_CREATE as u64,
+        );
+        if storage.is_null() {
+            return Err(-1);
+        }
+        ptr::copy_nonoverlapping(value as *const V, storage as *mut V, 1);
+
+        Ok(())
+    }
+
+    /// Deletes the value stored for `socket`.
+    #[inline]
+    pub unsafe fn delete<C: BpfContext>(
+        &mut self,
+        _ctx: &C,
+        socket: *mut bpf_sock,
+    ) -> Result<(), c_long> {
+        let ret = bpf_sk_storage_delete(&mut self.def as *mut _ as *mut _, socket as *mut c_void);
+        (ret >= 0).then(|| ()).ok_or(ret)
+    }
+}
+
+const fn build_def<V>(flags: u32, pin: PinningType) -> bpf_map_def {
+    bpf_map_def {
+        type_: BPF_MAP_TYPE_SK_STORAGE,
+        key_size: mem::size_of::<i32>() as u32,
+        value_size: mem::size_of::<V>() as u32,
+        max_entries: 0,
+        map_flags: flags | BPF_F_NO_PREALLOC,
+        id: 0,
+        pinning: pin as u32,
+    }
+}
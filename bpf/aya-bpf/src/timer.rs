@@ -0,0 +1,92 @@
+//! BPF timers.
+//!
+//! A [`BpfTimer`] is embedded in the value type of a map and lets a program
+//! schedule a callback to run at a later time. Timers require kernel 5.15 or
+//! later and can only be stored in `HashMap`, `LruHashMap` and `Array` values.
+//! The verifier finds the timer through the BTF describing the map value,
+//! and the map must be preallocated, so don't create it with
+//! `BPF_F_NO_PREALLOC`.
+//!
+//! The maps of `aya-bpf` don't carry BTF, so user space must provide the BTF
+//! of the value when loading the program, using
+//! `MapCreateOptions::with_btf` and `BpfLoader::map_create_options` in
+//! `aya`. `MapInfo::has_timer` tells whether a loaded map stores timers.
+
+use aya_bpf_cty::{c_int, c_long, c_void};
+
+use crate::{
+    bindings::bpf_timer,
+    helpers::{bpf_timer_cancel, bpf_timer_init, bpf_timer_set_callback, bpf_timer_start},
+};
+
+/// The callback invoked when a timer fires.
+///
+/// The callback receives the map the timer is stored in, the key of the
+/// element and a pointer to the element itself.
+pub type BpfTimerCallback<K, V> =
+    unsafe extern "C" fn(map: *mut c_void, key: *mut K, value: *mut V) -> c_int;
+
+/// A timer stored in a map value.
+#[repr(transparent)]
+pub struct BpfTimer {
+    timer: bpf_timer,
+}
+
+impl BpfTimer {
+    /// Initializes the timer. `map` must be the map the timer is stored in.
+    ///
+    /// `flags` selects the clock used by the timer, for example
+    /// `CLOCK_MONOTONIC`.
+    #[inline]
+    pub unsafe fn init<M>(&mut self, map: &mut M, flags: u64) -> Result<(), c_long> {
+        let ret = bpf_timer_init(
+            &mut self.timer as *mut _,
+            map as *mut _ as *mut c_void,
+            flags,
+        );
+        if ret < 0 {
+            return Err(ret);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the callback invoked when the timer fires.
+    #[inline]
+    pub unsafe fn set_callback<K, V>(
+        &mut self,
+        callback: BpfTimerCallback<K, V>,
+    ) -> Result<(), c_long> {
+        let ret = bpf_timer_set_callback(&mut self.timer as *mut _, callback as *mut c_void);
+        if ret < 0 {
+            return Err(ret);
+        }
+
+        Ok(())
+    }
+
+    /// Arms the timer to fire in `nsecs` nanoseconds.
+    #[inline]
+    pub unsafe fn set(&mut self, nsecs: u64, flags: u64) -> Result<(), c_long> {
+        let ret = bpf_timer_start(&mut self.timer as *mut _, nsecs, flags);
+        if ret < 0 {
+            return Err(ret);
+        }
+
+        Ok(())
+    }
+
+    /// Cancels the timer and waits for its callback to complete if it's
+    /// running.
+    ///
+    /// Returns `true` if the timer was armed.
+    #[inline]
+    pub unsafe fn cancel(&mut self) -> Result<bool, c_long> {
+        let ret = bpf_timer_cancel(&mut self.timer as *mut _);
+        if ret < 0 {
+            return Err(ret);
+        }
+
+        Ok(ret == 1)
+    }
+}
@@ -20,5 +20,11 @@ fn main() {
     let mut bpf = BpfLoader::new().extension("drop").load_file("ext.o").unwrap();
     let drop_: &mut Extension = bpf.program_mut("drop").unwrap().try_into().unwrap();
     drop_.load(pass.fd().unwrap(), "xdp_pass").unwrap();
+
+    println!("Attaching Extension Program");
+    let link = drop_.attach().unwrap();
+
+    println!("Restoring Root XDP program");
+    drop_.detach_and_restore(link).unwrap();
     println!("Success...");
 }
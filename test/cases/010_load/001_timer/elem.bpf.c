@@ -0,0 +1,12 @@
+#include <linux/bpf.h>
+
+// the BTF of the value of the TIMERS map of timer_test.ebpf.rs
+struct elem {
+    struct bpf_timer timer;
+    __u64 fired;
+};
+
+struct {
+    __u32 key;
+    struct elem value;
+} elem_kv;
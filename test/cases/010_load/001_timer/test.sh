@@ -0,0 +1,34 @@
+#!/bin/sh
+# SUMMARY: Check that a timer stored in an array element fires after a delay
+# LABELS:
+
+set -e
+
+# Source libraries. Uncomment if needed/defined
+#. "${RT_LIB}"
+. "${RT_PROJECT_ROOT}/_lib/lib.sh"
+
+NAME=timer_test
+
+clean_up() {
+    rm -rf ebpf user elem.o elem.btf ${NAME}.o ${NAME}
+    exec_vm rm -f elem.btf ${NAME}.o ${NAME}
+}
+
+trap clean_up EXIT
+
+# Test code goes here
+min_kernel_version 5.15
+
+compile_ebpf "$(pwd)/${NAME}.ebpf.rs"
+compile_c_ebpf "$(pwd)/elem.bpf.c"
+llvm-objcopy --dump-section .BTF=elem.btf elem.o
+compile_user "$(pwd)/${NAME}.rs"
+
+scp_vm elem.btf
+scp_vm ${NAME}.o
+scp_vm ${NAME}
+
+exec_vm sudo ./${NAME}
+
+exit 0
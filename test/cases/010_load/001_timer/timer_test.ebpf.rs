@@ -0,0 +1,59 @@
+//! ```cargo
+//! [dependencies]
+//! aya-bpf = { path = "../../../../bpf/aya-bpf" }
+//! ```
+
+#![no_std]
+#![no_main]
+
+use aya_bpf::{
+    bindings::xdp_action,
+    cty::c_int,
+    macros::{map, xdp},
+    maps::Array,
+    programs::XdpContext,
+    timer::BpfTimer,
+};
+use core::ffi::c_void;
+
+const CLOCK_MONOTONIC: u64 = 1;
+
+#[repr(C)]
+pub struct Elem {
+    timer: BpfTimer,
+    fired: u64,
+}
+
+#[map(name = "TIMERS")]
+static mut TIMERS: Array<Elem> = Array::with_max_entries(1, 0);
+
+unsafe extern "C" fn fired(_map: *mut c_void, _key: *mut u32, value: *mut Elem) -> c_int {
+    (*value).fired = 1;
+    0
+}
+
+#[xdp(name = "arm_timer")]
+pub fn arm_timer(_ctx: XdpContext) -> u32 {
+    let _ = unsafe { try_arm_timer() };
+    xdp_action::XDP_PASS
+}
+
+unsafe fn try_arm_timer() -> Result<(), i64> {
+    let elem = match TIMERS.get(0) {
+        Some(elem) => elem as *const Elem as *mut Elem,
+        None => return Ok(()),
+    };
+    // the timer is armed once, later packets fail to init it again
+    (*elem).timer.init(&mut TIMERS, CLOCK_MONOTONIC)?;
+    (*elem).timer.set_callback(fired)?;
+    (*elem).timer.set(100_000_000, 0)
+}
+
+#[no_mangle]
+#[link_section = "license"]
+pub static LICENSE: [u8; 4] = *b"GPL\0";
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    unsafe { core::hint::unreachable_unchecked() }
+}
@@ -0,0 +1,46 @@
+//! ```cargo
+//! [dependencies]
+//! aya = { path = "../../../../aya" }
+//! ```
+
+use aya::{
+    maps::{Array, MapCreateOptions},
+    programs::{Xdp, XdpFlags},
+    Btf, BpfLoader, Endianness, Pod,
+};
+use std::convert::{TryFrom, TryInto};
+use std::net::UdpSocket;
+use std::{thread, time};
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Elem {
+    timer: [u64; 2],
+    fired: u64,
+}
+
+unsafe impl Pod for Elem {}
+
+fn main() {
+    let btf = Btf::parse_file("elem.btf", Endianness::default()).unwrap();
+    let options = MapCreateOptions::new().with_btf(&btf, "__u32", "elem").unwrap();
+    let mut bpf = BpfLoader::new()
+        .map_create_options("TIMERS", options)
+        .load_file("timer_test.o")
+        .unwrap();
+    assert!(bpf.map("TIMERS").unwrap().info().unwrap().has_timer().unwrap());
+
+    println!("Loading XDP program");
+    let prog: &mut Xdp = bpf.program_mut("arm_timer").unwrap().try_into().unwrap();
+    prog.load().unwrap();
+    prog.attach("lo", XdpFlags::SKB_MODE).unwrap();
+
+    // send a packet to arm the timer, which fires after 100ms
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(b"timer", "127.0.0.1:9").unwrap();
+    thread::sleep(time::Duration::from_secs(1));
+
+    let timers = Array::<_, Elem>::try_from(bpf.map("TIMERS").unwrap()).unwrap();
+    assert_eq!(timers.get(&0, 0).unwrap().fired, 1);
+    println!("Success...");
+}
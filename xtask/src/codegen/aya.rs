@@ -1,9 +1,9 @@
 use anyhow::anyhow;
 use std::path::PathBuf;
 
-use aya_gen::{bindgen, write_to_file};
+use aya_gen::bindgen;
 
-use crate::codegen::{Architecture, Options};
+use crate::codegen::{write_to_file, Architecture, Options};
 
 pub fn codegen(opts: &Options) -> Result<(), anyhow::Error> {
     codegen_internal_btf_bindings(opts)?;
@@ -49,7 +49,7 @@ fn codegen_internal_btf_bindings(opts: &Options) -> Result<(), anyhow::Error> {
         .to_string();
 
     // write the bindings, with the original helpers removed
-    write_to_file(&generated.join("btf_internal_bindings.rs"), &bindings)?;
+    write_to_file(opts, &generated.join("btf_internal_bindings.rs"), &bindings)?;
 
     Ok(())
 }
@@ -65,7 +65,10 @@ fn codegen_bindings(opts: &Options) -> Result<(), anyhow::Error> {
         "bpf_prog_type",
         "bpf_attach_type",
         "bpf_prog_info",
+        "bpf_map_info",
         "bpf_btf_info",
+        "bpf_link_type",
+        "bpf_link_info",
         "bpf_func_info",
         "bpf_line_info",
         "bpf_lpm_trie_key",
@@ -209,6 +212,7 @@ fn codegen_bindings(opts: &Options) -> Result<(), anyhow::Error> {
 
         // write the bindings, with the original helpers removed
         write_to_file(
+            opts,
             &generated.join(format!("linux_bindings_{}.rs", arch)),
             &bindings.to_string(),
         )?;
@@ -6,13 +6,12 @@ use std::path::PathBuf;
 use aya_gen::{
     bindgen,
     getters::{generate_getters_for_items, read_getter},
-    write_to_file_fmt,
 };
 use syn::{parse_str, Item};
 
 use crate::codegen::{
     helpers::{expand_helpers, extract_helpers},
-    Architecture, Options,
+    write_to_file_fmt, Architecture, Options,
 };
 
 pub fn codegen(opts: &Options) -> Result<(), anyhow::Error> {
@@ -106,12 +105,14 @@ pub fn codegen(opts: &Options) -> Result<(), anyhow::Error> {
         let generated = dir.join("src").join(arch.to_string());
         // write the bindings, with the original helpers removed
         write_to_file_fmt(
+            opts,
             &generated.join("bindings.rs"),
             &tree.to_token_stream().to_string(),
         )?;
 
         // write the new helpers as expanded by expand_helpers()
         write_to_file_fmt(
+            opts,
             &generated.join("helpers.rs"),
             &format!("use super::bindings::*; {}", helpers),
         )?;
@@ -119,6 +120,7 @@ pub fn codegen(opts: &Options) -> Result<(), anyhow::Error> {
         // write the bpf_probe_read() getters
         let bpf_probe_read = syn::parse_str("crate::bpf_probe_read").unwrap();
         write_to_file_fmt(
+            opts,
             &generated.join("getters.rs"),
             &format!(
                 "use super::bindings::*; {}",
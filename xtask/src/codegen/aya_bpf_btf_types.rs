@@ -0,0 +1,77 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use anyhow::{anyhow, Context};
+
+use aya_gen::{bindgen, btf_types::c_header_from_btf};
+
+use crate::codegen::{write_to_file_fmt, Options};
+
+// Generates the kernel types listed in bindings.toml from the BTF given with
+// --btf, eg the BTF of the running kernel.
+pub fn codegen(opts: &Options) -> Result<(), anyhow::Error> {
+    let dir = PathBuf::from("bpf/aya-bpf-bindings");
+
+    let config_path = dir.join("bindings.toml");
+    let config = fs::read_to_string(&config_path)
+        .with_context(|| format!("failed to read {}", config_path.display()))?;
+    let config = parse_config(&config)
+        .with_context(|| format!("failed to parse {}", config_path.display()))?;
+
+    let c_header = c_header_from_btf(&opts.btf)?;
+    let mut bindgen = bindgen::bpf_builder()
+        .ctypes_prefix("::aya_bpf_cty")
+        .header_contents("kernel_types.h", &c_header);
+    for x in config.get("types").into_iter().flatten() {
+        bindgen = bindgen.allowlist_type(x);
+    }
+
+    let mut bindings = bindgen
+        .generate()
+        .map_err(|_| anyhow!("bindgen failed"))?
+        .to_string();
+    for x in config.get("pod").into_iter().flatten() {
+        bindings.push_str(&format!("unsafe impl crate::Pod for {} {{}}\n", x));
+    }
+
+    write_to_file_fmt(opts, &dir.join("src/btf_types.rs"), &bindings)
+}
+
+// bindings.toml only contains arrays of strings, eg `types = ["ethhdr"]`, so
+// this doesn't implement the rest of TOML.
+fn parse_config(config: &str) -> Result<BTreeMap<String, Vec<String>>, anyhow::Error> {
+    let config = config
+        .lines()
+        .map(|line| line.split('#').next().unwrap())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut values = BTreeMap::new();
+    for entry in config.split(']') {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let (key, list) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected `key = [...]`, found `{}`", entry))?;
+        let key = key.trim();
+        let list = list
+            .trim()
+            .strip_prefix('[')
+            .ok_or_else(|| anyhow!("the value of `{}` isn't an array", key))?;
+        let items = list
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                item.strip_prefix('"')
+                    .and_then(|item| item.strip_suffix('"'))
+                    .map(str::to_owned)
+                    .ok_or_else(|| anyhow!("`{}` in `{}` isn't a string", item, key))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        values.insert(key.to_owned(), items);
+    }
+
+    Ok(values)
+}
@@ -1,9 +1,15 @@
 mod aya;
 mod aya_bpf_bindings;
+mod aya_bpf_btf_types;
 mod helpers;
 
-use std::path::PathBuf;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
+use anyhow::anyhow;
+use aya_gen::rustfmt;
 use structopt::StructOpt;
 
 const SUPPORTED_ARCHS: &[Architecture] = &[
@@ -64,6 +70,15 @@ pub struct Options {
     #[structopt(long, default_value = "/usr/arm-linux-gnueabi/include")]
     armv7_sysroot: PathBuf,
 
+    // the BTF the aya-bpf-btf-types command generates the types from
+    #[structopt(long, default_value = "/sys/kernel/btf/vmlinux")]
+    btf: PathBuf,
+
+    // don't write the generated files, fail if they differ from the ones in
+    // the tree instead
+    #[structopt(long)]
+    check: bool,
+
     #[structopt(subcommand)]
     command: Option<Command>,
 }
@@ -74,6 +89,8 @@ enum Command {
     Aya,
     #[structopt(name = "aya-bpf-bindings")]
     AyaBpfBindings,
+    #[structopt(name = "aya-bpf-btf-types")]
+    AyaBpfBtfTypes,
 }
 
 pub fn codegen(opts: Options) -> Result<(), anyhow::Error> {
@@ -81,9 +98,32 @@ pub fn codegen(opts: Options) -> Result<(), anyhow::Error> {
     match opts.command {
         Some(Aya) => aya::codegen(&opts),
         Some(AyaBpfBindings) => aya_bpf_bindings::codegen(&opts),
+        Some(AyaBpfBtfTypes) => aya_bpf_btf_types::codegen(&opts),
+        // the BTF types need bpftool and are generated separately
         None => {
             aya::codegen(&opts)?;
             aya_bpf_bindings::codegen(&opts)
         }
     }
 }
+
+// Writes the generated `code` to `path`, or with --check, fails if `path`
+// doesn't already contain it.
+fn write_to_file(opts: &Options, path: &Path, code: &str) -> Result<(), anyhow::Error> {
+    if opts.check {
+        let current = fs::read_to_string(path).unwrap_or_default();
+        if current != code {
+            return Err(anyhow!(
+                "{} is out of date, run `cargo xtask codegen`",
+                path.display()
+            ));
+        }
+    } else {
+        aya_gen::write_to_file(path, code)?;
+    }
+    Ok(())
+}
+
+fn write_to_file_fmt(opts: &Options, path: &Path, code: &str) -> Result<(), anyhow::Error> {
+    write_to_file(opts, path, &rustfmt::format(code)?)
+}
@@ -0,0 +1,217 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, bail, Context};
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+pub struct Options {
+    /// Run the tests in a VM booting `--kernel`, with the host root
+    /// filesystem shared over 9p. Uses virtme-ng (`vng`) when it's installed
+    /// and qemu otherwise.
+    #[structopt(long, conflicts_with = "local")]
+    pub vm: bool,
+    /// Run the tests on the host, this is the default.
+    #[structopt(long)]
+    pub local: bool,
+    /// The kernel booted by the VM, defaults to the kernel of the host. When
+    /// qemu is used, virtio, 9p and bpffs must be built into the kernel.
+    #[structopt(long)]
+    pub kernel: Option<PathBuf>,
+    /// The qemu binary used when virtme-ng isn't installed.
+    #[structopt(long, default_value = "qemu-system-x86_64")]
+    pub qemu: String,
+    /// Arguments passed to the test binaries, eg a test name filter.
+    #[structopt(last = true)]
+    pub args: Vec<String>,
+}
+
+pub fn test(opts: Options) -> Result<(), anyhow::Error> {
+    if opts.vm && !opts.local {
+        test_vm(&opts)
+    } else {
+        let status = Command::new("cargo")
+            .args(["test", "--workspace", "--"])
+            .args(&opts.args)
+            .status()
+            .context("failed to run cargo test")?;
+        if !status.success() {
+            bail!("cargo test failed: {}", status);
+        }
+        Ok(())
+    }
+}
+
+fn test_vm(opts: &Options) -> Result<(), anyhow::Error> {
+    let workspace = env::current_dir()?;
+    let binaries = build_test_binaries()?;
+
+    // the directory is shared with the VM, which writes the exit status of
+    // the tests to it
+    let dir = workspace.join("target/xtask-vm");
+    fs::create_dir_all(&dir)?;
+    let script = dir.join("run.sh");
+    let status = dir.join("status");
+    let _ = fs::remove_file(&status);
+    fs::write(
+        &script,
+        runner_script(&workspace, &binaries, &opts.args, &status),
+    )?;
+
+    let vm_status = if which("vng") {
+        let mut vng = Command::new("vng");
+        match &opts.kernel {
+            Some(kernel) => vng.arg("--run").arg(kernel),
+            None => vng.arg("--run"),
+        };
+        vng.arg("--rwdir")
+            .arg(&dir)
+            .arg("--exec")
+            .arg(format!("sh {}", script.display()))
+            .status()
+            .context("failed to run vng")?
+    } else {
+        let kernel = match &opts.kernel {
+            Some(kernel) => kernel.clone(),
+            None => host_kernel()?,
+        };
+        Command::new(&opts.qemu)
+            .args(qemu_args(&kernel, &workspace, &script))
+            .status()
+            .with_context(|| format!("failed to run {}", opts.qemu))?
+    };
+    if !vm_status.success() {
+        bail!("the VM failed: {}", vm_status);
+    }
+
+    // the status of the VM is the status of the kernel, the status of the
+    // tests is only known from the file written by the runner script
+    let code =
+        fs::read_to_string(&status).map_err(|_| anyhow!("the tests didn't complete in the VM"))?;
+    match code.trim() {
+        "0" => Ok(()),
+        code => bail!("the tests failed in the VM with status {}", code),
+    }
+}
+
+// Builds the test binaries of the workspace and returns their paths, which
+// cargo prints as `Executable unittests src/lib.rs (target/debug/deps/...)`.
+fn build_test_binaries() -> Result<Vec<PathBuf>, anyhow::Error> {
+    let output = Command::new("cargo")
+        .args(["test", "--workspace", "--no-run"])
+        .output()
+        .context("failed to run cargo test")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        bail!("failed to build the tests:\n{}", stderr);
+    }
+
+    let binaries = parse_executables(&stderr);
+    if binaries.is_empty() {
+        bail!("cargo didn't build any test binary");
+    }
+    Ok(binaries)
+}
+
+fn parse_executables(cargo_output: &str) -> Vec<PathBuf> {
+    cargo_output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim().strip_prefix("Executable ")?;
+            let start = line.rfind('(')?;
+            line[start + 1..].strip_suffix(')').map(PathBuf::from)
+        })
+        .collect()
+}
+
+// The script runs the test binaries in the VM. With qemu it's the init
+// process, so it mounts the filesystems the tests need and the workspace,
+// which is writable unlike the shared root filesystem.
+fn runner_script(workspace: &Path, binaries: &[PathBuf], args: &[String], status: &Path) -> String {
+    let mut script = format!(
+        r#"if [ $$ -eq 1 ]; then
+    mount -t proc proc /proc
+    mount -t sysfs sys /sys
+    mount -t tmpfs tmpfs /tmp
+    mount -t bpf bpf /sys/fs/bpf
+    mount -t debugfs debugfs /sys/kernel/debug
+    mount -t tracefs tracefs /sys/kernel/tracing
+    mount -t 9p -o trans=virtio,version=9p2000.L workspace {workspace}
+fi
+cd {workspace}
+ret=0
+"#,
+        workspace = workspace.display()
+    );
+    let args = args
+        .iter()
+        .map(|arg| format!("'{}'", arg.replace('\'', r"'\''")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    for binary in binaries {
+        script.push_str(&format!("{} {} || ret=1\n", binary.display(), args));
+    }
+    script.push_str(&format!("echo $ret > {}\nsync\n", status.display()));
+    script
+}
+
+fn qemu_args(kernel: &Path, workspace: &Path, script: &Path) -> Vec<String> {
+    let mut args = vec![
+        "-nographic".to_owned(),
+        "-no-reboot".to_owned(),
+        "-m".to_owned(),
+        "1G".to_owned(),
+        "-kernel".to_owned(),
+        kernel.display().to_string(),
+        // the root filesystem of the host, mounted read-only as the root of
+        // the VM
+        "-fsdev".to_owned(),
+        "local,id=root,path=/,readonly=on,security_model=none".to_owned(),
+        "-device".to_owned(),
+        "virtio-9p-pci,fsdev=root,mount_tag=/dev/root".to_owned(),
+        "-fsdev".to_owned(),
+        format!(
+            "local,id=workspace,path={},security_model=none",
+            workspace.display()
+        ),
+        "-device".to_owned(),
+        "virtio-9p-pci,fsdev=workspace,mount_tag=workspace".to_owned(),
+        "-append".to_owned(),
+        // the kernel panics when the script exits, and reboots right away,
+        // which stops qemu because of -no-reboot
+        format!(
+            "console=ttyS0 panic=-1 root=/dev/root rootfstype=9p \
+             rootflags=trans=virtio,version=9p2000.L ro init=/bin/sh -- {}",
+            script.display()
+        ),
+    ];
+    if Path::new("/dev/kvm").exists() {
+        args.extend(vec![
+            "-enable-kvm".to_owned(),
+            "-cpu".to_owned(),
+            "host".to_owned(),
+        ]);
+    }
+    args
+}
+
+fn host_kernel() -> Result<PathBuf, anyhow::Error> {
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease")?;
+    let kernel = PathBuf::from(format!("/boot/vmlinuz-{}", release.trim()));
+    if !kernel.exists() {
+        bail!(
+            "{} doesn't exist, pass the kernel to boot with --kernel",
+            kernel.display()
+        );
+    }
+    Ok(kernel)
+}
+
+fn which(program: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}